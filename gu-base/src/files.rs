@@ -155,25 +155,31 @@ pub fn write_async<Ins: Stream<Item = Bytes, Error = E>, P: AsRef<Path>, E: Debu
         }
     };
 
-    future::Either::A(
-        input_stream
-            .map_err(|e| {
-                eprintln!("stream err={:?}", e);
-                format!("stream err: {:?}", e)
-            })
-            .fold(file, |mut file, chunk| {
-                match file.write_all(chunk.as_ref()).map_err(|e| format!("{}", e)) {
-                    Ok(()) => (),
-                    Err(e) => return future::err(e),
-                }
-
-                future::ok(file)
-            })
-            .and_then(|_file| Ok(())),
-    )
+    future::Either::A(write_file_async(input_stream, file))
     //stream_with_positions(input_stream, path).for_each(|(x, pos, file)| write_bytes(x, pos, file))
 }
 
+/// Writes the stream to an already opened file.
+pub fn write_file_async<Ins: Stream<Item = Bytes, Error = E>, E: Debug>(
+    input_stream: Ins,
+    file: File,
+) -> impl Future<Item = (), Error = String> {
+    input_stream
+        .map_err(|e| {
+            eprintln!("stream err={:?}", e);
+            format!("stream err: {:?}", e)
+        })
+        .fold(file, |mut file, chunk| {
+            match file.write_all(chunk.as_ref()).map_err(|e| format!("{}", e)) {
+                Ok(()) => (),
+                Err(e) => return future::err(e),
+            }
+
+            future::ok(file)
+        })
+        .and_then(|_file| Ok(()))
+}
+
 fn write_bytes(x: Bytes, pos: u64, file: File) -> impl Future<Item = (), Error = String> {
     let msg = WriteToFile { file, x, pos };
     FILE_HANDLER
@@ -194,11 +200,15 @@ pub fn read_async<P: AsRef<Path>>(path: P) -> impl Stream<Item = Bytes, Error =
                 e
             )
         })
-        .and_then(|file| Ok(ReadFile { file, range: None }))
-        .and_then(|read| Ok(FILE_HANDLER.read_file(read)))
+        .and_then(|file| Ok(read_file_async(file)))
         .flatten_stream()
 }
 
+/// Reads an already opened file.
+pub fn read_file_async(file: File) -> impl Stream<Item = Bytes, Error = String> {
+    FILE_HANDLER.read_file(ReadFile { file, range: None })
+}
+
 /// https://actix.rs/api/actix-web/stable/src/actix_web/fs.rs.html#477-484
 pub struct ChunkedReadFile {
     size: u64,
@@ -581,7 +581,7 @@ fn run_worker<S: Stream<Item = (Blob, BlenderTaskSpec), Error = Error>>(
                         Command::Exec {
                             executable: "./gu-render".into(),
                             args: Vec::new(),
-                            working_dir: None,
+                            options: Default::default(),
                         },
                         Command::UploadFile {
                             uri: blob.uri(),
@@ -708,6 +708,7 @@ fn blender_deployment_spec(
             image: Image {
                 url: "http://52.31.143.91/images/x86_64/linux/gu-blender.hdi".to_string(),
                 hash: "SHA1:213fad4e020ded42e6a949f61cb660cb69bc9845".to_string(),
+                peers: Vec::new(),
             },
             name: "".to_string(),
             tags: vec!["gu:render".into(), "gu:blender".into()],
@@ -724,6 +725,7 @@ fn blender_deployment_spec(
                     url: "prekucki/gu-render-blender".to_string(),
                     hash: "sha256:53d11e6866835986b625e9fb07aa73b31dc667da39fe04f56da0ef06a50e0083"
                         .to_string(),
+                    peers: Vec::new(),
                 },
                 name: "".to_string(),
                 tags: vec!["gu:render".into(), "gu:blender".into()],
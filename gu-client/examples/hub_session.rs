@@ -86,6 +86,7 @@ fn main() {
                                     url: "http://52.31.143.91/images/gu-factor-linux.tar.gz"
                                         .to_string(),
                                     hash: "not_implemented".to_string(),
+                                    peers: Vec::new(),
                                 },
                                 name: "peer_session".to_string(),
                                 tags: vec![],
@@ -102,7 +103,7 @@ fn main() {
                         envman::Command::Exec {
                             executable: "gu-factor".to_string(),
                             args: vec!["100".to_string()],
-                            working_dir: None,
+                            options: Default::default(),
                         },
                         envman::Command::AddTags(vec!["my_tag_2".to_string()]),
                     ]))
@@ -25,6 +25,7 @@ fn main() {
             url: "tomcat:6.0.44".to_string(),
             hash: "sha256:4f00109135274b73a9cd8b3a46f43353a095515088e724a442752a62e9cfa3b3"
                 .to_string(),
+            peers: Vec::new(),
         },
         name: "tomcat".to_string(),
         tags: vec![],
@@ -47,6 +48,7 @@ fn main() {
                         image: Image {
                             url: "tomcat:6.0.44".to_string(),
                             hash: "sha256:4f00109135274b73a9cd8b3a46f43353a095515088e724a442752a62e9cfa3b3".to_string(),
+                            peers: Vec::new(),
                         },
                         name: "tomcat".to_string(),
                         tags: vec![],
@@ -1,21 +1,33 @@
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 use std::{env, str};
 
 use actix_web::{client, http, HttpMessage};
 use bytes::Bytes;
-use futures::{future, prelude::*};
+use futures::{future, prelude::*, stream};
 use log::{debug, info};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use url::Url;
 
+#[cfg(feature = "ssl")]
+use actix::{Actor, Addr};
+#[cfg(feature = "ssl")]
+use openssl::ssl::SslConnector;
+#[cfg(feature = "ssl")]
+use std::sync::Mutex;
+
 use gu_actix::release::{AsyncRelease, Handle};
 use gu_model::{
     deployment::DeploymentInfo,
     envman,
+    events::EventMessage,
     peers::PeerInfo,
-    session::{self, BlobInfo, HubExistingSession, HubSessionSpec, Metadata},
+    session::{
+        self, BlobInfo, HubExistingSession, HubSessionSpec, Metadata, PeerDeploymentResult,
+        SessionCommandSpec, SessionDeploymentSpec,
+    },
+    task::{TaskStatus, TaskSubmit},
     HubInfo,
 };
 use gu_net::types::NodeId;
@@ -34,14 +46,81 @@ pub struct HubConnection {
 #[derive(Debug)]
 struct HubConnectionInner {
     url: Url,
+    auth: RwLock<Option<AppAuth>>,
+    #[cfg(feature = "ssl")]
+    tls: Option<PinnedTls>,
+}
+
+/// Parses a single Server-Sent Events frame, comments and keep-alives yield nothing.
+fn parse_event_frame(frame: &[u8]) -> Option<Result<EventMessage, Error>> {
+    let frame = match str::from_utf8(frame) {
+        Ok(frame) => frame,
+        Err(e) => return Some(Err(e.into())),
+    };
+    let data: Vec<&str> = frame
+        .lines()
+        .filter(|line| line.starts_with("data:"))
+        .map(|line| line["data:".len()..].trim_start())
+        .collect();
+    if data.is_empty() {
+        return None;
+    }
+
+    Some(serde_json::from_str(&data.join("\n")).map_err(|e| {
+        Error::InvalidJSONResponse(actix_web::error::JsonPayloadError::Deserialize(e))
+    }))
+}
+
+/// Connector for a hub serving https with a pinned certificate.
+#[cfg(feature = "ssl")]
+struct PinnedTls {
+    fingerprint: String,
+    ssl: SslConnector,
+    connector: Mutex<Option<Addr<client::ClientConnector>>>,
+}
+
+#[cfg(feature = "ssl")]
+impl PinnedTls {
+    fn connector(&self) -> Addr<client::ClientConnector> {
+        self.connector
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| {
+                client::ClientConnector::with_connector(self.ssl.clone()).start()
+            })
+            .clone()
+    }
+}
+
+#[cfg(feature = "ssl")]
+impl std::fmt::Debug for PinnedTls {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("PinnedTls")
+            .field("fingerprint", &self.fingerprint)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+struct AppAuth {
+    app_name: String,
+    token: Option<String>,
 }
 
 impl Default for HubConnection {
     fn default() -> Self {
-        match env::var("GU_HUB_ADDR") {
-            Ok(addr) => HubConnection::from_addr(addr).unwrap(),
-            Err(_) => HubConnection::from_addr("127.0.0.1:61622").unwrap(),
+        let addr = env::var("GU_HUB_ADDR").unwrap_or_else(|_| "127.0.0.1:61622".into());
+        #[cfg(feature = "ssl")]
+        let connection = match env::var("GU_HUB_CERT") {
+            Ok(fingerprint) => HubConnection::from_addr_pinned(addr, fingerprint).unwrap(),
+            Err(_) => HubConnection::from_addr(addr).unwrap(),
+        };
+        #[cfg(not(feature = "ssl"))]
+        let connection = HubConnection::from_addr(addr).unwrap();
+        if let Ok(token) = env::var("GU_HUB_TOKEN") {
+            connection.auth_app("gu-client", Some(token));
         }
+        connection
     }
 }
 
@@ -51,10 +130,41 @@ impl HubConnection {
         Url::parse(&format!("http://{}/", addr.into()))
             .map_err(Error::InvalidAddress)
             .map(|url| HubConnection {
-                hub_connection_inner: Arc::new(HubConnectionInner { url: url }),
+                hub_connection_inner: Arc::new(HubConnectionInner {
+                    url: url,
+                    auth: RwLock::new(None),
+                    #[cfg(feature = "ssl")]
+                    tls: None,
+                }),
             })
     }
 
+    /// creates a https hub connection accepting only the certificate with given
+    /// SHA-256 fingerprint, e.g. the one logged by the hub on startup
+    #[cfg(feature = "ssl")]
+    pub fn from_addr_pinned<T: Into<String>, F: Into<String>>(
+        addr: T,
+        fingerprint: F,
+    ) -> Result<HubConnection, Error> {
+        let fingerprint = fingerprint.into();
+        let ssl =
+            gu_net::tls::pinned_connector(&fingerprint).map_err(|e| Error::Other(e.to_string()))?;
+        let url =
+            Url::parse(&format!("https://{}/", addr.into())).map_err(Error::InvalidAddress)?;
+
+        Ok(HubConnection {
+            hub_connection_inner: Arc::new(HubConnectionInner {
+                url,
+                auth: RwLock::new(None),
+                tls: Some(PinnedTls {
+                    fingerprint,
+                    ssl,
+                    connector: Mutex::new(None),
+                }),
+            }),
+        })
+    }
+
     /// creates a new hub session
     pub fn new_session(
         &self,
@@ -62,7 +172,7 @@ impl HubConnection {
     ) -> impl Future<Item = Handle<HubSession>, Error = Error> + 'static {
         let sessions_url = format!("{}sessions", self.hub_connection_inner.url);
         let hub_connection = self.clone();
-        client::ClientRequest::post(sessions_url)
+        self.request(http::Method::POST, sessions_url)
             .json(session_info)
             .into_future()
             .map_err(Error::CreateRequest)
@@ -85,7 +195,32 @@ impl HubConnection {
             })
     }
 
-    pub fn auth_app<T: Into<String>, U: Into<String>>(&self, _app_name: T, _token: Option<U>) {}
+    /// sets the application name and the API token sent with every request to the hub
+    pub fn auth_app<T: Into<String>, U: Into<String>>(&self, app_name: T, token: Option<U>) {
+        *self.hub_connection_inner.auth.write().unwrap() = Some(AppAuth {
+            app_name: app_name.into(),
+            token: token.map(Into::into),
+        });
+    }
+
+    fn request<U: AsRef<str>>(&self, method: http::Method, url: U) -> client::ClientRequestBuilder {
+        let mut builder = client::ClientRequest::build();
+        builder.method(method).uri(url);
+        #[cfg(feature = "ssl")]
+        {
+            if let Some(tls) = self.hub_connection_inner.tls.as_ref() {
+                builder.with_connector(tls.connector());
+            }
+        }
+        if let Some(auth) = self.hub_connection_inner.auth.read().unwrap().as_ref() {
+            builder.header("X-App-Name", auth.app_name.as_str());
+            if let Some(token) = auth.token.as_ref() {
+                builder.header(http::header::AUTHORIZATION, format!("Bearer {}", token));
+            }
+        }
+        builder
+    }
+
     /// returns all peers connected to the hub
     pub fn list_peers(
         &self,
@@ -104,6 +239,51 @@ impl HubConnection {
         self.fetch_json(&url)
             .and_then(|answer_json: Vec<_>| future::ok(answer_json.into_iter()))
     }
+    /// subscribes to hub events posted under the given path, e.g. `/sessions/12`;
+    /// empty path subscribes to all events
+    pub fn subscribe_events(
+        &self,
+        path: &str,
+    ) -> impl Stream<Item = EventMessage, Error = Error> + 'static {
+        let connection = self.clone();
+        let path = path.to_string();
+
+        future::result(self.hub_connection_inner.url.join("events"))
+            .map_err(Error::InvalidAddress)
+            .and_then(move |mut url| {
+                url.query_pairs_mut().append_pair("path", &path);
+                connection
+                    .request(http::Method::GET, url.as_str())
+                    .header(http::header::ACCEPT, "text/event-stream")
+                    .finish()
+                    .map_err(Error::CreateRequest)
+            })
+            // the request timeout covers the whole body, which never ends for event streams
+            .and_then(|request| {
+                request
+                    .send()
+                    .timeout(Duration::from_secs(365 * 24 * 3600))
+                    .from_err()
+            })
+            .and_then(|response| match response.status() {
+                http::StatusCode::OK => future::ok(response.payload().from_err()),
+                status => future::err(Error::ResponseErr(status)),
+            })
+            .flatten_stream()
+            .map({
+                let mut buf = Vec::new();
+                move |chunk: Bytes| {
+                    buf.extend_from_slice(&chunk);
+                    let mut messages = Vec::new();
+                    while let Some(end) = buf.windows(2).position(|w| w == b"\n\n") {
+                        let frame: Vec<u8> = buf.drain(..end + 2).collect();
+                        messages.extend(parse_event_frame(&frame));
+                    }
+                    stream::iter_result(messages)
+                }
+            })
+            .flatten()
+    }
     /// returns hub session object
     pub fn hub_session(&self, session_id: u64) -> HubSession {
         let hub_connection = self.clone();
@@ -131,7 +311,7 @@ impl HubConnection {
         &self,
         url: &str,
     ) -> impl Future<Item = T, Error = Error> + 'static {
-        client::ClientRequest::get(&url)
+        self.request(http::Method::GET, &url)
             .finish()
             .into_future()
             .map_err(Error::CreateRequest)
@@ -144,7 +324,7 @@ impl HubConnection {
     }
 
     fn delete_resource(&self, url: &str) -> impl Future<Item = (), Error = Error> + 'static {
-        client::ClientRequest::delete(&url)
+        self.request(http::Method::DELETE, &url)
             .finish()
             .into_future()
             .map_err(Error::CreateRequest)
@@ -206,7 +386,11 @@ impl HubSession {
             Err(e) => return future::Either::A(future::err(Error::Other(format!("{}", e)))),
         };
 
-        let request = match client::ClientRequest::post(add_url).json(peers) {
+        let request = match self
+            .hub_connection
+            .request(http::Method::POST, add_url)
+            .json(peers)
+        {
             Ok(r) => r,
             Err(e) => return future::Either::A(future::err(Error::CreateRequest(e))),
         };
@@ -233,7 +417,32 @@ impl HubSession {
             self.hub_connection.url(),
             self.session_id
         );
-        let request = match client::ClientRequest::post(new_blob_url).finish() {
+        self.create_blob(new_blob_url)
+    }
+
+    /// creates a new blob that will be deleted by the hub after given time
+    pub fn new_blob_with_ttl(
+        &self,
+        ttl: Duration,
+    ) -> impl Future<Item = Blob, Error = Error> + 'static {
+        let new_blob_url = format!(
+            "{}sessions/{}/blobs?ttl={}",
+            self.hub_connection.url(),
+            self.session_id,
+            ttl.as_secs()
+        );
+        self.create_blob(new_blob_url)
+    }
+
+    fn create_blob(
+        &self,
+        new_blob_url: String,
+    ) -> impl Future<Item = Blob, Error = Error> + 'static {
+        let request = match self
+            .hub_connection
+            .request(http::Method::POST, new_blob_url)
+            .finish()
+        {
             Ok(r) => r,
             Err(e) => return future::Either::A(future::err(Error::CreateRequest(e))),
         };
@@ -307,6 +516,119 @@ impl HubSession {
             .and_then(|blobs: Vec<BlobInfo>| Ok(blobs.into_iter()))
     }
 
+    /// creates the same deployment on all selected session peers
+    pub fn create_deployments(
+        &self,
+        spec: SessionDeploymentSpec,
+    ) -> impl Future<Item = Vec<PeerDeploymentResult>, Error = Error> + 'static {
+        let url = format!(
+            "{}sessions/{}/deployments",
+            self.hub_connection.url(),
+            self.session_id
+        );
+        future::result(
+            self.hub_connection
+                .request(http::Method::POST, url)
+                .json(spec),
+        )
+        .map_err(Error::CreateRequest)
+        .and_then(|request| request.send().timeout(Duration::from_secs(3600)).from_err())
+        .and_then(|response| match response.status() {
+            http::StatusCode::OK => future::Either::A(response.json().from_err()),
+            http::StatusCode::NOT_FOUND => future::Either::B(future::err(Error::ResourceNotFound)),
+            status => future::Either::B(future::err(Error::ResponseErr(status))),
+        })
+    }
+
+    /// runs a command batch on session deployments of all selected peers
+    pub fn update_deployments(
+        &self,
+        spec: SessionCommandSpec,
+    ) -> impl Future<Item = Vec<PeerDeploymentResult>, Error = Error> + 'static {
+        let url = format!(
+            "{}sessions/{}/deployments",
+            self.hub_connection.url(),
+            self.session_id
+        );
+        future::result(
+            self.hub_connection
+                .request(http::Method::PATCH, url)
+                .json(spec),
+        )
+        .map_err(Error::CreateRequest)
+        .and_then(|request| {
+            request
+                .send()
+                .timeout(Duration::from_secs(24 * 3600))
+                .from_err()
+        })
+        .and_then(|response| match response.status() {
+            http::StatusCode::OK => future::Either::A(response.json().from_err()),
+            http::StatusCode::NOT_FOUND => future::Either::B(future::err(Error::ResourceNotFound)),
+            status => future::Either::B(future::err(Error::ResponseErr(status))),
+        })
+    }
+
+    /// submits tasks to be scheduled by the hub on session deployments; returns task ids
+    pub fn submit_tasks(
+        &self,
+        submit: TaskSubmit,
+    ) -> impl Future<Item = Vec<u64>, Error = Error> + 'static {
+        let url = format!(
+            "{}sessions/{}/tasks",
+            self.hub_connection.url(),
+            self.session_id
+        );
+        future::result(
+            self.hub_connection
+                .request(http::Method::POST, url)
+                .json(submit),
+        )
+        .map_err(Error::CreateRequest)
+        .and_then(|request| request.send().from_err())
+        .and_then(|response| match response.status() {
+            http::StatusCode::CREATED => future::Either::A(response.json().from_err()),
+            http::StatusCode::NOT_FOUND => future::Either::B(future::err(Error::ResourceNotFound)),
+            status => future::Either::B(future::err(Error::ResponseErr(status))),
+        })
+    }
+
+    /// returns status of all session tasks
+    pub fn list_tasks(
+        &self,
+    ) -> impl Future<Item = impl Iterator<Item = TaskStatus>, Error = Error> + 'static {
+        let url = format!(
+            "{}sessions/{}/tasks",
+            self.hub_connection.url(),
+            self.session_id
+        );
+        self.hub_connection
+            .fetch_json(&url)
+            .and_then(|tasks: Vec<TaskStatus>| Ok(tasks.into_iter()))
+    }
+
+    /// returns status of a single session task
+    pub fn task(&self, task_id: u64) -> impl Future<Item = TaskStatus, Error = Error> + 'static {
+        let url = format!(
+            "{}sessions/{}/tasks/{}",
+            self.hub_connection.url(),
+            self.session_id,
+            task_id
+        );
+        self.hub_connection.fetch_json(&url)
+    }
+
+    /// cancels a session task
+    pub fn cancel_task(&self, task_id: u64) -> impl Future<Item = (), Error = Error> + 'static {
+        let url = format!(
+            "{}sessions/{}/tasks/{}",
+            self.hub_connection.url(),
+            self.session_id,
+            task_id
+        );
+        self.hub_connection.delete_resource(&url)
+    }
+
     /// gets information about hub session
     pub fn info(&self) -> impl Future<Item = HubSessionSpec, Error = Error> + 'static {
         let url = format!("{}sessions/{}", self.hub_connection.url(), self.session_id);
@@ -320,13 +642,17 @@ impl HubSession {
             self.hub_connection.url(),
             self.session_id
         );
-        future::result(client::ClientRequest::put(url).json(config))
-            .map_err(Error::CreateRequest)
-            .and_then(|request| request.send().from_err())
-            .and_then(|response| match response.status() {
-                http::StatusCode::OK => future::ok(()),
-                status => future::err(Error::ResponseErr(status)),
-            })
+        future::result(
+            self.hub_connection
+                .request(http::Method::PUT, url)
+                .json(config),
+        )
+        .map_err(Error::CreateRequest)
+        .and_then(|request| request.send().from_err())
+        .and_then(|response| match response.status() {
+            http::StatusCode::OK => future::ok(()),
+            status => future::err(Error::ResponseErr(status)),
+        })
     }
 
     /// gets hub session config
@@ -339,6 +665,51 @@ impl HubSession {
         self.hub_connection.fetch_json(&url)
     }
 
+    /// waits until hub session config or blob set changes and returns the current config
+    /// with the session version;
+    /// returns immediately if the config version differs from `version` or the session
+    /// version differs from `session_version`, e.g. one returned by the previous call
+    pub fn watch_config(
+        &self,
+        version: u64,
+        session_version: u64,
+        timeout: Duration,
+    ) -> impl Future<Item = (Metadata, u64), Error = Error> + 'static {
+        let url = format!(
+            "{}sessions/{}/config?waitVersion={}&waitSessionVersion={}&timeout={}",
+            self.hub_connection.url(),
+            self.session_id,
+            version,
+            session_version,
+            timeout.as_secs()
+        );
+        future::result(self.hub_connection.request(http::Method::GET, url).finish())
+            .map_err(Error::CreateRequest)
+            .and_then(move |request| {
+                request
+                    .send()
+                    .timeout(timeout + Duration::from_secs(10))
+                    .from_err()
+            })
+            .and_then(|response| match response.status() {
+                http::StatusCode::OK => {
+                    let session_version = response
+                        .headers()
+                        .get("x-session-version")
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or_default();
+                    future::Either::A(
+                        response
+                            .json()
+                            .from_err()
+                            .map(move |metadata: Metadata| (metadata, session_version)),
+                    )
+                }
+                status => future::Either::B(future::err(Error::ResponseErr(status))),
+            })
+    }
+
     /// updates hub session
     pub fn update(
         &self,
@@ -346,9 +717,8 @@ impl HubSession {
     ) -> impl Future<Item = (), Error = Error> + 'static {
         let url = format!("{}sessions/{}", self.hub_connection.url(), self.session_id);
         future::result(
-            client::ClientRequest::build()
-                .method(actix_web::http::Method::PATCH)
-                .uri(url)
+            self.hub_connection
+                .request(http::Method::PATCH, url)
                 .json(command),
         )
         .map_err(Error::CreateRequest)
@@ -423,7 +793,12 @@ impl Blob {
             self.hub_session.session_id,
             self.blob_id
         );
-        let request = match client::ClientRequest::put(url).streaming(stream) {
+        let request = match self
+            .hub_session
+            .hub_connection
+            .request(http::Method::PUT, url)
+            .streaming(stream)
+        {
             Ok(r) => r,
             Err(e) => return future::Either::A(future::err(Error::CreateRequest(e))),
         };
@@ -447,14 +822,19 @@ impl Blob {
             self.blob_id
         );
 
-        future::result(client::ClientRequest::get(url).finish())
-            .map_err(Error::CreateRequest)
-            .and_then(|request| request.send().timeout(Duration::from_secs(3600)).from_err())
-            .and_then(|response| match response.status() {
-                http::StatusCode::OK => future::ok(response.payload().from_err()),
-                status => future::err(Error::ResponseErr(status)),
-            })
-            .flatten_stream()
+        future::result(
+            self.hub_session
+                .hub_connection
+                .request(http::Method::GET, url)
+                .finish(),
+        )
+        .map_err(Error::CreateRequest)
+        .and_then(|request| request.send().timeout(Duration::from_secs(3600)).from_err())
+        .and_then(|response| match response.status() {
+            http::StatusCode::OK => future::ok(response.payload().from_err()),
+            status => future::err(Error::ResponseErr(status)),
+        })
+        .flatten_stream()
     }
     /// deletes blob
     pub fn delete(self) -> impl Future<Item = (), Error = Error> {
@@ -488,7 +868,12 @@ impl Peer {
             self.hub_session.session_id,
             self.node_id.to_string()
         );
-        let request = match client::ClientRequest::post(url).json(session_info) {
+        let request = match self
+            .hub_session
+            .hub_connection
+            .request(http::Method::POST, url)
+            .json(session_info)
+        {
             Ok(r) => r,
             Err(e) => return future::Either::A(future::err(Error::CreateRequest(e))),
         };
@@ -570,9 +955,10 @@ impl PeerSession {
             self.session_id,
         );
         future::result(
-            client::ClientRequest::build()
-                .method(actix_web::http::Method::PATCH)
-                .uri(url)
+            self.peer
+                .hub_session
+                .hub_connection
+                .request(http::Method::PATCH, url)
                 .json(commands),
         )
         .map_err(Error::CreateRequest)
@@ -734,7 +1120,8 @@ impl ProviderRef {
             T::ID
         );
 
-        client::ClientRequest::post(url)
+        self.connection
+            .request(http::Method::POST, url)
             .json(Body { b: msg })
             .into_future()
             .map_err(|e| Error::Other(format!("client request err: {}", e)))
@@ -748,3 +1135,99 @@ impl ProviderRef {
 struct Body<T: Serialize + 'static> {
     b: T,
 }
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::mpsc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use actix::prelude::*;
+    use actix_web::{server, App, HttpResponse};
+    use bytes::Bytes;
+    use futures::{prelude::*, stream};
+    use gu_model::events::HubEvent;
+
+    use super::{parse_event_frame, HubConnection};
+
+    #[test]
+    fn test_parse_event_frame() {
+        let frame = concat!(
+            "id: 7\n",
+            r#"data: {"id": 7, "path": "/sessions/3", "event": {"type": "sessionCreated", "sessionId": 3}}"#,
+            "\n\n"
+        );
+        let message = parse_event_frame(frame.as_bytes()).unwrap().unwrap();
+        assert_eq!(message.id, 7);
+        assert_eq!(message.path, "/sessions/3");
+        match message.event {
+            HubEvent::SessionCreated { session_id } => assert_eq!(session_id, 3),
+            event => panic!("unexpected event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn test_parse_multiline_event_frame() {
+        let frame = concat!(
+            "data: {\"id\": 1, \"path\": \"/sessions/4\",\n",
+            "data: \"event\": {\"type\": \"sessionDeleted\", \"sessionId\": 4}}\n\n"
+        );
+        let message = parse_event_frame(frame.as_bytes()).unwrap().unwrap();
+        assert_eq!(message.id, 1);
+    }
+
+    #[test]
+    fn test_parse_event_frame_without_data() {
+        assert!(parse_event_frame(b": keep-alive\n\n").is_none());
+        assert!(parse_event_frame(b"\n\n").is_none());
+    }
+
+    #[test]
+    fn test_parse_invalid_event_frame() {
+        assert!(parse_event_frame(b"data: {\"id\": \n\n").unwrap().is_err());
+        assert!(parse_event_frame(b"data: \xff\n\n").unwrap().is_err());
+    }
+
+    #[test]
+    fn test_event_stream_outlives_default_timeout() {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            System::run(move || {
+                let server = server::new(|| {
+                    App::new().resource("/events", |r| {
+                        r.get().f(|_r| {
+                            let frame = Bytes::from_static(concat!(
+                                r#"data: {"id": 7, "path": "/sessions/3", "event": {"type": "sessionDeleted", "sessionId": 3}}"#,
+                                "\n\n"
+                            ).as_bytes());
+                            let events = tokio_timer::Delay::new(
+                                Instant::now() + Duration::from_secs(7),
+                            )
+                            .map_err(actix_web::error::ErrorInternalServerError)
+                            .map(|()| stream::once(Ok(frame)))
+                            .flatten_stream();
+                            HttpResponse::Ok()
+                                .content_type("text/event-stream")
+                                .streaming(events)
+                        })
+                    })
+                })
+                .bind("127.0.0.1:0")
+                .unwrap();
+                tx.send(server.addrs()[0]).unwrap();
+                server.start();
+            });
+        });
+        let addr = rx.recv().unwrap();
+
+        let mut sys = System::new("test");
+        let connection = HubConnection::from_addr(addr.to_string()).unwrap();
+        let (message, _events) = sys
+            .block_on(connection.subscribe_events("").into_future())
+            .map_err(|(e, _events)| e)
+            .unwrap();
+        assert_eq!(message.unwrap().id, 7);
+    }
+}
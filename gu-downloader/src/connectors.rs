@@ -0,0 +1,92 @@
+//! Client connectors used for requests to particular hosts.
+//!
+//! Hubs usually serve their repo and blobs with self-signed certificates which
+//! providers pin, so requests to a hub must go through a connector checking its
+//! pin instead of the default one.
+
+use std::{collections::HashMap, sync::RwLock};
+
+use actix::Addr;
+use actix_web::{
+    client::{ClientConnector, ClientRequest, ClientRequestBuilder},
+    http::Method,
+};
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref CONNECTORS: RwLock<HashMap<String, Addr<ClientConnector>>> =
+        RwLock::new(HashMap::new());
+}
+
+/// Sets the connector for requests to `authority`, i.e. `host:port`.
+pub fn set_connector<S: Into<String>>(authority: S, connector: Addr<ClientConnector>) {
+    CONNECTORS
+        .write()
+        .unwrap()
+        .insert(authority.into(), connector);
+}
+
+/// `host:port` of the url, with the default port of its scheme.
+fn authority(url: &str) -> Option<String> {
+    let pos = url.find("://")?;
+    let (scheme, rest) = (&url[..pos], &url[pos + 3..]);
+    let authority = rest.split(|c| c == '/' || c == '?' || c == '#').next()?;
+    let host_port = authority.rsplit('@').next()?;
+    if host_port.is_empty() {
+        return None;
+    }
+    let has_port = host_port
+        .rfind(':')
+        .map(|pos| !host_port[pos..].contains(']'))
+        .unwrap_or(false);
+    if has_port {
+        return Some(host_port.to_string());
+    }
+    let port = match scheme {
+        "https" | "wss" => 443,
+        "http" | "ws" => 80,
+        _ => return None,
+    };
+    Some(format!("{}:{}", host_port, port))
+}
+
+/// Request to the url, through the connector set for its host if there is one.
+pub fn request(method: Method, url: &str) -> ClientRequestBuilder {
+    let mut builder = ClientRequest::build();
+    builder.method(method).uri(url);
+    let connector =
+        authority(url).and_then(|authority| CONNECTORS.read().unwrap().get(&authority).cloned());
+    if let Some(connector) = connector {
+        builder.with_connector(connector);
+    }
+    builder
+}
+
+#[cfg(test)]
+mod test {
+    use super::authority;
+
+    #[test]
+    fn test_authority() {
+        assert_eq!(
+            authority("https://10.0.0.1:61622/repo/x")
+                .as_ref()
+                .map(AsRef::as_ref),
+            Some("10.0.0.1:61622")
+        );
+        assert_eq!(
+            authority("https://hub/repo").as_ref().map(AsRef::as_ref),
+            Some("hub:443")
+        );
+        assert_eq!(
+            authority("http://user@[::1]?x").as_ref().map(AsRef::as_ref),
+            Some("[::1]:80")
+        );
+        assert_eq!(
+            authority("http://[::1]:8080/").as_ref().map(AsRef::as_ref),
+            Some("[::1]:8080")
+        );
+        assert_eq!(authority("file:///etc/passwd"), None);
+        assert_eq!(authority("repo/x"), None);
+    }
+}
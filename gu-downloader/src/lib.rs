@@ -11,10 +11,12 @@ use futures::prelude::*;
 use futures_cpupool::CpuPool;
 
 use gu_actix::prelude::*;
+use gu_model::hash::{self, ContentChecker};
 
 pub use self::error::Error;
 use self::sync_io::{CheckType, DownloadFile, LogMetadata, Proxy};
 
+pub mod connectors;
 mod error;
 mod sync_io;
 
@@ -36,7 +38,15 @@ impl DownloadOptions {
         url: &str,
         dest_file: String,
     ) -> impl Stream<Item = ProgressStatus, Error = Error> {
-        download(self, url, dest_file)
+        download(self, Sources::new(url), dest_file)
+    }
+
+    pub fn download_from(
+        self,
+        sources: Sources,
+        dest_file: String,
+    ) -> impl Stream<Item = ProgressStatus, Error = Error> {
+        download(self, sources, dest_file)
     }
 }
 
@@ -46,16 +56,68 @@ impl DownloadOptionsBuilder {
         url: &str,
         dest_file: String,
     ) -> impl Stream<Item = ProgressStatus, Error = Error> {
-        let url = url.to_owned();
+        self.download_from(Sources::new(url), dest_file)
+    }
 
+    pub fn download_from(
+        &self,
+        sources: Sources,
+        dest_file: String,
+    ) -> impl Stream<Item = ProgressStatus, Error = Error> {
         self.build()
             .into_future()
-            .and_then(move |o| Ok(o.download(&url, dest_file)))
+            .and_then(move |o| Ok(o.download_from(sources, dest_file)))
             .map_err(|e| Error::Other(e.into()))
             .flatten_stream()
     }
 }
 
+/// Where the file is downloaded from: the origin url and peers serving the same content.
+///
+/// Peers are used only when the hash of the file is known. Each chunk got from a peer
+/// is checked against chunk hashes listed by the peers, and a chunk that does not match
+/// is fetched from the origin. The whole file is checked against its hash at the end,
+/// and if it does not match, chunks got from peers are fetched again from the origin.
+#[derive(Clone, Debug)]
+pub struct Sources {
+    origin: String,
+    peers: Vec<String>,
+    hash: Option<String>,
+}
+
+impl Sources {
+    pub fn new<S: Into<String>>(origin: S) -> Self {
+        Sources {
+            origin: origin.into(),
+            peers: Vec::new(),
+            hash: None,
+        }
+    }
+
+    /// Expected hash of the whole file, e.g. `SHA1:<hex>`
+    pub fn hash<S: Into<String>>(mut self, hash: S) -> Self {
+        self.hash = Some(hash.into());
+        self
+    }
+
+    /// Urls of peers serving the file
+    pub fn peers(mut self, peers: Vec<String>) -> Self {
+        self.peers = peers;
+        self
+    }
+
+    /// Peer the chunk is fetched from, `None` for the origin.
+    /// Chunks are spread round-robin over the peers and the origin.
+    fn peer_for(&self, chunk_nr: u32) -> Option<&str> {
+        if self.hash.is_none() {
+            return None;
+        }
+        self.peers
+            .get(chunk_nr as usize % (self.peers.len() + 1))
+            .map(AsRef::as_ref)
+    }
+}
+
 pub fn cpu_pool() -> CpuPool {
     actix_web::server::ServerSettings::default()
         .cpu_pool()
@@ -66,64 +128,295 @@ trait ProgressReport: Clone {
     fn progress(progress: ProgressStatus);
 }
 
+/// Chunk hashes listed by all peers that answered. Lists of a wrong length are
+/// ignored, and chunks the peers disagree on have no hash.
+fn agreed_chunk_hashes(lists: Vec<Vec<String>>, chunks: u32) -> Vec<Option<String>> {
+    let lists: Vec<Vec<String>> = lists
+        .into_iter()
+        .filter(|list| list.len() == chunks as usize)
+        .collect();
+    let first = match lists.first() {
+        Some(first) => first,
+        None => return Vec::new(),
+    };
+
+    first
+        .iter()
+        .enumerate()
+        .map(|(n, hash)| {
+            if lists.iter().all(|list| &list[n] == hash) {
+                Some(hash.clone())
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Gets the chunk hashes from the peers, see `agreed_chunk_hashes`.
+fn fetch_chunk_hashes(
+    sources: &Sources,
+    meta: &LogMetadata,
+    timeout: time::Duration,
+) -> impl Future<Item = Vec<Option<String>>, Error = Error> {
+    use actix_web::{http::Method, HttpMessage};
+    use futures::future::{self, Either};
+
+    if sources.hash.is_none() || sources.peers.is_empty() {
+        return Either::A(future::ok(Vec::new()));
+    }
+    let chunks = meta.chunks;
+    let limit = 1024 + chunks as usize * 256;
+
+    let lists = sources.peers.iter().map(move |peer| {
+        let url = format!("{}/chunks/{}", peer, meta.chunk_size);
+        connectors::request(Method::GET, &url)
+            .finish()
+            .into_future()
+            .from_err()
+            .and_then(move |request| request.send().timeout(timeout).from_err())
+            .and_then(move |resp| {
+                resp.json::<Vec<String>>()
+                    .limit(limit)
+                    .map_err(|e| Error::Other(format!("resp: {}", e)))
+            })
+            .then(move |list| match list {
+                Ok(list) => Ok(Some(list)),
+                Err(e) => {
+                    log::debug!("cannot get chunk hashes from {}: {}", url, e);
+                    Ok(None)
+                }
+            })
+    });
+
+    Either::B(
+        future::join_all(lists.collect::<Vec<_>>())
+            .map(move |lists| agreed_chunk_hashes(lists.into_iter().flatten().collect(), chunks)),
+    )
+}
+
+/// Downloads bytes `from..to` of the resource into the part file. The chunk is
+/// rejected if it does not match the `expected` hash.
+fn fetch_chunk(
+    proxy: Proxy<DownloadFile>,
+    url: &str,
+    if_range: Option<String>,
+    timeout: time::Duration,
+    from: u64,
+    to: u64,
+    expected: Option<String>,
+) -> impl Future<Item = (), Error = Error> {
+    use actix_web::{http::Method, HttpMessage};
+    use futures::future::{self, Either};
+    let limit = (to - from) as usize;
+
+    let mut request = connectors::request(Method::GET, url);
+    request.header(header::RANGE, format!("bytes={}-{}", from, to - 1));
+    if let Some(if_range) = if_range {
+        request.header(header::IF_RANGE, if_range);
+    }
+
+    request
+        .finish()
+        .into_future()
+        .from_err()
+        .and_then(move |request| request.send().timeout(timeout).from_err())
+        .and_then(move |resp| {
+            if !resp.status().is_success() {
+                return Either::A(future::err(Error::Other(format!(
+                    "invalid response status: {}",
+                    resp.status()
+                ))));
+            }
+            Either::B(
+                resp.body()
+                    .limit(limit)
+                    .map_err(|e| Error::Other(format!("resp: {}", e))),
+            )
+        })
+        .and_then(move |bytes| {
+            if bytes.len() != limit {
+                return Either::A(future::err(Error::Other(format!(
+                    "invalid chunk size: {}",
+                    bytes.len()
+                ))));
+            }
+            if let Some(expected) = expected {
+                let valid = hash::checker(&expected)
+                    .map(|mut checker| {
+                        checker.update(bytes.as_ref());
+                        checker.verify()
+                    })
+                    .unwrap_or(false);
+                if !valid {
+                    return Either::A(future::err(Error::HashMismatch(expected)));
+                }
+            }
+            Either::B(
+                proxy
+                    .with(move |df| df.add_chunk(from, to, bytes.as_ref()))
+                    .flatten_fut(),
+            )
+        })
+}
+
+/// Downloads the chunk from the origin, retrying on failures.
+fn fetch_from_origin(
+    meta: Arc<LogMetadata>,
+    options: Arc<DownloadOptions>,
+    proxy: Proxy<DownloadFile>,
+    chunk: Chunk,
+) -> impl Future<Item = Chunk, Error = Error> {
+    use futures::future::{loop_fn, Loop};
+
+    loop_fn(options.connect_retry, move |n_retries| {
+        fetch_chunk(
+            proxy.clone(),
+            &meta.url,
+            meta.to_if_range().map(ToString::to_string),
+            options.chunk_timeout,
+            chunk.from,
+            chunk.to,
+            None,
+        )
+        .then(move |result| match result {
+            Ok(()) => Ok(Loop::Break(Chunk {
+                peer: false,
+                checked: false,
+                ..chunk
+            })),
+            Err(_) if n_retries > 0 => Ok(Loop::Continue(n_retries - 1)),
+            Err(e) => Err(e),
+        })
+    })
+}
+
+/// Downloads the chunk unless the part file already has it. A chunk that cannot be
+/// got from the peer or does not match its `expected` hash is downloaded from the origin.
 fn download_chunk(
     meta: Arc<LogMetadata>,
     options: Arc<DownloadOptions>,
     proxy: Proxy<DownloadFile>,
+    peer: Option<String>,
+    expected: Option<String>,
     chunk_nr: u32,
     from: u64,
     to: u64,
 ) -> impl Future<Item = Chunk, Error = Error> {
-    use actix_web::{client, HttpMessage};
-    use futures::future::{self, loop_fn, Loop};
-    let limit = (to - from) as usize;
+    use futures::future::{self, Either};
+    let chunk = Chunk {
+        chunk_nr,
+        from,
+        to,
+        peer: false,
+        checked: false,
+    };
 
-    loop_fn(options.connect_retry, move |n_retries| {
-        let proxy = proxy.clone();
-        let meta = meta.clone();
-        let options = options.clone();
+    proxy
+        .with(move |df| df.check_chunk(chunk_nr))
+        .from_err()
+        .and_then(move |present| match (present, peer) {
+            (Ok(true), _) => Either::A(future::ok(chunk)),
+            (_, Some(url)) => Either::B(Either::A(
+                fetch_chunk(
+                    proxy.clone(),
+                    &url,
+                    None,
+                    options.chunk_timeout,
+                    from,
+                    to,
+                    expected.clone(),
+                )
+                .map(move |()| Chunk {
+                    peer: true,
+                    checked: expected.is_some(),
+                    ..chunk
+                })
+                .or_else(move |e| {
+                    log::debug!("cannot get chunk {} from {}: {}", chunk_nr, url, e);
+                    fetch_from_origin(meta, options, proxy, chunk)
+                }),
+            )),
+            (_, None) => Either::B(Either::B(fetch_from_origin(meta, options, proxy, chunk))),
+        })
+}
 
-        proxy
-            .with(move |df| df.check_chunk(chunk_nr))
-            .from_err()
-            .and_then(move |v| match v {
-                Ok(true) => {
-                    return future::Either::A(future::ok(Loop::Break(Chunk {
-                        chunk_nr,
-                        from,
-                        to,
-                    })));
-                }
-                _ => future::Either::B(
-                    client::get(&meta.url)
-                        .header(header::IF_RANGE, format!("{}", meta.to_if_range().unwrap()))
-                        .header(header::RANGE, format!("bytes={}-{}", from, to - 1))
-                        .finish()
-                        .unwrap()
-                        .send()
-                        .timeout(options.chunk_timeout)
-                        .map_err(|e| Error::Other(format!("{}", e)))
-                        .and_then(move |resp| {
-                            resp.body()
-                                .limit(limit)
-                                .map_err(|e| Error::Other(format!("resp: {}", e)))
-                        })
-                        .and_then(move |bytes| {
-                            proxy
-                                .with(move |df| df.add_chunk(from, to, bytes.as_ref()))
-                                .from_err()
-                        })
-                        .and_then(move |_| Ok(Loop::Break(Chunk { chunk_nr, from, to })))
-                        .or_else(move |e| {
-                            if n_retries > 0 {
-                                Ok(Loop::Continue(n_retries - 1))
-                            } else {
-                                Err(e)
-                            }
-                        }),
-                ),
-            })
-    })
+fn check_hash(proxy: Proxy<DownloadFile>, hash: String) -> impl Future<Item = bool, Error = Error> {
+    proxy.with(move |df| df.verify(&hash)).flatten_fut()
+}
+
+/// Checks the file against the expected hash. If it does not match, chunks got from
+/// peers are downloaded again from the origin and the file is checked once more:
+/// first the chunks not checked against a chunk hash, then, if it still does not match,
+/// the checked ones, since all peers may have listed wrong hashes.
+/// The part file of a file that still does not match is removed.
+fn verify(
+    meta: Arc<LogMetadata>,
+    options: Arc<DownloadOptions>,
+    proxy: Proxy<DownloadFile>,
+    hash: Option<String>,
+    from_peers: Vec<Chunk>,
+) -> impl Future<Item = Proxy<DownloadFile>, Error = Error> {
+    use futures::{
+        future::{self, loop_fn, Either, Loop},
+        stream,
+    };
+
+    let hash = match hash {
+        Some(hash) => hash,
+        None => return Either::A(future::ok(proxy)),
+    };
+    let connections = options.connections as usize;
+    let (checked, unchecked): (Vec<Chunk>, Vec<Chunk>) =
+        from_peers.into_iter().partition(|chunk| chunk.checked);
+    let batches: Vec<Vec<Chunk>> = vec![unchecked, checked]
+        .into_iter()
+        .filter(|batch| !batch.is_empty())
+        .collect();
+
+    Either::B(
+        loop_fn(
+            (proxy, hash, batches.into_iter()),
+            move |(proxy, hash, mut batches)| {
+                let meta = meta.clone();
+                let options = options.clone();
+                check_hash(proxy.clone(), hash.clone()).and_then(move |valid| {
+                    let batch = match batches.next() {
+                        Some(batch) if !valid => batch,
+                        _ => return Either::A(future::ok(Loop::Break((proxy, hash, valid)))),
+                    };
+
+                    log::warn!(
+                        "{} does not match {}, downloading {} chunks got from peers again",
+                        meta.url,
+                        hash,
+                        batch.len()
+                    );
+                    let df = proxy.clone();
+                    Either::B(
+                        stream::iter_ok(batch.into_iter().map(move |chunk| {
+                            fetch_from_origin(meta.clone(), options.clone(), df.clone(), chunk)
+                        }))
+                        .buffer_unordered(connections)
+                        .for_each(|_| Ok(()))
+                        .map(move |()| Loop::Continue((proxy, hash, batches))),
+                    )
+                })
+            },
+        )
+        .and_then(|(proxy, hash, valid)| {
+            if valid {
+                Either::A(future::ok(proxy))
+            } else {
+                Either::B(
+                    proxy
+                        .close(|df| df.discard())
+                        .flatten_fut()
+                        .and_then(move |()| Err(Error::HashMismatch(hash))),
+                )
+            }
+        }),
+    )
 }
 
 pub struct UrlInfo {
@@ -147,11 +440,11 @@ fn extract_check<M: actix_web::HttpMessage>(resp: &M) -> Result<CheckType, heade
 
 */
 fn check_url(url: &str) -> impl Future<Item = UrlInfo, Error = Error> {
-    use actix_web::{client, HttpMessage};
+    use actix_web::{http::Method, HttpMessage};
     use futures::future::{loop_fn, Loop};
 
     loop_fn((url.to_owned(), 0), |(url, retry)| {
-        client::get(&url)
+        connectors::request(Method::GET, &url)
             .header("user-agent", "gu-downloader")
             .finish()
             .into_future()
@@ -198,7 +491,7 @@ fn check_url(url: &str) -> impl Future<Item = UrlInfo, Error = Error> {
 
 fn download(
     options: DownloadOptions,
-    url: &str,
+    sources: Sources,
     dest_file: String,
 ) -> impl Stream<Item = ProgressStatus, Error = Error> {
     use futures::{prelude::*, stream, unsync::mpsc};
@@ -210,7 +503,7 @@ fn download(
     let options = Arc::new(options);
     let connections = options.connections;
 
-    let chunks_stream = check_url(url)
+    let chunks_stream = check_url(&sources.origin)
         .and_then(|info| {
             Proxy::new(cpu_pool(), move || {
                 DownloadFile::new(
@@ -251,29 +544,46 @@ fn download(
                     downloaded_bytes: 0,
                 };
                 let df = download_file.clone();
+                let verify_meta = meta.clone();
+                let verify_options = options.clone();
+                let hash = sources.hash.clone();
 
-                stream::iter_ok(chunks.into_iter().map(move |(from, to, n)| {
-                    download_chunk(
-                        meta.clone(),
-                        options.clone(),
-                        download_file.clone(),
-                        n,
-                        from,
-                        to,
-                    )
-                }))
-                .buffer_unordered(connections as usize)
-                .fold(init_progress, move |mut progress, chunk| {
-                    progress.downloaded_bytes += chunk.to - chunk.from;
-                    let _ = tx.unbounded_send(progress.clone());
-                    Ok::<_, Error>(progress)
-                })
-                .and_then(|_| {
-                    df.close(|df| df.finish()).flatten_fut().and_then(move |_| {
-                        let _ = end_tx.close();
-                        Ok(())
+                fetch_chunk_hashes(&sources, &meta, options.chunk_timeout)
+                    .and_then(move |chunk_hashes| {
+                        stream::iter_ok(chunks.into_iter().map(move |(from, to, n)| {
+                            download_chunk(
+                                meta.clone(),
+                                options.clone(),
+                                download_file.clone(),
+                                sources.peer_for(n).map(ToString::to_string),
+                                chunk_hashes.get(n as usize).and_then(Clone::clone),
+                                n,
+                                from,
+                                to,
+                            )
+                        }))
+                        .buffer_unordered(connections as usize)
+                        .fold(
+                            (init_progress, Vec::new()),
+                            move |(mut progress, mut from_peers), chunk| {
+                                progress.downloaded_bytes += chunk.to - chunk.from;
+                                let _ = tx.unbounded_send(progress.clone());
+                                if chunk.peer {
+                                    from_peers.push(chunk);
+                                }
+                                Ok::<_, Error>((progress, from_peers))
+                            },
+                        )
+                    })
+                    .and_then(move |(_, from_peers)| {
+                        verify(verify_meta, verify_options, df, hash, from_peers)
+                    })
+                    .and_then(|df| {
+                        df.close(|df| df.finish()).flatten_fut().and_then(move |_| {
+                            let _ = end_tx.close();
+                            Ok(())
+                        })
                     })
-                })
             },
         );
 
@@ -286,11 +596,15 @@ fn download(
     rx.map_err(|e| Error::Other(format!("receiver error: {:?}", e)))
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug)]
 struct Chunk {
     chunk_nr: u32,
     from: u64,
     to: u64,
+    /// downloaded from a peer
+    peer: bool,
+    /// matched the chunk hash listed by the peers
+    checked: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -314,4 +628,40 @@ mod test {
         assert_eq!(b.chunk_timeout, time::Duration::from_secs(120));
         assert_eq!(b.chunk_size, 3000);
     }
+
+    #[test]
+    fn test_peer_for() {
+        let peers = vec!["http://a/x".to_string(), "http://b/x".to_string()];
+        let sources = Sources::new("http://origin/x").peers(peers);
+        assert_eq!(sources.peer_for(0), None);
+
+        let sources = sources.hash("SHA1:00");
+        assert_eq!(sources.peer_for(0), Some("http://a/x"));
+        assert_eq!(sources.peer_for(1), Some("http://b/x"));
+        assert_eq!(sources.peer_for(2), None);
+        assert_eq!(sources.peer_for(3), Some("http://a/x"));
+
+        let sources = Sources::new("http://origin/x").hash("SHA1:00");
+        assert_eq!(sources.peer_for(0), None);
+    }
+
+    #[test]
+    fn test_agreed_chunk_hashes() {
+        let list = |hashes: &[&str]| hashes.iter().map(|h| h.to_string()).collect::<Vec<_>>();
+
+        assert!(agreed_chunk_hashes(Vec::new(), 2).is_empty());
+        assert_eq!(
+            agreed_chunk_hashes(vec![list(&["a", "b"])], 2),
+            vec![Some("a".to_string()), Some("b".to_string())]
+        );
+        assert_eq!(
+            agreed_chunk_hashes(vec![list(&["a", "b"]), list(&["a", "c"])], 2),
+            vec![Some("a".to_string()), None]
+        );
+        assert_eq!(
+            agreed_chunk_hashes(vec![list(&["x"]), list(&["a", "b"])], 2),
+            vec![Some("a".to_string()), Some("b".to_string())]
+        );
+        assert!(agreed_chunk_hashes(vec![list(&["a"])], 2).is_empty());
+    }
 }
@@ -1,7 +1,7 @@
 use std::cell::RefCell;
 use std::io::prelude::*;
 use std::sync::Arc;
-use std::{fs, io, path};
+use std::{cmp, fs, io, path};
 
 use futures::prelude::*;
 use futures::sync::oneshot;
@@ -9,6 +9,7 @@ use futures_cpupool::CpuPool;
 use serde::{Deserialize, Serialize};
 
 use gu_actix::safe::*;
+use gu_model::hash::{self, ContentChecker};
 
 use super::Error;
 
@@ -37,7 +38,7 @@ pub(super) struct LogMetadata {
     pub(super) check: CheckType,
     pub(super) size: u64,
     pub(super) chunks: u32,
-    chunk_size: u32,
+    pub(super) chunk_size: u32,
     ts: chrono::DateTime<chrono::Utc>,
 }
 
@@ -297,6 +298,30 @@ impl DownloadFile {
         }
     }
 
+    /// Checks the downloaded data against the hash of the whole file, e.g. `SHA1:<hex>`.
+    pub fn verify(&mut self, hash_str: &str) -> Result<bool, Error> {
+        let mut checker = hash::checker(hash_str)
+            .map_err(|e| Error::Other(format!("invalid hash {}: {}", hash_str, e)))?;
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut left = self.meta.size;
+
+        self.inner.seek(io::SeekFrom::Start(0))?;
+        while left > 0 {
+            let n_bytes = cmp::min(left, buf.len() as u64) as usize;
+            self.inner.read_exact(&mut buf[0..n_bytes])?;
+            checker.update(&buf[0..n_bytes]);
+            left -= n_bytes as u64;
+        }
+        Ok(checker.verify())
+    }
+
+    /// Removes the part file, the next download starts from scratch.
+    pub fn discard(self) -> Result<(), Error> {
+        drop(self.inner);
+        fs::remove_file(self.temp_file_name)?;
+        Ok(())
+    }
+
     pub fn finish(self) -> Result<(), Error> {
         self.inner.set_len(self.meta.size)?;
         let file_name = self.meta.file_name;
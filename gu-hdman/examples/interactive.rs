@@ -35,6 +35,7 @@ fn main() {
                             pp.send(Exec {
                                 executable: "bin/bash".into(),
                                 args: vec!["-c".into(), (&l[4..]).into()],
+                                ..Default::default()
                             })
                             .map_err(|e| eprintln!("err={}", e))
                             .and_then(|r| match r {
@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::{fs, path::PathBuf};
 
 use actix::prelude::*;
 use failure::Fail;
@@ -6,10 +6,10 @@ use futures::prelude::*;
 use futures::sync::oneshot::Canceled;
 
 use gu_model::envman::Image;
-use gu_model::hash::{Error as HashParseError, ParsedHash};
+use gu_model::hash::{self, Error as HashParseError, ParsedHash};
 
 use super::cache::{resolve, CacheProvider};
-use super::download::DownloadOptionsBuilder;
+use super::download::{DownloadOptionsBuilder, Sources};
 
 #[derive(Clone, Debug, Fail)]
 pub enum Error {
@@ -67,10 +67,15 @@ impl CacheProvider for ImageCacheProvider {
 
     fn fetch(&mut self, hash: Self::Key, image: Self::Hint) -> Self::FetchResult {
         let p = self.path(&hash).unwrap();
+        let mut sources = Sources::new(image.url);
+        // peers are used only for images with a hash the download can be checked against
+        if hash::checker(&hash).is_ok() {
+            sources = sources.hash(hash).peers(image.peers);
+        }
 
         Box::new(
             DownloadOptionsBuilder::default()
-                .download(&image.url, p.to_string_lossy().into())
+                .download_from(sources, p.to_string_lossy().into())
                 .for_each(|progress| Ok(eprintln!("progress={:?}", progress)))
                 .and_then(|_v| Ok(p))
                 .map_err(|e| Error::Other(format!("{}", e))),
@@ -81,3 +86,48 @@ impl CacheProvider for ImageCacheProvider {
 pub fn image(spec: Image) -> impl Future<Item = PathBuf, Error = Error> {
     resolve::<ImageCacheProvider>(spec.hash.clone(), spec)
 }
+
+/// Image hashes name files in the cache dir, so both parts must be plain words.
+fn is_plain(h: &ParsedHash) -> bool {
+    let algo_valid = h
+        .algo_name()
+        .map(|algo| !algo.is_empty() && algo.chars().all(|c| c.is_ascii_alphanumeric()))
+        .unwrap_or(false);
+    algo_valid && !h.value_bytes().is_empty() && h.value_bytes().iter().all(u8::is_ascii_hexdigit)
+}
+
+/// Hashes of complete images in the cache.
+pub fn cached_images() -> Vec<String> {
+    let cache_dir = gu_persist::config::ConfigModule::new().cache_dir();
+    let entries = match fs::read_dir(cache_dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().map(|t| t.is_file()).unwrap_or(false))
+        .filter_map(|entry| {
+            let path = entry.path();
+            let h = ParsedHash::from_file_name(&path).ok()?;
+            if is_plain(&h) {
+                h.to_hash_str().ok()
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Path of the cached image with the given hash, e.g. `SHA1:<hex>`.
+pub fn cached_image(hash: &str) -> Option<PathBuf> {
+    if !is_plain(&ParsedHash::from_hash_bytes(hash.as_bytes()).ok()?) {
+        return None;
+    }
+    let path = ImageCacheProvider.path(hash).ok()?;
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
@@ -78,6 +78,9 @@ impl ProcessPool {
         ctx: &mut <Self as Actor>::Context,
         executable: &P,
         args: I,
+        env: BTreeMap<String, String>,
+        clear_env: bool,
+        stdin: Option<Vec<u8>>,
     ) -> impl Future<Item = (String, String), Error = String> {
         let exec = executable.as_ref();
         if !self.white_list.contains(exec) {
@@ -88,8 +91,16 @@ impl ProcessPool {
 
         let exec_path = self.work_dir.join(exec);
         eprintln!("running = {:?}", &exec_path);
-        let mut child: Child = async_try!(Command::new(exec_path)
+        let mut command = Command::new(exec_path);
+        if clear_env {
+            command.env_clear();
+        }
+        if stdin.is_some() {
+            command.stdin(Stdio::piped());
+        }
+        let mut child: Child = async_try!(command
             .args(args)
+            .envs(env)
             .current_dir(&self.work_dir)
             .stderr(Stdio::piped())
             .stdout(Stdio::piped())
@@ -97,6 +108,14 @@ impl ProcessPool {
             .map_err(|e| format!("run: {}", e)));
         let stdout = child.stdout().take().unwrap();
         let stderr = child.stderr().take().unwrap();
+        if let (Some(input), Some(child_stdin)) = (stdin, child.stdin().take()) {
+            // stdin is closed once the whole input is written
+            Arbiter::spawn(
+                io::write_all(child_stdin, input)
+                    .map(|_| ())
+                    .map_err(|e| log::error!("stdin write fail: {}", e)),
+            );
+        }
 
         self.spawn_child(ctx, child);
 
@@ -163,9 +182,16 @@ impl ProcessPool {
     }
 }
 
+#[derive(Default)]
 pub struct Exec {
     pub executable: PathBuf,
     pub args: Vec<String>,
+    /// variables set for the process
+    pub env: BTreeMap<String, String>,
+    /// do not inherit the provider environment
+    pub clear_env: bool,
+    /// fed to the process stdin
+    pub stdin: Option<Vec<u8>>,
 }
 
 impl Message for Exec {
@@ -176,7 +202,17 @@ impl Handler<Exec> for ProcessPool {
     type Result = ActorResponse<ProcessPool, (String, String), String>;
 
     fn handle(&mut self, msg: Exec, ctx: &mut Self::Context) -> <Self as Handler<Exec>>::Result {
-        ActorResponse::r#async(self.exec(ctx, &msg.executable, msg.args).into_actor(self))
+        ActorResponse::r#async(
+            self.exec(
+                ctx,
+                &msg.executable,
+                msg.args,
+                msg.env,
+                msg.clear_env,
+                msg.stdin,
+            )
+            .into_actor(self),
+        )
     }
 }
 
@@ -0,0 +1,629 @@
+//! API tokens and role checks for the hub REST API.
+//!
+//! Every request except the web UI assets and provider connections needs a bearer
+//! token with a role sufficient for the route. Requests to the loopback-only listener
+//! (the hub CLI) are trusted as admin unless `trustLocal` is disabled in the hub config.
+//!
+
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use actix::prelude::*;
+use actix_web::{
+    self,
+    http::{Method, StatusCode},
+    middleware::{Middleware, Started},
+    AsyncResponder, HttpMessage, HttpRequest, HttpResponse, Json, Path, Responder, Scope,
+};
+use chrono::Utc;
+use futures::prelude::*;
+use log::{debug, error, info};
+use prettytable::{cell, row};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+
+use gu_base::{cli, App, AppSettings, Arg, ArgMatches, Decorator, Module, SubCommand};
+use gu_model::auth::{CreatedToken, Identity, NewToken, Role, TokenInfo};
+use gu_model::config::{GetConfig, SetConfig};
+use gu_model::plugin::{InstallProviderPlugin, RetireProviderPlugin};
+use gu_net::rpc::PublicMessage;
+use gu_persist::config::ConfigModule;
+
+use crate::server::HubClient;
+
+const TOKENS_FILE: &str = "tokens.json";
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct TokenEntry {
+    #[serde(flatten)]
+    info: TokenInfo,
+    token_hash: String,
+}
+
+fn token_hash(token: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(token.as_bytes());
+    sha1.digest().to_string()
+}
+
+fn generate_token() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[derive(Default)]
+pub struct TokenStore {
+    path: PathBuf,
+    tokens: BTreeMap<u64, TokenEntry>,
+}
+
+impl TokenStore {
+    fn load(&mut self) {
+        let tokens: Vec<TokenEntry> = match fs::read(&self.path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                error!("Invalid API tokens file {:?}: {}", self.path, e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        self.tokens = tokens
+            .into_iter()
+            .map(|entry| (entry.info.id, entry))
+            .collect();
+    }
+
+    fn save(&self) {
+        let tokens: Vec<&TokenEntry> = self.tokens.values().collect();
+        match serde_json::to_vec_pretty(&tokens) {
+            Ok(bytes) => {
+                if let Err(e) = fs::write(&self.path, bytes) {
+                    error!("Cannot save API tokens {:?}: {}", self.path, e)
+                }
+            }
+            Err(e) => error!("Cannot serialize API tokens: {}", e),
+        }
+    }
+}
+
+impl Actor for TokenStore {
+    type Context = Context<Self>;
+
+    fn started(&mut self, _ctx: &mut Self::Context) {
+        self.path = ConfigModule::new().work_dir().join(TOKENS_FILE);
+        self.load();
+    }
+}
+
+impl Supervised for TokenStore {}
+
+impl SystemService for TokenStore {}
+
+/// Looks up the identity of a bearer token.
+pub struct Authenticate(pub String);
+
+impl Message for Authenticate {
+    type Result = Option<Identity>;
+}
+
+impl Handler<Authenticate> for TokenStore {
+    type Result = Option<Identity>;
+
+    fn handle(&mut self, msg: Authenticate, _ctx: &mut Self::Context) -> Self::Result {
+        let hash = token_hash(&msg.0);
+        self.tokens
+            .values()
+            .find(|entry| entry.token_hash == hash)
+            .map(|entry| Identity {
+                name: entry.info.name.clone(),
+                role: entry.info.role,
+            })
+    }
+}
+
+pub struct CreateToken(pub NewToken);
+
+impl Message for CreateToken {
+    type Result = CreatedToken;
+}
+
+impl Handler<CreateToken> for TokenStore {
+    type Result = MessageResult<CreateToken>;
+
+    fn handle(&mut self, msg: CreateToken, _ctx: &mut Self::Context) -> Self::Result {
+        let token = generate_token();
+        let info = TokenInfo {
+            id: self.tokens.keys().next_back().map(|id| id + 1).unwrap_or(1),
+            name: msg.0.name,
+            role: msg.0.role,
+            created: Utc::now(),
+        };
+        info!("API token {} ({}) created", info.name, info.role);
+
+        self.tokens.insert(
+            info.id,
+            TokenEntry {
+                info: info.clone(),
+                token_hash: token_hash(&token),
+            },
+        );
+        self.save();
+        MessageResult(CreatedToken { token, info })
+    }
+}
+
+pub struct ListTokens;
+
+impl Message for ListTokens {
+    type Result = Vec<TokenInfo>;
+}
+
+impl Handler<ListTokens> for TokenStore {
+    type Result = MessageResult<ListTokens>;
+
+    fn handle(&mut self, _msg: ListTokens, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(
+            self.tokens
+                .values()
+                .map(|entry| entry.info.clone())
+                .collect(),
+        )
+    }
+}
+
+pub struct RevokeToken(pub u64);
+
+impl Message for RevokeToken {
+    type Result = Option<TokenInfo>;
+}
+
+impl Handler<RevokeToken> for TokenStore {
+    type Result = Option<TokenInfo>;
+
+    fn handle(&mut self, msg: RevokeToken, _ctx: &mut Self::Context) -> Self::Result {
+        let removed = self.tokens.remove(&msg.0).map(|entry| entry.info);
+        if let Some(ref info) = removed {
+            info!("API token {} revoked", info.name);
+            self.save();
+        }
+        removed
+    }
+}
+
+/// Kind of plugin data (`config`, `kv` or `data`) the path points to.
+fn plugin_data(path: &str) -> Option<&str> {
+    if !path.starts_with("/plug/") {
+        return None;
+    }
+    let mut segments = path["/plug/".len()..].split('/');
+    match (segments.next(), segments.next()) {
+        (Some(name), Some(kind)) if name != "dev" && name != "publishers" => match kind {
+            "config" | "kv" | "data" => Some(kind),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Whether the path is a single session blob, `/sessions/{id}/blobs/{blobId}`.
+fn is_blob(path: &str) -> bool {
+    let segments: Vec<&str> = path.trim_end_matches('/').split('/').collect();
+    match segments.as_slice() {
+        ["", "sessions", session, "blobs", blob] => !session.is_empty() && !blob.is_empty(),
+        _ => false,
+    }
+}
+
+/// Returns the role needed for the route, `None` for public routes.
+fn required_role(method: &Method, path: &str) -> Option<Role> {
+    let read = *method == Method::GET || *method == Method::HEAD;
+
+    if path.starts_with("/app") || path.starts_with("/ws/") || path == "/node_id/" {
+        return None;
+    }
+    match plugin_data(path) {
+        // Configuration may hold credentials of plugin services, exports include it.
+        Some("config") | Some("data") => return Some(Role::Admin),
+        Some(_) if read => return Some(Role::ReadOnly),
+        // Plugin UIs keep their state in key-value data.
+        Some("kv") => return Some(Role::User),
+        _ => (),
+    }
+    // Plugin list and plugin UI files are loaded by the web UI before login.
+    if read && path.starts_with("/plug") && path != "/plug/search" && path != "/plug/indexes" {
+        return None;
+    }
+    // Providers download images and plugin packages by hash.
+    if read && path.starts_with("/repo/") {
+        return None;
+    }
+    // Providers download and upload blobs through URLs handed out in deployments.
+    if (read || *method == Method::PUT) && is_blob(path) {
+        return None;
+    }
+    if path.starts_with("/peers/send-to/") || path.starts_with("/m/") {
+        // Raw RPC can reach provider config and plugins, which are admin operations.
+        let destination = path
+            .rsplit('/')
+            .next()
+            .and_then(|id| id.parse::<u32>().ok());
+        let admin_only = [
+            GetConfig::ID,
+            SetConfig::ID,
+            InstallProviderPlugin::ID,
+            RetireProviderPlugin::ID,
+        ];
+        if destination.map(|id| admin_only.contains(&id)) == Some(true) {
+            return Some(Role::Admin);
+        }
+        return Some(Role::User);
+    }
+    if path == "/auth/whoami" {
+        return Some(Role::ReadOnly);
+    }
+    // Webhook subscriptions carry shared secrets.
+    if path.starts_with("/auth") || path.starts_with("/webhooks") {
+        return Some(Role::Admin);
+    }
+    // Registered backends receive the traffic of plugin commands.
+    if path == "/service/local" && *method == Method::PATCH {
+        return Some(Role::Admin);
+    }
+    if path.starts_with("/peers/") && path.contains("/config/") {
+        return Some(Role::Admin);
+    }
+    // Removing repo objects may break deployments of other users.
+    if path.starts_with("/repo/") && (*method == Method::DELETE || path == "/repo/gc") {
+        return Some(Role::Admin);
+    }
+    if read {
+        return Some(Role::ReadOnly);
+    }
+    if path.starts_with("/plug") || (path.starts_with("/peers/") && !path.contains("/deployments"))
+    {
+        return Some(Role::Admin);
+    }
+    Some(Role::User)
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AuthConfig {
+    #[serde(default = "AuthConfig::default_trust_local")]
+    pub(crate) trust_local: bool,
+    /// Plain HTTP port bound to loopback only, used by the local CLI.
+    #[serde(default = "AuthConfig::default_local_port")]
+    pub(crate) local_port: u16,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            trust_local: Self::default_trust_local(),
+            local_port: Self::default_local_port(),
+        }
+    }
+}
+
+impl AuthConfig {
+    fn default_trust_local() -> bool {
+        true
+    }
+
+    fn default_local_port() -> u16 {
+        61623
+    }
+}
+
+/// Middleware checking API tokens against route roles.
+pub struct ApiAuth {
+    trust_local: bool,
+}
+
+impl ApiAuth {
+    pub fn new(trust_local: bool) -> Self {
+        ApiAuth { trust_local }
+    }
+}
+
+fn bearer_token<S>(req: &HttpRequest<S>) -> Option<String> {
+    req.headers()
+        .get(actix_web::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| {
+            if value.starts_with("Bearer ") {
+                Some(value[7..].trim().to_string())
+            } else {
+                None
+            }
+        })
+        // Browsers can not set headers for event streams and websockets.
+        .or_else(|| req.query().get("access_token").cloned())
+}
+
+impl<S> Middleware<S> for ApiAuth {
+    fn start(&self, req: &HttpRequest<S>) -> actix_web::Result<Started> {
+        let required = match required_role(req.method(), req.path()) {
+            None => return Ok(Started::Done),
+            Some(role) => role,
+        };
+
+        // Set only on the loopback listener, other listeners pass `false`.
+        let local = req
+            .peer_addr()
+            .map(|addr| addr.ip().is_loopback())
+            .unwrap_or(false);
+        if local && self.trust_local {
+            req.extensions_mut().insert(Identity {
+                name: "local".to_string(),
+                role: Role::Admin,
+            });
+            return Ok(Started::Done);
+        }
+
+        let token = match bearer_token(req) {
+            Some(token) => token,
+            None => {
+                return Ok(Started::Response(
+                    HttpResponse::Unauthorized()
+                        .header("WWW-Authenticate", "Bearer")
+                        .body("API token required"),
+                ))
+            }
+        };
+
+        let req = req.clone();
+        Ok(Started::Future(Box::new(
+            TokenStore::from_registry()
+                .send(Authenticate(token))
+                .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))
+                .map(move |identity| match identity {
+                    None => Some(
+                        HttpResponse::Unauthorized()
+                            .header("WWW-Authenticate", "Bearer")
+                            .body("Invalid API token"),
+                    ),
+                    Some(identity) => {
+                        if identity.role < required {
+                            debug!(
+                                "{} ({}) denied {} {}",
+                                identity.name,
+                                identity.role,
+                                req.method(),
+                                req.path()
+                            );
+                            return Some(
+                                HttpResponse::Forbidden()
+                                    .body(format!("{} role required", required)),
+                            );
+                        }
+                        req.extensions_mut().insert(identity);
+                        None
+                    }
+                }),
+        )))
+    }
+}
+
+enum State {
+    None,
+    List,
+    Create(NewToken),
+    Revoke(u64),
+}
+
+pub struct AuthModule {
+    state: State,
+}
+
+pub fn module() -> AuthModule {
+    AuthModule { state: State::None }
+}
+
+impl Module for AuthModule {
+    fn args_declare<'a, 'b>(&self, app: App<'a, 'b>) -> App<'a, 'b> {
+        app.subcommand(
+            SubCommand::with_name("token")
+                .about("Manages hub API tokens")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("list").about("Lists API tokens"))
+                .subcommand(
+                    SubCommand::with_name("create")
+                        .about("Creates API token and prints it")
+                        .arg(
+                            Arg::with_name("NAME")
+                                .help("Token owner name")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::with_name("role")
+                                .short("r")
+                                .long("role")
+                                .takes_value(true)
+                                .possible_values(&["admin", "user", "read-only"])
+                                .default_value("user")
+                                .help("Role granted by the token"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("revoke")
+                        .about("Revokes API token")
+                        .arg(
+                            Arg::with_name("ID")
+                                .help("Token id")
+                                .required(true)
+                                .index(1),
+                        ),
+                ),
+        )
+    }
+
+    fn args_consume(&mut self, matches: &ArgMatches) -> bool {
+        if let Some(m) = matches.subcommand_matches("token") {
+            self.state = match m.subcommand() {
+                ("list", Some(_)) => State::List,
+                ("create", Some(m)) => State::Create(NewToken {
+                    name: m.value_of("NAME").unwrap().to_string(),
+                    role: m.value_of("role").unwrap().parse().unwrap(),
+                }),
+                ("revoke", Some(m)) => {
+                    State::Revoke(m.value_of("ID").unwrap().parse().expect("Invalid token id"))
+                }
+                _ => return false,
+            };
+            return true;
+        }
+        false
+    }
+
+    fn run<D: Decorator + Clone + 'static>(&self, _decorator: D) {
+        match self.state {
+            State::None => (),
+            State::List => {
+                System::run(|| {
+                    Arbiter::spawn(
+                        HubClient::get("/auth/tokens")
+                            .and_then(|tokens: Vec<TokenInfo>| Ok(format_tokens_table(tokens)))
+                            .map_err(|e| error!("{}", e))
+                            .then(|_r| Ok(System::current().stop())),
+                    )
+                });
+            }
+            State::Create(ref new_token) => {
+                let new_token = new_token.clone();
+                System::run(move || {
+                    Arbiter::spawn(
+                        HubClient::post_json("/auth/tokens", new_token)
+                            .and_then(|created: CreatedToken| Ok(println!("{}", created.token)))
+                            .map_err(|e| error!("{}", e))
+                            .then(|_r| Ok(System::current().stop())),
+                    )
+                });
+            }
+            State::Revoke(id) => {
+                System::run(move || {
+                    Arbiter::spawn(
+                        HubClient::delete(format!("/auth/tokens/{}", id))
+                            .and_then(|_r: TokenInfo| Ok(()))
+                            .map_err(|e| error!("{}", e))
+                            .then(|_r| Ok(System::current().stop())),
+                    )
+                });
+            }
+        }
+    }
+
+    fn decorate_webapp<S: 'static>(&self, app: actix_web::App<S>) -> actix_web::App<S> {
+        app.scope("/auth", scope)
+    }
+}
+
+fn format_tokens_table(tokens: Vec<TokenInfo>) {
+    cli::format_table(
+        row!["Id", "Name", "Role", "Created"],
+        || "No API tokens",
+        tokens
+            .iter()
+            .map(|token| row![token.id, token.name, token.role, token.created.to_rfc3339()]),
+    )
+}
+
+fn scope<S: 'static>(scope: Scope<S>) -> Scope<S> {
+    scope
+        .resource("/whoami", |r| r.get().f(whoami))
+        .resource("/tokens", |r| {
+            r.get().f(list_tokens);
+            r.post().with(create_token)
+        })
+        .resource("/tokens/{tokenId}", |r| r.delete().with(revoke_token))
+}
+
+fn whoami<S>(r: &HttpRequest<S>) -> HttpResponse {
+    match r.extensions().get::<Identity>() {
+        Some(identity) => HttpResponse::Ok().json(identity),
+        None => HttpResponse::Unauthorized().finish(),
+    }
+}
+
+fn list_tokens<S>(_r: &HttpRequest<S>) -> impl Responder {
+    TokenStore::from_registry()
+        .send(ListTokens)
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))
+        .and_then(|tokens| Ok(HttpResponse::Ok().json(tokens)))
+        .responder()
+}
+
+fn create_token(body: Json<NewToken>) -> impl Responder {
+    TokenStore::from_registry()
+        .send(CreateToken(body.into_inner()))
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))
+        .and_then(|created| Ok(HttpResponse::Created().json(created)))
+        .responder()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenPath {
+    token_id: u64,
+}
+
+fn revoke_token(path: Path<TokenPath>) -> impl Responder {
+    TokenStore::from_registry()
+        .send(RevokeToken(path.token_id))
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))
+        .and_then(|removed| match removed {
+            Some(info) => Ok(HttpResponse::Ok().json(info)),
+            None => Ok(HttpResponse::build(StatusCode::NOT_FOUND).body("Token not found")),
+        })
+        .responder()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_plugin_data_roles() {
+        assert_eq!(plugin_data("/plug/gu-render/kv/state"), Some("kv"));
+        assert_eq!(plugin_data("/plug/dev/config"), None);
+        assert_eq!(plugin_data("/plug/gu-render"), None);
+
+        let role = |method: Method, path: &str| required_role(&method, path);
+        assert_eq!(
+            role(Method::GET, "/plug/gu-render/kv/state"),
+            Some(Role::ReadOnly)
+        );
+        assert_eq!(
+            role(Method::PUT, "/plug/gu-render/kv/state"),
+            Some(Role::User)
+        );
+        assert_eq!(
+            role(Method::GET, "/plug/gu-render/config"),
+            Some(Role::Admin)
+        );
+        assert_eq!(
+            role(Method::PUT, "/plug/gu-render/config"),
+            Some(Role::Admin)
+        );
+        assert_eq!(role(Method::GET, "/plug/gu-render/data"), Some(Role::Admin));
+        assert_eq!(role(Method::GET, "/plug/gu-render/app/index.html"), None);
+    }
+
+    #[test]
+    fn test_provider_blob_transfer_is_public() {
+        let role = |method: Method, path: &str| required_role(&method, path);
+        assert_eq!(role(Method::GET, "/sessions/12/blobs/3"), None);
+        assert_eq!(role(Method::PUT, "/sessions/12/blobs/3"), None);
+        assert_eq!(
+            role(Method::DELETE, "/sessions/12/blobs/3"),
+            Some(Role::User)
+        );
+        assert_eq!(
+            role(Method::GET, "/sessions/12/blobs"),
+            Some(Role::ReadOnly)
+        );
+        assert_eq!(role(Method::POST, "/sessions/12/blobs"), Some(Role::User));
+        assert_eq!(role(Method::PUT, "/sessions/12"), Some(Role::User));
+    }
+}
@@ -0,0 +1,132 @@
+//! Server-Sent Events stream of hub events.
+//!
+//! Hub components post `HubEvent`s on the event bus, `GET /events?path=/sessions/12`
+//! streams every event posted under the given path.
+//!
+
+use std::time::Duration;
+
+use actix::prelude::*;
+use actix_web::{error::ErrorInternalServerError, App, HttpResponse, Query};
+use bytes::Bytes;
+use futures::{sync::mpsc, Future, Stream};
+use log::error;
+use serde::Deserialize;
+
+use gu_base::Module;
+use gu_event_bus::Event;
+use gu_model::events::{EventMessage, HubEvent};
+
+/// How often a comment line is sent to keep idle connections open.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Posts the event on the event bus under its path.
+pub(crate) fn post(event: HubEvent) {
+    gu_event_bus::post_event(event.path(), event)
+}
+
+/// Forwards events from the bus to a single SSE response.
+struct EventStream {
+    last_id: u64,
+    tx: mpsc::UnboundedSender<Bytes>,
+}
+
+impl EventStream {
+    fn send(&mut self, frame: String, ctx: &mut Context<Self>) {
+        if self.tx.unbounded_send(Bytes::from(frame)).is_err() {
+            // client is gone, the bus drops closed subscribers on next event
+            ctx.stop();
+        }
+    }
+}
+
+impl Actor for EventStream {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(KEEP_ALIVE_INTERVAL, |act, ctx| {
+            act.send(": keep-alive\n\n".to_string(), ctx)
+        });
+    }
+}
+
+impl Handler<Event<HubEvent>> for EventStream {
+    type Result = ();
+
+    fn handle(&mut self, msg: Event<HubEvent>, ctx: &mut Self::Context) {
+        self.last_id += 1;
+        let message = EventMessage {
+            id: self.last_id,
+            path: msg.path().to_string(),
+            event: msg.data().clone(),
+        };
+
+        match serde_json::to_string(&message) {
+            Ok(json) => self.send(format!("id: {}\ndata: {}\n\n", message.id, json), ctx),
+            Err(e) => error!("cannot serialize event: {}", e),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct EventsQuery {
+    #[serde(default)]
+    path: String,
+}
+
+/// Bus path for the query, empty path subscribes to all events.
+fn subscription_path(path: &str) -> String {
+    let path = path.trim_end_matches('/');
+    if path.is_empty() || path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("/{}", path)
+    }
+}
+
+fn events(query: Query<EventsQuery>) -> HttpResponse {
+    let (tx, rx) = mpsc::unbounded();
+    let stream = EventStream { last_id: 0, tx }.start();
+
+    Arbiter::spawn(
+        gu_event_bus::subscribe(subscription_path(&query.path), stream.recipient()).then(
+            |r| -> Result<(), ()> {
+                if r.is_err() {
+                    error!("cannot subscribe to hub events");
+                }
+                Ok(())
+            },
+        ),
+    );
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .streaming(rx.map_err(|_| ErrorInternalServerError("event stream closed")))
+}
+
+struct EventsModule;
+
+impl Module for EventsModule {
+    fn decorate_webapp<S: 'static>(&self, app: App<S>) -> App<S> {
+        app.resource("/events", |r| r.get().with(events))
+    }
+}
+
+pub fn module() -> impl Module {
+    EventsModule
+}
+
+#[cfg(test)]
+mod test {
+    use super::subscription_path;
+
+    #[test]
+    fn test_subscription_path() {
+        assert_eq!(subscription_path(""), "");
+        assert_eq!(subscription_path("/"), "");
+        assert_eq!(subscription_path("/sessions/12"), "/sessions/12");
+        assert_eq!(subscription_path("sessions/12/"), "/sessions/12");
+        assert_eq!(subscription_path("peers"), "/peers");
+    }
+}
@@ -0,0 +1,218 @@
+//! Peer-to-peer image distribution.
+//!
+//! Tracks which connected providers share which images from their caches. Deployments
+//! sent to a provider carry urls of other providers holding the image, so its chunks
+//! are pulled from them in parallel with the origin instead of only from the origin.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    net::SocketAddr,
+};
+
+use actix::prelude::*;
+use futures::prelude::*;
+use log::{debug, error};
+
+use gu_model::envman::{self, GenericCreateSession, ListSharedImages};
+use gu_net::{
+    rpc::{
+        peer,
+        peer::{PeerEvent, PeerInfo},
+        reply::SendError,
+    },
+    NodeId,
+};
+
+/// Maximal number of peers passed with a deployment
+const MAX_PEERS: usize = 8;
+
+#[derive(Default)]
+pub struct ImagePeers {
+    /// image server addresses and tokens of sharing providers
+    servers: HashMap<NodeId, (SocketAddr, String)>,
+    /// sharing providers by image hash
+    images: HashMap<String, BTreeSet<NodeId>>,
+    /// rotates peers handed out for an image to spread the load
+    next: usize,
+}
+
+impl ImagePeers {
+    fn connected(&mut self, info: PeerInfo, ctx: &mut Context<Self>) {
+        let ip = match info
+            .peer_addr
+            .as_ref()
+            .and_then(|addr| addr.parse::<SocketAddr>().ok())
+        {
+            Some(addr) => addr.ip(),
+            None => return,
+        };
+        let node_id = info.node_id;
+
+        peer(node_id)
+            .into_endpoint()
+            .send(ListSharedImages::default())
+            .map_err(move |e| debug!("Cannot list images shared by {:?}: {}", node_id, e))
+            .into_actor(self)
+            .and_then(move |shared, act, _ctx| {
+                if let Ok(envman::SharedImages {
+                    port: Some(port),
+                    token: Some(token),
+                    hashes,
+                }) = shared
+                {
+                    act.servers
+                        .insert(node_id, (SocketAddr::new(ip, port), token));
+                    for hash in hashes {
+                        act.add(node_id, hash);
+                    }
+                }
+                fut::ok(())
+            })
+            .spawn(ctx);
+    }
+
+    fn disconnected(&mut self, node_id: NodeId) {
+        if self.servers.remove(&node_id).is_none() {
+            return;
+        }
+        for peers in self.images.values_mut() {
+            peers.remove(&node_id);
+        }
+        self.images.retain(|_, peers| !peers.is_empty());
+    }
+
+    fn add(&mut self, node_id: NodeId, hash: String) {
+        self.images
+            .entry(hash)
+            .or_insert_with(BTreeSet::new)
+            .insert(node_id);
+    }
+
+    fn urls(&mut self, node_id: NodeId, hash: &str) -> Vec<String> {
+        let servers = &self.servers;
+        let addrs: Vec<&(SocketAddr, String)> = match self.images.get(hash) {
+            Some(peers) => peers
+                .iter()
+                .filter(|&&peer_id| peer_id != node_id)
+                .filter_map(|peer_id| servers.get(peer_id))
+                .collect(),
+            None => return Vec::new(),
+        };
+        if addrs.is_empty() {
+            return Vec::new();
+        }
+
+        let path = hash.replacen(':', "---", 1);
+        let start = self.next % addrs.len();
+        self.next = self.next.wrapping_add(1);
+        addrs
+            .iter()
+            .cycle()
+            .skip(start)
+            .take(addrs.len().min(MAX_PEERS))
+            .map(|(addr, token)| format!("http://{}/images/{}/{}", addr, token, path))
+            .collect()
+    }
+}
+
+impl Actor for ImagePeers {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.wait(
+            gu_event_bus::subscribe("/peers".into(), ctx.address().recipient())
+                .map_err(|_| error!("Cannot subscribe to peer events"))
+                .and_then(|_| {
+                    peer::PeerManager::from_registry()
+                        .send(peer::ListPeers)
+                        .map_err(|e| error!("Cannot list connected peers: {}", e))
+                })
+                .into_actor(self)
+                .and_then(|connected, act, ctx| {
+                    for info in connected {
+                        act.connected(info, ctx);
+                    }
+                    fut::ok(())
+                }),
+        );
+    }
+}
+
+impl Supervised for ImagePeers {}
+
+impl SystemService for ImagePeers {}
+
+impl Handler<gu_event_bus::Event<PeerEvent>> for ImagePeers {
+    type Result = ();
+
+    fn handle(&mut self, msg: gu_event_bus::Event<PeerEvent>, ctx: &mut Self::Context) {
+        match msg.data() {
+            PeerEvent::Connected(info) => self.connected(info.clone(), ctx),
+            PeerEvent::Disconnected(node_id) => self.disconnected(*node_id),
+        }
+    }
+}
+
+/// Urls of providers other than `node_id` sharing the image
+struct ImageSources {
+    node_id: NodeId,
+    hash: String,
+}
+
+impl Message for ImageSources {
+    type Result = Vec<String>;
+}
+
+impl Handler<ImageSources> for ImagePeers {
+    type Result = MessageResult<ImageSources>;
+
+    fn handle(&mut self, msg: ImageSources, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.urls(msg.node_id, &msg.hash))
+    }
+}
+
+/// The image is in the provider cache after a successful deployment
+struct ImageDeployed {
+    node_id: NodeId,
+    hash: String,
+}
+
+impl Message for ImageDeployed {
+    type Result = ();
+}
+
+impl Handler<ImageDeployed> for ImagePeers {
+    type Result = ();
+
+    fn handle(&mut self, msg: ImageDeployed, _ctx: &mut Self::Context) {
+        if self.servers.contains_key(&msg.node_id) {
+            self.add(msg.node_id, msg.hash);
+        }
+    }
+}
+
+/// Sends the deployment to the peer, along with urls of other providers sharing its image.
+pub fn create_session(
+    node_id: NodeId,
+    mut deployment: GenericCreateSession,
+) -> impl Future<Item = Result<String, envman::Error>, Error = SendError> {
+    let hash = deployment.image.hash.clone();
+
+    ImagePeers::from_registry()
+        .send(ImageSources {
+            node_id,
+            hash: hash.clone(),
+        })
+        .then(move |peers| {
+            if let Ok(peers) = peers {
+                deployment.image.peers.extend(peers);
+            }
+            peer(node_id).into_endpoint().send(deployment)
+        })
+        .map(move |result| {
+            if result.is_ok() {
+                ImagePeers::from_registry().do_send(ImageDeployed { node_id, hash });
+            }
+            result
+        })
+}
@@ -0,0 +1,619 @@
+//! Persistent inventory of providers that have connected to the hub.
+//!
+//! Unlike `PeerManager`, which only knows currently connected peers, the inventory
+//! remembers every provider until it is explicitly forgotten.
+//!
+
+use std::{collections::HashMap, fs, path::PathBuf, time::Duration};
+
+use actix::prelude::*;
+use chrono::{DateTime, Utc};
+use futures::prelude::*;
+use log::{debug, error, info};
+
+use gu_hardware::actor::HardwareQuery;
+use gu_model::{
+    envman::{DestroySession, GetActivity, GetSessions, SetDraining},
+    events::HubEvent,
+    peers::{
+        label_tags, DrainProgress, LabelSelector, Labels, PeerInventoryInfo, PeerPatch, PeerState,
+    },
+};
+use gu_net::{
+    rpc::{peer, peer::PeerEvent},
+    NodeId,
+};
+use gu_persist::config::ConfigModule;
+
+use crate::events;
+
+const INVENTORY_FILE: &str = "peers.json";
+/// How often draining peers are checked for running deployments.
+const DRAIN_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// How often online peers are asked about local activity.
+const ACTIVITY_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Default)]
+pub struct PeerInventory {
+    path: PathBuf,
+    peers: HashMap<NodeId, PeerInventoryInfo>,
+}
+
+impl PeerInventory {
+    fn load(&mut self) {
+        let peers: Vec<PeerInventoryInfo> = match fs::read(&self.path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                error!("Invalid peer inventory {:?}: {}", self.path, e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+
+        // Peers reconnect after hub restart, until then they are offline.
+        self.peers = peers
+            .into_iter()
+            .map(|info| {
+                (
+                    info.node_id,
+                    PeerInventoryInfo {
+                        online: false,
+                        activity: None,
+                        ..info
+                    },
+                )
+            })
+            .collect();
+    }
+
+    fn save(&self) {
+        let peers: Vec<&PeerInventoryInfo> = self.peers.values().collect();
+        match serde_json::to_vec_pretty(&peers) {
+            Ok(bytes) => {
+                if let Err(e) = fs::write(&self.path, bytes) {
+                    error!("Cannot save peer inventory {:?}: {}", self.path, e)
+                }
+            }
+            Err(e) => error!("Cannot serialize peer inventory: {}", e),
+        }
+    }
+
+    fn connected(&mut self, info: peer::PeerInfo, ctx: &mut Context<Self>) {
+        let now = Utc::now();
+        let node_id = info.node_id;
+        let node_name = Some(info.node_name).filter(|name| !name.is_empty());
+
+        let entry = self
+            .peers
+            .entry(node_id)
+            .or_insert_with(|| PeerInventoryInfo {
+                node_id,
+                node_name: None,
+                peer_addr: None,
+                first_seen: now,
+                last_seen: now,
+                online: true,
+                hardware: None,
+                labels: Labels::new(),
+                state: PeerState::default(),
+                drain: None,
+                activity: None,
+            });
+        entry.node_name = node_name.or_else(|| entry.node_name.take());
+        entry.peer_addr = info.peer_addr.or_else(|| entry.peer_addr.take());
+        entry.last_seen = now;
+        entry.online = true;
+        if !entry.labels.is_empty() {
+            peer::PeerManager::from_registry().do_send(peer::UpdatePeer::SetTags(
+                node_id,
+                label_tags(&entry.labels),
+            ));
+        }
+        // Provider keeps the flag only until restart, so it is resent on every connection.
+        set_draining(
+            node_id,
+            entry.state == PeerState::Draining,
+            drain_timeout(&entry.drain, now),
+        );
+        self.save();
+
+        ctx.spawn(
+            peer(node_id)
+                .into_endpoint()
+                .send(HardwareQuery::default())
+                .into_actor(self)
+                .then(move |result, act, _ctx| {
+                    match result {
+                        Ok(Ok(hardware)) => {
+                            if let Some(entry) = act.peers.get_mut(&node_id) {
+                                entry.hardware = serde_json::to_value(hardware).ok();
+                                act.save();
+                            }
+                        }
+                        Ok(Err(e)) => debug!("No hardware info for {:?}: {:?}", node_id, e),
+                        Err(e) => debug!("Cannot query hardware of {:?}: {}", node_id, e),
+                    }
+                    fut::ok(())
+                }),
+        );
+    }
+
+    /// Updates progress of running drains, destroys deployments left after the deadline.
+    fn check_drains(&mut self, ctx: &mut Context<Self>) {
+        let now = Utc::now();
+        let draining: Vec<(NodeId, bool)> = self
+            .peers
+            .values()
+            .filter(|info| info.online)
+            .filter_map(|info| match info.drain {
+                Some(ref drain) if !drain.finished => Some((
+                    info.node_id,
+                    drain.deadline.map_or(false, |deadline| deadline <= now),
+                )),
+                _ => None,
+            })
+            .collect();
+
+        for (node_id, expired) in draining {
+            ctx.spawn(
+                peer(node_id)
+                    .into_endpoint()
+                    .send(GetSessions::default())
+                    .into_actor(self)
+                    .then(move |result, act, _ctx| {
+                        let remaining: Vec<String> = match result {
+                            Ok(Ok(sessions)) => sessions.into_iter().map(|s| s.id).collect(),
+                            Ok(Err(())) => return fut::ok(()),
+                            Err(e) => {
+                                debug!("Cannot list deployments of {:?}: {}", node_id, e);
+                                return fut::ok(());
+                            }
+                        };
+                        let forced = expired && !remaining.is_empty();
+                        if forced {
+                            for deployment_id in &remaining {
+                                destroy_deployment(node_id, deployment_id.clone());
+                            }
+                        }
+
+                        if let Some(drain) = act
+                            .peers
+                            .get_mut(&node_id)
+                            .and_then(|entry| entry.drain.as_mut())
+                        {
+                            drain.forced |= forced;
+                            drain.finished = remaining.is_empty();
+                            drain.remaining = remaining;
+                            act.save();
+                        }
+                        fut::ok(())
+                    }),
+            );
+        }
+    }
+
+    /// Updates local activity of online peers, posts an event when one pauses or resumes.
+    fn check_activity(&mut self, ctx: &mut Context<Self>) {
+        let online: Vec<NodeId> = self
+            .peers
+            .values()
+            .filter(|info| info.online)
+            .map(|info| info.node_id)
+            .collect();
+
+        for node_id in online {
+            ctx.spawn(
+                peer(node_id)
+                    .into_endpoint()
+                    .send(GetActivity::default())
+                    .into_actor(self)
+                    .then(move |result, act, _ctx| {
+                        let activity = match result {
+                            Ok(Ok(activity)) => activity,
+                            Ok(Err(())) => return fut::ok(()),
+                            Err(e) => {
+                                debug!("Cannot get activity of {:?}: {}", node_id, e);
+                                return fut::ok(());
+                            }
+                        };
+                        let entry = match act.peers.get_mut(&node_id) {
+                            Some(entry) if entry.online => entry,
+                            _ => return fut::ok(()),
+                        };
+
+                        let was_paused = entry.activity.as_ref().map_or(false, |a| a.paused);
+                        let paused = activity.paused;
+                        entry.activity = Some(activity);
+                        if paused != was_paused {
+                            info!(
+                                "Peer {:?} {}",
+                                node_id,
+                                if paused { "paused" } else { "resumed" }
+                            );
+                            act.save();
+                            events::post(HubEvent::PeerActivityChanged { node_id, paused });
+                        }
+                        fut::ok(())
+                    }),
+            );
+        }
+    }
+
+    fn select(&self, selector: &LabelSelector) -> Vec<NodeId> {
+        self.peers
+            .values()
+            .filter(|info| info.online && selector.matches(&info.labels))
+            .map(|info| info.node_id)
+            .collect()
+    }
+
+    fn disconnected(&mut self, node_id: NodeId) {
+        if let Some(entry) = self.peers.get_mut(&node_id) {
+            entry.last_seen = Utc::now();
+            entry.online = false;
+            entry.activity = None;
+            self.save();
+        }
+    }
+}
+
+/// Seconds left until the drain deadline; the provider enforces it on its own, so
+/// that it also passes while the provider is disconnected.
+fn drain_timeout(drain: &Option<DrainProgress>, now: DateTime<Utc>) -> Option<u64> {
+    drain
+        .as_ref()
+        .and_then(|drain| drain.deadline)
+        .map(|deadline| (deadline - now).num_seconds().max(0) as u64)
+}
+
+fn set_draining(node_id: NodeId, draining: bool, timeout: Option<u64>) {
+    Arbiter::spawn(
+        peer(node_id)
+            .into_endpoint()
+            .send(SetDraining { draining, timeout })
+            .then(move |result| {
+                if let Err(e) = result {
+                    debug!("Cannot set draining flag of {:?}: {}", node_id, e)
+                }
+                Ok(())
+            }),
+    )
+}
+
+fn destroy_deployment(node_id: NodeId, session_id: String) {
+    Arbiter::spawn(
+        peer(node_id)
+            .into_endpoint()
+            .send(DestroySession {
+                session_id: session_id.clone(),
+            })
+            .then(move |result| {
+                match result {
+                    Ok(Ok(_)) => info!("Drain of {:?}: destroyed {}", node_id, session_id),
+                    Ok(Err(e)) => error!(
+                        "Drain of {:?}: cannot destroy {}: {}",
+                        node_id, session_id, e
+                    ),
+                    Err(e) => error!(
+                        "Drain of {:?}: cannot destroy {}: {}",
+                        node_id, session_id, e
+                    ),
+                }
+                Ok(())
+            }),
+    )
+}
+
+impl Actor for PeerInventory {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.path = ConfigModule::new().work_dir().join(INVENTORY_FILE);
+        self.load();
+        ctx.run_interval(DRAIN_CHECK_INTERVAL, |act, ctx| act.check_drains(ctx));
+        ctx.run_interval(ACTIVITY_CHECK_INTERVAL, |act, ctx| act.check_activity(ctx));
+
+        ctx.wait(
+            gu_event_bus::subscribe("/peers".into(), ctx.address().recipient())
+                .map_err(|_| error!("Cannot subscribe to peer events"))
+                .and_then(|_| {
+                    peer::PeerManager::from_registry()
+                        .send(peer::ListPeers)
+                        .map_err(|e| error!("Cannot list connected peers: {}", e))
+                })
+                .into_actor(self)
+                .and_then(|connected, act, ctx| {
+                    for info in connected {
+                        act.connected(info, ctx);
+                    }
+                    fut::ok(())
+                }),
+        );
+    }
+}
+
+impl Supervised for PeerInventory {}
+
+impl SystemService for PeerInventory {}
+
+impl Handler<gu_event_bus::Event<PeerEvent>> for PeerInventory {
+    type Result = ();
+
+    fn handle(&mut self, msg: gu_event_bus::Event<PeerEvent>, ctx: &mut Self::Context) {
+        match msg.data() {
+            PeerEvent::Connected(info) => {
+                self.connected(info.clone(), ctx);
+                events::post(HubEvent::PeerConnected {
+                    node_id: info.node_id,
+                });
+            }
+            PeerEvent::Disconnected(node_id) => {
+                self.disconnected(*node_id);
+                events::post(HubEvent::PeerDisconnected { node_id: *node_id });
+            }
+        }
+    }
+}
+
+pub struct ListInventory;
+
+impl Message for ListInventory {
+    type Result = Vec<PeerInventoryInfo>;
+}
+
+impl Handler<ListInventory> for PeerInventory {
+    type Result = MessageResult<ListInventory>;
+
+    fn handle(&mut self, _msg: ListInventory, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.peers.values().cloned().collect())
+    }
+}
+
+/// Removes the node from the inventory, returns `None` if it was not known.
+pub struct ForgetPeer(pub NodeId);
+
+impl Message for ForgetPeer {
+    type Result = Option<PeerInventoryInfo>;
+}
+
+impl Handler<ForgetPeer> for PeerInventory {
+    type Result = MessageResult<ForgetPeer>;
+
+    fn handle(&mut self, msg: ForgetPeer, _ctx: &mut Self::Context) -> Self::Result {
+        let removed = self.peers.remove(&msg.0);
+        if removed.is_some() {
+            self.save();
+        }
+        MessageResult(removed)
+    }
+}
+
+/// Online peers with labels matching the selector.
+pub struct SelectPeers(pub LabelSelector);
+
+impl Message for SelectPeers {
+    type Result = Vec<NodeId>;
+}
+
+impl Handler<SelectPeers> for PeerInventory {
+    type Result = MessageResult<SelectPeers>;
+
+    fn handle(&mut self, msg: SelectPeers, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.select(&msg.0))
+    }
+}
+
+/// Changes peer labels, returns `None` if the node is not known.
+pub struct PatchPeer {
+    pub node_id: NodeId,
+    pub patch: PeerPatch,
+}
+
+impl Message for PatchPeer {
+    type Result = Option<PeerInventoryInfo>;
+}
+
+impl Handler<PatchPeer> for PeerInventory {
+    type Result = MessageResult<PatchPeer>;
+
+    fn handle(&mut self, msg: PatchPeer, _ctx: &mut Self::Context) -> Self::Result {
+        let entry = match self.peers.get_mut(&msg.node_id) {
+            Some(entry) => entry,
+            None => return MessageResult(None),
+        };
+
+        for (key, value) in msg.patch.labels {
+            match value {
+                Some(value) => entry.labels.insert(key, value),
+                None => entry.labels.remove(&key),
+            };
+        }
+        if let Some(state) = msg.patch.state {
+            if entry.state == PeerState::Draining && state != PeerState::Draining {
+                entry.drain = None;
+                if entry.online {
+                    set_draining(msg.node_id, false, None);
+                }
+            }
+            entry.state = state;
+        }
+        if entry.online {
+            peer::PeerManager::from_registry().do_send(peer::UpdatePeer::SetTags(
+                msg.node_id,
+                label_tags(&entry.labels),
+            ));
+        }
+        let info = entry.clone();
+        self.save();
+
+        MessageResult(Some(info))
+    }
+}
+
+/// Starts draining the peer, returns `None` if the node is not known.
+pub struct Drain {
+    pub node_id: NodeId,
+    /// Deployments running longer are destroyed, `None` waits without a limit.
+    pub timeout: Option<Duration>,
+}
+
+impl Message for Drain {
+    type Result = Option<PeerInventoryInfo>;
+}
+
+impl Handler<Drain> for PeerInventory {
+    type Result = MessageResult<Drain>;
+
+    fn handle(&mut self, msg: Drain, ctx: &mut Self::Context) -> Self::Result {
+        let entry = match self.peers.get_mut(&msg.node_id) {
+            Some(entry) => entry,
+            None => return MessageResult(None),
+        };
+
+        let now = Utc::now();
+        entry.state = PeerState::Draining;
+        entry.drain = Some(DrainProgress {
+            started: now,
+            deadline: msg
+                .timeout
+                .and_then(|timeout| chrono::Duration::from_std(timeout).ok())
+                .map(|timeout| now + timeout),
+            remaining: Vec::new(),
+            finished: false,
+            forced: false,
+        });
+        if entry.online {
+            set_draining(msg.node_id, true, drain_timeout(&entry.drain, now));
+        }
+        let info = entry.clone();
+        self.save();
+        self.check_drains(ctx);
+
+        MessageResult(Some(info))
+    }
+}
+
+/// Returns those of given nodes that are cordoned or draining.
+pub struct Unschedulable(pub Vec<NodeId>);
+
+impl Message for Unschedulable {
+    type Result = Vec<NodeId>;
+}
+
+impl Handler<Unschedulable> for PeerInventory {
+    type Result = MessageResult<Unschedulable>;
+
+    fn handle(&mut self, msg: Unschedulable, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(
+            msg.0
+                .into_iter()
+                .filter(|node_id| {
+                    self.peers
+                        .get(node_id)
+                        .map_or(false, |info| !info.state.is_schedulable())
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn drain(deadline: Option<DateTime<Utc>>) -> Option<DrainProgress> {
+        Some(DrainProgress {
+            started: Utc::now(),
+            deadline,
+            remaining: Vec::new(),
+            finished: false,
+            forced: false,
+        })
+    }
+
+    fn entry(n: u8, online: bool, labels: &[(&str, &str)]) -> PeerInventoryInfo {
+        let now = Utc::now();
+        PeerInventoryInfo {
+            node_id: NodeId::from([n; 20]),
+            node_name: None,
+            peer_addr: None,
+            first_seen: now,
+            last_seen: now,
+            online,
+            hardware: None,
+            labels: labels
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            state: PeerState::default(),
+            drain: None,
+            activity: None,
+        }
+    }
+
+    fn inventory(path: PathBuf, entries: Vec<PeerInventoryInfo>) -> PeerInventory {
+        PeerInventory {
+            path,
+            peers: entries
+                .into_iter()
+                .map(|info| (info.node_id, info))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_load_saved_peers_offline() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(INVENTORY_FILE);
+        let mut online = entry(1, true, &[("gpu", "yes")]);
+        online.activity = Some(Default::default());
+        inventory(path.clone(), vec![online, entry(2, false, &[])]).save();
+
+        let mut loaded = inventory(path.clone(), Vec::new());
+        loaded.load();
+        assert_eq!(loaded.peers.len(), 2);
+        let info = &loaded.peers[&NodeId::from([1u8; 20])];
+        assert!(!info.online);
+        assert!(info.activity.is_none());
+        assert_eq!(info.labels.get("gpu").map(String::as_str), Some("yes"));
+
+        fs::write(&path, b"not json").unwrap();
+        loaded.load();
+        assert!(loaded.peers.is_empty());
+    }
+
+    #[test]
+    fn test_select() {
+        let inventory = inventory(
+            PathBuf::new(),
+            vec![
+                entry(1, true, &[("gpu", "yes")]),
+                entry(2, false, &[("gpu", "yes")]),
+                entry(3, true, &[]),
+            ],
+        );
+
+        assert_eq!(
+            inventory.select(&"gpu=yes".parse().unwrap()),
+            vec![NodeId::from([1u8; 20])]
+        );
+        let all = inventory.select(&LabelSelector::default());
+        assert_eq!(all.len(), 2);
+        assert!(!all.contains(&NodeId::from([2u8; 20])));
+    }
+
+    #[test]
+    fn test_drain_timeout() {
+        let now = Utc::now();
+        assert_eq!(drain_timeout(&None, now), None);
+        assert_eq!(drain_timeout(&drain(None), now), None);
+        assert_eq!(
+            drain_timeout(&drain(Some(now + chrono::Duration::seconds(90))), now),
+            Some(90)
+        );
+        // reconnecting after the deadline destroys the deployments at once
+        assert_eq!(
+            drain_timeout(&drain(Some(now - chrono::Duration::seconds(90))), now),
+            Some(0)
+        );
+    }
+}
@@ -1,6 +1,21 @@
+//! Proxy for plugin backend services at `/service/local/{plugin}/{command}/...`.
+//!
+//! Backends either register their URL with `PATCH /service/local` (see `gu-plugin-api`)
+//! or are declared by the plugin with an `exec` entry in its `gu-service` config.
+//! Declared backends are started by the hub in their own process group, listen on
+//! a free port at 127.0.0.1 and report it with a `GU_SERVICE_PORT=<port>` line on
+//! stdout. They are restarted with growing delays when they exit and stopped when
+//! the plugin is inactivated. Their output goes to the hub log.
+
 use std::{
     collections::{BTreeMap, BTreeSet},
-    sync::{Arc, RwLock},
+    fs,
+    io::{self, BufRead, BufReader, Read},
+    path::{Component, Path, PathBuf},
+    process::{Child, Command as ProcessCommand, Stdio},
+    sync::{Arc, Mutex, RwLock},
+    thread,
+    time::{Duration, Instant},
 };
 
 use actix::{fut, prelude::*};
@@ -8,53 +23,465 @@ use actix_web::{
     self, App, AsyncResponder, HttpMessage, HttpRequest, HttpResponse, Json, Responder,
 };
 use futures::prelude::*;
-use log::debug;
+use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
 
-use gu_base::{ArgMatches, Module};
+use gu_base::Module;
 use gu_event_bus;
+use gu_persist::config::ConfigModule;
+
+use super::plugins::{self, ListPlugins, PluginEvent, PluginFiles, PluginManager, PluginStatus};
 
-use super::plugins::{self, ListPlugins, PluginEvent, PluginManager, PluginStatus};
+/// How often backend processes are checked for exit.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Delay before a crashed backend is started again, doubled after each failed run.
+const RESTART_DELAY: Duration = Duration::from_secs(3);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(300);
+/// Backends running at least that long are restarted without delay growth.
+const STABLE_RUN: Duration = Duration::from_secs(60);
+/// Time stopped backends get to exit after SIGTERM, before they are killed.
+const STOP_GRACE: Duration = Duration::from_secs(10);
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "kebab-case")]
 struct ServiceConfig {
     cmd: String,
+    /// backend process started and supervised by the hub
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exec: Option<ExecConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+struct ExecConfig {
+    /// executable from the plugin package, relative to its app directory;
+    /// the whole directory of the program is extracted with it
+    program: String,
+    #[serde(default)]
+    args: Vec<String>,
 }
 
 pub fn module() -> impl Module {
     LocalServiceModule {
         plugin_commands: Arc::new(RwLock::new(BTreeMap::new())),
         command_proxy_path: Arc::new(RwLock::new(BTreeMap::new())),
-        _manager: None,
+        backend_ports: Arc::new(RwLock::new(BTreeMap::new())),
+        manager: Mutex::new(None),
+    }
+}
+
+/// Plugin and command of a hub managed backend; plugins may declare the same command.
+type BackendKey = (String, String);
+
+enum BackendState {
+    Starting,
+    Running(Child, Instant),
+    Exited,
+}
+
+struct Backend {
+    exec: ExecConfig,
+    state: BackendState,
+    /// failed runs since the backend last ran stable
+    failures: u32,
+}
+
+impl Backend {
+    fn is_starting(&self) -> bool {
+        match self.state {
+            BackendState::Starting => true,
+            _ => false,
+        }
+    }
+
+    /// Counts a failed run and returns the delay before the next start.
+    fn restart_delay(&mut self) -> Duration {
+        let delay = RESTART_DELAY * 2u32.pow(self.failures.min(7));
+        self.failures += 1;
+        delay.min(MAX_RESTART_DELAY)
     }
 }
 
 struct ProxyManager {
     // Plugin -> Set of Commands
     plugin_commands: Arc<RwLock<BTreeMap<String, BTreeSet<String>>>>,
+    command_proxy_path: Arc<RwLock<BTreeMap<String, ProxyPath>>>,
+    // (Plugin, Command) -> port of hub managed backend, `None` until reported
+    backend_ports: Arc<RwLock<BTreeMap<BackendKey, Option<u16>>>>,
+    backends: BTreeMap<BackendKey, Backend>,
 }
 
 impl ProxyManager {
-    fn configure<I>(&mut self, name: &str, it: I)
+    fn configure<I>(&mut self, name: &str, it: I, ctx: &mut Context<Self>)
     where
         I: Iterator<Item = ServiceConfig>,
     {
-        let mut w = self.plugin_commands.write().unwrap();
+        self.unconfigure(name);
 
-        let map: BTreeSet<_> = it.map(|cfg| cfg.cmd.clone()).collect();
+        let configs: Vec<ServiceConfig> = it.collect();
+        {
+            let mut w = self.plugin_commands.write().unwrap();
+            let map: BTreeSet<_> = configs.iter().map(|cfg| cfg.cmd.clone()).collect();
+            w.insert(name.to_string(), map);
+            debug!("w={:?}", *w);
+        }
 
-        w.insert(name.to_string(), map);
-        debug!("w={:?}", *w);
+        for cfg in configs {
+            if let Some(exec) = cfg.exec {
+                let key = (name.to_string(), cfg.cmd);
+                if self.backends.contains_key(&key) {
+                    warn!("Duplicate {}/{} backend ignored", key.0, key.1);
+                    continue;
+                }
+                self.backend_ports
+                    .write()
+                    .unwrap()
+                    .insert(key.clone(), None);
+                self.backends.insert(
+                    key.clone(),
+                    Backend {
+                        exec,
+                        state: BackendState::Exited,
+                        failures: 0,
+                    },
+                );
+                self.start_backend(key, ctx);
+            }
+        }
     }
 
     fn unconfigure(&mut self, name: &str) {
-        let mut w = self.plugin_commands.write().unwrap();
+        self.plugin_commands.write().unwrap().remove(name);
+
+        let keys: Vec<BackendKey> = self
+            .backends
+            .keys()
+            .filter(|(plugin, _)| plugin == name)
+            .cloned()
+            .collect();
+        let mut children = Vec::new();
+        for key in keys {
+            self.backend_ports.write().unwrap().remove(&key);
+            if let Some(BackendState::Running(child, _)) =
+                self.backends.remove(&key).map(|backend| backend.state)
+            {
+                info!("Stopping {}/{} backend", key.0, key.1);
+                children.push(child);
+            }
+        }
+        if !children.is_empty() {
+            thread::spawn(move || stop_backends(children));
+        }
+    }
 
-        w.remove(name);
+    /// Extracts the backend directory from the plugin package and spawns the program.
+    fn start_backend(&mut self, key: BackendKey, ctx: &mut Context<Self>) {
+        let program = match self.backends.get_mut(&key) {
+            Some(backend) => match backend.state {
+                BackendState::Exited => {
+                    backend.state = BackendState::Starting;
+                    backend.exec.program.clone()
+                }
+                _ => return,
+            },
+            None => return,
+        };
+
+        let run_dir = ConfigModule::new()
+            .work_dir()
+            .join("plugin-services")
+            .join(&key.0);
+
+        let program = match program_path(&program) {
+            Ok(program) => program,
+            Err(e) => return self.spawn_backend(key, Err(e), run_dir, ctx),
+        };
+        let dir = program.parent().map(Path::to_path_buf).unwrap_or_default();
+
+        PluginManager::from_registry()
+            .send(PluginFiles {
+                plugin: key.0.clone(),
+                dir,
+            })
+            .into_actor(self)
+            .then(move |files, act, ctx| {
+                let executable = match files {
+                    Ok(Ok(files)) => extract_backend(&run_dir, &program, files),
+                    Ok(Err(e)) => Err(e),
+                    Err(e) => Err(format!("Cannot read {:?}: {}", program, e)),
+                };
+                act.spawn_backend(key, executable, run_dir, ctx);
+                fut::ok(())
+            })
+            .spawn(ctx);
+    }
+
+    fn spawn_backend(
+        &mut self,
+        key: BackendKey,
+        executable: Result<PathBuf, String>,
+        run_dir: PathBuf,
+        ctx: &mut Context<Self>,
+    ) {
+        let backend = match self.backends.get_mut(&key) {
+            Some(backend) if backend.is_starting() => backend,
+            // plugin was inactivated or reconfigured in the meantime
+            _ => return,
+        };
+        let name = format!("{}/{}", key.0, key.1);
+
+        let child = executable.and_then(|executable| {
+            let mut command = ProcessCommand::new(&executable);
+            command
+                .args(&backend.exec.args)
+                .current_dir(&run_dir)
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+            #[cfg(unix)]
+            {
+                use std::os::unix::process::CommandExt;
+
+                // the backend and its children are stopped together
+                unsafe {
+                    command.pre_exec(|| {
+                        if libc::setpgid(0, 0) == 0 {
+                            Ok(())
+                        } else {
+                            Err(io::Error::last_os_error())
+                        }
+                    });
+                }
+            }
+            command
+                .spawn()
+                .map_err(|e| format!("Cannot start {:?}: {}", executable, e))
+        });
+
+        match child {
+            Ok(mut child) => {
+                info!("Started {} backend", name);
+                let pid = child.id();
+                let manager = ctx.address();
+                if let Some(stdout) = child.stdout.take() {
+                    let key = key.clone();
+                    log_output(
+                        name.clone(),
+                        stdout,
+                        false,
+                        Some(Box::new(move |port| {
+                            manager.do_send(BackendPort {
+                                key: key.clone(),
+                                pid,
+                                port,
+                            })
+                        })),
+                    );
+                }
+                if let Some(stderr) = child.stderr.take() {
+                    log_output(name, stderr, true, None);
+                }
+                backend.state = BackendState::Running(child, Instant::now());
+            }
+            Err(e) => {
+                let delay = backend.restart_delay();
+                error!("{} backend: {}, retrying in {:?}", name, e, delay);
+                backend.state = BackendState::Exited;
+                ctx.run_later(delay, move |act, ctx| act.start_backend(key, ctx));
+            }
+        }
+    }
+
+    /// Schedules restart of backends which exited.
+    fn check_backends(&mut self, ctx: &mut Context<Self>) {
+        for ((plugin, cmd), backend) in self.backends.iter_mut() {
+            let (status, started) = match backend.state {
+                BackendState::Running(ref mut child, started) => (child.try_wait(), started),
+                _ => continue,
+            };
+
+            match status {
+                Ok(None) => (),
+                Ok(Some(status)) => {
+                    if started.elapsed() >= STABLE_RUN {
+                        backend.failures = 0;
+                    }
+                    let delay = backend.restart_delay();
+                    warn!(
+                        "{}/{} backend exited with {}, restarting in {:?}",
+                        plugin, cmd, status, delay
+                    );
+                    backend.state = BackendState::Exited;
+                    let key = (plugin.clone(), cmd.clone());
+                    self.backend_ports
+                        .write()
+                        .unwrap()
+                        .insert(key.clone(), None);
+                    ctx.run_later(delay, move |act, ctx| act.start_backend(key, ctx));
+                }
+                Err(e) => error!("Cannot check {}/{} backend: {}", plugin, cmd, e),
+            }
+        }
+    }
+}
+
+/// Reports the port a backend listens on, taken from its `GU_SERVICE_PORT` line.
+struct BackendPort {
+    key: BackendKey,
+    pid: u32,
+    port: u16,
+}
+
+impl Message for BackendPort {
+    type Result = ();
+}
+
+impl Handler<BackendPort> for ProxyManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: BackendPort, _ctx: &mut Self::Context) -> Self::Result {
+        // the report may come from a run which already exited
+        let current = match self.backends.get(&msg.key) {
+            Some(Backend {
+                state: BackendState::Running(ref child, _),
+                ..
+            }) => child.id() == msg.pid,
+            _ => false,
+        };
+        if current {
+            info!(
+                "{}/{} backend listens on port {}",
+                msg.key.0, msg.key.1, msg.port
+            );
+            self.backend_ports
+                .write()
+                .unwrap()
+                .insert(msg.key, Some(msg.port));
+        }
     }
 }
 
+fn parse_port_line(line: &str) -> Option<u16> {
+    if line.starts_with("GU_SERVICE_PORT=") {
+        line["GU_SERVICE_PORT=".len()..].trim().parse().ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(unix)]
+fn signal_group(child: &Child, signal: libc::c_int) -> bool {
+    unsafe { libc::kill(-(child.id() as libc::pid_t), signal) == 0 }
+}
+
+/// Sends SIGTERM to the process groups of backends and kills those still running
+/// after the grace period.
+fn stop_backends(mut children: Vec<Child>) {
+    #[cfg(unix)]
+    {
+        for child in &children {
+            let _ = signal_group(child, libc::SIGTERM);
+        }
+        let deadline = Instant::now() + STOP_GRACE;
+        while Instant::now() < deadline
+            && children
+                .iter_mut()
+                .any(|child| child.try_wait().map(|s| s.is_none()).unwrap_or(false))
+        {
+            thread::sleep(Duration::from_millis(100));
+        }
+        for child in &children {
+            // processes left by the backend keep the group alive
+            let _ = signal_group(child, libc::SIGKILL);
+        }
+    }
+    for mut child in children {
+        #[cfg(not(unix))]
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+/// Checks that the program path stays inside the plugin package.
+fn check_program_path(program: &str) -> Result<(), String> {
+    let path = Path::new(program);
+    let valid = path.components().next().is_some()
+        && path.components().all(|c| match c {
+            Component::Normal(_) | Component::CurDir => true,
+            _ => false,
+        });
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid backend program path {:?}", program))
+    }
+}
+
+/// Program path relative to the plugin app directory, without `.` components.
+fn program_path(program: &str) -> Result<PathBuf, String> {
+    check_program_path(program)?;
+    Ok(Path::new(program)
+        .components()
+        .filter(|c| *c != Component::CurDir)
+        .collect())
+}
+
+/// Writes the files of the backend directory and returns the program path.
+fn extract_backend(
+    run_dir: &Path,
+    program: &Path,
+    files: Vec<(PathBuf, Vec<u8>)>,
+) -> Result<PathBuf, String> {
+    if !files.iter().any(|(path, _)| path == program) {
+        return Err(format!("Program {:?} not found in the plugin", program));
+    }
+    for (path, bytes) in files {
+        let path = path
+            .to_str()
+            .ok_or_else(|| format!("Invalid backend file path {:?}", path))
+            .and_then(|path| check_program_path(path).map(|()| run_dir.join(path)))?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Cannot create {:?}: {}", parent, e))?;
+        }
+        // other backends of the plugin may run from the same directory
+        let tmp_path = path.with_extension("gu-tmp");
+        fs::write(&tmp_path, bytes).map_err(|e| format!("Cannot write {:?}: {}", path, e))?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            fs::set_permissions(&tmp_path, fs::Permissions::from_mode(0o755))
+                .map_err(|e| format!("Cannot set permissions of {:?}: {}", path, e))?;
+        }
+        fs::rename(&tmp_path, &path).map_err(|e| format!("Cannot write {:?}: {}", path, e))?;
+    }
+    Ok(run_dir.join(program))
+}
+
+/// Copies lines of backend output to the hub log, passing reported ports to `on_port`.
+fn log_output<R: Read + Send + 'static>(
+    name: String,
+    output: R,
+    stderr: bool,
+    on_port: Option<Box<dyn Fn(u16) + Send>>,
+) {
+    thread::spawn(move || {
+        for line in BufReader::new(output).lines() {
+            match line {
+                Ok(line) if stderr => warn!("[{}] {}", name, line),
+                Ok(line) => {
+                    info!("[{}] {}", name, line);
+                    if let (Some(on_port), Some(port)) = (&on_port, parse_port_line(&line)) {
+                        on_port(port)
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+}
+
 impl Actor for ProxyManager {
     type Context = Context<Self>;
 
@@ -71,7 +498,7 @@ impl Actor for ProxyManager {
         ctx.wait(
             list_plugins
                 .into_actor(self)
-                .and_then(|plugins: Vec<_>, act, _ctx| {
+                .and_then(|plugins: Vec<_>, act, ctx| {
                     debug!("plugins={:?}", &plugins);
                     plugins
                         .iter()
@@ -86,21 +513,33 @@ impl Actor for ProxyManager {
                                 Some((name, service))
                             }
                         })
-                        .for_each(|(name, service)| act.configure(name, service.into_iter()));
+                        .for_each(|(name, service)| act.configure(name, service.into_iter(), ctx));
                     fut::ok(())
                 }),
         );
+        ctx.run_interval(CHECK_INTERVAL, |act, ctx| act.check_backends(ctx));
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        let mut children = Vec::new();
+        for ((plugin, cmd), backend) in std::mem::replace(&mut self.backends, BTreeMap::new()) {
+            if let BackendState::Running(child, _) = backend.state {
+                info!("Stopping {}/{} backend", plugin, cmd);
+                children.push(child);
+            }
+        }
+        stop_backends(children);
     }
 }
 
 impl Handler<gu_event_bus::Event<PluginEvent>> for ProxyManager {
     type Result = ();
 
-    fn handle(&mut self, msg: gu_event_bus::Event<PluginEvent>, _ctx: &mut Self::Context) -> () {
+    fn handle(&mut self, msg: gu_event_bus::Event<PluginEvent>, ctx: &mut Self::Context) -> () {
         match msg.data() {
             PluginEvent::New(plugin_meta) => {
                 let config: Vec<ServiceConfig> = plugin_meta.service("gu-service");
-                self.configure(plugin_meta.name(), config.into_iter());
+                self.configure(plugin_meta.name(), config.into_iter(), ctx);
             }
             PluginEvent::Drop(name) => self.unconfigure(&name),
         }
@@ -111,7 +550,9 @@ struct LocalServiceModule {
     // <plugin> -> <path> -> <url>
     plugin_commands: Arc<RwLock<BTreeMap<String, BTreeSet<String>>>>,
     command_proxy_path: Arc<RwLock<BTreeMap<String, ProxyPath>>>,
-    _manager: Option<Addr<ProxyManager>>,
+    backend_ports: Arc<RwLock<BTreeMap<BackendKey, Option<u16>>>>,
+    // started once, by the first web worker
+    manager: Mutex<Option<Addr<ProxyManager>>>,
 }
 
 #[derive(Deserialize)]
@@ -138,15 +579,25 @@ fn split_path2(path: &str) -> Option<(&str, &str, &str)> {
 }
 
 impl Module for LocalServiceModule {
-    fn args_consume(&mut self, _matches: &ArgMatches) -> bool {
-        //let plugin_commands = self.plugin_commands.clone();
-        //self.manager = Some(ProxyManager { plugin_commands }.start());
-        false
-    }
-
     fn decorate_webapp<S: 'static>(&self, app: App<S>) -> App<S> {
         let plugin_commands = self.plugin_commands.clone();
         let command_proxy_path = self.command_proxy_path.clone();
+        let backend_ports = self.backend_ports.clone();
+
+        {
+            let mut manager = self.manager.lock().unwrap();
+            if manager.is_none() {
+                *manager = Some(
+                    ProxyManager {
+                        plugin_commands: plugin_commands.clone(),
+                        command_proxy_path: command_proxy_path.clone(),
+                        backend_ports: backend_ports.clone(),
+                        backends: BTreeMap::new(),
+                    }
+                    .start(),
+                );
+            }
+        }
 
         let command_proxy_path_r = command_proxy_path.clone();
         let plugin_commands_r = plugin_commands.clone();
@@ -155,11 +606,14 @@ impl Module for LocalServiceModule {
             actix_web::http::Method::PATCH,
             move |r: Json<Command>| match r.into_inner() {
                 Command::RegisterCommand { cmd_name, url } => {
-                    let r = command_proxy_path_r
-                        .write()
-                        .unwrap()
-                        .insert(cmd_name, ProxyPath::Remote { url });
-                    Json(r.is_none())
+                    let mut paths = command_proxy_path_r.write().unwrap();
+                    // registered backends keep their address
+                    if paths.contains_key(&cmd_name) {
+                        return HttpResponse::Conflict()
+                            .body(format!("command {} is already registered", cmd_name));
+                    }
+                    paths.insert(cmd_name, ProxyPath::Remote { url });
+                    HttpResponse::Ok().json(true)
                 }
             },
         )
@@ -187,6 +641,22 @@ impl Module for LocalServiceModule {
                         );
                     }
                 };
+                // hub managed backend of the plugin takes precedence over a registered one
+                let key = (plugin_name.to_string(), command.to_string());
+                match backend_ports.read().unwrap().get(&key) {
+                    Some(&Some(port)) => {
+                        return actix_web::Either::B(
+                            ProxyPath::Local { port }.create_request(request_url, r),
+                        );
+                    }
+                    Some(None) => {
+                        return actix_web::Either::A(
+                            HttpResponse::ServiceUnavailable()
+                                .body(format!("{} backend is not listening yet", command)),
+                        );
+                    }
+                    None => (),
+                }
                 if let Some(proxy_path) = command_proxy_path.read().unwrap().get(command) {
                     return actix_web::Either::B(proxy_path.create_request(request_url, r));
                 } else {
@@ -204,16 +674,15 @@ enum ProxyPath {
     Remote {
         url: String,
     },
-    // TODO: needs implementation
-    #[allow(unused)]
+    /// backend started by the hub, listening on 127.0.0.1
     Local {
-        cmd: String,
+        port: u16,
     },
 }
 
 impl ProxyPath {
     fn create_request<S>(&self, path: &str, req: &HttpRequest<S>) -> impl Responder {
-        use actix_web::{client, Body};
+        use actix_web::{client, http::header, Body};
 
         let mut b = client::ClientRequest::build();
 
@@ -221,15 +690,18 @@ impl ProxyPath {
             ProxyPath::Remote { url } => {
                 b.uri(&format!("{}{}", url, path));
             }
-            ProxyPath::Local { .. } => {
-                unimplemented!();
+            ProxyPath::Local { port } => {
+                b.uri(&format!("http://127.0.0.1:{}/{}", port, path));
             }
         }
 
         b.method(req.method().clone());
 
+        // hub credentials are not passed to plugin backends
         for (k, v) in req.headers() {
-            b.header(k, v.clone());
+            if k != header::AUTHORIZATION && k != header::COOKIE {
+                b.header(k, v.clone());
+            }
         }
 
         b.streaming(req.payload())
@@ -243,3 +715,54 @@ impl ProxyPath {
             .responder()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_program_path() {
+        assert!(check_program_path("bin/render").is_ok());
+        assert!(check_program_path("./render").is_ok());
+        for program in &["", "/bin/sh", "../render", "bin/../../render"] {
+            assert!(check_program_path(program).is_err(), "{}", program);
+        }
+    }
+
+    #[test]
+    fn test_restart_delay() {
+        let mut backend = Backend {
+            exec: ExecConfig {
+                program: "bin/render".to_string(),
+                args: Vec::new(),
+            },
+            state: BackendState::Exited,
+            failures: 0,
+        };
+        assert_eq!(backend.restart_delay(), Duration::from_secs(3));
+        assert_eq!(backend.restart_delay(), Duration::from_secs(6));
+        assert_eq!(backend.restart_delay(), Duration::from_secs(12));
+        for _ in 0..20 {
+            backend.restart_delay();
+        }
+        assert_eq!(backend.restart_delay(), MAX_RESTART_DELAY);
+    }
+
+    #[test]
+    fn test_program_path() {
+        assert_eq!(program_path("./render"), Ok(PathBuf::from("render")));
+        assert_eq!(
+            program_path("bin/./render"),
+            Ok(PathBuf::from("bin/render"))
+        );
+        assert!(program_path("../render").is_err());
+    }
+
+    #[test]
+    fn test_parse_port_line() {
+        assert_eq!(parse_port_line("GU_SERVICE_PORT=41234"), Some(41234));
+        assert_eq!(parse_port_line("GU_SERVICE_PORT=41234\r"), Some(41234));
+        assert_eq!(parse_port_line("listening on 41234"), None);
+        assert_eq!(parse_port_line("GU_SERVICE_PORT=99999"), None);
+    }
+}
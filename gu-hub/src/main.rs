@@ -37,7 +37,11 @@ mod version {
 
 const VERSION: &str = env!("VERGEN_SEMVER_LIGHTWEIGHT");
 
+mod auth;
+mod events;
 mod hub_info;
+mod image_peers;
+mod inventory;
 mod local_service;
 mod peer;
 mod plugins;
@@ -45,6 +49,8 @@ mod proxy_service;
 mod repo;
 mod server;
 mod sessions;
+mod tls;
+mod webhooks;
 
 fn main() {
     GuApp(|| {
@@ -64,6 +70,9 @@ fn main() {
             .chain(proxy_service::module())
             .chain(local_service::module())
             .chain(peer::PeerModule::new())
+            .chain(auth::module())
+            .chain(events::module())
+            .chain(webhooks::module())
             .chain(AutocompleteModule::new())
             .chain(hub_info::module())
             .chain(repo::module())
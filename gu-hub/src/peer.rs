@@ -1,26 +1,37 @@
 //!
 
+use std::{collections::HashSet, time::Duration};
+
 use actix::prelude::*;
 use actix_web::{
     self,
     http::{Method, StatusCode},
-    AsyncResponder, FromRequest, HttpRequest, HttpResponse, Json, Path, Responder, Scope,
+    AsyncResponder, FromRequest, HttpRequest, HttpResponse, Json, Path, Query, Responder, Scope,
 };
-use futures::prelude::*;
+use futures::{future, prelude::*};
 use log::error;
 use prettytable::{cell, row};
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
 
 use gu_actix::prelude::*;
-use gu_base::{cli, App, AppSettings, ArgMatches, Decorator, Module, SubCommand};
-use gu_model::peers as peers_api;
+use gu_base::{cli, App, AppSettings, Arg, ArgMatches, Decorator, Module, SubCommand};
+use gu_model::config::{ConfigError, GetConfig, SetConfig, REMOTE_SECTIONS};
+use gu_model::peers::{
+    self as peers_api, valid_label_key, valid_label_value, LabelSelector, PeerPatch, PeerState,
+};
 use gu_net::{
     rpc::{peer, public_destination, reply::CallRemoteUntyped, reply::SendError, ReplyRouter},
     NodeId,
 };
 
-use crate::server::HubClient;
+use crate::{
+    image_peers,
+    inventory::{
+        Drain, ForgetPeer, ListInventory, PatchPeer, PeerInventory, SelectPeers, Unschedulable,
+    },
+    server::HubClient,
+};
 
 pub struct PeerModule {
     inner: State,
@@ -29,6 +40,9 @@ pub struct PeerModule {
 enum State {
     None,
     List,
+    ListAll,
+    Forget(NodeId),
+    Drain(NodeId, Option<u64>),
 }
 
 impl PeerModule {
@@ -44,15 +58,74 @@ impl Module for PeerModule {
                 .about("Shows information about connected providers (peers)")
                 .setting(AppSettings::SubcommandRequiredElseHelp)
                 .subcommand(
-                    SubCommand::with_name("list").about("Lists connected providers (peers)"),
+                    SubCommand::with_name("list")
+                        .about("Lists connected providers (peers)")
+                        .arg(
+                            Arg::with_name("all")
+                                .short("a")
+                                .long("all")
+                                .help("Lists all providers that have ever connected"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("forget")
+                        .about("Removes the provider from the peer inventory")
+                        .arg(
+                            Arg::with_name("NODE_ID")
+                                .help("Provider node id")
+                                .required(true)
+                                .index(1),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("drain")
+                        .about("Stops scheduling on the provider and waits for its deployments")
+                        .arg(
+                            Arg::with_name("NODE_ID")
+                                .help("Provider node id")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::with_name("timeout")
+                                .short("t")
+                                .long("timeout")
+                                .takes_value(true)
+                                .value_name("SECS")
+                                .help("Destroys remaining deployments after the timeout"),
+                        ),
                 ),
         )
     }
 
     fn args_consume(&mut self, matches: &ArgMatches) -> bool {
         if let Some(m) = matches.subcommand_matches("peer") {
-            if let Some(_m) = m.subcommand_matches("list") {
-                self.inner = State::List;
+            if let Some(m) = m.subcommand_matches("list") {
+                self.inner = match m.is_present("all") {
+                    true => State::ListAll,
+                    false => State::List,
+                };
+                return true;
+            }
+            if let Some(m) = m.subcommand_matches("forget") {
+                let node_id = m
+                    .value_of("NODE_ID")
+                    .expect("Lack of required `node-id` argument")
+                    .parse()
+                    .expect("Invalid node id");
+                self.inner = State::Forget(node_id);
+                return true;
+            }
+            if let Some(m) = m.subcommand_matches("drain") {
+                let node_id = m
+                    .value_of("NODE_ID")
+                    .expect("Lack of required `node-id` argument")
+                    .parse()
+                    .expect("Invalid node id");
+                let timeout = m
+                    .value_of("timeout")
+                    .map(|t| t.parse().expect("Invalid timeout"));
+                self.inner = State::Drain(node_id, timeout);
                 return true;
             }
         }
@@ -72,6 +145,42 @@ impl Module for PeerModule {
                     )
                 });
             }
+            State::ListAll => {
+                System::run(|| {
+                    Arbiter::spawn(
+                        HubClient::get("/peers?all=true")
+                            .and_then(|r: Vec<peers_api::PeerInventoryInfo>| {
+                                Ok(format_inventory_table(r))
+                            })
+                            .map_err(|e| error!("{}", e))
+                            .then(|_r| Ok(System::current().stop())),
+                    )
+                });
+            }
+            State::Forget(node_id) => {
+                System::run(move || {
+                    Arbiter::spawn(
+                        HubClient::delete(format!("/peers/{}", node_id.to_string()))
+                            .and_then(|_r: peers_api::PeerInventoryInfo| Ok(()))
+                            .map_err(|e| error!("{}", e))
+                            .then(|_r| Ok(System::current().stop())),
+                    )
+                });
+            }
+            State::Drain(node_id, timeout) => {
+                let path = match timeout {
+                    Some(secs) => format!("/peers/{}/drain?timeout={}", node_id.to_string(), secs),
+                    None => format!("/peers/{}/drain", node_id.to_string()),
+                };
+                System::run(move || {
+                    Arbiter::spawn(
+                        HubClient::empty_post(path)
+                            .and_then(|_r: peers_api::PeerInventoryInfo| Ok(()))
+                            .map_err(|e| error!("{}", e))
+                            .then(|_r| Ok(System::current().stop())),
+                    )
+                });
+            }
         }
     }
 
@@ -83,8 +192,20 @@ impl Module for PeerModule {
 pub fn scope<S: 'static>(scope: Scope<S>) -> Scope<S> {
     scope
         .route("", Method::GET, list_peers)
-        .resource("/{nodeId}", |r| r.get().with(fetch_peer))
+        .resource("/{nodeId}", |r| {
+            r.get().with(fetch_peer);
+            r.method(Method::PATCH).with(patch_peer);
+            r.delete().with(forget_peer)
+        })
+        .resource("/{nodeId}/drain", |r| {
+            r.get().with(fetch_drain);
+            r.post().with(drain_peer)
+        })
         .resource("/{nodeId}/hardware", |r| r.get().with(fetch_peer_hardware))
+        .resource("/{nodeId}/config/{section}", |r| {
+            r.get().with(fetch_peer_config);
+            r.put().with(update_peer_config)
+        })
         .resource("/{nodeId}/deployments", |r| {
             r.get().with(fetch_deployments);
             r.post().with(new_deployment)
@@ -146,9 +267,53 @@ pub fn scope<S: 'static>(scope: Scope<S>) -> Scope<S> {
         )
 }
 
-fn list_peers<S>(_r: HttpRequest<S>) -> impl Responder {
-    peer::PeerManager::from_registry()
-        .send(peer::ListPeers)
+fn list_peers<S>(r: HttpRequest<S>) -> impl Responder {
+    let selector: LabelSelector = match r.query().get("selector").map(|s| s.parse()) {
+        None => LabelSelector::default(),
+        Some(Ok(selector)) => selector,
+        Some(Err(e)) => {
+            return future::err::<HttpResponse, actix_web::Error>(
+                actix_web::error::ErrorBadRequest(e.to_string()),
+            )
+            .responder()
+        }
+    };
+
+    if r.query().get("all").map(|v| v == "true").unwrap_or(false) {
+        return PeerInventory::from_registry()
+            .send(ListInventory)
+            .map_err(|e| actix_web::error::ErrorInternalServerError(format!("err: {}", e)))
+            .and_then(move |res| {
+                Ok(HttpResponse::Ok().json(
+                    res.into_iter()
+                        .filter(|info| selector.matches(&info.labels))
+                        .collect::<Vec<_>>(),
+                ))
+            })
+            .responder();
+    }
+
+    let selected = match selector.is_empty() {
+        true => future::Either::A(future::ok::<_, MailboxError>(None)),
+        false => future::Either::B(
+            PeerInventory::from_registry()
+                .send(SelectPeers(selector))
+                .map(|node_ids| Some(node_ids.into_iter().collect::<HashSet<_>>())),
+        ),
+    };
+
+    selected
+        .and_then(|selected| {
+            peer::PeerManager::from_registry()
+                .send(peer::ListPeers)
+                .map(move |peers| match selected {
+                    None => peers,
+                    Some(selected) => peers
+                        .into_iter()
+                        .filter(|info| selected.contains(&info.node_id))
+                        .collect(),
+                })
+        })
         .map_err(|e| actix_web::error::ErrorInternalServerError(format!("err: {}", e)))
         .and_then(|res| {
             //debug!("res={:?}", res);
@@ -157,6 +322,45 @@ fn list_peers<S>(_r: HttpRequest<S>) -> impl Responder {
         .responder()
 }
 
+fn patch_peer((info, body): (Path<PeerPath>, Json<PeerPatch>)) -> impl Responder {
+    let patch = body.into_inner();
+    if let Some(key) = patch.labels.keys().find(|key| !valid_label_key(key)) {
+        return future::err::<HttpResponse, actix_web::Error>(actix_web::error::ErrorBadRequest(
+            format!("Invalid label key: {:?}", key),
+        ))
+        .responder();
+    }
+    if let Some(value) = patch
+        .labels
+        .values()
+        .filter_map(Option::as_ref)
+        .find(|value| !valid_label_value(value))
+    {
+        return future::err::<HttpResponse, actix_web::Error>(actix_web::error::ErrorBadRequest(
+            format!("Invalid label value: {:?}", value),
+        ))
+        .responder();
+    }
+    if patch.state == Some(PeerState::Draining) {
+        return future::err::<HttpResponse, actix_web::Error>(actix_web::error::ErrorBadRequest(
+            "Use the drain resource to drain the peer",
+        ))
+        .responder();
+    }
+
+    PeerInventory::from_registry()
+        .send(PatchPeer {
+            node_id: info.node_id,
+            patch,
+        })
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("err: {}", e)))
+        .and_then(|res| match res {
+            None => Ok(HttpResponse::build(StatusCode::NOT_FOUND).body("Peer not found")),
+            Some(info) => Ok(HttpResponse::Ok().json(info)),
+        })
+        .responder()
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct PeerPath {
@@ -187,6 +391,17 @@ fn fetch_peer(info: Path<PeerPath>) -> impl Responder {
         .responder()
 }
 
+fn forget_peer(info: Path<PeerPath>) -> impl Responder {
+    PeerInventory::from_registry()
+        .send(ForgetPeer(info.node_id))
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("err: {}", e)))
+        .and_then(|removed| match removed {
+            None => Ok(HttpResponse::build(StatusCode::NOT_FOUND).body("Peer not found")),
+            Some(info) => Ok(HttpResponse::Ok().json(info)),
+        })
+        .responder()
+}
+
 fn fetch_peer_hardware(info: Path<PeerPath>) -> impl Responder {
     use gu_hardware::actor::HardwareQuery;
     peer(info.node_id)
@@ -211,6 +426,79 @@ fn fetch_peer_hardware(info: Path<PeerPath>) -> impl Responder {
         .responder()
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ConfigPath {
+    node_id: NodeId,
+    section: String,
+}
+
+fn config_error_response(e: ConfigError) -> HttpResponse {
+    match e {
+        ConfigError::AccessDenied => HttpResponse::Forbidden().body(e.to_string()),
+        ConfigError::UnknownSection(_) => HttpResponse::NotFound().body(e.to_string()),
+        ConfigError::InvalidValue(_) => HttpResponse::BadRequest().body(e.to_string()),
+        ConfigError::Error(_) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+fn send_error(e: SendError) -> actix_web::Error {
+    match e {
+        SendError::NoDestination => actix_web::error::ErrorNotFound("peer not found"),
+        SendError::NotConnected(node_id) => {
+            actix_web::error::ErrorNotFound(format!("Peer not found {:?}", node_id))
+        }
+        _ => actix_web::error::ErrorInternalServerError(format!("{}", e)),
+    }
+}
+
+fn fetch_peer_config(path: Path<ConfigPath>) -> impl Responder {
+    let ConfigPath { node_id, section } = path.into_inner();
+    if !REMOTE_SECTIONS.contains(&section.as_str()) {
+        return future::Either::A(future::ok(config_error_response(
+            ConfigError::UnknownSection(section),
+        )))
+        .responder();
+    }
+
+    future::Either::B(
+        peer(node_id)
+            .into_endpoint()
+            .send(GetConfig { section })
+            .map_err(send_error)
+            .and_then(|result| match result {
+                Ok(value) => Ok(HttpResponse::Ok().json(value)),
+                Err(e) => Ok(config_error_response(e)),
+            }),
+    )
+    .responder()
+}
+
+fn update_peer_config((path, body): (Path<ConfigPath>, Json<JsonValue>)) -> impl Responder {
+    let ConfigPath { node_id, section } = path.into_inner();
+    if !REMOTE_SECTIONS.contains(&section.as_str()) {
+        return future::Either::A(future::ok(config_error_response(
+            ConfigError::UnknownSection(section),
+        )))
+        .responder();
+    }
+
+    future::Either::B(
+        peer(node_id)
+            .into_endpoint()
+            .send(SetConfig {
+                section,
+                value: body.into_inner(),
+            })
+            .map_err(send_error)
+            .and_then(|result| match result {
+                Ok(()) => Ok(HttpResponse::NoContent().finish()),
+                Err(e) => Ok(config_error_response(e)),
+            }),
+    )
+    .responder()
+}
+
 fn fetch_deployments(info: Path<PeerPath>) -> impl Responder {
     use gu_model::deployment::DeploymentInfo;
     use gu_model::envman::GetSessions;
@@ -237,19 +525,69 @@ fn fetch_deployments(info: Path<PeerPath>) -> impl Responder {
         .responder()
 }
 
+#[derive(Deserialize)]
+struct DrainQuery {
+    /// Seconds after which remaining deployments are destroyed.
+    timeout: Option<u64>,
+}
+
+fn drain_peer((info, query): (Path<PeerPath>, Query<DrainQuery>)) -> impl Responder {
+    PeerInventory::from_registry()
+        .send(Drain {
+            node_id: info.node_id,
+            timeout: query.timeout.map(Duration::from_secs),
+        })
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("err: {}", e)))
+        .and_then(|res| match res {
+            None => Ok(HttpResponse::build(StatusCode::NOT_FOUND).body("Peer not found")),
+            Some(info) => Ok(HttpResponse::Accepted().json(info)),
+        })
+        .responder()
+}
+
+fn fetch_drain(info: Path<PeerPath>) -> impl Responder {
+    let node_id = info.node_id;
+
+    PeerInventory::from_registry()
+        .send(ListInventory)
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("err: {}", e)))
+        .and_then(move |peers| {
+            match peers
+                .into_iter()
+                .find(|info| info.node_id == node_id)
+                .and_then(|info| info.drain)
+            {
+                None => Ok(HttpResponse::build(StatusCode::NOT_FOUND).body("Peer is not draining")),
+                Some(drain) => Ok(HttpResponse::Ok().json(drain)),
+            }
+        })
+        .responder()
+}
+
 fn new_deployment(
     info: Path<PeerPath>,
     body: Json<gu_model::envman::GenericCreateSession>,
 ) -> impl Responder {
-    peer(info.node_id)
-        .into_endpoint()
-        .send(body.into_inner())
-        .map_err(|e| match e {
-            SendError::NoDestination => actix_web::error::ErrorNotFound("peer not found"),
-            SendError::NotConnected(node_id) => {
-                actix_web::error::ErrorNotFound(format!("Peer not found {:?}", node_id))
+    let node_id = info.node_id;
+
+    PeerInventory::from_registry()
+        .send(Unschedulable(vec![node_id]))
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("err: {}", e)))
+        .and_then(move |unschedulable| {
+            if !unschedulable.is_empty() {
+                return future::Either::A(future::err(actix_web::error::ErrorConflict(
+                    "Peer is cordoned",
+                )));
             }
-            _ => actix_web::error::ErrorInternalServerError(format!("{}", e)),
+            future::Either::B(
+                image_peers::create_session(node_id, body.into_inner()).map_err(|e| match e {
+                    SendError::NoDestination => actix_web::error::ErrorNotFound("peer not found"),
+                    SendError::NotConnected(node_id) => {
+                        actix_web::error::ErrorNotFound(format!("Peer not found {:?}", node_id))
+                    }
+                    _ => actix_web::error::ErrorInternalServerError(format!("{}", e)),
+                }),
+            )
         })
         .and_then(|session_result| match session_result {
             Ok(session_id) => Ok(HttpResponse::Ok().json(session_id)),
@@ -321,3 +659,23 @@ fn format_peer_table(peers: Vec<peer::PeerInfo>) {
         }),
     )
 }
+
+fn format_inventory_table(peers: Vec<peers_api::PeerInventoryInfo>) {
+    cli::format_table(
+        row!["Node id", "Name", "Last address", "Status", "Last seen"],
+        || "No peers known",
+        peers.into_iter().map(|peer| {
+            row![
+                peer.node_id,
+                peer.node_name.unwrap_or_default(),
+                peer.peer_addr.unwrap_or_default(),
+                match (peer.online, peer.activity.map_or(false, |a| a.paused)) {
+                    (true, true) => "online, paused",
+                    (true, false) => "online",
+                    _ => "offline",
+                },
+                peer.last_seen.to_rfc3339()
+            ]
+        }),
+    )
+}
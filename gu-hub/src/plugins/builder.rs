@@ -8,15 +8,18 @@ use actix::{
     fut::WrapFuture, Actor, ActorResponse, Arbiter, ArbiterService, Context, Handler, Message,
     Supervised, System,
 };
+use ethkey::EthAccount;
 use futures::future::{self, Future};
 use log::error;
 use zip::{write::FileOptions, ZipWriter};
 
 use gu_base::{App, Arg, ArgMatches, SubCommand};
+use gu_persist::config::ConfigModule;
 
 use crate::plugins::{
     plugin::{DirectoryHandler, PluginHandler},
-    rest,
+    provider::PROVIDER_DIR,
+    rest, signature,
 };
 
 #[derive(Debug, Clone, Default)]
@@ -37,6 +40,8 @@ pub struct BuildPluginQuery {
     overwrite: bool,
     /// Install plugin after building
     install: bool,
+    /// Sign plugin package with the key from given keystore (hub key by default)
+    sign: Option<Option<PathBuf>>,
     // format: Zip
 }
 
@@ -51,6 +56,11 @@ impl<'a> From<ArgMatches<'a>> for BuildPluginQuery {
             target: matches.value_of("TARGET").unwrap().to_string(),
             overwrite: matches.is_present("replace"),
             install: matches.is_present("install"),
+            sign: if matches.is_present("sign") {
+                Some(matches.value_of("sign").map(PathBuf::from))
+            } else {
+                None
+            },
         }
     }
 }
@@ -80,6 +90,14 @@ pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
                 .short("i")
                 .help("Install the plugin in the gu-hub after build"),
         )
+        .arg(
+            Arg::with_name("sign")
+                .long("sign")
+                .takes_value(true)
+                .min_values(0)
+                .value_name("KEYSTORE")
+                .help("Sign the package with the key from keystore file (hub key by default)"),
+        )
 }
 
 fn relative_path(filename: &Path, base: &Path) -> Result<String, String> {
@@ -94,10 +112,15 @@ fn relative_path(filename: &Path, base: &Path) -> Result<String, String> {
         .map(|s| s.to_string())
 }
 
-fn zip_file(zip: &mut ZipWriter<File>, filename: &Path, base: &Path) -> Result<(), String> {
+fn zip_file(
+    zip: &mut ZipWriter<File>,
+    filename: &Path,
+    base: &Path,
+    files: &mut Vec<(String, Vec<u8>)>,
+) -> Result<(), String> {
     let relative = relative_path(filename, base)?;
 
-    zip.start_file(relative, FileOptions::default())
+    zip.start_file(relative.clone(), FileOptions::default())
         .map_err(|_| format!("Cannot create {:?} file in archive", filename))?;
 
     let mut file = File::open(filename).map_err(|_| format!("Cannot open {:?} file", filename))?;
@@ -108,6 +131,7 @@ fn zip_file(zip: &mut ZipWriter<File>, filename: &Path, base: &Path) -> Result<(
     zip.write(buf.as_ref())
         .map_err(|_| format!("Cannot write {:?} file in archive", filename))?;
 
+    files.push((relative, buf));
     Ok(())
 }
 
@@ -115,6 +139,7 @@ fn add_directory_recursive(
     zip: &mut ZipWriter<File>,
     dir: &Path,
     base: &Path,
+    files: &mut Vec<(String, Vec<u8>)>,
 ) -> Result<(), String> {
     let relative = relative_path(dir, base)?;
 
@@ -128,9 +153,9 @@ fn add_directory_recursive(
             .map_err(|_| format!("Cannot get file type of {:?}", entry))?
             .is_dir()
         {
-            add_directory_recursive(zip, &filename, base)?;
+            add_directory_recursive(zip, &filename, base, files)?;
         } else {
-            zip_file(zip, &filename, base)?;
+            zip_file(zip, &filename, base, files)?;
         }
     }
 
@@ -155,9 +180,38 @@ fn build_plugin(msg: BuildPluginQuery) -> Result<PathBuf, String> {
     let file = File::create(&target_file).map_err(|_| "Cannot create target file")?;
 
     let mut writer = ZipWriter::new(file);
-    zip_file(&mut writer, &source.join("gu-plugin.json"), &source)?;
+    let mut files = Vec::new();
+    zip_file(
+        &mut writer,
+        &source.join("gu-plugin.json"),
+        &source,
+        &mut files,
+    )?;
+
+    add_directory_recursive(&mut writer, &app_dir, &source, &mut files)?;
+
+    let provider_dir = source.join(PROVIDER_DIR);
+    if provider_dir.is_dir() {
+        add_directory_recursive(&mut writer, &provider_dir, &source, &mut files)?;
+    }
+
+    if let Some(keystore) = msg.sign {
+        let keystore = keystore.unwrap_or_else(|| ConfigModule::new().keystore_path());
+        let account = EthAccount::load_or_generate(&keystore, "")
+            .map_err(|e| format!("Cannot load key from {:?}: {}", keystore, e))?;
+        let sig = signature::sign(&account, &signature::digest(files))?;
+
+        writer
+            .start_file(signature::SIGNATURE_FILE, FileOptions::default())
+            .map_err(|_| "Cannot create signature file in archive".to_string())?;
+        serde_json::to_writer(&mut writer, &sig)
+            .map_err(|e| format!("Cannot write signature file: {}", e))?;
+        println!("Signed by {}", account.address());
+    }
 
-    add_directory_recursive(&mut writer, &app_dir, &source)?;
+    writer
+        .finish()
+        .map_err(|e| format!("Cannot write archive: {}", e))?;
 
     Ok(target_file)
 }
@@ -0,0 +1,677 @@
+//! Plugin configuration and key-value data.
+//!
+//! Every installed plugin gets its own namespace in the `plugin-data` storage: a
+//! JSON configuration, validated against the `config-schema` of the plugin
+//! metadata if one is declared, and free-form JSON values by key. The data
+//! survives upgrades and rollbacks and is removed when the plugin is uninstalled.
+
+use std::{borrow::Cow, collections::BTreeMap};
+
+use actix::prelude::*;
+use actix_web::{http::StatusCode, HttpResponse};
+use failure::Fail;
+use futures::{future, prelude::*};
+use log::{error, info};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+use gu_actix::prelude::*;
+use gu_persist::{
+    config::ConfigModule,
+    file_storage::FileStorage,
+    storage::{Delete, Fetch, List, Put},
+};
+
+use super::{
+    manager::{GetPluginMetadata, PluginManager},
+    plugin::PluginMetadata,
+};
+
+/// Maximal size of a stored configuration or value
+pub const MAX_VALUE_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Fail, Clone)]
+pub enum PluginDataError {
+    #[fail(display = "{}", _0)]
+    PluginNotFound(String),
+    #[fail(display = "Key {} not found", _0)]
+    KeyNotFound(String),
+    #[fail(display = "Invalid key {}", _0)]
+    InvalidKey(String),
+    #[fail(display = "Configuration does not match the plugin schema: {}", _0)]
+    InvalidConfig(String),
+    #[fail(display = "Storage error: {}", _0)]
+    Storage(String),
+}
+
+impl actix_web::ResponseError for PluginDataError {
+    fn error_response(&self) -> HttpResponse {
+        let status = match self {
+            PluginDataError::PluginNotFound(_) | PluginDataError::KeyNotFound(_) => {
+                StatusCode::NOT_FOUND
+            }
+            PluginDataError::InvalidKey(_) | PluginDataError::InvalidConfig(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            PluginDataError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        HttpResponse::build(status).json(self.to_string())
+    }
+}
+
+impl From<gu_persist::error::Error> for PluginDataError {
+    fn from(e: gu_persist::error::Error) -> Self {
+        PluginDataError::Storage(e.to_string())
+    }
+}
+
+impl From<MailboxError> for PluginDataError {
+    fn from(e: MailboxError) -> Self {
+        PluginDataError::Storage(e.to_string())
+    }
+}
+
+/// Plugin data as included in plugin export.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginDataExport {
+    pub name: String,
+    pub version: Version,
+    #[serde(default)]
+    pub config: JsonValue,
+    #[serde(default)]
+    pub kv: BTreeMap<String, JsonValue>,
+}
+
+/// Keys are used as file names, so only `[A-Za-z0-9_.-]` is allowed.
+fn check_key(key: &str) -> Result<(), PluginDataError> {
+    let valid = !key.is_empty()
+        && !key.starts_with('.')
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+    if valid {
+        Ok(())
+    } else {
+        Err(PluginDataError::InvalidKey(key.to_string()))
+    }
+}
+
+fn config_key(plugin: &str) -> Cow<'static, str> {
+    format!("{}/config", plugin).into()
+}
+
+fn kv_prefix(plugin: &str) -> Cow<'static, str> {
+    format!("{}/kv", plugin).into()
+}
+
+fn kv_key(plugin: &str, key: &str) -> Cow<'static, str> {
+    format!("{}/kv/{}", plugin, key).into()
+}
+
+/// Checks `value` against the subset of JSON schema supported for plugin configuration:
+/// `type`, `enum`, `minimum`, `maximum`, `minLength`, `maxLength`, `properties`,
+/// `required`, `additionalProperties` and `items`.
+pub fn validate(schema: &JsonValue, value: &JsonValue, path: &str) -> Result<(), String> {
+    let schema = match schema {
+        JsonValue::Object(schema) => schema,
+        JsonValue::Bool(true) => return Ok(()),
+        JsonValue::Bool(false) => return Err(format!("{} is not allowed", path)),
+        _ => return Err("invalid config-schema in plugin metadata".to_string()),
+    };
+
+    if let Some(types) = schema.get("type") {
+        let matches = |t: &JsonValue| t.as_str().map(|t| has_type(value, t)) == Some(true);
+        let valid = match types {
+            JsonValue::Array(types) => types.iter().any(matches),
+            t => matches(t),
+        };
+        if !valid {
+            return Err(format!("{} must be of type {}", path, types));
+        }
+    }
+    if let Some(JsonValue::Array(values)) = schema.get("enum") {
+        if !values.contains(value) {
+            return Err(format!("{} must be one of {}", path, schema["enum"]));
+        }
+    }
+    if let Some(n) = value.as_f64() {
+        if let Some(min) = schema.get("minimum").and_then(JsonValue::as_f64) {
+            if n < min {
+                return Err(format!("{} must be at least {}", path, min));
+            }
+        }
+        if let Some(max) = schema.get("maximum").and_then(JsonValue::as_f64) {
+            if n > max {
+                return Err(format!("{} must be at most {}", path, max));
+            }
+        }
+    }
+    if let Some(s) = value.as_str() {
+        let len = s.chars().count() as u64;
+        if let Some(min) = schema.get("minLength").and_then(JsonValue::as_u64) {
+            if len < min {
+                return Err(format!("{} must have at least {} characters", path, min));
+            }
+        }
+        if let Some(max) = schema.get("maxLength").and_then(JsonValue::as_u64) {
+            if len > max {
+                return Err(format!("{} must have at most {} characters", path, max));
+            }
+        }
+    }
+
+    match value {
+        JsonValue::Object(object) => {
+            if let Some(JsonValue::Array(required)) = schema.get("required") {
+                for key in required.iter().filter_map(JsonValue::as_str) {
+                    if !object.contains_key(key) {
+                        return Err(format!("{}.{} is required", path, key));
+                    }
+                }
+            }
+            let properties = schema.get("properties").and_then(JsonValue::as_object);
+            for (key, item) in object {
+                let item_path = format!("{}.{}", path, key);
+                match (
+                    properties.and_then(|p| p.get(key)),
+                    schema.get("additionalProperties"),
+                ) {
+                    (Some(item_schema), _) | (None, Some(item_schema)) => {
+                        validate(item_schema, item, &item_path)?
+                    }
+                    (None, None) => (),
+                }
+            }
+        }
+        JsonValue::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    validate(item_schema, item, &format!("{}[{}]", path, i))?;
+                }
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+fn has_type(value: &JsonValue, t: &str) -> bool {
+    match (t, value) {
+        ("null", JsonValue::Null)
+        | ("boolean", JsonValue::Bool(_))
+        | ("number", JsonValue::Number(_))
+        | ("string", JsonValue::String(_))
+        | ("array", JsonValue::Array(_))
+        | ("object", JsonValue::Object(_)) => true,
+        ("integer", JsonValue::Number(n)) => n.is_i64() || n.is_u64(),
+        _ => false,
+    }
+}
+
+fn check_config(metadata: &PluginMetadata, config: &JsonValue) -> Result<(), PluginDataError> {
+    match metadata.config_schema() {
+        Some(schema) => validate(schema, config, "$").map_err(PluginDataError::InvalidConfig),
+        None => Ok(()),
+    }
+}
+
+fn metadata(plugin: &str) -> impl Future<Item = PluginMetadata, Error = PluginDataError> {
+    PluginManager::from_registry()
+        .send(GetPluginMetadata {
+            plugin: plugin.to_string(),
+        })
+        .map_err(PluginDataError::from)
+        .and_then(|r| r.map_err(PluginDataError::PluginNotFound))
+}
+
+fn fetch(
+    storage: &Addr<FileStorage>,
+    key: Cow<'static, str>,
+) -> impl Future<Item = Option<JsonValue>, Error = PluginDataError> {
+    storage
+        .send(Fetch(key))
+        .flatten_fut()
+        .map_err(PluginDataError::from)
+        .and_then(|bytes| match bytes {
+            Some(bytes) => serde_json::from_slice(&bytes)
+                .map(Some)
+                .map_err(|e| PluginDataError::Storage(e.to_string())),
+            None => Ok(None),
+        })
+}
+
+fn put(
+    storage: &Addr<FileStorage>,
+    key: Cow<'static, str>,
+    value: &JsonValue,
+) -> impl Future<Item = (), Error = PluginDataError> {
+    let storage = storage.clone();
+    future::result(serde_json::to_vec(value))
+        .map_err(|e| PluginDataError::Storage(e.to_string()))
+        .and_then(|bytes| {
+            if bytes.len() > MAX_VALUE_SIZE {
+                return future::Either::A(future::err(PluginDataError::Storage(format!(
+                    "value exceeds {} bytes",
+                    MAX_VALUE_SIZE
+                ))));
+            }
+            future::Either::B(
+                storage
+                    .send(Put(key, bytes))
+                    .flatten_fut()
+                    .map_err(PluginDataError::from),
+            )
+        })
+}
+
+fn delete(
+    storage: &Addr<FileStorage>,
+    key: Cow<'static, str>,
+) -> impl Future<Item = (), Error = PluginDataError> {
+    storage
+        .send(Delete(key))
+        .flatten_fut()
+        .map_err(PluginDataError::from)
+}
+
+fn list(
+    storage: &Addr<FileStorage>,
+    plugin: &str,
+) -> impl Future<Item = Vec<String>, Error = PluginDataError> {
+    storage
+        .send(List(kv_prefix(plugin)))
+        .flatten_fut()
+        .map_err(PluginDataError::from)
+}
+
+#[derive(Default)]
+pub struct PluginData {
+    storage: Option<Addr<FileStorage>>,
+}
+
+impl PluginData {
+    fn storage(&mut self) -> Addr<FileStorage> {
+        if self.storage.is_none() {
+            let dir = ConfigModule::new().work_dir().join("plugin-data");
+            info!("Plugin data dir: {:?}", &dir);
+            self.storage = Some(SyncArbiter::start(1, move || FileStorage::from_path(&dir)));
+        }
+        self.storage.clone().unwrap()
+    }
+}
+
+impl Actor for PluginData {
+    type Context = Context<Self>;
+}
+
+impl Supervised for PluginData {}
+impl SystemService for PluginData {}
+
+type DataResponse<T> = ActorResponse<PluginData, T, PluginDataError>;
+type DataFuture<T> = Box<dyn Future<Item = T, Error = PluginDataError>>;
+
+/// GET CONFIG, `null` if not set
+pub struct GetPluginConfig {
+    pub plugin: String,
+}
+
+impl Message for GetPluginConfig {
+    type Result = Result<JsonValue, PluginDataError>;
+}
+
+impl Handler<GetPluginConfig> for PluginData {
+    type Result = DataResponse<JsonValue>;
+
+    fn handle(&mut self, msg: GetPluginConfig, _ctx: &mut Self::Context) -> Self::Result {
+        let storage = self.storage();
+
+        ActorResponse::r#async(
+            metadata(&msg.plugin)
+                .and_then(move |_| fetch(&storage, config_key(&msg.plugin)))
+                .map(|config| config.unwrap_or(JsonValue::Null))
+                .into_actor(self),
+        )
+    }
+}
+
+/// SET CONFIG
+pub struct SetPluginConfig {
+    pub plugin: String,
+    pub config: JsonValue,
+}
+
+impl Message for SetPluginConfig {
+    type Result = Result<(), PluginDataError>;
+}
+
+impl Handler<SetPluginConfig> for PluginData {
+    type Result = DataResponse<()>;
+
+    fn handle(&mut self, msg: SetPluginConfig, _ctx: &mut Self::Context) -> Self::Result {
+        let storage = self.storage();
+        let SetPluginConfig { plugin, config } = msg;
+
+        ActorResponse::r#async(
+            metadata(&plugin)
+                .and_then(move |metadata| {
+                    future::result(check_config(&metadata, &config))
+                        .and_then(move |()| put(&storage, config_key(&plugin), &config))
+                })
+                .into_actor(self),
+        )
+    }
+}
+
+/// LIST KEYS
+pub struct ListPluginKeys {
+    pub plugin: String,
+}
+
+impl Message for ListPluginKeys {
+    type Result = Result<Vec<String>, PluginDataError>;
+}
+
+impl Handler<ListPluginKeys> for PluginData {
+    type Result = DataResponse<Vec<String>>;
+
+    fn handle(&mut self, msg: ListPluginKeys, _ctx: &mut Self::Context) -> Self::Result {
+        let storage = self.storage();
+
+        ActorResponse::r#async(
+            metadata(&msg.plugin)
+                .and_then(move |_| list(&storage, &msg.plugin))
+                .into_actor(self),
+        )
+    }
+}
+
+/// GET VALUE
+pub struct GetPluginValue {
+    pub plugin: String,
+    pub key: String,
+}
+
+impl Message for GetPluginValue {
+    type Result = Result<JsonValue, PluginDataError>;
+}
+
+impl Handler<GetPluginValue> for PluginData {
+    type Result = DataResponse<JsonValue>;
+
+    fn handle(&mut self, msg: GetPluginValue, _ctx: &mut Self::Context) -> Self::Result {
+        let storage = self.storage();
+        let GetPluginValue { plugin, key } = msg;
+
+        ActorResponse::r#async(
+            future::result(check_key(&key))
+                .and_then(move |()| metadata(&plugin).map(|_| plugin))
+                .and_then(move |plugin| {
+                    fetch(&storage, kv_key(&plugin, &key))
+                        .and_then(move |value| value.ok_or(PluginDataError::KeyNotFound(key)))
+                })
+                .into_actor(self),
+        )
+    }
+}
+
+/// SET VALUE
+pub struct SetPluginValue {
+    pub plugin: String,
+    pub key: String,
+    pub value: JsonValue,
+}
+
+impl Message for SetPluginValue {
+    type Result = Result<(), PluginDataError>;
+}
+
+impl Handler<SetPluginValue> for PluginData {
+    type Result = DataResponse<()>;
+
+    fn handle(&mut self, msg: SetPluginValue, _ctx: &mut Self::Context) -> Self::Result {
+        let storage = self.storage();
+        let SetPluginValue { plugin, key, value } = msg;
+
+        ActorResponse::r#async(
+            future::result(check_key(&key))
+                .and_then(move |()| metadata(&plugin).map(|_| plugin))
+                .and_then(move |plugin| put(&storage, kv_key(&plugin, &key), &value))
+                .into_actor(self),
+        )
+    }
+}
+
+/// DELETE VALUE
+pub struct DeletePluginValue {
+    pub plugin: String,
+    pub key: String,
+}
+
+impl Message for DeletePluginValue {
+    type Result = Result<(), PluginDataError>;
+}
+
+impl Handler<DeletePluginValue> for PluginData {
+    type Result = DataResponse<()>;
+
+    fn handle(&mut self, msg: DeletePluginValue, _ctx: &mut Self::Context) -> Self::Result {
+        let storage = self.storage();
+        let DeletePluginValue { plugin, key } = msg;
+
+        ActorResponse::r#async(
+            future::result(check_key(&key))
+                .and_then(move |()| metadata(&plugin).map(|_| plugin))
+                .and_then(move |plugin| delete(&storage, kv_key(&plugin, &key)))
+                .into_actor(self),
+        )
+    }
+}
+
+/// EXPORT
+pub struct ExportPluginData {
+    pub plugin: String,
+}
+
+impl Message for ExportPluginData {
+    type Result = Result<PluginDataExport, PluginDataError>;
+}
+
+impl Handler<ExportPluginData> for PluginData {
+    type Result = DataResponse<PluginDataExport>;
+
+    fn handle(&mut self, msg: ExportPluginData, _ctx: &mut Self::Context) -> Self::Result {
+        let storage = self.storage();
+        let plugin = msg.plugin;
+
+        ActorResponse::r#async(
+            metadata(&plugin)
+                .join3(
+                    fetch(&storage, config_key(&plugin)),
+                    list(&storage, &plugin),
+                )
+                .and_then(move |(metadata, config, keys)| {
+                    let values = keys.into_iter().map(move |key| {
+                        fetch(&storage, kv_key(&plugin, &key)).map(move |value| (key, value))
+                    });
+                    future::join_all(values).map(move |values| PluginDataExport {
+                        name: metadata.name().to_string(),
+                        version: metadata.version().clone(),
+                        config: config.unwrap_or(JsonValue::Null),
+                        kv: values
+                            .into_iter()
+                            .filter_map(|(key, value)| Some((key, value?)))
+                            .collect(),
+                    })
+                })
+                .into_actor(self),
+        )
+    }
+}
+
+/// IMPORT, replaces the configuration and all values of the plugin
+pub struct ImportPluginData {
+    pub plugin: String,
+    pub data: PluginDataExport,
+}
+
+impl Message for ImportPluginData {
+    type Result = Result<(), PluginDataError>;
+}
+
+impl Handler<ImportPluginData> for PluginData {
+    type Result = DataResponse<()>;
+
+    fn handle(&mut self, msg: ImportPluginData, _ctx: &mut Self::Context) -> Self::Result {
+        let storage = self.storage();
+        let ImportPluginData { plugin, data } = msg;
+
+        if let Err(e) = data
+            .kv
+            .keys()
+            .map(|key| check_key(key))
+            .collect::<Result<(), _>>()
+        {
+            return ActorResponse::reply(Err(e));
+        }
+
+        ActorResponse::r#async(
+            metadata(&plugin)
+                .and_then(|metadata| {
+                    if data.config.is_null() {
+                        return Ok(data);
+                    }
+                    check_config(&metadata, &data.config).map(|()| data)
+                })
+                .and_then(move |data| {
+                    list(&storage, &plugin).and_then(move |keys| {
+                        let mut ops: Vec<DataFuture<()>> = keys
+                            .into_iter()
+                            .filter(|key| !data.kv.contains_key(key))
+                            .map(|key| -> DataFuture<()> {
+                                Box::new(delete(&storage, kv_key(&plugin, &key)))
+                            })
+                            .collect();
+                        for (key, value) in data.kv.iter() {
+                            ops.push(Box::new(put(&storage, kv_key(&plugin, key), value)));
+                        }
+                        ops.push(if data.config.is_null() {
+                            Box::new(delete(&storage, config_key(&plugin)))
+                        } else {
+                            Box::new(put(&storage, config_key(&plugin), &data.config))
+                        });
+                        future::join_all(ops).map(|_| ())
+                    })
+                })
+                .into_actor(self),
+        )
+    }
+}
+
+/// REMOVE ALL DATA of an uninstalled plugin
+pub struct RemovePluginData {
+    pub plugin: String,
+}
+
+impl Message for RemovePluginData {
+    type Result = ();
+}
+
+impl Handler<RemovePluginData> for PluginData {
+    type Result = ();
+
+    fn handle(&mut self, msg: RemovePluginData, ctx: &mut Self::Context) {
+        let storage = self.storage();
+        let plugin = msg.plugin;
+
+        list(&storage, &plugin)
+            .and_then(move |keys| {
+                let mut ops: Vec<_> = keys
+                    .into_iter()
+                    .map(|key| delete(&storage, kv_key(&plugin, &key)))
+                    .collect();
+                ops.push(delete(&storage, config_key(&plugin)));
+                future::join_all(ops)
+            })
+            .map(|_| ())
+            .map_err(|e| error!("Cannot remove plugin data: {}", e))
+            .into_actor(self)
+            .spawn(ctx);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate_types() {
+        assert!(validate(&json!({"type": "integer"}), &json!(3), "$").is_ok());
+        assert!(validate(&json!({"type": "integer"}), &json!(3.5), "$").is_err());
+        assert!(validate(&json!({"type": "number"}), &json!(3.5), "$").is_ok());
+        assert!(validate(&json!({"type": ["string", "null"]}), &json!(null), "$").is_ok());
+        assert_eq!(
+            validate(&json!({"type": "string"}), &json!(1), "$"),
+            Err("$ must be of type \"string\"".to_string())
+        );
+        assert!(validate(&json!(true), &json!(1), "$").is_ok());
+        assert!(validate(&json!(false), &json!(1), "$").is_err());
+        assert!(validate(&json!("string"), &json!(1), "$").is_err());
+    }
+
+    #[test]
+    fn test_validate_limits() {
+        let schema = json!({"minimum": 1, "maximum": 10, "minLength": 2, "maxLength": 3});
+        assert!(validate(&schema, &json!(1), "$").is_ok());
+        assert!(validate(&schema, &json!(0), "$").is_err());
+        assert!(validate(&schema, &json!(11), "$").is_err());
+        assert!(validate(&schema, &json!("ab"), "$").is_ok());
+        assert!(validate(&schema, &json!("a"), "$").is_err());
+        assert!(validate(&schema, &json!("abcd"), "$").is_err());
+
+        let schema = json!({"enum": ["low", "high"]});
+        assert!(validate(&schema, &json!("low"), "$").is_ok());
+        assert!(validate(&schema, &json!("mid"), "$").is_err());
+    }
+
+    #[test]
+    fn test_validate_objects() {
+        let schema = json!({
+            "type": "object",
+            "required": ["url"],
+            "properties": {
+                "url": {"type": "string"},
+                "ports": {"type": "array", "items": {"type": "integer"}}
+            },
+            "additionalProperties": false
+        });
+
+        assert!(validate(&schema, &json!({"url": "http://x", "ports": [1, 2]}), "$").is_ok());
+        assert_eq!(
+            validate(&schema, &json!({}), "$"),
+            Err("$.url is required".to_string())
+        );
+        assert_eq!(
+            validate(&schema, &json!({"url": "x", "ports": [1, "2"]}), "$"),
+            Err("$.ports[1] must be of type \"integer\"".to_string())
+        );
+        assert_eq!(
+            validate(&schema, &json!({"url": "x", "debug": true}), "$"),
+            Err("$.debug is not allowed".to_string())
+        );
+
+        let schema = json!({"additionalProperties": {"type": "boolean"}});
+        assert!(validate(&schema, &json!({"a": true}), "$").is_ok());
+        assert!(validate(&schema, &json!({"a": 1}), "$").is_err());
+    }
+
+    #[test]
+    fn test_check_key() {
+        assert!(check_key("state.json").is_ok());
+        for key in &["", ".hidden", "a/b", "a b"] {
+            assert!(check_key(key).is_err(), "{}", key);
+        }
+    }
+}
@@ -0,0 +1,404 @@
+//! Self-hosted plugin indexes.
+//!
+//! An index is a JSON catalogue of plugin packages that can be served by any HTTP
+//! server, e.g. another hub's `/repo`, or read from a `file://` url. Package urls
+//! may be relative to the index url; `file://` packages are only accepted from
+//! `file://` indexes.
+
+use std::{fs, time::Duration};
+
+use actix_web::{client, HttpMessage};
+use bytes::Bytes;
+use futures::{future, prelude::*};
+use log::warn;
+use prettytable::{cell, row};
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+
+use gu_base::cli;
+use gu_model::hash::{self, ContentChecker};
+use gu_persist::config::HasSectionId;
+
+/// Maximal size of a downloaded index or package
+const MAX_DOWNLOAD_SIZE: usize = 256 * 1024 * 1024;
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexesConfig {
+    /// urls of plugin indexes, searched in order
+    #[serde(default)]
+    pub indexes: Vec<String>,
+}
+
+impl HasSectionId for IndexesConfig {
+    const SECTION_ID: &'static str = "plugin-indexes";
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginIndex {
+    #[serde(default)]
+    pub plugins: Vec<IndexedPlugin>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexedPlugin {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub versions: Vec<IndexedVersion>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexedVersion {
+    pub version: Version,
+    /// package url, absolute or relative to the index
+    pub url: String,
+    /// package hash, e.g. `SHA1:<hex>`
+    pub hash: String,
+    /// hub versions supported by the package
+    #[serde(default = "VersionReq::any")]
+    pub gu_version_req: VersionReq,
+    /// address of the package signer; if set, the package must carry its signature
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publisher: Option<String>,
+}
+
+/// Plugin version found in an index
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexEntry {
+    pub index: String,
+    pub name: String,
+    pub description: String,
+    #[serde(flatten)]
+    pub version: IndexedVersion,
+    /// whether the package supports the running hub version
+    pub compatible: bool,
+}
+
+/// Plugin to install from indexes; the newest compatible version if `version` is not set
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexInstall {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<Version>,
+}
+
+impl IndexInstall {
+    /// Parses `name` or `name@version`
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut parts = spec.splitn(2, '@');
+        let name = parts.next().unwrap_or_default().to_string();
+        if name.is_empty() {
+            return Err(format!("invalid plugin name: {}", spec));
+        }
+        let version = match parts.next() {
+            Some(v) => {
+                Some(Version::parse(v).map_err(|e| format!("invalid version {}: {}", v, e))?)
+            }
+            None => None,
+        };
+        Ok(IndexInstall { name, version })
+    }
+}
+
+pub fn gu_version() -> Version {
+    Version::parse(env!("CARGO_PKG_VERSION")).expect("Couldn't parse crate version")
+}
+
+fn is_file_url(url: &str) -> bool {
+    url.starts_with("file://")
+}
+
+/// Resolves package url relative to the index url. Remote indexes can not point
+/// at local files.
+pub fn resolve_url(base: &str, url: &str) -> Result<String, String> {
+    let resolved = resolve_relative(base, url);
+    if is_file_url(&resolved) && !is_file_url(base) {
+        return Err(format!("Local package {} in remote index {}", url, base));
+    }
+    Ok(resolved)
+}
+
+fn resolve_relative(base: &str, url: &str) -> String {
+    if url.contains("://") {
+        return url.to_string();
+    }
+
+    let (scheme, rest) = match base.find("://") {
+        Some(pos) => base.split_at(pos + 3),
+        None => ("", base),
+    };
+    if url.starts_with('/') {
+        let host = rest.split('/').next().unwrap_or_default();
+        format!("{}{}{}", scheme, host, url)
+    } else {
+        match rest.rfind('/') {
+            Some(pos) => format!("{}{}/{}", scheme, &rest[..pos], url),
+            None => format!("{}{}/{}", scheme, rest, url),
+        }
+    }
+}
+
+/// Downloads the resource from http(s) or reads it from a `file://` url
+pub fn fetch(url: &str) -> Box<dyn Future<Item = Bytes, Error = String>> {
+    if is_file_url(url) {
+        let path = url.trim_start_matches("file://");
+        return Box::new(future::result(
+            fs::read(path)
+                .map(Bytes::from)
+                .map_err(|e| format!("Cannot read {}: {}", url, e)),
+        ));
+    }
+
+    let url = url.to_string();
+    Box::new(
+        future::result(client::ClientRequest::get(&url).finish())
+            .map_err(|e| format!("Invalid url: {}", e))
+            .and_then(|request| {
+                request
+                    .send()
+                    .timeout(DOWNLOAD_TIMEOUT)
+                    .map_err(|e| format!("Request failed: {}", e))
+            })
+            .and_then(move |response| {
+                if !response.status().is_success() {
+                    return future::Either::A(future::err(format!(
+                        "Cannot download {}: {}",
+                        url,
+                        response.status()
+                    )));
+                }
+                future::Either::B(
+                    response
+                        .body()
+                        .limit(MAX_DOWNLOAD_SIZE)
+                        .map_err(|e| format!("Download error: {}", e)),
+                )
+            }),
+    )
+}
+
+/// Fetches all indexes, unavailable ones are skipped
+pub fn load_indexes(
+    urls: Vec<String>,
+) -> impl Future<Item = Vec<(String, PluginIndex)>, Error = String> {
+    future::join_all(urls.into_iter().map(|url| {
+        fetch(&url).then(move |result| {
+            Ok(result
+                .and_then(|bytes| {
+                    serde_json::from_slice::<PluginIndex>(&bytes)
+                        .map_err(|e| format!("Invalid index: {}", e))
+                })
+                .map_err(|e| warn!("Skipping plugin index {}: {}", url, e))
+                .ok()
+                .map(|index| (url, index)))
+        })
+    }))
+    .map(|indexes| indexes.into_iter().filter_map(|index| index).collect())
+}
+
+/// Lists plugin versions with names containing `query`, newest first
+pub fn search(indexes: &[(String, PluginIndex)], query: &str) -> Vec<IndexEntry> {
+    let hub_version = gu_version();
+    let hub_version = &hub_version;
+    let mut entries: Vec<IndexEntry> = indexes
+        .iter()
+        .flat_map(|(url, index)| index.plugins.iter().map(move |plugin| (url, plugin)))
+        .filter(|(_, plugin)| plugin.name.contains(query))
+        .flat_map(|(url, plugin)| {
+            plugin.versions.iter().filter_map(move |version| {
+                let package_url = resolve_url(url, &version.url)
+                    .map_err(|e| warn!("Skipping {}@{}: {}", plugin.name, version.version, e))
+                    .ok()?;
+                Some(IndexEntry {
+                    index: url.clone(),
+                    name: plugin.name.clone(),
+                    description: plugin.description.clone(),
+                    compatible: version.gu_version_req.matches(hub_version),
+                    version: IndexedVersion {
+                        url: package_url,
+                        ..version.clone()
+                    },
+                })
+            })
+        })
+        .collect();
+
+    entries.sort_by(|a, b| {
+        a.name
+            .cmp(&b.name)
+            .then_with(|| b.version.version.cmp(&a.version.version))
+    });
+    entries
+}
+
+/// Selects the package to install: the requested version or the newest compatible one
+pub fn select(
+    indexes: &[(String, PluginIndex)],
+    request: &IndexInstall,
+) -> Result<IndexEntry, String> {
+    let candidates: Vec<IndexEntry> = search(indexes, &request.name)
+        .into_iter()
+        .filter(|entry| entry.name == request.name)
+        .filter(|entry| {
+            request
+                .version
+                .as_ref()
+                .map(|v| v == &entry.version.version)
+                .unwrap_or(true)
+        })
+        .collect();
+
+    if candidates.is_empty() {
+        return Err(match request.version {
+            Some(ref v) => format!("Plugin {}@{} not found in indexes", request.name, v),
+            None => format!("Plugin {} not found in indexes", request.name),
+        });
+    }
+
+    candidates
+        .iter()
+        .find(|entry| entry.compatible)
+        .cloned()
+        .ok_or_else(|| {
+            format!(
+                "No version of plugin {} supports hub {} (requires {})",
+                request.name,
+                gu_version(),
+                candidates[0].version.gu_version_req
+            )
+        })
+}
+
+pub fn verify_hash(bytes: &[u8], hash_str: &str) -> Result<(), String> {
+    let mut checker =
+        hash::checker(hash_str).map_err(|e| format!("Invalid hash {}: {}", hash_str, e))?;
+    checker.update(bytes);
+    if checker.verify() {
+        Ok(())
+    } else {
+        Err(format!("Package does not match hash {}", hash_str))
+    }
+}
+
+pub fn format_entries_table(entries: Vec<IndexEntry>) {
+    cli::format_table(
+        row!["Name", "Version", "Hub version", "Compatible", "Index"],
+        || "No plugins found",
+        entries.iter().map(|entry| {
+            row![
+                entry.name,
+                entry.version.version.to_string(),
+                entry.version.gu_version_req.to_string(),
+                if entry.compatible { "yes" } else { "no" },
+                entry.index,
+            ]
+        }),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn version(version: &str, req: &str) -> IndexedVersion {
+        IndexedVersion {
+            version: Version::parse(version).unwrap(),
+            url: format!("pkg-{}.gu-plugin", version),
+            hash: "SHA1:00".to_string(),
+            gu_version_req: VersionReq::parse(req).unwrap(),
+            publisher: None,
+        }
+    }
+
+    fn indexes() -> Vec<(String, PluginIndex)> {
+        vec![(
+            "http://example.com/plugins/index.json".to_string(),
+            PluginIndex {
+                plugins: vec![IndexedPlugin {
+                    name: "gu-render".to_string(),
+                    description: String::new(),
+                    versions: vec![
+                        version("0.1.0", "*"),
+                        version("0.2.0", "*"),
+                        version("9.0.0", ">=1000.0.0"),
+                    ],
+                }],
+            },
+        )]
+    }
+
+    #[test]
+    fn test_resolve_url() {
+        let base = "http://example.com/plugins/index.json";
+        assert_eq!(
+            resolve_url(base, "a.gu-plugin").unwrap(),
+            "http://example.com/plugins/a.gu-plugin"
+        );
+        assert_eq!(
+            resolve_url(base, "/repo/a").unwrap(),
+            "http://example.com/repo/a"
+        );
+        assert_eq!(
+            resolve_url(base, "https://other.org/a").unwrap(),
+            "https://other.org/a"
+        );
+        assert!(resolve_url(base, "file:///etc/passwd").is_err());
+
+        let base = "file:///srv/index.json";
+        assert_eq!(
+            resolve_url(base, "a.gu-plugin").unwrap(),
+            "file:///srv/a.gu-plugin"
+        );
+        assert_eq!(resolve_url(base, "file:///opt/a").unwrap(), "file:///opt/a");
+    }
+
+    #[test]
+    fn test_parse_install() {
+        let install = IndexInstall::parse("gu-render").unwrap();
+        assert_eq!(install.name, "gu-render");
+        assert!(install.version.is_none());
+
+        let install = IndexInstall::parse("gu-render@0.2.1").unwrap();
+        assert_eq!(install.version, Some(Version::parse("0.2.1").unwrap()));
+
+        assert!(IndexInstall::parse("").is_err());
+        assert!(IndexInstall::parse("@0.2.1").is_err());
+        assert!(IndexInstall::parse("gu-render@latest").is_err());
+    }
+
+    #[test]
+    fn test_select() {
+        let indexes = indexes();
+
+        let entry = select(&indexes, &IndexInstall::parse("gu-render").unwrap()).unwrap();
+        assert_eq!(entry.version.version, Version::parse("0.2.0").unwrap());
+        assert_eq!(
+            entry.version.url,
+            "http://example.com/plugins/pkg-0.2.0.gu-plugin"
+        );
+
+        let entry = select(&indexes, &IndexInstall::parse("gu-render@0.1.0").unwrap()).unwrap();
+        assert_eq!(entry.version.version, Version::parse("0.1.0").unwrap());
+
+        assert!(select(&indexes, &IndexInstall::parse("gu-render@9.0.0").unwrap()).is_err());
+        assert!(select(&indexes, &IndexInstall::parse("gu-render@0.3.0").unwrap()).is_err());
+        assert!(select(&indexes, &IndexInstall::parse("gu-rend").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_search_skips_local_packages_of_remote_index() {
+        let mut indexes = indexes();
+        indexes[0].1.plugins[0].versions[1].url = "file:///etc/passwd".to_string();
+
+        let entries = search(&indexes, "gu-render");
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|entry| !is_file_url(&entry.version.url)));
+    }
+}
@@ -1,25 +1,40 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap},
     fmt,
-    fs::{self, remove_file, DirBuilder},
+    fs::{self, remove_file, DirBuilder, File},
     io::{BufReader, Cursor},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 
-use actix::{Actor, Context, Handler, Message, MessageResult, Supervised, SystemService};
+use actix::{
+    fut, Actor, ActorFuture, AsyncContext, Context, ContextFutureSpawner, Handler, Message,
+    MessageResult, Supervised, SystemService, WrapFuture,
+};
 use bytes::Bytes;
+use futures::Future;
 use log::{error, info, warn};
 use semver::Version;
 
+use gu_actix::prelude::*;
 use gu_event_bus::post_event;
-use gu_persist::config::ConfigModule;
+use gu_model::events::HubEvent;
+use gu_persist::config::{ConfigManager, ConfigModule, GetConfig, SetConfig};
+
+use crate::events;
 
 use super::{
-    parser::{BytesPluginParser, PluginParser, ZipParser},
+    data::{PluginData, RemovePluginData},
+    index::IndexEntry,
+    parser::{BytesPluginParser, PathPluginParser, PluginParser, ZipParser},
     plugin::{
-        DirectoryHandler, Plugin, PluginEvent, PluginHandler, PluginInfo, PluginStatus, ZipHandler,
+        DirectoryHandler, Plugin, PluginEvent, PluginHandler, PluginInfo, PluginMetadata,
+        PluginStatus, ZipHandler,
     },
-    rest_result::InstallQueryResult,
+    provider::{ProviderPackage, ProviderPlugins},
+    rest_result::{InstallQueryResult, ToHttpResponse},
+    signature::{self, PublishersConfig},
+    versions::PluginVersions,
 };
 
 #[derive(Debug)]
@@ -30,6 +45,8 @@ pub struct PluginManager {
     plugins: HashMap<String, Plugin>,
     /// directory containing plugin files
     directory: Option<PathBuf>,
+    /// trusted publishers of plugin packages
+    publishers: PublishersConfig,
 }
 
 impl Default for PluginManager {
@@ -41,6 +58,7 @@ impl Default for PluginManager {
             gu_version,
             plugins: HashMap::new(),
             directory: None,
+            publishers: PublishersConfig::default(),
         }
     }
 }
@@ -57,18 +75,34 @@ impl PluginManager {
         }
     }
 
-    fn install_plugin<T: 'static + PluginHandler>(&mut self, handler: T) -> InstallQueryResult {
+    /// Installs plugin, signed plugins are activated only if their publisher is trusted
+    fn install_plugin<T: 'static + PluginHandler>(
+        &mut self,
+        handler: T,
+        verify: bool,
+    ) -> InstallQueryResult {
         use super::rest_result::InstallQueryResult::*;
 
         let mut plugin = Plugin::new(handler);
-        plugin.activate();
+        let signature = plugin.signature(&self.publishers);
+        if !verify || self.publishers.allows(&signature) {
+            plugin.activate();
+        } else {
+            warn!(
+                "Plugin {:?} is {}, not activating",
+                plugin.metadata().map(|m| m.name().to_string()),
+                signature
+            );
+        }
 
         plugin
             .metadata()
             .map_err(|a| InvalidMetadata(a))
             .map(|meta| {
-                let event_path = format!("/plugins/{}", meta.name());
-                post_event(event_path, PluginEvent::New(meta.clone()));
+                post_state_event(meta.name(), &plugin);
+                events::post(HubEvent::PluginInstalled {
+                    name: meta.name().to_string(),
+                });
                 match self.plugins.insert(meta.name().to_string(), plugin) {
                     None => Installed,
                     Some(_a) => Overwritten,
@@ -77,33 +111,59 @@ impl PluginManager {
             .unwrap_or_else(|e| e)
     }
 
-    fn uninstall_plugin(&mut self, name: &String) {
-        let prev = self.plugins.remove(name);
-        if prev.is_some() {
-            let event_path = format!("/plugins/{}", name);
-            post_event(event_path, PluginEvent::Drop(name.clone()));
-        }
+    fn uninstall_plugin(&mut self, name: &String) -> Result<(), String> {
+        let dir = self.plugin_dir(name)?;
+        self.plugins
+            .remove(name)
+            .ok_or(format!("Cannot find {} plugin", name))?;
+
+        let event_path = format!("/plugins/{}", name);
+        post_event(event_path, PluginEvent::Drop(name.clone()));
+        events::post(HubEvent::PluginRemoved { name: name.clone() });
+        PluginData::from_registry().do_send(RemovePluginData {
+            plugin: name.clone(),
+        });
 
         // TODO: I would prefer some clear function in Plugin trait instead of this
-        let file = self.directory().join(name);
-        let _ = remove_file(file).map_err(|_| format!("Cannot remove plugin file {:?}", name));
+        let _ =
+            fs::remove_dir_all(dir).map_err(|_| format!("Cannot remove plugin files {:?}", name));
+        Ok(())
     }
 
-    fn load_zip(&mut self, name: &str) -> InstallQueryResult {
-        let path = self.directory().join(name.to_string());
+    /// Directory with all installed versions of the plugin
+    fn plugin_dir(&mut self, name: &str) -> Result<PathBuf, String> {
+        check_plugin_name(name)?;
+        Ok(self.directory().join(name))
+    }
+
+    fn load_version(&mut self, name: &str, version: &Version) -> InstallQueryResult {
+        let dir = match self.plugin_dir(name) {
+            Ok(dir) => dir,
+            Err(e) => return InstallQueryResult::InvalidMetadata(e),
+        };
+        let path = PluginVersions::package_path(&dir, version);
         ZipHandler::new(&path, self.gu_version.clone())
             .map_err(|e| InstallQueryResult::InvalidFile(e))
-            .map(|handler| self.install_plugin(handler))
+            .map(|handler| self.install_plugin(handler, true))
             .unwrap_or_else(|e| e)
     }
 
-    fn save_plugin_file(&mut self, name: &str, bytes: &[u8]) -> Result<(), InstallQueryResult> {
+    fn save_plugin_file(
+        &mut self,
+        name: &str,
+        version: &Version,
+        bytes: &[u8],
+    ) -> Result<(), InstallQueryResult> {
         use self::InstallQueryResult::*;
-        use std::path::Path;
 
-        let path = self.directory().join(name.to_string());
-        println!("{:?}", &path);
-        if Path::new(&path).exists() {
+        let dir = self.plugin_dir(name).map_err(InvalidMetadata)?;
+        DirBuilder::new()
+            .recursive(true)
+            .create(&dir)
+            .map_err(|e| InvalidFile(e.to_string()))?;
+
+        let path = PluginVersions::package_path(&dir, version);
+        if path.exists() {
             return Err(FileAlreadyExists);
         }
 
@@ -112,26 +172,85 @@ impl PluginManager {
             .map_err(|e| InvalidFile(e.to_string()))
     }
 
+    /// Saves a new plugin version and selects it; previous versions are kept for rollback.
+    /// The package is dropped if it cannot be loaded.
+    fn upgrade_plugin(&mut self, name: &str, version: Version, bytes: &[u8]) -> InstallQueryResult {
+        let dir = match self.plugin_dir(name) {
+            Ok(dir) => dir,
+            Err(e) => return InstallQueryResult::InvalidMetadata(e),
+        };
+        let mut versions = PluginVersions::load(&dir);
+
+        if let Err(e) = self.save_plugin_file(name, &version, bytes) {
+            return e;
+        }
+
+        let result = self.load_version(name, &version);
+        if result.status_code().is_success() {
+            versions.select(version);
+            let _ = versions.save(&dir).map_err(|e| error!("{}", e));
+        } else {
+            let _ = remove_file(PluginVersions::package_path(&dir, &version));
+        }
+        result
+    }
+
+    /// Moves a package saved by older hub versions into its plugin directory
+    fn migrate_package(&mut self, path: &Path) -> Result<PathBuf, String> {
+        let metadata = ZipParser::<File>::from_path(path)?.load_metadata()?;
+        let dir = self.plugin_dir(metadata.name())?;
+        let tmp_path = path.with_extension("migrating");
+        fs::rename(path, &tmp_path).map_err(|e| e.to_string())?;
+
+        DirBuilder::new()
+            .recursive(true)
+            .create(&dir)
+            .map_err(|e| e.to_string())?;
+        fs::rename(
+            &tmp_path,
+            PluginVersions::package_path(&dir, metadata.version()),
+        )
+        .map_err(|e| e.to_string())?;
+
+        Ok(dir)
+    }
+
     /// Startup-only function for plugins loading
     fn reload_plugins(&mut self) {
         self.plugins.clear();
 
         let dir_res = fs::read_dir(&self.directory());
-        match dir_res {
-            Ok(dir) => {
-                for plug_pack in dir {
-                    let res = plug_pack
-                        .map_err(|e| e.to_string())
-                        .map(|pack| {
-                            self.load_zip(pack.path().to_str().expect("Invalid path of plugin"))
-                        })
-                        .map(|a| format!("{:?}", a))
-                        .unwrap_or_else(|e| e);
-
-                    warn!("{:?}", res);
-                }
+        let entries: Vec<PathBuf> = match dir_res {
+            Ok(dir) => dir
+                .filter_map(|entry| entry.ok())
+                .map(|e| e.path())
+                .collect(),
+            Err(_) => {
+                error!("Cannot open {:?}.", &self.directory());
+                return;
+            }
+        };
+
+        let mut plugin_dirs = BTreeSet::new();
+        for path in entries {
+            if path.is_dir() {
+                plugin_dirs.insert(path);
+            } else {
+                let _ = self
+                    .migrate_package(&path)
+                    .map(|dir| plugin_dirs.insert(dir))
+                    .map_err(|e| warn!("Cannot migrate plugin file {:?}: {}", path, e));
             }
-            Err(_) => error!("Cannot open {:?}.", &self.directory()),
+        }
+
+        for dir in plugin_dirs {
+            let name = dir.file_name().and_then(|n| n.to_str()).map(String::from);
+            let res = match (name, PluginVersions::load(&dir).current()) {
+                (Some(name), Some(version)) => format!("{:?}", self.load_version(&name, version)),
+                _ => format!("No plugin versions in {:?}", dir),
+            };
+
+            warn!("{:?}", res);
         }
     }
 
@@ -146,6 +265,41 @@ impl PluginManager {
             .get_mut(name)
             .ok_or(format!("Cannot find {} plugin", name))
     }
+
+    /// Inactivates plugins which are no longer allowed by the publishers config
+    fn apply_publishers(&mut self) {
+        let publishers = &self.publishers;
+        for (name, plugin) in self.plugins.iter_mut() {
+            if plugin.status() == PluginStatus::Active
+                && !publishers.allows(&plugin.signature(publishers))
+            {
+                warn!("Inactivating plugin {} from untrusted publisher", name);
+                plugin.inactivate();
+                post_state_event(name, plugin);
+            }
+        }
+    }
+}
+
+/// Plugin names are used as directory names below the plugins directory
+fn check_plugin_name(name: &str) -> Result<(), String> {
+    if name.is_empty()
+        || name == "."
+        || name == ".."
+        || name.contains(|c| c == '/' || c == '\\' || c == '\0')
+    {
+        return Err(format!("Invalid plugin name {:?}", name));
+    }
+    Ok(())
+}
+
+/// Notifies plugin services that the plugin was activated or inactivated
+fn post_state_event(name: &str, plugin: &Plugin) {
+    let event = match (plugin.status(), plugin.metadata()) {
+        (PluginStatus::Active, Ok(meta)) => PluginEvent::New(meta),
+        _ => PluginEvent::Drop(name.to_string()),
+    };
+    post_event(format!("/plugins/{}", name), event);
 }
 
 impl Supervised for PluginManager {}
@@ -154,13 +308,27 @@ impl SystemService for PluginManager {}
 impl Actor for PluginManager {
     type Context = Context<Self>;
 
-    fn started(&mut self, _ctx: &mut Self::Context) {
+    fn started(&mut self, ctx: &mut Self::Context) {
         match DirBuilder::new().recursive(true).create(&self.directory()) {
             Ok(_) => (),
             Err(e) => error!("Cannot create plugin dir ({})", e),
         }
-
-        self.reload_plugins();
+        let _ = ProviderPlugins::from_registry();
+
+        ctx.wait(
+            ConfigManager::from_registry()
+                .send(GetConfig::new())
+                .flatten_fut()
+                .into_actor(self)
+                .then(|config: Result<Arc<PublishersConfig>, _>, act, _ctx| {
+                    match config {
+                        Ok(config) => act.publishers = config.as_ref().clone(),
+                        Err(e) => error!("Cannot load trusted publishers: {}", e),
+                    }
+                    act.reload_plugins();
+                    fut::ok(())
+                }),
+        );
     }
 }
 
@@ -179,10 +347,14 @@ impl Handler<ListPlugins> for PluginManager {
         _msg: ListPlugins,
         _ctx: &mut Context<Self>,
     ) -> <Self as Handler<ListPlugins>>::Result {
+        let directory = self.directory().clone();
         let mut vec = Vec::new();
-        for plugin in self.plugins.values() {
+        for (name, plugin) in self.plugins.iter() {
             let _ = plugin
-                .info()
+                .info(
+                    &self.publishers,
+                    PluginVersions::installed(&directory.join(name)),
+                )
                 .map(|info| vec.push(info))
                 .map_err(|e| warn!("Cannot get info: {}", e));
         }
@@ -216,16 +388,113 @@ impl Handler<PluginFile> for PluginManager {
     }
 }
 
+/// Reads all files below a directory of an active plugin.
+#[derive(Debug)]
+pub struct PluginFiles {
+    pub plugin: String,
+    pub dir: PathBuf,
+}
+
+impl Message for PluginFiles {
+    type Result = Result<Vec<(PathBuf, Vec<u8>)>, String>;
+}
+
+impl Handler<PluginFiles> for PluginManager {
+    type Result = MessageResult<PluginFiles>;
+
+    fn handle(&mut self, msg: PluginFiles, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(
+            self.plugin(&msg.plugin)
+                .and_then(|plug| plug.files(&msg.dir)),
+        )
+    }
+}
+
+/// GET PLUGIN METADATA
+#[derive(Debug)]
+pub struct GetPluginMetadata {
+    pub plugin: String,
+}
+
+impl Message for GetPluginMetadata {
+    type Result = Result<PluginMetadata, String>;
+}
+
+impl Handler<GetPluginMetadata> for PluginManager {
+    type Result = Result<PluginMetadata, String>;
+
+    fn handle(&mut self, msg: GetPluginMetadata, _ctx: &mut Context<Self>) -> Self::Result {
+        self.plugin(&msg.plugin).and_then(|plug| plug.metadata())
+    }
+}
+
+/// GET PROVIDER PACKAGE
+#[derive(Debug)]
+pub struct GetProviderPackage {
+    pub plugin: String,
+}
+
+impl Message for GetProviderPackage {
+    type Result = Option<ProviderPackage>;
+}
+
+impl Handler<GetProviderPackage> for PluginManager {
+    type Result = MessageResult<GetProviderPackage>;
+
+    fn handle(&mut self, msg: GetProviderPackage, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(
+            self.plugin(&msg.plugin)
+                .ok()
+                .and_then(|plug| plug.provider_package()),
+        )
+    }
+}
+
 /// INSTALL PLUGIN
 #[derive(Debug)]
 pub struct InstallPlugin {
     pub bytes: Cursor<Bytes>,
+    /// plugin index entry the package was downloaded for
+    pub entry: Option<IndexEntry>,
 }
 
 impl Message for InstallPlugin {
     type Result = InstallQueryResult;
 }
 
+/// Checks that the package is the one listed in the index
+fn check_entry(
+    entry: &IndexEntry,
+    metadata: &PluginMetadata,
+    publisher: &Option<String>,
+) -> Result<(), InstallQueryResult> {
+    use self::InstallQueryResult::*;
+
+    if metadata.name() != entry.name || metadata.version() != &entry.version.version {
+        return Err(InvalidMetadata(format!(
+            "package contains {}@{}, index lists {}@{}",
+            metadata.name(),
+            metadata.version(),
+            entry.name,
+            entry.version.version
+        )));
+    }
+    match entry.version.publisher {
+        Some(ref expected) => {
+            let expected = signature::normalize_publisher(expected).map_err(InvalidSignature)?;
+            if publisher.as_ref() == Some(&expected) {
+                Ok(())
+            } else {
+                Err(InvalidSignature(format!(
+                    "package is not signed by {}",
+                    expected
+                )))
+            }
+        }
+        None => Ok(()),
+    }
+}
+
 impl Handler<InstallPlugin> for PluginManager {
     type Result = MessageResult<InstallPlugin>;
 
@@ -236,18 +505,31 @@ impl Handler<InstallPlugin> for PluginManager {
     ) -> <Self as Handler<InstallPlugin>>::Result {
         use self::InstallQueryResult::*;
 
+        let InstallPlugin { bytes, entry } = msg;
         MessageResult(
-            ZipParser::<BufReader<Cursor<Bytes>>>::from_bytes(msg.bytes.clone())
+            ZipParser::<BufReader<Cursor<Bytes>>>::from_bytes(bytes.clone())
                 .map_err(|a| InvalidFile(a))
                 .and_then(|mut parser| {
-                    parser
+                    let metadata = parser
                         .validate_and_load_metadata(self.gu_version.clone())
-                        .map_err(|e| InvalidMetadata(e))
+                        .map_err(|e| InvalidMetadata(e))?;
+                    let publisher = parser.publisher().map_err(|e| InvalidSignature(e))?;
+                    if let Some(ref entry) = entry {
+                        check_entry(entry, &metadata, &publisher)?;
+                    }
+                    let signature = self.publishers.status(&Ok(publisher));
+                    if self.publishers.allows(&signature) {
+                        Ok(metadata)
+                    } else {
+                        Err(UntrustedPublisher(signature.to_string()))
+                    }
                 })
-                .and_then(|metadata| {
-                    let name = metadata.name();
-                    self.save_plugin_file(name, msg.bytes.into_inner().as_ref())
-                        .map(|_| self.load_zip(&name.to_string()))
+                .map(|metadata| {
+                    self.upgrade_plugin(
+                        metadata.name(),
+                        metadata.version().clone(),
+                        bytes.into_inner().as_ref(),
+                    )
                 })
                 .unwrap_or_else(|a| a),
         )
@@ -290,18 +572,62 @@ impl Handler<ChangePluginState> for PluginManager {
         let previous: Option<PluginStatus> =
             self.plugin(&msg.plugin).map(|plug| plug.status()).ok();
 
-        let _ = match msg.state.clone() {
-            QueriedStatus::Uninstall => Ok(self.uninstall_plugin(&msg.plugin)),
+        let publishers = self.publishers.clone();
+        match msg.state.clone() {
+            QueriedStatus::Uninstall => self.uninstall_plugin(&msg.plugin),
 
             _ => self
                 .plugin_mut(&msg.plugin)
-                .map(|plug| match msg.state.clone() {
-                    QueriedStatus::Activate => plug.activate(),
-                    QueriedStatus::Inactivate => plug.inactivate(),
+                .and_then(|plug| match msg.state.clone() {
+                    QueriedStatus::Activate => {
+                        let signature = plug.signature(&publishers);
+                        if publishers.allows(&signature) {
+                            plug.activate();
+                            Ok(post_state_event(&msg.plugin, plug))
+                        } else {
+                            Err(format!("Cannot activate {} plugin", signature))
+                        }
+                    }
+                    QueriedStatus::Inactivate => {
+                        plug.inactivate();
+                        Ok(post_state_event(&msg.plugin, plug))
+                    }
                     _ => unreachable!(),
                 }),
-        };
+        }?;
+
+        Ok(previous)
+    }
+}
+
+/// ROLLBACK
+#[derive(Debug)]
+pub struct RollbackPlugin {
+    pub plugin: String,
+}
 
+impl Message for RollbackPlugin {
+    type Result = Result<Version, String>;
+}
+
+impl Handler<RollbackPlugin> for PluginManager {
+    type Result = Result<Version, String>;
+
+    fn handle(&mut self, msg: RollbackPlugin, _ctx: &mut Context<Self>) -> Self::Result {
+        let dir = self.plugin_dir(&msg.plugin)?;
+        let mut versions = PluginVersions::load(&dir);
+        let previous = versions
+            .previous()
+            .cloned()
+            .ok_or(format!("No previous version of {} plugin", msg.plugin))?;
+
+        let result = self.load_version(&msg.plugin, &previous);
+        if !result.status_code().is_success() {
+            return Err(result.message());
+        }
+
+        versions.rollback();
+        versions.save(&dir)?;
         Ok(previous)
     }
 }
@@ -328,9 +654,71 @@ impl Handler<InstallDevPlugin> for PluginManager {
 
         let res = DirectoryHandler::new(msg.path)
             .map_err(|_| InvalidPath)
-            .map(|handler| self.install_plugin(handler))
+            .map(|handler| self.install_plugin(handler, false))
             .unwrap_or_else(|e| e);
 
         MessageResult(res)
     }
 }
+
+/// TRUSTED PUBLISHERS
+pub struct ListPublishers;
+
+impl Message for ListPublishers {
+    type Result = Vec<String>;
+}
+
+impl Handler<ListPublishers> for PluginManager {
+    type Result = MessageResult<ListPublishers>;
+
+    fn handle(&mut self, _msg: ListPublishers, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.publishers.trusted_publishers.iter().cloned().collect())
+    }
+}
+
+#[derive(Debug)]
+pub struct TrustPublisher {
+    pub address: String,
+    pub trusted: bool,
+}
+
+impl Message for TrustPublisher {
+    type Result = Result<Vec<String>, String>;
+}
+
+impl Handler<TrustPublisher> for PluginManager {
+    type Result = Result<Vec<String>, String>;
+
+    fn handle(&mut self, msg: TrustPublisher, ctx: &mut Context<Self>) -> Self::Result {
+        let address = signature::normalize_publisher(&msg.address)?;
+        if msg.trusted {
+            self.publishers.trusted_publishers.insert(address);
+        } else {
+            self.publishers.trusted_publishers.remove(&address);
+            self.apply_publishers();
+        }
+
+        ConfigManager::from_registry()
+            .send(SetConfig::new(self.publishers.clone()))
+            .flatten_fut()
+            .map_err(|e| error!("Cannot save trusted publishers: {}", e))
+            .into_actor(self)
+            .spawn(ctx);
+
+        Ok(self.publishers.trusted_publishers.iter().cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::check_plugin_name;
+
+    #[test]
+    fn test_check_plugin_name() {
+        assert!(check_plugin_name("gu-plugin").is_ok());
+        assert!(check_plugin_name("plugin.v2").is_ok());
+        for name in &["", ".", "..", "../x", "a/b", "a\\b", "/etc", "a\0"] {
+            assert!(check_plugin_name(name).is_err(), "{:?}", name);
+        }
+    }
+}
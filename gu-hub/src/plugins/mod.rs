@@ -1,13 +1,19 @@
 mod builder;
+mod data;
+mod index;
 mod manager;
 pub mod module;
 mod parser;
 mod plugin;
+mod provider;
 mod rest;
 mod rest_result;
+mod signature;
+mod versions;
 
+pub(crate) use self::provider::{ListPackageHashes, ProviderPlugins};
 pub use self::{
-    manager::{ListPlugins, PluginManager},
+    manager::{ListPlugins, PluginFiles, PluginManager},
     module::PluginModule,
     plugin::{PluginEvent, PluginMetadata, PluginStatus},
 };
@@ -20,6 +20,12 @@ enum Command {
     Uninstall(String),
     Activate(String),
     Inactivate(String),
+    Rollback(String),
+    Export(String, Option<PathBuf>),
+    Import(String, PathBuf),
+    Trust(String, bool),
+    Search(String),
+    Indexes(Option<(String, bool)>),
     Build(builder::BuildPluginQuery),
 }
 
@@ -43,18 +49,28 @@ impl Module for PluginModule {
             .required(true)
             .index(1);
 
+        let address = Arg::with_name("ADDRESS")
+            .help("Publisher address (e.g. 0x0123...)")
+            .required(true)
+            .index(1);
+
         let path = Arg::with_name("PATH")
             .help("path to the package")
             .required(true)
             .index(1);
 
+        let url = Arg::with_name("URL")
+            .help("Plugin index url (http(s):// or file://)")
+            .required(true)
+            .index(1);
+
         app.subcommand(
             SubCommand::with_name("plugin")
                 .about("Manages web UI plugins (e.g. builds, installs, lists, starts, stops and uninstalls them)")
                 .setting(AppSettings::SubcommandRequiredElseHelp)
                 .subcommands(vec![
                     SubCommand::with_name("install")
-                        .about("Installs plugins from a package (e.g. plugin.gu-plugin) or a GitHub repository (e.g. golemfactory/golem-unlimited)")
+                        .about("Installs plugins from a package (e.g. plugin.gu-plugin), a GitHub repository (e.g. golemfactory/golem-unlimited) or plugin indexes (e.g. gu-blender@0.2.1)")
                         .arg(path),
                     SubCommand::with_name("dev")
                         .about("Installs the plugin in a dev mode")
@@ -69,6 +85,51 @@ impl Module for PluginModule {
                     SubCommand::with_name("uninstall")
                         .about("Uninstalls the plugin")
                         .arg(Arg::from(&plugin)),
+                    SubCommand::with_name("rollback")
+                        .about("Restores the previously installed version of the plugin")
+                        .arg(Arg::from(&plugin)),
+                    SubCommand::with_name("export")
+                        .about("Exports configuration and data of the plugin")
+                        .arg(Arg::from(&plugin))
+                        .arg(
+                            Arg::with_name("FILE")
+                                .help("Output file, printed if not given")
+                                .index(2),
+                        ),
+                    SubCommand::with_name("import")
+                        .about("Replaces configuration and data of the plugin with exported ones")
+                        .arg(Arg::from(&plugin))
+                        .arg(
+                            Arg::with_name("FILE")
+                                .help("File created by `plugin export`")
+                                .required(true)
+                                .index(2),
+                        ),
+                    SubCommand::with_name("trust")
+                        .about("Adds the publisher to trusted plugin publishers")
+                        .arg(Arg::from(&address)),
+                    SubCommand::with_name("untrust")
+                        .about("Removes the publisher from trusted plugin publishers")
+                        .arg(Arg::from(&address)),
+                    SubCommand::with_name("search")
+                        .about("Searches configured plugin indexes")
+                        .arg(
+                            Arg::with_name("QUERY")
+                                .help("Part of the plugin name")
+                                .index(1),
+                        ),
+                    SubCommand::with_name("index")
+                        .about("Manages plugin indexes")
+                        .setting(AppSettings::SubcommandRequiredElseHelp)
+                        .subcommands(vec![
+                            SubCommand::with_name("list").about("Lists configured plugin indexes"),
+                            SubCommand::with_name("add")
+                                .about("Adds the plugin index")
+                                .arg(Arg::from(&url)),
+                            SubCommand::with_name("remove")
+                                .about("Removes the plugin index")
+                                .arg(Arg::from(&url)),
+                        ]),
                     builder::subcommand(),
                 ]),
         )
@@ -113,6 +174,58 @@ impl Module for PluginModule {
                     );
                     Command::Inactivate(name)
                 }
+                ("rollback", Some(m)) => {
+                    let name = String::from(
+                        m.value_of("PLUGIN")
+                            .expect("Lack of required `plugin` argument"),
+                    );
+                    Command::Rollback(name)
+                }
+                ("export", Some(m)) => Command::Export(
+                    m.value_of("PLUGIN")
+                        .expect("Lack of required `plugin` argument")
+                        .to_string(),
+                    m.value_of("FILE").map(PathBuf::from),
+                ),
+                ("import", Some(m)) => Command::Import(
+                    m.value_of("PLUGIN")
+                        .expect("Lack of required `plugin` argument")
+                        .to_string(),
+                    PathBuf::from(
+                        m.value_of("FILE")
+                            .expect("Lack of required `file` argument"),
+                    ),
+                ),
+                ("trust", Some(m)) => Command::Trust(
+                    m.value_of("ADDRESS")
+                        .expect("Lack of required `address` argument")
+                        .to_string(),
+                    true,
+                ),
+                ("untrust", Some(m)) => Command::Trust(
+                    m.value_of("ADDRESS")
+                        .expect("Lack of required `address` argument")
+                        .to_string(),
+                    false,
+                ),
+                ("search", Some(m)) => {
+                    Command::Search(m.value_of("QUERY").unwrap_or_default().to_string())
+                }
+                ("index", Some(m)) => match m.subcommand() {
+                    ("add", Some(m)) => Command::Indexes(Some((
+                        m.value_of("URL")
+                            .expect("Lack of required `url` argument")
+                            .to_string(),
+                        true,
+                    ))),
+                    ("remove", Some(m)) => Command::Indexes(Some((
+                        m.value_of("URL")
+                            .expect("Lack of required `url` argument")
+                            .to_string(),
+                        false,
+                    ))),
+                    _ => Command::Indexes(None),
+                },
                 ("build", Some(m)) => Command::Build(m.to_owned().into()),
                 _ => Command::None,
             };
@@ -138,6 +251,16 @@ impl Module for PluginModule {
             Command::Inactivate(ref name) => {
                 rest::status_query(name.to_string(), QueriedStatus::Inactivate)
             }
+            Command::Rollback(ref name) => rest::rollback_query(name.to_string()),
+            Command::Export(ref name, ref path) => {
+                rest::export_query(name.to_string(), path.clone())
+            }
+            Command::Import(ref name, ref path) => {
+                rest::import_query(name.to_string(), path.to_path_buf())
+            }
+            Command::Trust(ref address, trusted) => rest::trust_query(address.to_string(), trusted),
+            Command::Search(ref query) => rest::search_query(query.to_string()),
+            Command::Indexes(ref change) => rest::indexes_query(change.clone()),
             Command::Build(ref obj) => builder::build_query(obj),
         }
     }
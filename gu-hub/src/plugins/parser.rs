@@ -12,7 +12,11 @@ use semver::{Version, VersionReq};
 use serde_json;
 use zip::ZipArchive;
 
-use super::plugin::PluginMetadata;
+use super::{
+    plugin::PluginMetadata,
+    provider::{ProviderPackage, PROVIDER_DIR, PROVIDER_MANIFEST},
+    signature::{self, PluginSignature, SIGNATURE_FILE},
+};
 
 pub trait PluginParser: Debug {
     /// Performs all checks on plugin resource and returns its metadata on success
@@ -111,6 +115,76 @@ impl<T: Debug + Read + Seek> ZipParser<T> {
     fn inner_new(zip: ZipArchive<T>) -> Self {
         Self { archive: zip }
     }
+
+    /// Verifies package signature. Returns publisher address of a signed package,
+    /// `None` for unsigned one and an error if the signature does not match.
+    pub fn publisher(&mut self) -> Result<Option<String>, String> {
+        let signature: PluginSignature = match self.archive.by_name(SIGNATURE_FILE) {
+            Ok(file) => serde_json::from_reader(file)
+                .map_err(|e| format!("Cannot parse {} file: {:?}", SIGNATURE_FILE, e))?,
+            Err(_) => return Ok(None),
+        };
+
+        let mut files = Vec::new();
+        for i in 0..self.archive.len() {
+            let mut file = self
+                .archive
+                .by_index(i)
+                .map_err(|e| format!("Error during reading zip: {:?}", e))?;
+            let name = file.name().to_string();
+            if name == SIGNATURE_FILE || name.ends_with('/') {
+                continue;
+            }
+            files.push((name, read_file(&mut file)?));
+        }
+
+        signature::verify(&signature, &signature::digest(files)).map(Some)
+    }
+
+    /// Packs files from the `provider/` dir into a tar archive for providers.
+    /// Returns `None` if the plugin has no provider part.
+    pub fn provider_package(&mut self) -> Result<Option<ProviderPackage>, String> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut manifest = None;
+
+        for i in 0..self.archive.len() {
+            let mut file = self
+                .archive
+                .by_index(i)
+                .map_err(|e| format!("Error during reading zip: {:?}", e))?;
+            if file.name().ends_with('/') {
+                continue;
+            }
+            let path = match file.sanitized_name().strip_prefix(PROVIDER_DIR) {
+                Ok(path) => path.to_path_buf(),
+                Err(_) => continue,
+            };
+
+            let content = read_file(&mut file)?;
+            if path == Path::new(PROVIDER_MANIFEST) {
+                manifest = Some(serde_json::from_slice(&content).map_err(|e| {
+                    format!("Cannot parse provider {} file: {}", PROVIDER_MANIFEST, e)
+                })?);
+            }
+
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            builder
+                .append_data(&mut header, &path, content.as_slice())
+                .map_err(|e| format!("Cannot pack provider file {:?}: {}", path, e))?;
+        }
+
+        match manifest {
+            Some(manifest) => Ok(Some(ProviderPackage {
+                manifest,
+                archive: builder
+                    .into_inner()
+                    .map_err(|e| format!("Cannot pack provider files: {}", e))?,
+            })),
+            None => Ok(None),
+        }
+    }
 }
 
 impl<T: Read + Debug + Seek> PluginParser for ZipParser<T> {
@@ -1,7 +1,7 @@
 use std::{
     collections::HashMap,
     fmt::{self, Debug},
-    fs::File,
+    fs::{self, File},
     io::Read,
     path::{Path, PathBuf},
 };
@@ -14,7 +14,11 @@ use serde_json::{self, Value as JsonValue};
 
 use gu_base::cli;
 
-use super::parser::{self, PathPluginParser, PluginParser};
+use super::{
+    parser::{self, PathPluginParser, PluginParser},
+    provider::ProviderPackage,
+    signature::{PublishersConfig, SignatureStatus},
+};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum PluginEvent {
@@ -46,6 +50,9 @@ pub struct PluginMetadata {
 
     #[serde(default)]
     required_services: Vec<JsonValue>,
+    /// JSON schema of the plugin configuration
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    config_schema: Option<JsonValue>,
 }
 
 impl PluginMetadata {
@@ -57,10 +64,18 @@ impl PluginMetadata {
         self.name.as_ref()
     }
 
+    pub fn version(&self) -> &Version {
+        &self.version
+    }
+
     pub fn load(&self) -> &Vec<String> {
         self.load.as_ref()
     }
 
+    pub fn config_schema(&self) -> Option<&JsonValue> {
+        self.config_schema.as_ref()
+    }
+
     fn default_name() -> String {
         "plugin".to_string()
     }
@@ -86,9 +101,18 @@ pub struct PluginInfo {
     #[serde(flatten)]
     metadata: PluginMetadata,
     status: PluginStatus,
+    #[serde(default = "PluginInfo::default_signature")]
+    signature: SignatureStatus,
+    /// all installed versions of the plugin
+    #[serde(default)]
+    versions: Vec<Version>,
 }
 
 impl PluginInfo {
+    fn default_signature() -> SignatureStatus {
+        SignatureStatus::Unsigned
+    }
+
     #[inline]
     pub fn status(&self) -> PluginStatus {
         self.status.clone()
@@ -98,6 +122,11 @@ impl PluginInfo {
     pub fn metadata(&self) -> &PluginMetadata {
         &self.metadata
     }
+
+    #[inline]
+    pub fn signature(&self) -> &SignatureStatus {
+        &self.signature
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -115,13 +144,26 @@ impl fmt::Display for PluginStatus {
 
 pub fn format_plugins_table(plugins: Vec<PluginInfo>) {
     cli::format_table(
-        row!["Name", "Version", "Status"],
+        row![
+            "Name",
+            "Version",
+            "Status",
+            "Signature",
+            "Installed versions"
+        ],
         || "No plugins installed",
         plugins.iter().map(|plugin| {
             row![
                 plugin.metadata.name,
                 plugin.metadata.version.to_string(),
                 plugin.status.to_string(),
+                plugin.signature.to_string(),
+                plugin
+                    .versions
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "),
             ]
         }),
     )
@@ -132,6 +174,19 @@ pub trait PluginHandler: Debug {
     fn metadata(&self) -> Result<PluginMetadata, String>;
 
     fn file(&self, path: &Path) -> Result<Vec<u8>, String>;
+
+    /// All files below the directory, with paths relative to the app directory
+    fn files(&self, dir: &Path) -> Result<Vec<(PathBuf, Vec<u8>)>, String>;
+
+    /// Verified publisher address, `None` for unsigned plugins
+    fn publisher(&self) -> Option<String> {
+        None
+    }
+
+    /// Provider part of the plugin, shipped to connected providers
+    fn provider_package(&self) -> Option<ProviderPackage> {
+        None
+    }
 }
 
 #[derive(Debug)]
@@ -171,12 +226,39 @@ impl PluginHandler for DirectoryHandler {
             .map_err(|e| format!("Reading file failed: {:?}", e))?;
         Ok(buf)
     }
+
+    fn files(&self, dir: &Path) -> Result<Vec<(PathBuf, Vec<u8>)>, String> {
+        let app_dir = self.directory.join(self.metadata()?.name);
+        let mut files = Vec::new();
+        read_files(&app_dir, dir.to_path_buf(), &mut files)
+            .map_err(|e| format!("Cannot read directory {:?}: {}", dir, e))?;
+        Ok(files)
+    }
+}
+
+fn read_files(
+    app_dir: &Path,
+    dir: PathBuf,
+    files: &mut Vec<(PathBuf, Vec<u8>)>,
+) -> std::io::Result<()> {
+    for entry in fs::read_dir(app_dir.join(&dir))? {
+        let entry = entry?;
+        let path = dir.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            read_files(app_dir, path, files)?;
+        } else {
+            files.push((path, fs::read(entry.path())?));
+        }
+    }
+    Ok(())
 }
 
 #[derive(Debug)]
 pub struct ZipHandler {
     metadata: PluginMetadata,
     files: HashMap<PathBuf, Vec<u8>>,
+    publisher: Option<String>,
+    provider: Option<ProviderPackage>,
 }
 
 impl ZipHandler {
@@ -185,8 +267,17 @@ impl ZipHandler {
 
         let metadata = parser.validate_and_load_metadata(gu_version)?;
         let files = parser.load_files(metadata.name())?;
-
-        Ok(Self { metadata, files })
+        let publisher = parser
+            .publisher()
+            .map_err(|e| format!("Invalid signature: {}", e))?;
+        let provider = parser.provider_package()?;
+
+        Ok(Self {
+            metadata,
+            files,
+            publisher,
+            provider,
+        })
     }
 }
 
@@ -201,6 +292,30 @@ impl PluginHandler for ZipHandler {
             .map(|data| data.clone())
             .ok_or(format!("File {:?} not found", path))
     }
+
+    fn files(&self, dir: &Path) -> Result<Vec<(PathBuf, Vec<u8>)>, String> {
+        Ok(self
+            .files
+            .iter()
+            .filter(|(path, _)| path.starts_with(dir))
+            // directory entries of the archive are loaded as empty files
+            .filter(|(path, _)| {
+                !self
+                    .files
+                    .keys()
+                    .any(|other| other != *path && other.starts_with(path))
+            })
+            .map(|(path, data)| (path.clone(), data.clone()))
+            .collect())
+    }
+
+    fn publisher(&self) -> Option<String> {
+        self.publisher.clone()
+    }
+
+    fn provider_package(&self) -> Option<ProviderPackage> {
+        self.provider.clone()
+    }
 }
 
 #[derive(Debug)]
@@ -229,12 +344,22 @@ impl Plugin {
         self.status.clone()
     }
 
-    pub fn info(&self) -> Result<PluginInfo, String> {
+    pub fn signature(&self, publishers: &PublishersConfig) -> SignatureStatus {
+        publishers.status(&Ok(self.handler.publisher()))
+    }
+
+    pub fn info(
+        &self,
+        publishers: &PublishersConfig,
+        versions: Vec<Version>,
+    ) -> Result<PluginInfo, String> {
         let meta = self.handler.metadata()?;
 
         Ok(PluginInfo {
             metadata: meta.clone(),
             status: self.status(),
+            signature: self.signature(publishers),
+            versions,
         })
     }
 
@@ -245,7 +370,21 @@ impl Plugin {
         }
     }
 
+    pub fn files(&self, dir: &Path) -> Result<Vec<(PathBuf, Vec<u8>)>, String> {
+        match self.status() {
+            PluginStatus::Active => self.handler.files(dir),
+            a => Err(format!("Plugin is not active (State - {})", a)),
+        }
+    }
+
     pub fn metadata(&self) -> Result<PluginMetadata, String> {
         self.handler.metadata()
     }
+
+    pub fn provider_package(&self) -> Option<ProviderPackage> {
+        match self.status() {
+            PluginStatus::Active => self.handler.provider_package(),
+            _ => None,
+        }
+    }
 }
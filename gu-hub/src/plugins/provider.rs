@@ -0,0 +1,299 @@
+//! Provider parts of plugins.
+//!
+//! A plugin package can bundle a provider exec-env plugin in its `provider/` dir.
+//! While the plugin is active, the part is stored in the hub repo and installed on
+//! every connected provider that gives the hub full access. Inactivated and
+//! uninstalled plugins are retired on providers.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr, UdpSocket},
+    sync::Arc,
+};
+
+use actix::prelude::*;
+use futures::prelude::*;
+use log::{debug, error, info, warn};
+
+use gu_actix::prelude::*;
+use gu_event_bus::Event;
+use gu_model::plugin::{InstallProviderPlugin, PluginManifest, RetireProviderPlugin};
+use gu_net::{
+    rpc::{
+        peer,
+        peer::{PeerEvent, PeerInfo},
+    },
+    NodeId,
+};
+use gu_persist::config::{ConfigManager, GetConfig};
+
+use super::{
+    manager::{GetProviderPackage, ListPlugins, PluginManager},
+    plugin::{PluginEvent, PluginStatus},
+};
+use crate::{repo, server::HubConfig};
+
+pub const PROVIDER_DIR: &str = "provider";
+pub const PROVIDER_MANIFEST: &str = "gu-plugin.json";
+
+/// Files from the `provider/` dir of a plugin packed into a tar archive.
+#[derive(Debug, Clone)]
+pub struct ProviderPackage {
+    pub manifest: PluginManifest,
+    pub archive: Vec<u8>,
+}
+
+/// Provider part stored in the hub repo.
+#[derive(Debug, Clone)]
+struct StoredPackage {
+    id: String,
+    manifest: PluginManifest,
+    /// hash string of the archive in the repo
+    hash: String,
+}
+
+#[derive(Default)]
+pub struct ProviderPlugins {
+    /// provider parts by hub plugin name
+    packages: HashMap<String, StoredPackage>,
+    /// scheme and port of the hub repo for providers
+    repo_base: Option<(&'static str, u16)>,
+}
+
+impl ProviderPlugins {
+    /// Stores provider part of an active plugin and pushes it to connected peers.
+    fn load(&mut self, name: String, ctx: &mut Context<Self>) {
+        PluginManager::from_registry()
+            .send(GetProviderPackage {
+                plugin: name.clone(),
+            })
+            .map_err(|e| error!("Cannot get provider package: {}", e))
+            .into_actor(self)
+            .and_then(move |package, act, _ctx| {
+                let package = match package {
+                    Some(package) => package,
+                    None => {
+                        act.retire(&name);
+                        return fut::ok(());
+                    }
+                };
+
+                let hash = match repo::store(&package.archive) {
+                    Ok(hash) => hash,
+                    Err(e) => {
+                        error!("Cannot store provider part of plugin {}: {}", name, e);
+                        return fut::ok(());
+                    }
+                };
+                let stored = StoredPackage {
+                    id: package.manifest.id.clone(),
+                    manifest: package.manifest,
+                    hash,
+                };
+                if act.packages.get(&name).map(|p| p.id != stored.id) == Some(true) {
+                    act.retire(&name);
+                }
+
+                info!("Shipping provider part of plugin {} to providers", name);
+                act.packages.insert(name, stored.clone());
+                act.push_to_all(stored);
+                fut::ok(())
+            })
+            .spawn(ctx);
+    }
+
+    fn retire(&mut self, name: &str) {
+        let package = match self.packages.remove(name) {
+            Some(package) => package,
+            None => return,
+        };
+
+        info!("Retiring provider part of plugin {}", name);
+        Arbiter::spawn(connected_peers().and_then(move |peers| {
+            for info in peers {
+                send_retire(info.node_id, package.id.clone());
+            }
+            Ok(())
+        }));
+    }
+
+    fn push_to_all(&self, package: StoredPackage) {
+        let repo_base = self.repo_base;
+
+        Arbiter::spawn(connected_peers().and_then(move |peers| {
+            for info in peers {
+                push(&info, &package, repo_base);
+            }
+            Ok(())
+        }));
+    }
+}
+
+fn connected_peers() -> impl Future<Item = Vec<PeerInfo>, Error = ()> {
+    peer::PeerManager::from_registry()
+        .send(peer::ListPeers)
+        .map_err(|e| error!("Cannot list connected peers: {}", e))
+}
+
+/// Hub address reachable from the peer: the local address of the route to it.
+fn local_ip(peer_addr: &str) -> Option<IpAddr> {
+    let addr: SocketAddr = peer_addr.parse().ok()?;
+    let socket = UdpSocket::bind(match addr {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    })
+    .ok()?;
+    socket.connect(addr).ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+fn push(info: &PeerInfo, package: &StoredPackage, repo_base: Option<(&'static str, u16)>) {
+    let node_id = info.node_id;
+    let url = match (
+        repo_base,
+        info.peer_addr.as_ref().and_then(|addr| local_ip(addr)),
+    ) {
+        (Some((scheme, port)), Some(ip)) => format!(
+            "{}://{}{}",
+            scheme,
+            SocketAddr::new(ip, port),
+            repo::url_path(&package.hash)
+        ),
+        _ => {
+            warn!("Cannot resolve hub address for {:?}", node_id);
+            return;
+        }
+    };
+    let id = package.id.clone();
+
+    Arbiter::spawn(
+        peer(node_id)
+            .into_endpoint()
+            .send(InstallProviderPlugin {
+                id: package.id.clone(),
+                version: package.manifest.version.clone(),
+                url,
+                hash: package.hash.clone(),
+            })
+            .then(move |result| {
+                match result {
+                    Ok(Ok(())) => info!("Plugin {} installed on {:?}", id, node_id),
+                    Ok(Err(e)) => warn!("Plugin {} not installed on {:?}: {}", id, node_id, e),
+                    Err(e) => debug!("Cannot install plugin {} on {:?}: {}", id, node_id, e),
+                }
+                Ok(())
+            }),
+    )
+}
+
+fn send_retire(node_id: NodeId, id: String) {
+    Arbiter::spawn(
+        peer(node_id)
+            .into_endpoint()
+            .send(RetireProviderPlugin { id: id.clone() })
+            .then(move |result| {
+                match result {
+                    Ok(Ok(())) => info!("Plugin {} retired on {:?}", id, node_id),
+                    Ok(Err(e)) => debug!("Plugin {} not retired on {:?}: {}", id, node_id, e),
+                    Err(e) => debug!("Cannot retire plugin {} on {:?}: {}", id, node_id, e),
+                }
+                Ok(())
+            }),
+    )
+}
+
+impl Actor for ProviderPlugins {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.wait(
+            ConfigManager::from_registry()
+                .send(GetConfig::new())
+                .flatten_fut()
+                .map_err(|e| error!("Cannot read hub config: {}", e))
+                .into_actor(self)
+                .and_then(|config: Arc<HubConfig>, act, _ctx| {
+                    let scheme = if config.tls.enabled { "https" } else { "http" };
+                    act.repo_base = Some((scheme, config.p2p_port));
+                    fut::ok(())
+                }),
+        );
+
+        let peers = gu_event_bus::subscribe(
+            "/peers".into(),
+            ctx.address().recipient::<Event<PeerEvent>>(),
+        )
+        .map_err(|_| error!("Cannot subscribe to peer events"));
+        let plugins = gu_event_bus::subscribe(
+            "/plugins".into(),
+            ctx.address().recipient::<Event<PluginEvent>>(),
+        )
+        .map_err(|_| error!("Cannot subscribe to plugin events"));
+
+        ctx.wait(
+            peers
+                .join(plugins)
+                .and_then(|_| {
+                    PluginManager::from_registry()
+                        .send(ListPlugins)
+                        .map_err(|e| error!("Cannot list plugins: {}", e))
+                })
+                .into_actor(self)
+                .and_then(|plugins, act, ctx| {
+                    for plugin in plugins {
+                        if plugin.status() == PluginStatus::Active {
+                            act.load(plugin.metadata().name().to_string(), ctx);
+                        }
+                    }
+                    fut::ok(())
+                }),
+        );
+    }
+}
+
+impl Supervised for ProviderPlugins {}
+
+impl SystemService for ProviderPlugins {}
+
+/// Repo hashes of stored provider parts
+pub struct ListPackageHashes;
+
+impl Message for ListPackageHashes {
+    type Result = Vec<String>;
+}
+
+impl Handler<ListPackageHashes> for ProviderPlugins {
+    type Result = MessageResult<ListPackageHashes>;
+
+    fn handle(&mut self, _msg: ListPackageHashes, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(
+            self.packages
+                .values()
+                .map(|package| package.hash.clone())
+                .collect(),
+        )
+    }
+}
+
+impl Handler<Event<PluginEvent>> for ProviderPlugins {
+    type Result = ();
+
+    fn handle(&mut self, msg: Event<PluginEvent>, ctx: &mut Self::Context) {
+        match msg.data() {
+            PluginEvent::New(metadata) => self.load(metadata.name().to_string(), ctx),
+            PluginEvent::Drop(name) => self.retire(name),
+        }
+    }
+}
+
+impl Handler<Event<PeerEvent>> for ProviderPlugins {
+    type Result = ();
+
+    fn handle(&mut self, msg: Event<PeerEvent>, _ctx: &mut Self::Context) {
+        if let PeerEvent::Connected(info) = msg.data() {
+            for package in self.packages.values() {
+                push(info, package, self.repo_base);
+            }
+        }
+    }
+}
@@ -3,12 +3,13 @@ use std::{
     fs::File,
     io::{Cursor, Read},
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use actix::{Arbiter, System, SystemService};
 use actix_web::{
     client,
-    error::{ErrorBadRequest, ErrorInternalServerError},
+    error::{ErrorBadRequest, ErrorForbidden, ErrorInternalServerError},
     http, AsyncResponder, HttpMessage, HttpRequest, HttpResponse, Responder, Scope,
 };
 use bytes::{buf::IntoBuf, Bytes};
@@ -19,12 +20,20 @@ use futures::{
 };
 use log::{debug, error};
 
+use gu_actix::prelude::*;
+use gu_persist::config::{ConfigManager, GetConfig, SetConfig};
+
 use crate::server::HubClient as ServerClient;
 
 use super::{
+    data::{
+        DeletePluginValue, ExportPluginData, GetPluginConfig, GetPluginValue, ImportPluginData,
+        ListPluginKeys, PluginData, PluginDataExport, SetPluginConfig, SetPluginValue,
+    },
+    index::{self, IndexEntry, IndexInstall, IndexesConfig, PluginIndex},
     manager::{
-        ChangePluginState, InstallDevPlugin, InstallPlugin, ListPlugins, PluginFile, PluginManager,
-        QueriedStatus,
+        ChangePluginState, InstallDevPlugin, InstallPlugin, ListPlugins, ListPublishers,
+        PluginFile, PluginManager, QueriedStatus, RollbackPlugin, TrustPublisher,
     },
     plugin::{format_plugins_table, PluginInfo},
     rest_result::{InstallQueryResult, RestResponse, ToHttpResponse},
@@ -176,19 +185,70 @@ fn install_from_github(path: &PathBuf) -> impl Future<Item = (), Error = ()> {
     })
 }
 
+fn install_from_index(spec: &str) -> impl Future<Item = (), Error = ()> {
+    future::result(IndexInstall::parse(spec))
+        .map_err(|e| error!("{}", e))
+        .and_then(|request| {
+            ServerClient::post_json("/plug/install-index", request)
+                .map_err(|e| error!("{}", e))
+                .and_then(|r: RestResponse<InstallQueryResult>| {
+                    Ok(println!("{}", r.message.message()))
+                })
+        })
+}
+
 pub fn install_query(path: PathBuf) {
+    System::run(move || {
+        let spec = path.to_string_lossy().to_string();
+        let install: Box<dyn Future<Item = (), Error = ()>> =
+            match path.extension().and_then(OsStr::to_str) {
+                Some("guplug") | Some("gu-plugin") => Box::new(
+                    future::result(read_file(&path)).and_then(|buf| install_query_inner(buf)),
+                ),
+                // GitHub repositories are given as `owner/repo`
+                _ if spec.contains('/') => Box::new(install_from_github(&path)),
+                _ => Box::new(install_from_index(&spec)),
+            };
+
+        Arbiter::spawn(install.then(|_r: Result<(), ()>| Ok(System::current().stop())))
+    });
+}
+
+pub fn search_query(query: String) {
+    let query: String = url::form_urlencoded::byte_serialize(query.as_bytes()).collect();
+    System::run(move || {
+        Arbiter::spawn(
+            ServerClient::get(format!("/plug/search?q={}", query))
+                .and_then(|entries: Vec<IndexEntry>| Ok(index::format_entries_table(entries)))
+                .map_err(|e| error!("{}", e))
+                .then(|_r| Ok(System::current().stop())),
+        )
+    });
+}
+
+/// Lists configured plugin indexes, adds (`true`) or removes (`false`) the index first
+pub fn indexes_query(change: Option<(String, bool)>) {
     System::run(move || {
         Arbiter::spawn(
-            path.extension()
-                .and_then(OsStr::to_str)
-                .and_then(|ext| match ext {
-                    "guplug" | "gu-plugin" => Some(future::Either::A(
-                        future::result(read_file(&path)).and_then(|buf| install_query_inner(buf)),
-                    )),
-                    _ => None,
+            ServerClient::get("/plug/indexes")
+                .and_then(move |mut indexes: Vec<String>| match change {
+                    None => future::Either::A(future::ok(indexes)),
+                    Some((url, add)) => {
+                        indexes.retain(|index| index != &url);
+                        if add {
+                            indexes.push(url);
+                        }
+                        future::Either::B(ServerClient::post_json("/plug/indexes", indexes))
+                    }
+                })
+                .and_then(|indexes: Vec<String>| {
+                    for index in indexes {
+                        println!("{}", index);
+                    }
+                    Ok(())
                 })
-                .unwrap_or_else(|| future::Either::B(install_from_github(&path)))
-                .then(|_r: Result<(), ()>| Ok(System::current().stop())),
+                .map_err(|e| error!("{}", e))
+                .then(|_r| Ok(System::current().stop())),
         )
     });
 }
@@ -215,6 +275,72 @@ pub fn status_query(plugin: String, status: QueriedStatus) {
     });
 }
 
+pub fn rollback_query(plugin: String) {
+    System::run(move || {
+        Arbiter::spawn(
+            ServerClient::patch(format!("/plug/{}/rollback", plugin))
+                .and_then(|version: String| Ok(println!("Restored version {}", version)))
+                .map_err(|e| error!("{}", e))
+                .then(|_r| Ok(System::current().stop())),
+        )
+    });
+}
+
+pub fn trust_query(address: String, trusted: bool) {
+    let path = format!("/plug/publishers/{}", address);
+
+    System::run(move || {
+        let request = if trusted {
+            future::Either::A(ServerClient::empty_put::<Vec<String>, _>(path))
+        } else {
+            future::Either::B(ServerClient::delete::<Vec<String>, _>(path))
+        };
+
+        Arbiter::spawn(
+            request
+                .and_then(|publishers: Vec<String>| {
+                    Ok(println!("Trusted publishers: {:?}", publishers))
+                })
+                .map_err(|e| error!("{}", e))
+                .then(|_r| Ok(System::current().stop())),
+        )
+    });
+}
+
+/// Saves configuration and data of the plugin to the file, or prints it
+pub fn export_query(plugin: String, path: Option<PathBuf>) {
+    System::run(move || {
+        Arbiter::spawn(
+            ServerClient::get(format!("/plug/{}/data", plugin))
+                .map_err(|e| error!("{}", e))
+                .and_then(move |data: PluginDataExport| {
+                    let json = serde_json::to_string_pretty(&data).map_err(|e| error!("{}", e))?;
+                    match path {
+                        Some(path) => std::fs::write(&path, json)
+                            .map_err(|e| error!("Cannot write {:?}: {}", path, e)),
+                        None => Ok(println!("{}", json)),
+                    }
+                })
+                .then(|_r| Ok(System::current().stop())),
+        )
+    });
+}
+
+/// Replaces configuration and data of the plugin with the exported ones
+pub fn import_query(plugin: String, path: PathBuf) {
+    System::run(move || {
+        Arbiter::spawn(
+            future::result(read_file(&path))
+                .and_then(move |buf| {
+                    ServerClient::put(format!("/plug/{}/data", plugin), buf)
+                        .map_err(|e| error!("{}", e))
+                })
+                .and_then(|_r: ()| Ok(()))
+                .then(|_r| Ok(System::current().stop())),
+        )
+    });
+}
+
 pub fn dev_query(path: PathBuf) {
     let path = path
         .canonicalize()
@@ -240,10 +366,26 @@ pub fn scope<S: 'static>(scope: Scope<S>) -> Scope<S> {
         .route("", http::Method::GET, list_scope)
         .route("", http::Method::POST, install_scope)
         .route("/install-github", http::Method::POST, install_github_scope)
+        .route("/install-index", http::Method::POST, install_index_scope)
+        .route("/search", http::Method::GET, search_scope)
+        .route("/indexes", http::Method::GET, indexes_scope)
+        .route("/indexes", http::Method::POST, set_indexes_scope)
         .route("/dev/{pluginPath:.*}", http::Method::POST, dev_scope)
+        .route("/publishers", http::Method::GET, publishers_scope)
+        .route("/publishers/{address}", http::Method::PUT, |r| {
+            trust_scope(true, r)
+        })
+        .route("/publishers/{address}", http::Method::DELETE, |r| {
+            trust_scope(false, r)
+        })
         .route("/{pluginName}", http::Method::DELETE, |r| {
             state_scope(QueriedStatus::Uninstall, r)
         })
+        .route(
+            "/{pluginName}/rollback",
+            http::Method::PATCH,
+            rollback_scope,
+        )
         .route("/{pluginName}/activate", http::Method::PATCH, |r| {
             state_scope(QueriedStatus::Activate, r)
         })
@@ -252,6 +394,18 @@ pub fn scope<S: 'static>(scope: Scope<S>) -> Scope<S> {
             http::Method::PATCH,
             |r| state_scope(QueriedStatus::Inactivate, r),
         )
+        .route("/{pluginName}/config", http::Method::GET, config_scope)
+        .route("/{pluginName}/config", http::Method::PUT, set_config_scope)
+        .route("/{pluginName}/kv", http::Method::GET, keys_scope)
+        .route("/{pluginName}/kv/{key}", http::Method::GET, value_scope)
+        .route("/{pluginName}/kv/{key}", http::Method::PUT, set_value_scope)
+        .route(
+            "/{pluginName}/kv/{key}",
+            http::Method::DELETE,
+            delete_value_scope,
+        )
+        .route("/{pluginName}/data", http::Method::GET, export_scope)
+        .route("/{pluginName}/data", http::Method::PUT, import_scope)
         .route("/{pluginName}/{fileName:.*}", http::Method::GET, file_scope)
 }
 
@@ -344,7 +498,10 @@ fn install_scope<S>(r: HttpRequest<S>) -> impl Responder {
         .and_then(|a| Ok(a.into_buf()))
         .and_then(move |a: Cursor<Bytes>| {
             manager
-                .send(InstallPlugin { bytes: a })
+                .send(InstallPlugin {
+                    bytes: a,
+                    entry: None,
+                })
                 .map_err(|e| ErrorInternalServerError(format!("{:?}", e)))
         })
         .and_then(|result| Ok(result.to_http_response()))
@@ -377,6 +534,7 @@ fn install_github_scope<S>(r: HttpRequest<S>) -> impl Responder {
                             PluginManager::from_registry()
                                 .send(InstallPlugin {
                                     bytes: Bytes::from(buf).into_buf(),
+                                    entry: None,
                                 })
                                 .map_err(|e| error!("{:?}", e))
                         })
@@ -401,6 +559,90 @@ fn install_github_scope<S>(r: HttpRequest<S>) -> impl Responder {
         .responder()
 }
 
+fn configured_indexes() -> impl Future<Item = Vec<(String, PluginIndex)>, Error = String> {
+    ConfigManager::from_registry()
+        .send(GetConfig::new())
+        .flatten_fut()
+        .map_err(|e| format!("Cannot read plugin indexes: {}", e))
+        .and_then(|config: Arc<IndexesConfig>| index::load_indexes(config.indexes.clone()))
+}
+
+fn install_index_scope<S>(r: HttpRequest<S>) -> impl Responder {
+    use super::rest_result::InstallQueryResult::*;
+
+    r.payload()
+        .map_err(|e| ErrorBadRequest(format!("Couldn't get request body: {:?}", e)))
+        .concat2()
+        .and_then(|body| {
+            serde_json::from_slice::<IndexInstall>(&body)
+                .map_err(|e| ErrorBadRequest(format!("Invalid request: {}", e)))
+        })
+        .and_then(|request| {
+            configured_indexes()
+                .map_err(DownloadFailed)
+                .and_then(move |indexes| index::select(&indexes, &request).map_err(PluginNotFound))
+                .and_then(|entry: IndexEntry| {
+                    index::fetch(&entry.version.url)
+                        .map_err(DownloadFailed)
+                        .and_then(move |bytes| {
+                            index::verify_hash(&bytes, &entry.version.hash)
+                                .map_err(InvalidFile)
+                                .map(|()| (bytes, entry))
+                        })
+                })
+                .and_then(|(bytes, entry)| {
+                    PluginManager::from_registry()
+                        .send(InstallPlugin {
+                            bytes: bytes.into_buf(),
+                            entry: Some(entry),
+                        })
+                        .map_err(|e| InvalidFile(format!("{}", e)))
+                })
+                .then(|result| -> Result<HttpResponse, actix_web::Error> {
+                    Ok(result.unwrap_or_else(|e| e).to_http_response())
+                })
+        })
+        .responder()
+}
+
+fn search_scope<S>(r: HttpRequest<S>) -> impl Responder {
+    let query = r.query().get("q").cloned().unwrap_or_default();
+
+    configured_indexes()
+        .map_err(ErrorInternalServerError)
+        .and_then(move |indexes| Ok(HttpResponse::Ok().json(index::search(&indexes, &query))))
+        .responder()
+}
+
+fn indexes_scope<S>(_r: HttpRequest<S>) -> impl Responder {
+    ConfigManager::from_registry()
+        .send(GetConfig::new())
+        .flatten_fut()
+        .map_err(|e| ErrorInternalServerError(format!("{}", e)))
+        .and_then(|config: Arc<IndexesConfig>| Ok(HttpResponse::Ok().json(&config.indexes)))
+        .responder()
+}
+
+fn set_indexes_scope<S>(r: HttpRequest<S>) -> impl Responder {
+    r.payload()
+        .map_err(|e| ErrorBadRequest(format!("Couldn't get request body: {:?}", e)))
+        .concat2()
+        .and_then(|body| {
+            serde_json::from_slice::<Vec<String>>(&body)
+                .map_err(|e| ErrorBadRequest(format!("Invalid index list: {}", e)))
+        })
+        .and_then(|indexes| {
+            ConfigManager::from_registry()
+                .send(SetConfig::new(IndexesConfig {
+                    indexes: indexes.clone(),
+                }))
+                .flatten_fut()
+                .map_err(|e| ErrorInternalServerError(format!("{}", e)))
+                .and_then(move |()| Ok(HttpResponse::Ok().json(indexes)))
+        })
+        .responder()
+}
+
 fn state_scope<S>(state: QueriedStatus, r: HttpRequest<S>) -> impl Responder {
     let manager = PluginManager::from_registry();
     let match_info = r.match_info();
@@ -412,6 +654,8 @@ fn state_scope<S>(state: QueriedStatus, r: HttpRequest<S>) -> impl Responder {
 
     manager
         .send(ChangePluginState { plugin, state })
+        .map_err(|e| ErrorInternalServerError(format!("err: {}", e)))
+        .and_then(|res| res.map_err(ErrorForbidden))
         .and_then(move |_res| {
             Ok(HttpResponse::Ok()
                 .content_type("application/json")
@@ -420,6 +664,44 @@ fn state_scope<S>(state: QueriedStatus, r: HttpRequest<S>) -> impl Responder {
         .responder()
 }
 
+fn rollback_scope<S>(r: HttpRequest<S>) -> impl Responder {
+    let plugin = r
+        .match_info()
+        .get("pluginName")
+        .expect("Can't get plugin name from query")
+        .to_string();
+
+    PluginManager::from_registry()
+        .send(RollbackPlugin { plugin })
+        .map_err(|e| ErrorInternalServerError(format!("err: {}", e)))
+        .and_then(|res| res.map_err(ErrorBadRequest))
+        .and_then(|version| Ok(HttpResponse::Ok().json(version.to_string())))
+        .responder()
+}
+
+fn publishers_scope<S>(_r: HttpRequest<S>) -> impl Responder {
+    PluginManager::from_registry()
+        .send(ListPublishers)
+        .map_err(|e| ErrorInternalServerError(format!("err: {}", e)))
+        .and_then(|res| Ok(HttpResponse::Ok().json(res)))
+        .responder()
+}
+
+fn trust_scope<S>(trusted: bool, r: HttpRequest<S>) -> impl Responder {
+    let address = r
+        .match_info()
+        .get("address")
+        .expect("Can't get publisher address from query")
+        .to_string();
+
+    PluginManager::from_registry()
+        .send(TrustPublisher { address, trusted })
+        .map_err(|e| ErrorInternalServerError(format!("err: {}", e)))
+        .and_then(|res| res.map_err(ErrorBadRequest))
+        .and_then(|res| Ok(HttpResponse::Ok().json(res)))
+        .responder()
+}
+
 fn dev_scope<S>(r: HttpRequest<S>) -> impl Responder {
     let manager = PluginManager::from_registry();
     let match_info = r.match_info();
@@ -436,3 +718,134 @@ fn dev_scope<S>(r: HttpRequest<S>) -> impl Responder {
         .and_then(|result| Ok(result.to_http_response()))
         .responder()
 }
+
+fn plugin_name<S>(r: &HttpRequest<S>) -> String {
+    r.match_info()
+        .get("pluginName")
+        .expect("Can't get plugin name from query")
+        .to_string()
+}
+
+fn key_name<S>(r: &HttpRequest<S>) -> String {
+    r.match_info()
+        .get("key")
+        .expect("Can't get key from query")
+        .to_string()
+}
+
+fn json_body<S: 'static, T: serde::de::DeserializeOwned + 'static>(
+    r: &HttpRequest<S>,
+) -> impl Future<Item = T, Error = actix_web::Error> {
+    r.payload()
+        .map_err(|e| ErrorBadRequest(format!("Couldn't get request body: {:?}", e)))
+        .concat2()
+        .and_then(|body| {
+            serde_json::from_slice::<T>(&body)
+                .map_err(|e| ErrorBadRequest(format!("Invalid json: {}", e)))
+        })
+}
+
+fn null_response() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body("null")
+}
+
+fn config_scope<S>(r: HttpRequest<S>) -> impl Responder {
+    PluginData::from_registry()
+        .send(GetPluginConfig {
+            plugin: plugin_name(&r),
+        })
+        .flatten_fut()
+        .map_err(actix_web::Error::from)
+        .and_then(|config| Ok(HttpResponse::Ok().json(config)))
+        .responder()
+}
+
+fn set_config_scope<S: 'static>(r: HttpRequest<S>) -> impl Responder {
+    let plugin = plugin_name(&r);
+
+    json_body(&r)
+        .and_then(|config| {
+            PluginData::from_registry()
+                .send(SetPluginConfig { plugin, config })
+                .flatten_fut()
+                .map_err(actix_web::Error::from)
+        })
+        .and_then(|()| Ok(null_response()))
+        .responder()
+}
+
+fn keys_scope<S>(r: HttpRequest<S>) -> impl Responder {
+    PluginData::from_registry()
+        .send(ListPluginKeys {
+            plugin: plugin_name(&r),
+        })
+        .flatten_fut()
+        .map_err(actix_web::Error::from)
+        .and_then(|keys| Ok(HttpResponse::Ok().json(keys)))
+        .responder()
+}
+
+fn value_scope<S>(r: HttpRequest<S>) -> impl Responder {
+    PluginData::from_registry()
+        .send(GetPluginValue {
+            plugin: plugin_name(&r),
+            key: key_name(&r),
+        })
+        .flatten_fut()
+        .map_err(actix_web::Error::from)
+        .and_then(|value| Ok(HttpResponse::Ok().json(value)))
+        .responder()
+}
+
+fn set_value_scope<S: 'static>(r: HttpRequest<S>) -> impl Responder {
+    let (plugin, key) = (plugin_name(&r), key_name(&r));
+
+    json_body(&r)
+        .and_then(|value| {
+            PluginData::from_registry()
+                .send(SetPluginValue { plugin, key, value })
+                .flatten_fut()
+                .map_err(actix_web::Error::from)
+        })
+        .and_then(|()| Ok(null_response()))
+        .responder()
+}
+
+fn delete_value_scope<S>(r: HttpRequest<S>) -> impl Responder {
+    PluginData::from_registry()
+        .send(DeletePluginValue {
+            plugin: plugin_name(&r),
+            key: key_name(&r),
+        })
+        .flatten_fut()
+        .map_err(actix_web::Error::from)
+        .and_then(|()| Ok(null_response()))
+        .responder()
+}
+
+fn export_scope<S>(r: HttpRequest<S>) -> impl Responder {
+    PluginData::from_registry()
+        .send(ExportPluginData {
+            plugin: plugin_name(&r),
+        })
+        .flatten_fut()
+        .map_err(actix_web::Error::from)
+        .and_then(|data| Ok(HttpResponse::Ok().json(data)))
+        .responder()
+}
+
+fn import_scope<S: 'static>(r: HttpRequest<S>) -> impl Responder {
+    let plugin = plugin_name(&r);
+
+    json_body(&r)
+        .and_then(|data| {
+            PluginData::from_registry()
+                .send(ImportPluginData { plugin, data })
+                .flatten_fut()
+                .map_err(actix_web::Error::from)
+        })
+        .and_then(|()| Ok(null_response()))
+        .responder()
+}
@@ -38,6 +38,10 @@ pub enum InstallQueryResult {
     InvalidPath,
     InvalidMetadata(String),
     InvalidFile(String),
+    InvalidSignature(String),
+    UntrustedPublisher(String),
+    PluginNotFound(String),
+    DownloadFailed(String),
 }
 
 impl ToHttpResponse for InstallQueryResult {
@@ -52,6 +56,10 @@ impl ToHttpResponse for InstallQueryResult {
             InvalidPath => "Path to resource is invalid".to_string(),
             InvalidMetadata(m) => format!("Metadata file is invalid - {}", m),
             InvalidFile(m) => format!("Received data is invalid - {}", m),
+            InvalidSignature(m) => format!("Plugin signature is invalid - {}", m),
+            UntrustedPublisher(m) => format!("Plugin is not signed by a trusted publisher - {}", m),
+            PluginNotFound(m) => format!("Plugin not found - {}", m),
+            DownloadFailed(m) => format!("Plugin download failed - {}", m),
         }
     }
 
@@ -66,6 +74,10 @@ impl ToHttpResponse for InstallQueryResult {
             InvalidPath => StatusCode::BAD_REQUEST,
             InvalidMetadata(_) => StatusCode::BAD_REQUEST,
             InvalidFile(_) => StatusCode::BAD_REQUEST,
+            InvalidSignature(_) => StatusCode::BAD_REQUEST,
+            UntrustedPublisher(_) => StatusCode::FORBIDDEN,
+            PluginNotFound(_) => StatusCode::NOT_FOUND,
+            DownloadFailed(_) => StatusCode::BAD_GATEWAY,
         }
     }
 }
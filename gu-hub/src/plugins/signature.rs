@@ -0,0 +1,265 @@
+//! Plugin package signatures.
+//!
+//! A signed package contains `gu-plugin.sig` with the publisher address and an ethkey
+//! signature of the SHA-256 digest of all other package files. With `allowUnsigned`
+//! turned off the hub only activates plugins signed by a trusted publisher.
+
+use std::{collections::BTreeSet, fmt};
+
+use ethkey::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use gu_persist::config::HasSectionId;
+
+pub const SIGNATURE_FILE: &str = "gu-plugin.sig";
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginSignature {
+    /// ethereum address of the publisher key
+    publisher: String,
+    /// hex encoded `r || s || v`
+    signature: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum SignatureStatus {
+    Unsigned,
+    Invalid { reason: String },
+    Untrusted { publisher: String },
+    Trusted { publisher: String },
+}
+
+impl fmt::Display for SignatureStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignatureStatus::Unsigned => write!(f, "unsigned"),
+            SignatureStatus::Invalid { reason } => write!(f, "invalid ({})", reason),
+            SignatureStatus::Untrusted { publisher } => write!(f, "untrusted ({})", publisher),
+            SignatureStatus::Trusted { publisher } => write!(f, "trusted ({})", publisher),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PublishersConfig {
+    /// addresses of trusted plugin publishers
+    #[serde(default)]
+    pub trusted_publishers: BTreeSet<String>,
+    /// allows activating unsigned and untrusted plugins; on by default, so that
+    /// plugins installed before signatures were checked stay active
+    #[serde(default = "PublishersConfig::default_allow_unsigned")]
+    pub allow_unsigned: bool,
+}
+
+impl Default for PublishersConfig {
+    fn default() -> Self {
+        PublishersConfig {
+            trusted_publishers: BTreeSet::new(),
+            allow_unsigned: Self::default_allow_unsigned(),
+        }
+    }
+}
+
+impl HasSectionId for PublishersConfig {
+    const SECTION_ID: &'static str = "plugin-publishers";
+}
+
+impl PublishersConfig {
+    fn default_allow_unsigned() -> bool {
+        true
+    }
+
+    /// Signature status of a package with verified `publisher` (`Err` if verification failed).
+    pub fn status(&self, publisher: &Result<Option<String>, String>) -> SignatureStatus {
+        match publisher {
+            Err(reason) => SignatureStatus::Invalid {
+                reason: reason.clone(),
+            },
+            Ok(None) => SignatureStatus::Unsigned,
+            Ok(Some(publisher)) if self.trusted_publishers.contains(publisher) => {
+                SignatureStatus::Trusted {
+                    publisher: publisher.clone(),
+                }
+            }
+            Ok(Some(publisher)) => SignatureStatus::Untrusted {
+                publisher: publisher.clone(),
+            },
+        }
+    }
+
+    /// Checks if a plugin with given signature status may be active.
+    pub fn allows(&self, status: &SignatureStatus) -> bool {
+        match status {
+            SignatureStatus::Trusted { .. } => true,
+            SignatureStatus::Invalid { .. } => false,
+            _ => self.allow_unsigned,
+        }
+    }
+}
+
+/// Lowercase `0x` prefixed publisher address.
+pub fn normalize_publisher(address: &str) -> Result<String, String> {
+    let hex = address.trim_start_matches("0x").to_ascii_lowercase();
+    if hex.len() != 40 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("invalid publisher address: {}", address));
+    }
+    Ok(format!("0x{}", hex))
+}
+
+/// Digest of package files, `(name, content)` pairs in any order.
+pub fn digest(mut files: Vec<(String, Vec<u8>)>) -> [u8; 32] {
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut hasher = Sha256::new();
+    for (name, content) in files {
+        hasher.input(name.as_bytes());
+        hasher.input(&[0]);
+        hasher.input(&(content.len() as u64).to_be_bytes());
+        hasher.input(&content);
+    }
+
+    let mut digest = [0u8; 32];
+    digest.copy_from_slice(&hasher.result());
+    digest
+}
+
+pub fn sign(account: &EthAccount, digest: &[u8; 32]) -> Result<PluginSignature, String> {
+    let signature = account.sign(digest).map_err(|e| e.to_string())?;
+
+    let mut bytes = Vec::with_capacity(65);
+    bytes.extend_from_slice(&signature.r);
+    bytes.extend_from_slice(&signature.s);
+    bytes.push(signature.v);
+
+    Ok(PluginSignature {
+        publisher: account.address().to_string(),
+        signature: to_hex(&bytes),
+    })
+}
+
+/// Returns the publisher address if the signature matches the digest.
+pub fn verify(signature: &PluginSignature, digest: &[u8; 32]) -> Result<String, String> {
+    let bytes = from_hex(&signature.signature)?;
+    if bytes.len() != 65 {
+        return Err("invalid signature length".to_string());
+    }
+
+    let mut r = [0u8; 32];
+    let mut s = [0u8; 32];
+    r.copy_from_slice(&bytes[0..32]);
+    s.copy_from_slice(&bytes[32..64]);
+    let recovered = Signature { v: bytes[64], r, s }
+        .recover(digest)
+        .map_err(|e| format!("cannot recover signer: {}", e))?;
+
+    let signer = Address::from(recovered.address().as_ref()).to_string();
+    if signer != normalize_publisher(&signature.publisher)? {
+        return Err(format!(
+            "package signed by {}, not by {}",
+            signer, signature.publisher
+        ));
+    }
+    Ok(signer)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, String> {
+    if hex.len() % 2 != 0 {
+        return Err("invalid hex length".to_string());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| "invalid hex".to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn files() -> Vec<(String, Vec<u8>)> {
+        vec![
+            (
+                "gu-plugin.json".to_string(),
+                b"{\"name\": \"test\"}".to_vec(),
+            ),
+            ("app/index.html".to_string(), b"<html></html>".to_vec()),
+        ]
+    }
+
+    fn account(dir: &tempfile::TempDir) -> Box<EthAccount> {
+        EthAccount::load_or_generate(dir.path().join("keystore.json"), "").unwrap()
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let account = account(&dir);
+        let signature = sign(&account, &digest(files())).unwrap();
+
+        let publisher = account.address().to_string();
+        assert_eq!(verify(&signature, &digest(files())), Ok(publisher.clone()));
+
+        let mut reordered = files();
+        reordered.reverse();
+        assert_eq!(verify(&signature, &digest(reordered)), Ok(publisher));
+    }
+
+    #[test]
+    fn test_verify_tampered() {
+        let dir = tempfile::tempdir().unwrap();
+        let account = account(&dir);
+        let signature = sign(&account, &digest(files())).unwrap();
+
+        let mut tampered = files();
+        tampered[1].1.extend_from_slice(b"<script></script>");
+        assert!(verify(&signature, &digest(tampered)).is_err());
+
+        let mut renamed = files();
+        renamed[1].0 = "app/main.html".to_string();
+        assert!(verify(&signature, &digest(renamed)).is_err());
+
+        let forged = PluginSignature {
+            publisher: format!("0x{}", "1".repeat(40)),
+            signature: signature.signature.clone(),
+        };
+        assert!(verify(&forged, &digest(files())).is_err());
+    }
+
+    #[test]
+    fn test_publishers_config() {
+        let config: PublishersConfig = serde_json::from_str("{}").unwrap();
+        assert!(config.allow_unsigned);
+        assert!(config.allows(&SignatureStatus::Unsigned));
+        assert!(!config.allows(&SignatureStatus::Invalid {
+            reason: "bad".to_string()
+        }));
+
+        let publisher = format!("0x{}", "a".repeat(40));
+        let config: PublishersConfig = serde_json::from_value(serde_json::json!({
+            "trustedPublishers": [publisher.clone()],
+            "allowUnsigned": false,
+        }))
+        .unwrap();
+        assert_eq!(
+            config.status(&Ok(Some(publisher.clone()))),
+            SignatureStatus::Trusted {
+                publisher: publisher.clone()
+            }
+        );
+        assert!(config.allows(&config.status(&Ok(Some(publisher)))));
+        assert!(!config.allows(&config.status(&Ok(None))));
+        assert!(!config.allows(&config.status(&Ok(Some(format!("0x{}", "b".repeat(40)))))));
+    }
+}
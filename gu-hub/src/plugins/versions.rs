@@ -0,0 +1,109 @@
+//! Installed plugin versions.
+//!
+//! Each plugin has its own directory with one `<version>.gu-plugin` package per
+//! installed version and `versions.json` recording the selected version and the
+//! versions selected before it.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+
+const VERSIONS_FILE: &str = "versions.json";
+const PACKAGE_EXTENSION: &str = "gu-plugin";
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginVersions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    current: Option<Version>,
+    /// previously selected versions, the most recent last
+    #[serde(default)]
+    history: Vec<Version>,
+}
+
+impl PluginVersions {
+    /// Reads versions of plugin stored in `dir`, selects the newest package if none is recorded
+    pub fn load(dir: &Path) -> Self {
+        let mut versions: PluginVersions = fs::read(dir.join(VERSIONS_FILE))
+            .ok()
+            .and_then(|bytes| {
+                serde_json::from_slice(&bytes)
+                    .map_err(|e| warn!("Invalid {:?} file: {}", dir.join(VERSIONS_FILE), e))
+                    .ok()
+            })
+            .unwrap_or_default();
+
+        let installed = Self::installed(dir);
+        versions.history.retain(|v| installed.contains(v));
+        if !versions
+            .current
+            .as_ref()
+            .map(|v| installed.contains(v))
+            .unwrap_or(false)
+        {
+            versions.current = installed.last().cloned();
+        }
+        versions
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), String> {
+        let json = serde_json::to_vec_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(dir.join(VERSIONS_FILE), json)
+            .map_err(|e| format!("Cannot save plugin versions: {}", e))
+    }
+
+    /// Versions with a package in `dir`, in ascending order
+    pub fn installed(dir: &Path) -> Vec<Version> {
+        let mut versions: Vec<Version> = fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .filter_map(|entry| {
+                        let path = entry.path();
+                        if path.extension()?.to_str()? != PACKAGE_EXTENSION {
+                            return None;
+                        }
+                        Version::parse(path.file_stem()?.to_str()?).ok()
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        versions.sort();
+        versions
+    }
+
+    pub fn package_path(dir: &Path, version: &Version) -> PathBuf {
+        dir.join(format!("{}.{}", version, PACKAGE_EXTENSION))
+    }
+
+    pub fn current(&self) -> Option<&Version> {
+        self.current.as_ref()
+    }
+
+    /// Version selected before the current one
+    pub fn previous(&self) -> Option<&Version> {
+        self.history.last()
+    }
+
+    pub fn select(&mut self, version: Version) {
+        if let Some(current) = self.current.take() {
+            if current != version {
+                self.history.push(current);
+            }
+        }
+        self.history.retain(|v| v != &version);
+        self.current = Some(version);
+    }
+
+    /// Selects the previous version again, dropping the current one from history
+    pub fn rollback(&mut self) -> Option<Version> {
+        let previous = self.history.pop()?;
+        self.current = Some(previous.clone());
+        Some(previous)
+    }
+}
@@ -1,16 +1,219 @@
-use actix_web::error::{ErrorBadRequest, ErrorInternalServerError};
+//! Hub image repository.
+//!
+//! Objects are stored under `<ALGO>---<hex>` names (see `ParsedHash::to_path`) and
+//! served at `/repo/<ALGO>---<hex>`. Uploads are hashed with the algorithm given in
+//! the `algo` query parameter, `SHA1` by default. Garbage collection keeps objects
+//! deployed in sessions and provider parts of plugins.
+
+use actix_web::error::{ErrorBadRequest, ErrorConflict, ErrorInternalServerError, ErrorNotFound};
 use actix_web::fs::NamedFile;
 use actix_web::{App, HttpMessage, HttpRequest, HttpResponse};
-use futures::prelude::*;
+use chrono::{DateTime, Utc};
+use futures::{future, prelude::*};
 use gu_base::{Decorator, Module};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::{Mutex, RwLock};
 
+use actix::SystemService;
+use gu_model::hash::{ContentHasher, ParsedHash};
 use gu_persist::config::ConfigModule;
 use tempfile::NamedTempFile;
 
+use crate::{
+    plugins::{ListPackageHashes, ProviderPlugins},
+    sessions::{ImageRefs, SessionsManager},
+};
+
+/// Objects uploaded within this time are not collected, they may be about to be deployed.
+const DEFAULT_GC_MIN_AGE_SECS: i64 = 3600;
+
+/// Object stored in the repo, `hash` and `url` are ready to use in a deployment `Image`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RepoObject {
+    pub hash: String,
+    pub url: String,
+    pub size: u64,
+    /// upload time
+    pub created: DateTime<Utc>,
+    /// whether a session or a plugin uses the object
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub referenced: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct GcResult {
+    pub removed: Vec<RepoObject>,
+    pub freed_bytes: u64,
+}
+
+/// Parses `ALGO:<hex>`, `ALGO---<hex>` or a bare SHA-1 hex into `ALGO:<hex>` with
+/// lowercase hex, `None` for unsupported or malformed hashes.
+pub(crate) fn normalize_hash(hash: &str) -> Option<String> {
+    let parsed = if hash.contains("---") {
+        ParsedHash::from_file_name(hash).ok()?
+    } else if hash.contains(':') {
+        ParsedHash::from_hash_bytes(hash.as_bytes()).ok()?
+    } else {
+        return normalize_hash(&format!("SHA1:{}", hash));
+    };
+    // the checker validates algorithm, length and hex digits
+    parsed.checker().ok()?;
+
+    Some(format!(
+        "{}:{}",
+        parsed.algo_name().ok()?,
+        parsed.value().ok()?.to_ascii_lowercase()
+    ))
+}
+
+/// File name of the object with a normalized hash
+fn object_name(hash: &str) -> String {
+    hash.replacen(':', "---", 1)
+}
+
+/// Path of the object in hub urls
+pub(crate) fn url_path(hash: &str) -> String {
+    format!("/repo/{}", object_name(hash))
+}
+
+fn base_url<S>(r: &HttpRequest<S>) -> String {
+    let info = r.connection_info();
+    format!("{}://{}", info.scheme(), info.host())
+}
+
+/// Saves a blob in the repo, returns its hash string.
+pub(crate) fn store(bytes: &[u8]) -> std::io::Result<String> {
+    let mut hasher = ContentHasher::from_algo("SHA1").expect("SHA1 is supported");
+    hasher.update(bytes);
+    let hash = hasher.finish();
+
+    let repo = ConfigModule::new().cache_dir().join("repo");
+    std::fs::create_dir_all(&repo)?;
+    let path = repo.join(object_name(&hash));
+    if !path.exists() {
+        std::fs::write(path, bytes)?;
+    }
+    Ok(hash)
+}
+
+fn load_object(path: &Path) -> Option<RepoObject> {
+    let hash = normalize_hash(path.file_name()?.to_str()?)?;
+    let meta = fs::metadata(path).ok()?;
+    if !meta.is_file() {
+        return None;
+    }
+
+    Some(RepoObject {
+        url: url_path(&hash),
+        hash,
+        size: meta.len(),
+        created: meta.modified().ok()?.into(),
+        referenced: None,
+    })
+}
+
+fn list_objects(repo: &Path) -> Vec<RepoObject> {
+    let mut objects: Vec<RepoObject> = fs::read_dir(repo)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| load_object(&entry.path()))
+                .collect()
+        })
+        .unwrap_or_default();
+    objects.sort_by(|a, b| a.created.cmp(&b.created));
+    objects
+}
+
+/// Moves objects saved by older hubs under a bare SHA-1 hex name.
+fn migrate(repo: &Path) {
+    let entries = match fs::read_dir(repo) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for path in entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+    {
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if !name.contains("---") => name.to_string(),
+            _ => continue,
+        };
+        if let Some(hash) = normalize_hash(&name) {
+            let _ = fs::rename(&path, repo.join(object_name(&hash)))
+                .map_err(|e| warn!("Cannot migrate repo object {}: {}", name, e));
+        }
+    }
+}
+
+/// Hashes of objects deployed in sessions or used by provider parts of plugins
+fn references() -> impl Future<Item = BTreeSet<String>, Error = actix_web::Error> {
+    SessionsManager::from_registry()
+        .send(ImageRefs)
+        .join(ProviderPlugins::from_registry().send(ListPackageHashes))
+        .map_err(|e| ErrorInternalServerError(format!("Cannot list image references: {}", e)))
+        .map(|(mut refs, packages)| {
+            refs.extend(packages);
+            refs
+        })
+}
+
+/// Removes objects which are not referenced and older than `min_age`, as well as
+/// abandoned uploads.
+fn collect_garbage(
+    repo: &Path,
+    repo_temp: &Path,
+    refs: &BTreeSet<String>,
+    min_age: chrono::Duration,
+) -> GcResult {
+    let cutoff = Utc::now() - min_age;
+    let mut result = GcResult::default();
+
+    for object in list_objects(repo) {
+        if refs.contains(&object.hash) || object.created > cutoff {
+            continue;
+        }
+        match fs::remove_file(repo.join(object_name(&object.hash))) {
+            Ok(()) => {
+                result.freed_bytes += object.size;
+                result.removed.push(object);
+            }
+            Err(e) => warn!("Cannot remove repo object {}: {}", object.hash, e),
+        }
+    }
+
+    if let Ok(entries) = fs::read_dir(repo_temp) {
+        for path in entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+        {
+            let abandoned = fs::metadata(&path)
+                .and_then(|meta| meta.modified())
+                .map(|modified| DateTime::<Utc>::from(modified) <= cutoff)
+                .unwrap_or(false);
+            if abandoned {
+                let _ = fs::remove_file(&path);
+            }
+        }
+    }
+
+    info!(
+        "Repo garbage collection removed {} objects ({} bytes)",
+        result.removed.len(),
+        result.freed_bytes
+    );
+    result
+}
+
 struct RepoModule {
     // repo, repo_cache
     paths: Mutex<Option<(PathBuf, PathBuf)>>,
@@ -26,6 +229,7 @@ impl Module for RepoModule {
         let mut g = self.paths.lock().unwrap();
         std::fs::create_dir_all(&repo).unwrap();
         std::fs::create_dir_all(&repo_cache).unwrap();
+        migrate(&repo);
 
         *g = Some((repo, repo_cache))
     }
@@ -34,51 +238,140 @@ impl Module for RepoModule {
         let (repo, repo_cache) = self.paths.lock().unwrap().clone().unwrap();
 
         let repo_temp: Rc<Path> = repo_cache.into();
+        let repo_temp_gc = repo_temp.clone();
 
         let cache_path: Rc<Path> = repo.into();
+        let cache_path_list = cache_path.clone();
+        let cache_path_gc = cache_path.clone();
         let cache_path_get = cache_path.clone();
         std::fs::create_dir_all(&cache_path).unwrap();
 
         app
                 .resource("/repo", |r| {
+                r.get().with_async(move |r: HttpRequest<S>| {
+                    let cache_path = cache_path_list.clone();
+                    let base = base_url(&r);
+
+                    references().and_then(move |refs| {
+                        let objects: Vec<RepoObject> = list_objects(&cache_path)
+                            .into_iter()
+                            .map(|object| RepoObject {
+                                url: format!("{}{}", base, object.url),
+                                referenced: Some(refs.contains(&object.hash)),
+                                ..object
+                            })
+                            .collect();
+                        Ok(HttpResponse::Ok().json(objects))
+                    })
+                });
                 r.post().with_async(move |r: HttpRequest<S>| {
                     let cache_path = cache_path.clone();
+                    let base = base_url(&r);
+                    let algo = r.query().get("algo").cloned().unwrap_or_else(|| "SHA1".to_string());
+                    let expected = r.query().get("hash").cloned();
+
+                    let hasher = gu_actix::async_try!(ContentHasher::from_algo(&algo)
+                        .map_err(|e| ErrorBadRequest(format!("Unsupported hash algorithm: {}", e))));
                     let lob_file = Rc::new(RwLock::new(gu_actix::async_try!(NamedTempFile::new_in(repo_temp.as_ref()))));
-                    let sha1 = Rc::new(RwLock::new(sha1::Sha1::new()));
+                    let hasher = Rc::new(RwLock::new(hasher));
 
                     let lob_file_f = lob_file.clone();
-                    let sha1_f = sha1.clone();
+                    let hasher_f = hasher.clone();
 
                     gu_actix::async_result! {
                     r.payload()
                         .map_err(|e| ErrorBadRequest(format!("Couldn't get request body: {:?}", e)))
                         .for_each(move |chunk| {
                             lob_file.write().unwrap().write_all(chunk.as_ref())?;
-                            sha1.write().unwrap().update(chunk.as_ref());
+                            hasher.write().unwrap().update(chunk.as_ref());
                             Ok(())
                         })
                         .and_then(move |()| {
-                            let hash = sha1_f.write().unwrap().hexdigest();
+                            let hash = match Rc::try_unwrap(hasher_f) {
+                                Ok(hasher) => hasher.into_inner().unwrap().finish(),
+                                Err(_) => return Err(ErrorInternalServerError("Couldn't hash image")),
+                            };
+                            if let Some(expected) = expected {
+                                if normalize_hash(&expected).as_ref() != Some(&hash) {
+                                    return Err(ErrorBadRequest(format!("Image hash is {}, not {}", hash, expected)));
+                                }
+                            }
 
+                            let path = cache_path.join(object_name(&hash));
                             Rc::try_unwrap(lob_file_f).unwrap()
                                 .into_inner()
                                 .unwrap()
-                                .persist(cache_path.join(&hash))
+                                .persist(&path)
                                 .map_err(|e| ErrorInternalServerError(format!("Couldn't save image: {:?}", e)))?;
+                            let object = load_object(&path)
+                                .ok_or_else(|| ErrorInternalServerError("Couldn't read saved image"))?;
 
                             Ok(HttpResponse::Created()
-                                .header("Location", format!("/repo/{}", hash))
-                                .json(hash))
+                                .header("Location", object.url.clone())
+                                .json(RepoObject {
+                                    url: format!("{}{}", base, object.url),
+                                    ..object
+                                }))
                         })
                 }
                 })
             })
+            .resource("/repo/gc", move |r| {
+                r.post().with_async(move |r: HttpRequest<S>| {
+                    let cache_path = cache_path_gc.clone();
+                    let repo_temp = repo_temp_gc.clone();
+                    let base = base_url(&r);
+                    let min_age = match r.query().get("minAge") {
+                        Some(secs) => gu_actix::async_try!(secs
+                            .parse::<i64>()
+                            .map_err(|e| ErrorBadRequest(format!("Invalid minAge: {}", e)))),
+                        None => DEFAULT_GC_MIN_AGE_SECS,
+                    };
+
+                    gu_actix::async_result! {
+                        references().and_then(move |refs| {
+                            let mut result = collect_garbage(
+                                &cache_path,
+                                &repo_temp,
+                                &refs,
+                                chrono::Duration::seconds(min_age),
+                            );
+                            for object in result.removed.iter_mut() {
+                                object.url = format!("{}{}", base, object.url);
+                            }
+                            Ok(HttpResponse::Ok().json(result))
+                        })
+                    }
+                })
+            })
             .resource("/repo/{hash}", move |r| {
                 let cache_path = cache_path_get.clone();
-                r.get().with(move |p: actix_web::Path<(String, )>| -> Result<NamedFile, actix_web::Error>{
-                    let hexhash = p.0.as_str();
-                    let file_path = cache_path.join(hexhash);
-                    Ok(NamedFile::open(file_path)?)
+                let cache_path_delete = cache_path_get.clone();
+                let get = move |r: HttpRequest<S>| -> Result<NamedFile, actix_web::Error> {
+                    let hash = normalize_hash(r.match_info().get("hash").unwrap_or_default())
+                        .ok_or_else(|| ErrorNotFound("Invalid image hash"))?;
+                    Ok(NamedFile::open(cache_path.join(object_name(&hash)))?)
+                };
+                r.get().with(get.clone());
+                r.head().with(get);
+                r.delete().with_async(move |r: HttpRequest<S>| {
+                    let hash = gu_actix::async_try!(normalize_hash(r.match_info().get("hash").unwrap_or_default())
+                        .ok_or_else(|| ErrorNotFound("Invalid image hash")));
+                    let path = cache_path_delete.join(object_name(&hash));
+                    if !path.exists() {
+                        return future::Either::B(future::err(ErrorNotFound(format!("Image {} not found", hash))));
+                    }
+                    let force = r.query().get("force").map(String::as_str) == Some("true");
+
+                    gu_actix::async_result! {
+                        references().and_then(move |refs| {
+                            if !force && refs.contains(&hash) {
+                                return Err(ErrorConflict(format!("Image {} is used by sessions or plugins", hash)));
+                            }
+                            fs::remove_file(&path)?;
+                            Ok(HttpResponse::Ok().json(hash))
+                        })
+                    }
                 })
             })
     }
@@ -89,3 +382,87 @@ pub fn module() -> impl Module {
         paths: Mutex::new(None),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const HEX: &str = "2fd4e1c67a2d28fced849ee1bb76e7391b93eb12";
+
+    #[test]
+    fn test_normalize_hash() {
+        let hash = format!("SHA1:{}", HEX);
+        assert_eq!(normalize_hash(&hash), Some(hash.clone()));
+        assert_eq!(
+            normalize_hash(&format!("SHA1---{}", HEX)),
+            Some(hash.clone())
+        );
+        assert_eq!(normalize_hash(HEX), Some(hash.clone()));
+        assert_eq!(
+            normalize_hash(&HEX.to_ascii_uppercase()),
+            Some(hash.clone())
+        );
+
+        assert_eq!(normalize_hash(&HEX[1..]), None);
+        assert_eq!(normalize_hash(&format!("MD5:{}", HEX)), None);
+        assert_eq!(normalize_hash(&HEX.replace('f', "x")), None);
+        assert_eq!(normalize_hash(""), None);
+
+        assert_eq!(url_path(&hash), format!("/repo/SHA1---{}", HEX));
+    }
+
+    #[test]
+    fn test_migrate() {
+        let repo = tempfile::tempdir().unwrap();
+        fs::write(repo.path().join(HEX), b"old").unwrap();
+        fs::write(repo.path().join("invalid"), b"other").unwrap();
+
+        migrate(repo.path());
+        assert!(repo.path().join(format!("SHA1---{}", HEX)).is_file());
+        assert!(!repo.path().join(HEX).exists());
+        assert!(repo.path().join("invalid").exists());
+    }
+
+    #[test]
+    fn test_collect_garbage() {
+        let repo = tempfile::tempdir().unwrap();
+        let repo_temp = tempfile::tempdir().unwrap();
+        let used = format!("SHA1:{}", HEX);
+        let unused = format!("SHA1:{}", HEX.replace('2', "3"));
+        for hash in &[&used, &unused] {
+            fs::write(repo.path().join(object_name(hash)), b"data").unwrap();
+        }
+        fs::write(repo_temp.path().join("upload"), b"part").unwrap();
+        let refs: BTreeSet<String> = vec![used.clone()].into_iter().collect();
+
+        // recent objects are kept
+        let result = collect_garbage(
+            repo.path(),
+            repo_temp.path(),
+            &refs,
+            chrono::Duration::hours(1),
+        );
+        assert!(result.removed.is_empty());
+        assert!(repo_temp.path().join("upload").exists());
+
+        let result = collect_garbage(
+            repo.path(),
+            repo_temp.path(),
+            &refs,
+            chrono::Duration::seconds(-60),
+        );
+        assert_eq!(
+            result.removed.iter().map(|o| &o.hash).collect::<Vec<_>>(),
+            vec![&unused]
+        );
+        assert_eq!(result.freed_bytes, 4);
+        assert_eq!(
+            list_objects(repo.path())
+                .into_iter()
+                .map(|o| o.hash)
+                .collect::<Vec<_>>(),
+            vec![used]
+        );
+        assert!(!repo_temp.path().join("upload").exists());
+    }
+}
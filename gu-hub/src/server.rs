@@ -2,6 +2,7 @@ use std::{net::ToSocketAddrs, path::PathBuf, sync::Arc};
 
 use actix::prelude::*;
 use actix_web;
+use actix_web::server::{HttpServer, IntoHttpHandler};
 #[cfg(unix)]
 use clap::App;
 use clap::ArgMatches;
@@ -26,6 +27,10 @@ use gu_persist::{
     http::{ServerClient, ServerConfig},
 };
 
+use crate::auth::{ApiAuth, AuthConfig};
+use crate::sessions::StorageQuota;
+use crate::tls::TlsConfig;
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct HubConfig {
@@ -35,6 +40,12 @@ pub(crate) struct HubConfig {
     control_socket: Option<String>,
     #[serde(default = "HubConfig::publish_service")]
     pub(crate) publish_service: bool,
+    #[serde(default)]
+    pub(crate) storage_quota: StorageQuota,
+    #[serde(default)]
+    pub(crate) auth: AuthConfig,
+    #[serde(default)]
+    pub(crate) tls: TlsConfig,
 }
 
 pub(crate) type HubClient = ServerClient<HubConfig>;
@@ -45,13 +56,17 @@ impl Default for HubConfig {
             p2p_port: Self::default_p2p_port(),
             control_socket: None,
             publish_service: Self::publish_service(),
+            storage_quota: StorageQuota::default(),
+            auth: AuthConfig::default(),
+            tls: TlsConfig::default(),
         }
     }
 }
 
 impl ServerConfig for HubConfig {
     fn port(&self) -> u16 {
-        self.p2p_port
+        // local clients talk plain http to the loopback listener, the only one trusted
+        self.auth.local_port
     }
 }
 
@@ -157,14 +172,53 @@ impl Module for ServerModule {
     }
 }
 
-fn mdns_publisher(port: u16, node_id: NodeId) -> std::io::Result<MdnsPublisher> {
+fn mdns_publisher(
+    port: u16,
+    node_id: NodeId,
+    cert_fingerprint: Option<&str>,
+) -> std::io::Result<MdnsPublisher> {
     let _ = mdns::Responder::new()?;
 
     let mut publisher = MdnsPublisher::init_publisher(port, node_id.to_string(), true);
+    if let Some(fingerprint) = cert_fingerprint {
+        publisher.add_txt("cert", fingerprint);
+    }
     publisher.start();
     Ok(publisher)
 }
 
+#[cfg(feature = "ssl")]
+fn bind_tls<H, F>(
+    server: HttpServer<H, F>,
+    c: &HubConfig,
+    work_dir: PathBuf,
+) -> Result<(HttpServer<H, F>, String), String>
+where
+    H: IntoHttpHandler + 'static,
+    F: Fn() -> H + Send + Clone + 'static,
+{
+    let (acceptor, fingerprint) = crate::tls::acceptor(&c.tls, &work_dir)?;
+    info!("Hub certificate fingerprint: {}", fingerprint);
+
+    let server = server
+        .bind_ssl(c.p2p_addr(), acceptor)
+        .map_err(|e| format!("TLS socket binding err: {}", e))?;
+    Ok((server, fingerprint))
+}
+
+#[cfg(not(feature = "ssl"))]
+fn bind_tls<H, F>(
+    _server: HttpServer<H, F>,
+    _c: &HubConfig,
+    _work_dir: PathBuf,
+) -> Result<(HttpServer<H, F>, String), String>
+where
+    H: IntoHttpHandler + 'static,
+    F: Fn() -> H + Send + Clone + 'static,
+{
+    Err("TLS is enabled, but gu-hub was built without the ssl feature".to_string())
+}
+
 fn chat_route(
     req: &actix_web::HttpRequest<NodeId>,
 ) -> Result<actix_web::HttpResponse, actix_web::Error> {
@@ -202,32 +256,49 @@ impl<D: Decorator + 'static + Sync + Send> ServerConfigurer<D> {
             None => {}
         }
 
-        let server = actix_web::server::new(move || {
-            decorator.decorate_webapp(
-                actix_web::App::with_state(node_id)
-                    .middleware(actix_web::middleware::Logger::default())
-                    .handler(
-                        "/app",
-                        actix_web::fs::StaticFiles::new("webapp")
-                            .expect("cannot provide static files"),
-                    )
-                    .scope("/m", mock::scope)
-                    .resource("/ws/", |r| r.route().f(chat_route))
-                    .resource("/node_id/", |r| {
-                        r.get().f(|req| {
-                            actix_web::HttpResponse::with_body(
-                                actix_web::http::StatusCode::OK,
-                                format!(
-                                    "{} {}",
-                                    req.state().to_string(),
-                                    hostname::get_hostname().unwrap_or("unknown".to_string())
-                                ),
-                            )
-                        });
-                    }),
-            )
-        });
-        match server.bind(c.p2p_addr()) {
+        // Requests on the public port always need a token, also when they come from
+        // loopback through a reverse proxy; only the loopback listener trusts its clients.
+        let app = move |trust_local: bool| {
+            let decorator = decorator.clone();
+            move || {
+                decorator.decorate_webapp(
+                    actix_web::App::with_state(node_id)
+                        .middleware(actix_web::middleware::Logger::default())
+                        .middleware(ApiAuth::new(trust_local))
+                        .handler(
+                            "/app",
+                            actix_web::fs::StaticFiles::new("webapp")
+                                .expect("cannot provide static files"),
+                        )
+                        .scope("/m", mock::scope)
+                        .resource("/ws/", |r| r.route().f(chat_route))
+                        .resource("/node_id/", |r| {
+                            r.get().f(|req| {
+                                actix_web::HttpResponse::with_body(
+                                    actix_web::http::StatusCode::OK,
+                                    format!(
+                                        "{} {}",
+                                        req.state().to_string(),
+                                        hostname::get_hostname().unwrap_or("unknown".to_string())
+                                    ),
+                                )
+                            });
+                        }),
+                )
+            }
+        };
+        let local_server = actix_web::server::new(app(c.auth.trust_local))
+            .bind(("127.0.0.1", c.auth.local_port))
+            .map_err(|e| format!("local socket binding err: {}", e))?;
+        let server = actix_web::server::new(app(false));
+        let (server, cert_fingerprint) = if c.tls.enabled {
+            let (server, fingerprint) = bind_tls(server, &c, config_module.work_dir())?;
+            (Ok(server), Some(fingerprint))
+        } else {
+            (server.bind(c.p2p_addr()), None)
+        };
+
+        match server {
             Err(e) => {
                 for addr in c.p2p_addr().to_socket_addrs().unwrap() {
                     return Err(format!("P2P socket binding for {} err: {}", addr, e));
@@ -235,12 +306,19 @@ impl<D: Decorator + 'static + Sync + Send> ServerConfigurer<D> {
             }
             Ok(server) => {
                 server.start();
-                ()
+                local_server.start();
+                let _ = crate::inventory::PeerInventory::from_registry();
+                let _ = crate::webhooks::WebhookManager::from_registry();
+                let _ = crate::image_peers::ImagePeers::from_registry();
             }
         };
 
         if c.publish_service {
-            match mdns_publisher(c.p2p_port, node_id) {
+            match mdns_publisher(
+                c.p2p_port,
+                node_id,
+                cert_fingerprint.as_ref().map(String::as_str),
+            ) {
                 // we use Box::leak to prevent publisher from being dropped
                 Ok(publisher) => {
                     Box::leak(Box::new(publisher));
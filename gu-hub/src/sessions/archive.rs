@@ -0,0 +1,344 @@
+//! Session export and import.
+//!
+//! Archive is a tar with the session directory entries (`.info`, `.json`, `.blobs`,
+//! `.images` and numbered blob files), peer records and a manifest with blob SHA1 checksums.
+//!
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    thread,
+};
+
+use bytes::Bytes;
+use futures::{prelude::*, sync::mpsc, sync::oneshot};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+
+use gu_net::NodeId;
+
+use super::{responses::SessionErr, session::entries_id_iter};
+
+const SESSION_FILES: &[&str] = &[".info", ".json", ".blobs", ".images"];
+const PEERS_ENTRY: &str = ".peers";
+const MANIFEST_ENTRY: &str = ".manifest";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerRecord {
+    pub node_id: NodeId,
+    #[serde(default)]
+    pub deployments: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    blobs: BTreeMap<u64, BlobEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlobEntry {
+    sha1: String,
+    size: u64,
+}
+
+struct HashingReader<R> {
+    inner: R,
+    sha1: Sha1,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.sha1.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+fn append_bytes<W: Write>(
+    builder: &mut tar::Builder<W>,
+    name: &str,
+    bytes: &[u8],
+) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(bytes.len() as u64);
+    header.set_mode(0o644);
+    builder.append_data(&mut header, name, bytes)
+}
+
+/// Writes session directory content as a tar archive.
+pub fn write_archive<W: Write>(session_dir: &Path, peers: &[PeerRecord], out: W) -> io::Result<W> {
+    let mut builder = tar::Builder::new(out);
+    let mut manifest = Manifest::default();
+
+    for name in SESSION_FILES {
+        let path = session_dir.join(name);
+        if path.exists() {
+            builder.append_path_with_name(&path, name)?;
+        }
+    }
+
+    for blob_id in entries_id_iter(&session_dir.to_path_buf()) {
+        let file = File::open(session_dir.join(blob_id.to_string()))?;
+        let size = file.metadata()?.len();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(size);
+        header.set_mode(0o644);
+
+        let mut reader = HashingReader {
+            inner: file.take(size),
+            sha1: Sha1::new(),
+        };
+        builder.append_data(&mut header, blob_id.to_string(), &mut reader)?;
+        manifest.blobs.insert(
+            blob_id,
+            BlobEntry {
+                sha1: reader.sha1.digest().to_string(),
+                size,
+            },
+        );
+    }
+
+    append_bytes(&mut builder, PEERS_ENTRY, &serde_json::to_vec(peers)?)?;
+    append_bytes(
+        &mut builder,
+        MANIFEST_ENTRY,
+        &serde_json::to_vec(&manifest)?,
+    )?;
+
+    builder.into_inner()
+}
+
+/// Unpacks archive into an empty session directory, verifying blob checksums.
+pub fn unpack_archive<R: Read>(
+    input: R,
+    session_dir: &Path,
+) -> Result<Vec<PeerRecord>, SessionErr> {
+    let invalid = |e: io::Error| SessionErr::InvalidArchive(e.to_string());
+    let mut archive = tar::Archive::new(input);
+    let mut manifest = None;
+    let mut peers = Vec::new();
+    let mut hashes = BTreeMap::new();
+
+    for entry in archive.entries().map_err(invalid)? {
+        let mut entry = entry.map_err(invalid)?;
+        let name = entry
+            .path()
+            .map_err(invalid)?
+            .to_string_lossy()
+            .into_owned();
+
+        if SESSION_FILES.contains(&name.as_str()) {
+            let mut file = File::create(session_dir.join(&name))
+                .map_err(|e| SessionErr::FileError(e.to_string()))?;
+            io::copy(&mut entry, &mut file).map_err(invalid)?;
+        } else if name == PEERS_ENTRY {
+            peers = serde_json::from_reader(&mut entry)
+                .map_err(|e| SessionErr::InvalidArchive(e.to_string()))?;
+        } else if name == MANIFEST_ENTRY {
+            manifest = Some(
+                serde_json::from_reader::<_, Manifest>(&mut entry)
+                    .map_err(|e| SessionErr::InvalidArchive(e.to_string()))?,
+            );
+        } else if let Ok(blob_id) = name.parse::<u64>() {
+            let mut file = File::create(session_dir.join(blob_id.to_string()))
+                .map_err(|e| SessionErr::FileError(e.to_string()))?;
+            let mut reader = HashingReader {
+                inner: &mut entry,
+                sha1: Sha1::new(),
+            };
+            io::copy(&mut reader, &mut file).map_err(invalid)?;
+            hashes.insert(blob_id, reader.sha1.digest().to_string());
+        } else {
+            warn!("Skipping unknown session archive entry: {}", name);
+        }
+    }
+
+    let manifest =
+        manifest.ok_or_else(|| SessionErr::InvalidArchive("missing manifest".to_string()))?;
+    if !session_dir.join(".info").exists() {
+        return Err(SessionErr::InvalidArchive(
+            "missing session info".to_string(),
+        ));
+    }
+    if hashes.len() != manifest.blobs.len() {
+        return Err(SessionErr::InvalidArchive(
+            "blob list does not match manifest".to_string(),
+        ));
+    }
+    for (blob_id, entry) in manifest.blobs {
+        if hashes.get(&blob_id) != Some(&entry.sha1) {
+            return Err(SessionErr::InvalidArchive(format!(
+                "blob {} checksum mismatch",
+                blob_id
+            )));
+        }
+    }
+    if !session_dir.join(".json").exists() {
+        fs::write(session_dir.join(".json"), b"{\"version\":0}")
+            .map_err(|e| SessionErr::FileError(e.to_string()))?;
+    }
+
+    Ok(peers)
+}
+
+/// `io::Write` adapter sending written data to a channel.
+struct ChannelWriter(futures::sink::Wait<mpsc::Sender<Bytes>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .send(Bytes::from(buf))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "export stream closed"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0
+            .flush()
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "export stream closed"))
+    }
+}
+
+/// Streams the session archive; the archive is built on a separate thread.
+pub fn stream_archive(session_dir: PathBuf, peers: Vec<PeerRecord>) -> mpsc::Receiver<Bytes> {
+    let (tx, rx) = mpsc::channel(16);
+
+    thread::spawn(move || {
+        if let Err(e) = write_archive(&session_dir, &peers, ChannelWriter(tx.wait())) {
+            error!("Session export from {:?} failed: {}", session_dir, e);
+        }
+    });
+
+    rx
+}
+
+/// Unpacks the archive on a separate thread.
+pub fn unpack_archive_async(
+    archive_path: PathBuf,
+    session_dir: PathBuf,
+) -> impl Future<Item = Vec<PeerRecord>, Error = SessionErr> {
+    let (tx, rx) = oneshot::channel();
+
+    thread::spawn(move || {
+        let result = File::open(&archive_path)
+            .map_err(|e| SessionErr::FileError(e.to_string()))
+            .and_then(|file| unpack_archive(file, &session_dir));
+        let _ = tx.send(result);
+    });
+
+    rx.map_err(|_| SessionErr::FileError("session import interrupted".to_string()))
+        .and_then(|result| result)
+}
+
+/// Exports session directory to a file, used by the offline backup command.
+pub fn export_to_file(sessions_dir: &Path, session_id: u64, out: &Path) -> io::Result<()> {
+    let session_dir = sessions_dir.join(session_id.to_string());
+    if !session_dir.join(".info").exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("session {} not found", session_id),
+        ));
+    }
+
+    write_archive(&session_dir, &[], File::create(out)?)?.sync_all()
+}
+
+/// Imports session from a file into the sessions directory, returns the new session id.
+///
+/// Hub must not be running, as the new id is picked from the sessions directory content.
+pub fn import_from_file(sessions_dir: &Path, archive_path: &Path) -> Result<u64, SessionErr> {
+    let file = File::open(archive_path).map_err(|e| SessionErr::FileError(e.to_string()))?;
+    fs::create_dir_all(sessions_dir)
+        .map_err(|e| SessionErr::DirectoryCreationError(e.to_string()))?;
+    let staging = tempfile::Builder::new()
+        .prefix(".import")
+        .tempdir_in(sessions_dir)
+        .map_err(|e| SessionErr::DirectoryCreationError(e.to_string()))?;
+    let peers = unpack_archive(file, staging.path())?;
+    if !peers.is_empty() {
+        warn!("Session peers are not restored by offline import");
+    }
+
+    let session_id = entries_id_iter(&sessions_dir.to_path_buf())
+        .max()
+        .map(|id| id + 1)
+        .unwrap_or(0);
+    fs::rename(staging.path(), sessions_dir.join(session_id.to_string()))
+        .map_err(|e| SessionErr::FileError(e.to_string()))?;
+
+    Ok(session_id)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn session_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(".info"), b"{\"name\": \"test\"}").unwrap();
+        fs::write(dir.path().join(".json"), b"{\"version\": 3, \"key\": 1}").unwrap();
+        fs::write(dir.path().join("0"), b"first blob").unwrap();
+        fs::write(dir.path().join("2"), vec![7u8; 10_000]).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_export_import() {
+        let exported = session_dir();
+        let peers = vec![PeerRecord {
+            node_id: NodeId::from([1u8; 20]),
+            deployments: vec!["hd::1".to_string()],
+        }];
+        let archive = write_archive(exported.path(), &peers, Vec::new()).unwrap();
+
+        let imported = tempfile::tempdir().unwrap();
+        let imported_peers = unpack_archive(archive.as_slice(), imported.path()).unwrap();
+
+        assert_eq!(imported_peers.len(), 1);
+        assert_eq!(imported_peers[0].node_id, peers[0].node_id);
+        assert_eq!(imported_peers[0].deployments, peers[0].deployments);
+        for name in &[".info", ".json", "0", "2"] {
+            assert_eq!(
+                fs::read(imported.path().join(name)).unwrap(),
+                fs::read(exported.path().join(name)).unwrap(),
+                "{} differs",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_import_checks_blobs() {
+        let exported = session_dir();
+        let mut archive = write_archive(exported.path(), &[], Vec::new()).unwrap();
+
+        // corrupt the content of blob 0
+        let content = archive
+            .windows(b"first blob".len())
+            .position(|w| w == b"first blob")
+            .unwrap();
+        archive[content] = b'F';
+
+        let imported = tempfile::tempdir().unwrap();
+        match unpack_archive(archive.as_slice(), imported.path()) {
+            Err(SessionErr::InvalidArchive(e)) => assert!(e.contains("checksum")),
+            _ => panic!("tampered blob accepted"),
+        }
+    }
+
+    #[test]
+    fn test_import_requires_manifest() {
+        let mut builder = tar::Builder::new(Vec::new());
+        append_bytes(&mut builder, ".info", b"{}").unwrap();
+        let archive = builder.into_inner().unwrap();
+
+        let imported = tempfile::tempdir().unwrap();
+        assert!(unpack_archive(archive.as_slice(), imported.path()).is_err());
+    }
+}
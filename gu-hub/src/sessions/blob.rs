@@ -7,6 +7,10 @@ use std::{
     io,
     ops::Deref,
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
 };
 
 use actix::prelude::*;
@@ -245,6 +249,31 @@ fn write_dag_future(
         .map_err(|_| SessionErr::FileError("Lock on writer???!!!".to_string()))
 }
 
+fn limit_payload<Payload, Error>(
+    payload: Payload,
+    limit: Option<u64>,
+    exceeded: Arc<AtomicBool>,
+) -> impl Stream<Item = bytes::Bytes, Error = String>
+where
+    Payload: Stream<Item = bytes::Bytes, Error = Error>,
+    Error: Debug,
+{
+    let mut written = 0u64;
+
+    payload
+        .map_err(|e| format!("{:?}", e))
+        .and_then(move |chunk| {
+            written += chunk.len() as u64;
+            match limit {
+                Some(limit) if written > limit => {
+                    exceeded.store(true, Ordering::SeqCst);
+                    Err("storage quota exceeded".to_string())
+                }
+                _ => Ok(chunk),
+            }
+        })
+}
+
 #[derive(Clone)]
 pub struct Blob {
     path: PathBuf,
@@ -273,19 +302,48 @@ impl Blob {
         self.path.as_ref()
     }
 
+    /// Current size of the blob file in bytes.
+    pub fn size(&self) -> u64 {
+        fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0)
+    }
+
+    /// Appends payload to the blob.
+    ///
+    /// When `limit` is given and the payload is larger, the upload is rolled back
+    /// and `SessionErr::QuotaExceeded` is returned.
     pub fn write<Payload, Error>(
         self,
         fut: Payload,
+        limit: Option<u64>,
     ) -> impl Future<Item = SessionOk, Error = SessionErr>
     where
         Payload: Stream<Item = bytes::Bytes, Error = Error>,
         Error: Debug,
     {
+        let exceeded = Arc::new(AtomicBool::new(false));
+        let payload = limit_payload(fut, limit, exceeded.clone());
+
         self.lock
             .send(WriteAccessRequest)
             .flatten_fut()
             .and_then(move |_access: WriteAccess| {
-                write_async(fut, self.path.clone()).map_err(|e| SessionErr::FileError(e))
+                let initial_size = self.size();
+                let path = self.path.clone();
+
+                write_async(payload, self.path.clone()).then(move |r| match r {
+                    Ok(()) => Ok(()),
+                    Err(_) if exceeded.load(Ordering::SeqCst) => {
+                        let _ = fs::OpenOptions::new()
+                            .write(true)
+                            .open(&path)
+                            .and_then(|f| f.set_len(initial_size));
+                        Err(SessionErr::QuotaExceeded(format!(
+                            "upload larger than {} bytes",
+                            limit.unwrap_or_default()
+                        )))
+                    }
+                    Err(e) => Err(SessionErr::FileError(e)),
+                })
             })
             .and_then(|_a| Ok(SessionOk::Ok))
     }
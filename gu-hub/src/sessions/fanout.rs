@@ -0,0 +1,260 @@
+//! Fan-out of deployment operations over hub session peers.
+//!
+
+use actix::prelude::*;
+use futures::{future, prelude::*, stream};
+
+use gu_model::{
+    envman::{Command, GenericCreateSession, SessionUpdate},
+    session::{PeerDeploymentResult, PeerSelector},
+    Tags,
+};
+use gu_net::{
+    rpc::{peer, peer::PeerInfo},
+    NodeId,
+};
+
+use super::responses::SessionErr;
+use crate::{
+    image_peers,
+    inventory::{PeerInventory, SelectPeers, Unschedulable},
+};
+
+pub const DEFAULT_CONCURRENCY: usize = 8;
+
+/// Filters session peers with the selector.
+pub fn select_peers(
+    peers: Vec<NodeId>,
+    selector: PeerSelector,
+) -> impl Future<Item = Vec<NodeId>, Error = SessionErr> {
+    if selector.is_empty() {
+        return future::Either::A(future::ok(peers));
+    }
+    let PeerSelector { tags, selector } = selector;
+
+    let labeled = match selector {
+        None => future::Either::A(future::ok::<_, MailboxError>(None)),
+        Some(selector) => future::Either::B(
+            PeerInventory::from_registry()
+                .send(SelectPeers(selector))
+                .map(Some),
+        ),
+    };
+
+    future::Either::B(
+        labeled
+            .and_then(|labeled| {
+                peer::PeerManager::from_registry()
+                    .send(peer::ListPeers)
+                    .map(move |connected| (labeled, connected))
+            })
+            .from_err()
+            .and_then(move |(labeled, connected)| {
+                Ok(filter_peers(peers, labeled.as_ref(), &connected, &tags))
+            }),
+    )
+}
+
+/// Connected peers with all the tags, limited to the labeled ones if given.
+fn filter_peers(
+    peers: Vec<NodeId>,
+    labeled: Option<&Vec<NodeId>>,
+    connected: &[PeerInfo],
+    tags: &Tags,
+) -> Vec<NodeId> {
+    peers
+        .into_iter()
+        .filter(|node_id| {
+            labeled.map_or(true, |l| l.contains(node_id))
+                && connected.iter().any(|info| {
+                    info.node_id == *node_id && tags.iter().all(|tag| info.tags.contains(tag))
+                })
+        })
+        .collect()
+}
+
+/// Splits off deployments running a task, with an error result for each of them.
+pub fn split_busy<F>(
+    deployments: Vec<(NodeId, String)>,
+    running_task: F,
+) -> (Vec<(NodeId, String)>, Vec<PeerDeploymentResult>)
+where
+    F: Fn(NodeId, &str) -> Option<u64>,
+{
+    let mut busy = Vec::new();
+    let free = deployments
+        .into_iter()
+        .filter(
+            |(node_id, deployment_id)| match running_task(*node_id, deployment_id) {
+                Some(task_id) => {
+                    busy.push(PeerDeploymentResult {
+                        node_id: *node_id,
+                        deployment_id: Some(deployment_id.clone()),
+                        output: Vec::new(),
+                        error: Some(
+                            SessionErr::DeploymentBusy(deployment_id.clone(), task_id).to_string(),
+                        ),
+                    });
+                    false
+                }
+                None => true,
+            },
+        )
+        .collect();
+    (free, busy)
+}
+
+fn run_limited<T, F, R>(
+    items: Vec<T>,
+    concurrency: Option<usize>,
+    f: F,
+) -> impl Future<Item = Vec<PeerDeploymentResult>, Error = SessionErr>
+where
+    F: FnMut(T) -> R,
+    R: Future<Item = PeerDeploymentResult, Error = SessionErr>,
+{
+    stream::iter_ok(items)
+        .map(f)
+        .buffer_unordered(concurrency.unwrap_or(DEFAULT_CONCURRENCY).max(1))
+        .collect()
+}
+
+/// Creates the deployment on every given peer, cordoned peers are skipped.
+pub fn create_deployments(
+    node_ids: Vec<NodeId>,
+    deployment: GenericCreateSession,
+    concurrency: Option<usize>,
+) -> impl Future<Item = Vec<PeerDeploymentResult>, Error = SessionErr> {
+    PeerInventory::from_registry()
+        .send(Unschedulable(node_ids.clone()))
+        .from_err()
+        .and_then(move |unschedulable| {
+            run_limited(node_ids, concurrency, move |node_id| {
+                if unschedulable.contains(&node_id) {
+                    return future::Either::A(future::ok(PeerDeploymentResult {
+                        node_id,
+                        deployment_id: None,
+                        output: Vec::new(),
+                        error: Some(SessionErr::PeerUnschedulable(node_id).to_string()),
+                    }));
+                }
+
+                future::Either::B(
+                    image_peers::create_session(node_id, deployment.clone()).then(move |result| {
+                        let (deployment_id, error) = match result {
+                            Ok(Ok(deployment_id)) => (Some(deployment_id), None),
+                            Ok(Err(e)) => (None, Some(e.to_string())),
+                            Err(e) => (None, Some(e.to_string())),
+                        };
+                        Ok(PeerDeploymentResult {
+                            node_id,
+                            deployment_id,
+                            output: Vec::new(),
+                            error,
+                        })
+                    }),
+                )
+            })
+        })
+}
+
+/// Runs the command batch on every given deployment.
+pub fn update_deployments(
+    deployments: Vec<(NodeId, String)>,
+    commands: Vec<Command>,
+    concurrency: Option<usize>,
+) -> impl Future<Item = Vec<PeerDeploymentResult>, Error = SessionErr> {
+    run_limited(deployments, concurrency, move |(node_id, deployment_id)| {
+        peer(node_id)
+            .into_endpoint()
+            .send(SessionUpdate {
+                session_id: deployment_id.clone(),
+                commands: commands.clone(),
+            })
+            .then(move |result| {
+                let (output, error) = match result {
+                    Ok(Ok(output)) => (output, None),
+                    Ok(Err(output)) => (output, Some("processing error".to_string())),
+                    Err(e) => (Vec::new(), Some(e.to_string())),
+                };
+                Ok(PeerDeploymentResult {
+                    node_id,
+                    deployment_id: Some(deployment_id),
+                    output,
+                    error,
+                })
+            })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn node(n: u8) -> NodeId {
+        NodeId::from([n; 20])
+    }
+
+    fn info(n: u8, tags: &[&str]) -> PeerInfo {
+        PeerInfo {
+            node_name: format!("node{}", n),
+            peer_addr: None,
+            node_id: node(n),
+            sessions: Vec::new(),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_filter_peers() {
+        let peers = vec![node(1), node(2), node(3)];
+        let connected = vec![info(1, &["gpu", "linux"]), info(2, &["linux"])];
+        let tags = |tags: &[&str]| tags.iter().map(|tag| tag.to_string()).collect::<Tags>();
+
+        assert_eq!(
+            filter_peers(peers.clone(), None, &connected, &Tags::new()),
+            vec![node(1), node(2)]
+        );
+        assert_eq!(
+            filter_peers(peers.clone(), None, &connected, &tags(&["gpu"])),
+            vec![node(1)]
+        );
+        assert_eq!(
+            filter_peers(
+                peers,
+                Some(&vec![node(2), node(3)]),
+                &connected,
+                &tags(&["linux"])
+            ),
+            vec![node(2)]
+        );
+    }
+
+    #[test]
+    fn test_split_busy() {
+        let deployments = vec![
+            (node(1), "a".to_string()),
+            (node(1), "b".to_string()),
+            (node(2), "c".to_string()),
+        ];
+
+        let (free, busy) = split_busy(deployments, |node_id, deployment_id| {
+            if node_id == node(1) && deployment_id == "b" {
+                Some(7)
+            } else {
+                None
+            }
+        });
+        assert_eq!(
+            free,
+            vec![(node(1), "a".to_string()), (node(2), "c".to_string())]
+        );
+        assert_eq!(busy.len(), 1);
+        assert_eq!(busy[0].node_id, node(1));
+        assert_eq!(busy[0].deployment_id, Some("b".to_string()));
+        assert_eq!(
+            busy[0].error,
+            Some(SessionErr::DeploymentBusy("b".to_string(), 7).to_string())
+        );
+    }
+}
@@ -3,22 +3,53 @@
 //! Manages hub session state.
 //!
 
-use std::{cmp, collections::HashMap, fs, path::PathBuf};
+use std::{
+    cmp,
+    collections::{BTreeSet, HashMap},
+    fs,
+    path::PathBuf,
+    time::Duration,
+};
 
 use actix::prelude::*;
-use futures::{Future, IntoFuture};
-use log::error;
+use futures::{sync::oneshot, Future, IntoFuture};
+use log::{error, info};
 use serde::{Deserialize, Serialize};
-
+use tempfile::TempDir;
+
+use gu_actix::prelude::*;
+use gu_model::{
+    events::{DeploymentState, HubEvent},
+    session::{
+        Metadata, PeerDeploymentResult, SessionCommandSpec, SessionDeploymentSpec, StorageUsage,
+    },
+    task::TaskSubmit,
+};
 use gu_net::NodeId;
-use gu_persist::config::ConfigModule;
+use gu_persist::config::{ConfigManager, ConfigModule, GetConfig};
 
 use super::session::Session;
 use super::{
+    archive::PeerRecord,
     blob::Blob,
+    fanout,
+    quota::{Reservations, StorageQuota, Usage},
     responses::{SessionErr, SessionResult},
     session::{entries_id_iter, SessionInfo},
+    tasks::Assignment,
 };
+use crate::events;
+use crate::server::HubConfig;
+
+/// How often blobs are checked for expired TTL.
+const BLOB_EXPIRE_INTERVAL: Duration = Duration::from_secs(60);
+/// How often pending tasks are matched with free deployments.
+const TASK_SCHEDULE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Directory with hub sessions data.
+pub fn sessions_dir() -> PathBuf {
+    ConfigModule::new().work_dir().join("hub-sessions")
+}
 
 #[derive(Default)]
 pub struct SessionsManager {
@@ -26,13 +57,36 @@ pub struct SessionsManager {
     path: PathBuf,
     next_id: u64,
     sessions: HashMap<u64, Session>,
+    quota: StorageQuota,
+    reservations: Reservations,
 }
 
 impl Actor for SessionsManager {
     type Context = Context<Self>;
 
-    fn started(&mut self, _ctx: &mut <Self as Actor>::Context) {
-        let path = ConfigModule::new().work_dir().join("hub-sessions");
+    fn started(&mut self, ctx: &mut <Self as Actor>::Context) {
+        ctx.spawn(
+            ConfigManager::from_registry()
+                .send(GetConfig::<HubConfig>::new())
+                .flatten_fut()
+                .into_actor(self)
+                .then(|config, act, _ctx| {
+                    match config {
+                        Ok(config) => act.quota = config.storage_quota.clone(),
+                        Err(e) => error!("Cannot load session storage quota: {}", e),
+                    }
+                    fut::ok(())
+                }),
+        );
+        ctx.run_interval(BLOB_EXPIRE_INTERVAL, |act, _ctx| act.delete_expired_blobs());
+        ctx.run_interval(TASK_SCHEDULE_INTERVAL, |act, ctx| {
+            let session_ids: Vec<u64> = act.sessions.keys().cloned().collect();
+            for session_id in session_ids {
+                act.schedule_tasks(session_id, ctx);
+            }
+        });
+
+        let path = sessions_dir();
 
         fs::DirBuilder::new()
             .recursive(true)
@@ -158,7 +212,7 @@ impl SessionsManager {
             None => self.next_id,
             Some(v) => v,
         };
-        self.next_id = cmp::max(id, self.next_id) + 1;
+        self.next_id = cmp::max(id + 1, self.next_id);
         self.version += 1;
 
         match self.sessions.insert(id, session) {
@@ -171,13 +225,132 @@ impl SessionsManager {
         &mut self,
         info: SessionInfo,
     ) -> impl Future<Item = u64, Error = SessionErr> {
-        let (session, _fut) = Session::new(info, self.path.join(format!("{}", self.next_id)));
+        let (session, fut) = Session::new(info, self.path.join(format!("{}", self.next_id)));
+        let id = self.create_session_inner(session, None);
+
+        fut.and_then(|_| id).map(|session_id| {
+            events::post(HubEvent::SessionCreated { session_id });
+            session_id
+        })
+    }
+
+    /// Registers an imported session loaded from its directory under `id`.
+    fn import_session(
+        &mut self,
+        id: u64,
+        mut session: Session,
+        peers: Vec<PeerRecord>,
+    ) -> Result<u64, SessionErr> {
+        let total = self.total_usage() + self.reservations.total();
+        self.quota.check_added_session(&total, &session.usage())?;
+        // Deployments belong to the exported session, only peers are restored.
+        session.add_peers(peers.into_iter().map(|peer| peer.node_id).collect());
+
+        let session_id = self.create_session_inner(session, Some(id))?;
+        events::post(HubEvent::SessionCreated { session_id });
+        Ok(session_id)
+    }
+
+    fn total_usage(&self) -> Usage {
+        self.sessions
+            .values()
+            .fold(Usage::default(), |usage, s| usage + s.usage())
+    }
+
+    pub fn create_blob(&mut self, id: u64, ttl: Option<u64>) -> Result<(u64, Blob), SessionErr> {
+        self.quota.global.check_new_blob(&self.total_usage())?;
+        let per_session = self.quota.per_session.clone();
+
+        self.session_mut_fn(id, |s| {
+            per_session.check_new_blob(&s.usage())?;
+            s.new_blob(ttl.map(|secs| chrono::Duration::seconds(secs as i64)))
+        })
+    }
+
+    /// Reserves quota for an upload to the session, see `Reservations::reserve`.
+    pub fn reserve_upload(
+        &mut self,
+        id: u64,
+        size: Option<u64>,
+    ) -> Result<Option<u64>, SessionErr> {
+        let total = self.total_usage();
+        let session = self.session_fn(id, |s| Ok(s.usage()))?;
+
+        self.reservations
+            .reserve(&self.quota, id, &total, &session, size)
+    }
 
-        self.create_session_inner(session, None).into_future()
+    pub fn release_upload(&mut self, id: u64, bytes: u64) {
+        self.reservations.release(id, bytes)
     }
 
-    pub fn create_blob(&mut self, id: u64) -> Result<(u64, Blob), SessionErr> {
-        self.session_mut_fn(id, |s| s.new_blob())
+    /// Starts pending tasks of the session on its free deployments.
+    fn schedule_tasks(&mut self, session_id: u64, ctx: &mut Context<Self>) {
+        let session = match self.sessions.get_mut(&session_id) {
+            Some(session) => session,
+            None => return,
+        };
+
+        for assignment in session.next_task_assignments() {
+            let Assignment {
+                task_id,
+                attempt,
+                node_id,
+                deployment_id,
+                commands,
+                timeout,
+            } = assignment;
+
+            let update = session.update_deployment(node_id, deployment_id.clone(), commands);
+            ctx.spawn(fut::wrap_future(update).then(
+                move |result, act: &mut SessionsManager, ctx| {
+                    let result = match result {
+                        Ok(Ok(output)) => Ok(output),
+                        Ok(Err(output)) => Err(output.join("\n")),
+                        Err(e) => Err(e.to_string()),
+                    };
+                    if let Some(session) = act.sessions.get_mut(&session_id) {
+                        session.tasks_mut().finish(
+                            node_id,
+                            deployment_id,
+                            task_id,
+                            attempt,
+                            result,
+                        );
+                    }
+                    act.schedule_tasks(session_id, ctx);
+                    fut::ok(())
+                },
+            ));
+
+            if let Some(timeout) = timeout {
+                ctx.run_later(timeout, move |act, ctx| {
+                    let timed_out = act
+                        .sessions
+                        .get_mut(&session_id)
+                        .map(|session| session.tasks_mut().time_out(task_id, attempt))
+                        .unwrap_or(false);
+                    if timed_out {
+                        act.schedule_tasks(session_id, ctx);
+                    }
+                });
+            }
+        }
+    }
+
+    fn delete_expired_blobs(&mut self) {
+        let now = chrono::Utc::now();
+
+        for (session_id, session) in self.sessions.iter_mut() {
+            let expired = session.delete_expired_blobs(now);
+            if !expired.is_empty() {
+                self.version += 1;
+                info!(
+                    "Session {}: deleted expired blobs {:?}",
+                    session_id, expired
+                );
+            }
+        }
     }
 
     pub fn set_blob(&mut self, id: u64, b_id: u64, blob: Blob) -> SessionResult {
@@ -205,17 +378,23 @@ impl Supervised for SessionsManager {}
 impl SystemService for SessionsManager {}
 
 #[derive(Message)]
-#[rtype(result = "Result<Vec<(u64, SessionInfo)>, SessionErr>")]
+#[rtype(result = "Result<Vec<(u64, SessionInfo, StorageUsage)>, SessionErr>")]
 pub struct List;
 
 impl Handler<List> for SessionsManager {
-    type Result = Result<Vec<(u64, SessionInfo)>, SessionErr>;
+    type Result = Result<Vec<(u64, SessionInfo, StorageUsage)>, SessionErr>;
 
     fn handle(&mut self, _msg: List, _ctx: &mut Context<Self>) -> Self::Result {
         Ok(self
             .sessions
             .iter()
-            .map(|(session_id, session)| (*session_id, session.info()))
+            .map(|(session_id, session)| {
+                (
+                    *session_id,
+                    session.info(),
+                    self.quota.per_session.describe(&session.usage()),
+                )
+            })
             .collect())
     }
 }
@@ -242,21 +421,77 @@ impl Handler<Delete> for SessionsManager {
             Ok(_) => (),
             Err(e) => return ActorResponse::reply(Err(SessionErr::FileError(e.to_string()))),
         }
+        events::post(HubEvent::SessionDeleted {
+            session_id: msg.session_id,
+        });
         ActorResponse::r#async(session.drop_deployments().into_actor(self))
     }
 }
 
+/// Hashes of images deployed in all sessions.
+#[derive(Message)]
+#[rtype(result = "BTreeSet<String>")]
+pub struct ImageRefs;
+
+impl Handler<ImageRefs> for SessionsManager {
+    type Result = MessageResult<ImageRefs>;
+
+    fn handle(&mut self, _msg: ImageRefs, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(
+            self.sessions
+                .values()
+                .flat_map(|session| session.images().iter().cloned())
+                .collect(),
+        )
+    }
+}
+
 #[derive(Message)]
 #[rtype(result = "Result<(u64, Blob), SessionErr>")]
 pub struct CreateBlob {
     pub session: u64,
+    /// Blob time to live in seconds.
+    pub ttl: Option<u64>,
 }
 
 impl Handler<CreateBlob> for SessionsManager {
     type Result = Result<(u64, Blob), SessionErr>;
 
     fn handle(&mut self, msg: CreateBlob, _ctx: &mut Context<Self>) -> Self::Result {
-        self.create_blob(msg.session)
+        self.create_blob(msg.session, msg.ttl)
+    }
+}
+
+/// Reserves quota for an upload of `size` bytes (unknown if `None`) to the session.
+///
+/// Gets the upload limit (`None` if unlimited), which has to be given back
+/// with `ReleaseUpload` once the upload is over.
+#[derive(Message)]
+#[rtype(result = "Result<Option<u64>, SessionErr>")]
+pub struct ReserveUpload {
+    pub session: u64,
+    pub size: Option<u64>,
+}
+
+impl Handler<ReserveUpload> for SessionsManager {
+    type Result = Result<Option<u64>, SessionErr>;
+
+    fn handle(&mut self, msg: ReserveUpload, _ctx: &mut Context<Self>) -> Self::Result {
+        self.reserve_upload(msg.session, msg.size)
+    }
+}
+
+#[derive(Message)]
+pub struct ReleaseUpload {
+    pub session: u64,
+    pub bytes: u64,
+}
+
+impl Handler<ReleaseUpload> for SessionsManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: ReleaseUpload, _ctx: &mut Context<Self>) {
+        self.release_upload(msg.session, msg.bytes)
     }
 }
 
@@ -306,6 +541,15 @@ impl Handler<DeleteBlob> for SessionsManager {
     }
 }
 
+fn deployment_event(session_id: u64, node_id: NodeId, deployment_id: &str, state: DeploymentState) {
+    events::post(HubEvent::DeploymentChanged {
+        session_id,
+        node_id,
+        deployment_id: deployment_id.to_string(),
+        state,
+    })
+}
+
 #[derive(Message)]
 #[rtype(result = "Result<String, SessionErr>")]
 pub struct CreateDeployment {
@@ -338,9 +582,16 @@ impl Handler<CreateDeployment> for SessionsManager {
         if let Some(session) = self.sessions.get_mut(&msg.session_id) {
             ActorResponse::r#async(
                 fut::wrap_future(session.create_deployment(msg.node_id, msg.deployment_desc))
-                    .and_then(move |deployment_id, act: &mut SessionsManager, _ctx| {
+                    .and_then(move |deployment_id, act: &mut SessionsManager, ctx| {
                         if let Some(session) = act.sessions.get_mut(&session_id) {
                             session.add_deployment(node_id, deployment_id.clone());
+                            deployment_event(
+                                session_id,
+                                node_id,
+                                &deployment_id,
+                                DeploymentState::Created,
+                            );
+                            act.schedule_tasks(session_id, ctx);
                             fut::ok(deployment_id)
                         } else {
                             fut::err(SessionErr::SessionNotFoundError)
@@ -389,6 +640,12 @@ impl Handler<DeleteDeployment> for SessionsManager {
                         {
                             return fut::err(SessionErr::DeploymentNotFound(deployment_id));
                         }
+                        deployment_event(
+                            session_id,
+                            node_id,
+                            &deployment_id,
+                            DeploymentState::Deleted,
+                        );
                         fut::ok(())
                     }),
             )
@@ -427,14 +684,367 @@ impl Handler<UpdateDeployment> for SessionsManager {
     type Result = ActorResponse<SessionsManager, Result<Vec<String>, Vec<String>>, SessionErr>;
 
     fn handle(&mut self, msg: UpdateDeployment, _ctx: &mut Self::Context) -> Self::Result {
+        let session_id = msg.session_id;
+        let node_id = msg.node_id;
+        let deployment_id = msg.deployment_id.clone();
+
         if let Some(session) = self.sessions.get_mut(&msg.session_id) {
-            ActorResponse::r#async(fut::wrap_future(session.update_deployment(
-                msg.node_id,
-                msg.deployment_id,
-                msg.commands,
-            )))
+            ActorResponse::r#async(
+                fut::wrap_future(session.update_deployment(
+                    msg.node_id,
+                    msg.deployment_id,
+                    msg.commands,
+                ))
+                .map(move |result, _act: &mut SessionsManager, _ctx| {
+                    // failed commands may have left the deployment unchanged
+                    if result.is_ok() {
+                        deployment_event(
+                            session_id,
+                            node_id,
+                            &deployment_id,
+                            DeploymentState::Updated,
+                        );
+                    }
+                    result
+                }),
+            )
         } else {
             ActorResponse::reply(Err(SessionErr::SessionNotFoundError))
         }
     }
 }
+
+/// Waits until session config or blob set changes and gets the config with the session version.
+///
+/// Resolves immediately when the config version differs from `version` or the session
+/// version from `session_version`, otherwise after the next change or `timeout`.
+#[derive(Message)]
+#[rtype(result = "Result<(Metadata, u64), SessionErr>")]
+pub struct WatchConfig {
+    session_id: u64,
+    version: u64,
+    session_version: Option<u64>,
+    timeout: Duration,
+}
+
+impl WatchConfig {
+    pub fn new(
+        session_id: u64,
+        version: u64,
+        session_version: Option<u64>,
+        timeout: Duration,
+    ) -> WatchConfig {
+        WatchConfig {
+            session_id,
+            version,
+            session_version,
+            timeout,
+        }
+    }
+
+    /// Whether the session changed since the versions known to the watching client.
+    fn changed(&self, session: &Session) -> bool {
+        session.metadata().version != self.version
+            || self
+                .session_version
+                .map_or(false, |version| version != session.version())
+    }
+}
+
+impl Handler<WatchConfig> for SessionsManager {
+    type Result = ActorResponse<SessionsManager, (Metadata, u64), SessionErr>;
+
+    fn handle(&mut self, msg: WatchConfig, ctx: &mut Self::Context) -> Self::Result {
+        let session_id = msg.session_id;
+
+        let changed = match self.sessions.get_mut(&session_id) {
+            None => return ActorResponse::reply(Err(SessionErr::SessionNotFoundError)),
+            Some(session) if msg.changed(session) => {
+                return ActorResponse::reply(Ok((session.metadata().clone(), session.version())))
+            }
+            Some(session) => session.watch(),
+        };
+
+        let (timeout_tx, timeout_rx) = oneshot::channel();
+        ctx.run_later(msg.timeout, move |_act, _ctx| {
+            let _ = timeout_tx.send(());
+        });
+
+        ActorResponse::r#async(
+            changed
+                .select2(timeout_rx)
+                .then(|_| Ok::<(), SessionErr>(()))
+                .into_actor(self)
+                .and_then(move |_, act: &mut SessionsManager, _ctx| {
+                    fut::result(
+                        act.sessions
+                            .get(&session_id)
+                            .map(|session| (session.metadata().clone(), session.version()))
+                            .ok_or(SessionErr::SessionNotFoundError),
+                    )
+                }),
+        )
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<Vec<u64>, SessionErr>")]
+pub struct SubmitTasks {
+    session_id: u64,
+    submit: TaskSubmit,
+    blob_url: String,
+}
+
+impl SubmitTasks {
+    /// `blob_url` is the URL prefix under which providers can reach session blobs.
+    pub fn new(session_id: u64, submit: TaskSubmit, blob_url: String) -> SubmitTasks {
+        SubmitTasks {
+            session_id,
+            submit,
+            blob_url,
+        }
+    }
+}
+
+impl Handler<SubmitTasks> for SessionsManager {
+    type Result = Result<Vec<u64>, SessionErr>;
+
+    fn handle(&mut self, msg: SubmitTasks, ctx: &mut Self::Context) -> Self::Result {
+        let task_ids = self.session_mut_fn(msg.session_id, |session| {
+            session.submit_tasks(msg.submit, msg.blob_url)
+        })?;
+        self.schedule_tasks(msg.session_id, ctx);
+        Ok(task_ids)
+    }
+}
+
+/// Creates the same deployment on all selected session peers.
+#[derive(Message)]
+#[rtype(result = "Result<Vec<PeerDeploymentResult>, SessionErr>")]
+pub struct CreateDeployments {
+    session_id: u64,
+    spec: SessionDeploymentSpec,
+}
+
+impl CreateDeployments {
+    pub fn new(session_id: u64, spec: SessionDeploymentSpec) -> CreateDeployments {
+        CreateDeployments { session_id, spec }
+    }
+}
+
+impl Handler<CreateDeployments> for SessionsManager {
+    type Result = ActorResponse<SessionsManager, Vec<PeerDeploymentResult>, SessionErr>;
+
+    fn handle(&mut self, msg: CreateDeployments, _ctx: &mut Self::Context) -> Self::Result {
+        let session_id = msg.session_id;
+        let SessionDeploymentSpec {
+            deployment,
+            peers,
+            concurrency,
+        } = msg.spec;
+
+        let peer_ids = match self.sessions.get_mut(&session_id) {
+            Some(session) => {
+                session.add_image(&deployment.image);
+                session.peer_ids()
+            }
+            None => return ActorResponse::reply(Err(SessionErr::SessionNotFoundError)),
+        };
+
+        ActorResponse::r#async(
+            fut::wrap_future(
+                fanout::select_peers(peer_ids, peers).and_then(move |node_ids| {
+                    fanout::create_deployments(node_ids, deployment, concurrency)
+                }),
+            )
+            .map(move |results, act: &mut SessionsManager, ctx| {
+                if let Some(session) = act.sessions.get_mut(&session_id) {
+                    for result in &results {
+                        if let Some(ref deployment_id) = result.deployment_id {
+                            session.add_deployment(result.node_id, deployment_id.clone());
+                            deployment_event(
+                                session_id,
+                                result.node_id,
+                                deployment_id,
+                                DeploymentState::Created,
+                            );
+                        }
+                    }
+                }
+                act.schedule_tasks(session_id, ctx);
+                results
+            }),
+        )
+    }
+}
+
+/// Runs a command batch on session deployments of all selected peers.
+#[derive(Message)]
+#[rtype(result = "Result<Vec<PeerDeploymentResult>, SessionErr>")]
+pub struct UpdateDeployments {
+    session_id: u64,
+    spec: SessionCommandSpec,
+}
+
+impl UpdateDeployments {
+    pub fn new(session_id: u64, spec: SessionCommandSpec) -> UpdateDeployments {
+        UpdateDeployments { session_id, spec }
+    }
+}
+
+impl Handler<UpdateDeployments> for SessionsManager {
+    type Result = ActorResponse<SessionsManager, Vec<PeerDeploymentResult>, SessionErr>;
+
+    fn handle(&mut self, msg: UpdateDeployments, _ctx: &mut Self::Context) -> Self::Result {
+        let session_id = msg.session_id;
+        let SessionCommandSpec {
+            commands,
+            peers,
+            deployments,
+            concurrency,
+        } = msg.spec;
+
+        let peer_ids = match self.sessions.get(&session_id) {
+            Some(session) => session.peer_ids(),
+            None => return ActorResponse::reply(Err(SessionErr::SessionNotFoundError)),
+        };
+
+        ActorResponse::r#async(
+            fut::wrap_future(fanout::select_peers(peer_ids, peers))
+                .and_then(move |node_ids, act: &mut SessionsManager, _ctx| {
+                    // deployments running a task are skipped, the others get no tasks
+                    // until the batch completes
+                    let (targets, busy) = match act.sessions.get_mut(&session_id) {
+                        Some(session) => {
+                            let (targets, busy) = fanout::split_busy(
+                                session.peer_deployments(&node_ids, &deployments),
+                                |node_id, deployment_id| {
+                                    session.tasks().running_task(node_id, deployment_id)
+                                },
+                            );
+                            session.tasks_mut().reserve(&targets);
+                            (targets, busy)
+                        }
+                        None => (Vec::new(), Vec::new()),
+                    };
+                    fut::wrap_future(fanout::update_deployments(
+                        targets.clone(),
+                        commands,
+                        concurrency,
+                    ))
+                    .then(move |results, act: &mut SessionsManager, ctx| {
+                        if let Some(session) = act.sessions.get_mut(&session_id) {
+                            session.tasks_mut().release(&targets);
+                        }
+                        act.schedule_tasks(session_id, ctx);
+                        fut::result(results.map(|mut results: Vec<_>| {
+                            results.extend(busy);
+                            results
+                        }))
+                    })
+                })
+                .map(move |results, _act: &mut SessionsManager, _ctx| {
+                    for result in results.iter().filter(|result| result.error.is_none()) {
+                        if let Some(ref deployment_id) = result.deployment_id {
+                            deployment_event(
+                                session_id,
+                                result.node_id,
+                                deployment_id,
+                                DeploymentState::Updated,
+                            );
+                        }
+                    }
+                    results
+                }),
+        )
+    }
+}
+
+/// Gets session directory and peer records for the session export.
+#[derive(Message)]
+#[rtype(result = "Result<(PathBuf, Vec<PeerRecord>), SessionErr>")]
+pub struct ExportSession {
+    session_id: u64,
+}
+
+impl ExportSession {
+    pub fn new(session_id: u64) -> ExportSession {
+        ExportSession { session_id }
+    }
+}
+
+impl Handler<ExportSession> for SessionsManager {
+    type Result = Result<(PathBuf, Vec<PeerRecord>), SessionErr>;
+
+    fn handle(&mut self, msg: ExportSession, _ctx: &mut Self::Context) -> Self::Result {
+        self.session_fn(msg.session_id, |session| {
+            Ok((session.path().clone(), session.peer_records()))
+        })
+    }
+}
+
+/// Creates a session from an archive unpacked to the staging directory.
+#[derive(Message)]
+#[rtype(result = "Result<u64, SessionErr>")]
+pub struct ImportSession {
+    staging: TempDir,
+    peers: Vec<PeerRecord>,
+}
+
+impl ImportSession {
+    pub fn new(staging: TempDir, peers: Vec<PeerRecord>) -> ImportSession {
+        ImportSession { staging, peers }
+    }
+}
+
+impl Handler<ImportSession> for SessionsManager {
+    type Result = ActorResponse<SessionsManager, u64, SessionErr>;
+
+    fn handle(&mut self, msg: ImportSession, _ctx: &mut Self::Context) -> Self::Result {
+        // the id is taken now, so that sessions created meanwhile do not get it
+        let id = self.next_id;
+        self.next_id += 1;
+        let path = self.path.join(format!("{}", id));
+        if let Err(e) = fs::rename(msg.staging.path(), &path) {
+            return ActorResponse::reply(Err(SessionErr::FileError(e.to_string())));
+        }
+        let peers = msg.peers;
+
+        ActorResponse::r#async(
+            fut::wrap_future(Session::from_existing(path.clone()).map_err(SessionErr::FileError))
+                .then(move |session, act: &mut SessionsManager, _ctx| {
+                    let result = session.and_then(|session| act.import_session(id, session, peers));
+                    if result.is_err() {
+                        if let Err(e) = fs::remove_dir_all(&path) {
+                            error!("Cannot remove rejected session {:?}: {}", path, e);
+                        }
+                    }
+                    fut::result(result)
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_watch_config_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut session, _) = Session::new(SessionInfo::default(), dir.path().to_path_buf());
+        let timeout = Duration::from_secs(1);
+
+        let watch =
+            |version, session_version| WatchConfig::new(0, version, session_version, timeout);
+        assert!(!watch(0, None).changed(&session));
+        assert!(!watch(0, Some(session.version())).changed(&session));
+        assert!(watch(1, None).changed(&session));
+
+        // a change of the blob set shows only in the session version
+        let version = session.version();
+        let _ = session.delete_blob(0);
+        assert!(!watch(0, None).changed(&session));
+        assert!(watch(0, Some(version)).changed(&session));
+    }
+}
@@ -2,10 +2,16 @@
 //!
 //! Session aggregates resources.
 //!
+mod archive;
 mod blob;
+mod fanout;
 mod manager;
 mod module;
+mod quota;
 mod responses;
 mod session;
+mod tasks;
 
+pub(crate) use self::manager::{ImageRefs, SessionsManager};
 pub use self::module::SessionsModule;
+pub use self::quota::StorageQuota;
@@ -1,25 +1,127 @@
+use std::{path::PathBuf, time::Duration};
+
 use actix::SystemService;
 use actix_web::{
     error::{ErrorBadRequest, ErrorInternalServerError},
-    http::{Method, StatusCode},
+    http::{
+        header::{CONTENT_LENGTH, IF_MATCH},
+        Method, StatusCode,
+    },
     App, AsyncResponder, Error as ActixError, HttpMessage, HttpRequest, HttpResponse, Json, Path,
-    Responder, Result as ActixResult, Scope,
+    Query, Responder, Result as ActixResult, Scope,
+};
+use futures::{
+    future::{self, Future, IntoFuture},
+    stream::Stream,
 };
-use futures::{future::Future, stream::Stream};
 use serde::Deserialize;
 
 use gu_actix::prelude::*;
-use gu_base::Module;
+use gu_base::{
+    files::write_async, App as CliApp, AppSettings, Arg, ArgMatches, Decorator, Module, SubCommand,
+};
 use gu_model::deployment::DeploymentInfo;
-use gu_model::session::HubSessionSpec;
+use gu_model::events::HubEvent;
+use gu_model::session::{AddPeers, HubSessionSpec, SessionCommandSpec, SessionDeploymentSpec};
+use gu_model::task::TaskSubmit;
 use gu_net::NodeId;
 
-use super::{manager, manager::SessionsManager, responses::*, session::SessionInfo};
+use crate::events;
+use crate::inventory::{PeerInventory, SelectPeers, Unschedulable};
+
+use super::{archive, manager, manager::SessionsManager, responses::*, session::SessionInfo};
 
 #[derive(Default)]
-pub struct SessionsModule {}
+pub struct SessionsModule {
+    command: Command,
+}
+
+enum Command {
+    None,
+    Export(u64, PathBuf),
+    Import(PathBuf),
+}
+
+impl Default for Command {
+    fn default() -> Self {
+        Command::None
+    }
+}
 
 impl Module for SessionsModule {
+    fn args_declare<'a, 'b>(&self, app: CliApp<'a, 'b>) -> CliApp<'a, 'b> {
+        let file = Arg::with_name("FILE")
+            .help("Path to the session archive")
+            .required(true);
+
+        app.subcommand(
+            SubCommand::with_name("session")
+                .about("Backs up and restores hub sessions (the hub should not be running)")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommands(vec![
+                    SubCommand::with_name("export")
+                        .about("Exports the session to an archive")
+                        .arg(
+                            Arg::with_name("ID")
+                                .help("Session id")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(Arg::from(&file).index(2)),
+                    SubCommand::with_name("import")
+                        .about("Imports the session from an archive as a new session")
+                        .arg(Arg::from(&file).index(1)),
+                ]),
+        )
+    }
+
+    fn args_consume(&mut self, matches: &ArgMatches) -> bool {
+        if let Some(m) = matches.subcommand_matches("session") {
+            self.command = match m.subcommand() {
+                ("export", Some(m)) => {
+                    let session_id = m
+                        .value_of("ID")
+                        .expect("Lack of required `id` argument")
+                        .parse()
+                        .expect("Invalid session id");
+                    let file = m
+                        .value_of("FILE")
+                        .expect("Lack of required `file` argument");
+                    Command::Export(session_id, PathBuf::from(file))
+                }
+                ("import", Some(m)) => Command::Import(PathBuf::from(
+                    m.value_of("FILE")
+                        .expect("Lack of required `file` argument"),
+                )),
+                _ => Command::None,
+            };
+            match self.command {
+                Command::None => false,
+                _ => true,
+            }
+        } else {
+            false
+        }
+    }
+
+    fn run<D: Decorator + Clone + 'static>(&self, _decorator: D) {
+        match self.command {
+            Command::None => (),
+            Command::Export(session_id, ref file) => {
+                match archive::export_to_file(&manager::sessions_dir(), session_id, file) {
+                    Ok(()) => println!("Session {} exported to {}", session_id, file.display()),
+                    Err(e) => eprintln!("Cannot export session {}: {}", session_id, e),
+                }
+            }
+            Command::Import(ref file) => {
+                match archive::import_from_file(&manager::sessions_dir(), file) {
+                    Ok(session_id) => println!("{}", session_id),
+                    Err(e) => eprintln!("Cannot import {}: {}", file.display(), e),
+                }
+            }
+        }
+    }
+
     fn decorate_webapp<S: 'static>(&self, app: App<S>) -> App<S> {
         app.scope("/sessions", scope)
     }
@@ -39,12 +141,13 @@ fn scope<S: 'static>(scope: Scope<S>) -> Scope<S> {
                         Ok(HttpResponse::Ok().json(
                             sessions
                                 .into_iter()
-                                .map(|(session_id, session_info)| {
+                                .map(|(session_id, session_info, usage)| {
                                     gu_model::session::SessionDetails {
                                         id: session_id,
                                         created: Some(session_info.created),
                                         name: session_info.name,
                                         tags: session_info.tags.unwrap_or_default(),
+                                        usage: Some(usage),
                                         ..gu_model::session::SessionDetails::default()
                                     }
                                 })
@@ -56,6 +159,10 @@ fn scope<S: 'static>(scope: Scope<S>) -> Scope<S> {
                 cfg.limit(4096);
             });
         })
+        .resource("/import", |r| {
+            r.name("hub-sessions-import");
+            r.post().with_async(import_session);
+        })
         .resource("/{sessionId}", |r| {
             r.get().with_async(get_session);
             r.delete().with_async(|path: Path<SessionPath>| {
@@ -66,6 +173,10 @@ fn scope<S: 'static>(scope: Scope<S>) -> Scope<S> {
                     .and_then(|()| Ok(HttpResponse::NoContent()))
             })
         })
+        .resource("/{sessionId}/export", |r| {
+            r.name("hub-session-export");
+            r.get().with_async(export_session);
+        })
         .resource("/{sessionId}/config", |r| {
             r.name("hub-session-config");
             r.get().with_async(get_config);
@@ -120,6 +231,39 @@ fn scope<S: 'static>(scope: Scope<S>) -> Scope<S> {
                     })
             })
         })
+        .resource("/{sessionId}/deployments", |r| {
+            r.name("hub-session-deployments");
+            r.post().with_async(create_deployments);
+            r.method(Method::PATCH).with_async(update_deployments);
+        })
+        .resource("/{sessionId}/tasks", |r| {
+            r.name("hub-session-tasks");
+            r.get().with_async(list_tasks);
+            r.post().with_async(submit_tasks);
+        })
+        .resource("/{sessionId}/tasks/{taskId}", |r| {
+            r.name("hub-session-task");
+            r.get().with_async(|path: Path<SessionTaskPath>| {
+                let task_id = path.task_id;
+                SessionsManager::from_registry()
+                    .send(manager::Update::new(path.session_id, move |session| {
+                        session.tasks().status(task_id)
+                    }))
+                    .flatten_fut()
+                    .from_err::<ActixError>()
+                    .and_then(|status| Ok(HttpResponse::Ok().json(status)))
+            });
+            r.delete().with_async(|path: Path<SessionTaskPath>| {
+                let task_id = path.task_id;
+                SessionsManager::from_registry()
+                    .send(manager::Update::new(path.session_id, move |session| {
+                        session.tasks_mut().cancel(task_id)
+                    }))
+                    .flatten_fut()
+                    .from_err::<ActixError>()
+                    .and_then(|_status| Ok(HttpResponse::NoContent().finish()))
+            });
+        })
         .resource(
             "/{sessionId}/peers/{nodeId}/deployments/{deploymentId}",
             |r| {
@@ -166,6 +310,13 @@ struct SessionPeerDeploymentPath {
     deployment_id: String,
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionTaskPath {
+    session_id: u64,
+    task_id: u64,
+}
+
 fn session_id<S>(r: &HttpRequest<S>) -> ActixResult<u64> {
     get_param(r, "sessionId")
 }
@@ -208,68 +359,266 @@ fn get_session(
         .and_then(|session_details| Ok(HttpResponse::Ok().json(session_details)))
 }
 
-fn get_config(
+fn export_session(
     path: Path<SessionPath>,
 ) -> impl Future<Item = HttpResponse, Error = actix_web::Error> {
+    let session_id = path.session_id;
+
     SessionsManager::from_registry()
-        .send(manager::Update::new(path.session_id, |session| {
-            Ok(session.metadata().clone())
-        }))
+        .send(manager::ExportSession::new(session_id))
         .flatten_fut()
         .from_err()
-        .and_then(|metadata| Ok(HttpResponse::Ok().json(metadata)))
+        .and_then(move |(session_dir, peers)| {
+            Ok(HttpResponse::Ok()
+                .content_type("application/x-tar")
+                .header(
+                    "Content-Disposition",
+                    format!("attachment; filename=\"session-{}.tar\"", session_id),
+                )
+                .streaming(
+                    archive::stream_archive(session_dir, peers)
+                        .map_err(|_| ErrorInternalServerError("session export failed")),
+                ))
+        })
 }
 
-fn set_config(
-    (path, body): (Path<SessionPath>, Json<gu_model::session::Metadata>),
+fn import_session<S: 'static>(
+    r: HttpRequest<S>,
 ) -> impl Future<Item = HttpResponse, Error = actix_web::Error> {
-    let new_metadata = body.into_inner();
-    SessionsManager::from_registry()
-        .send(manager::Update::new(path.session_id, |session| {
-            session.set_metadata(new_metadata)
-        }))
-        .flatten_fut()
+    let sessions_dir = manager::sessions_dir();
+    let staging = tempfile::Builder::new()
+        .prefix(".import")
+        .tempdir_in(&sessions_dir);
+    let archive_file = tempfile::Builder::new()
+        .prefix(".import")
+        .suffix(".tar")
+        .tempfile_in(&sessions_dir);
+    let (staging, archive_file) = match (staging, archive_file) {
+        (Ok(staging), Ok(archive_file)) => (staging, archive_file),
+        (Err(e), _) | (_, Err(e)) => {
+            return future::Either::A(future::err(ErrorInternalServerError(e)))
+        }
+    };
+    let archive_path = archive_file.path().to_path_buf();
+    let staging_path = staging.path().to_path_buf();
+
+    future::Either::B(
+        write_async(r.payload(), archive_path.clone())
+            .map_err(SessionErr::FileError)
+            .and_then(move |()| archive::unpack_archive_async(archive_path, staging_path))
+            .and_then(move |peers| {
+                drop(archive_file);
+                SessionsManager::from_registry()
+                    .send(manager::ImportSession::new(staging, peers))
+                    .flatten_fut()
+            })
+            .from_err()
+            .and_then(|session_id| {
+                Ok(HttpResponse::build(StatusCode::CREATED)
+                    .header("Location", format!("/sessions/{}", session_id))
+                    .json(session_id))
+            }),
+    )
+}
+
+/// Default and maximal time the config watch request is held by the hub.
+const DEFAULT_WATCH_TIMEOUT_SECS: u64 = 30;
+const MAX_WATCH_TIMEOUT_SECS: u64 = 600;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ConfigQuery {
+    wait_version: Option<u64>,
+    /// session version from the `x-session-version` header, which also covers the blob set
+    wait_session_version: Option<u64>,
+    timeout: Option<u64>,
+}
+
+fn get_config(
+    (path, query): (Path<SessionPath>, Query<ConfigQuery>),
+) -> impl Future<Item = HttpResponse, Error = actix_web::Error> {
+    let manager = SessionsManager::from_registry();
+
+    let metadata_fut = match query.wait_version {
+        None => future::Either::A(
+            manager
+                .send(manager::Update::new(path.session_id, |session| {
+                    Ok((session.metadata().clone(), session.version()))
+                }))
+                .flatten_fut(),
+        ),
+        Some(version) => {
+            let timeout = query
+                .timeout
+                .unwrap_or(DEFAULT_WATCH_TIMEOUT_SECS)
+                .min(MAX_WATCH_TIMEOUT_SECS);
+            future::Either::B(
+                manager
+                    .send(manager::WatchConfig::new(
+                        path.session_id,
+                        version,
+                        query.wait_session_version,
+                        Duration::from_secs(timeout),
+                    ))
+                    .flatten_fut(),
+            )
+        }
+    };
+
+    metadata_fut
         .from_err()
-        .and_then(|new_version| Ok(HttpResponse::Ok().json(new_version)))
+        .and_then(|(metadata, session_version)| {
+            Ok(include_version(HttpResponse::Ok(), metadata.version)
+                .header("x-session-version", session_version.to_string())
+                .json(metadata))
+        })
+}
+
+fn if_match<S>(r: &HttpRequest<S>) -> ActixResult<Option<u64>> {
+    match r.headers().get(IF_MATCH) {
+        None => Ok(None),
+        Some(v) => v
+            .to_str()
+            .ok()
+            .map(|v| v.trim().trim_start_matches("W/").trim_matches('"'))
+            .and_then(|v| v.parse().ok())
+            .map(Some)
+            .ok_or_else(|| ErrorBadRequest("Invalid If-Match header")),
+    }
+}
+
+fn set_config<S>(
+    (r, path, body): (
+        HttpRequest<S>,
+        Path<SessionPath>,
+        Json<gu_model::session::Metadata>,
+    ),
+) -> impl Future<Item = HttpResponse, Error = actix_web::Error> {
+    let mut new_metadata = body.into_inner();
+    match if_match(&r) {
+        Ok(Some(version)) => new_metadata.version = version,
+        Ok(None) => (),
+        Err(e) => return future::Either::A(future::err(e)),
+    }
+
+    future::Either::B(
+        SessionsManager::from_registry()
+            .send(manager::Update::new(path.session_id, |session| {
+                session.set_metadata(new_metadata)
+            }))
+            .flatten_fut()
+            .from_err()
+            .and_then(|new_version| {
+                Ok(include_version(HttpResponse::Ok(), new_version).json(new_version))
+            }),
+    )
+}
+
+fn blob_ttl<S>(r: &HttpRequest<S>) -> ActixResult<Option<u64>> {
+    match r.query().get("ttl") {
+        None => Ok(None),
+        Some(ttl) => ttl
+            .parse()
+            .map(Some)
+            .map_err(|_| ErrorBadRequest("Cannot parse ttl parameter")),
+    }
+}
+
+fn content_length<S>(r: &HttpRequest<S>) -> Option<u64> {
+    r.headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+}
+
+/// Runs `upload` with storage quota reserved for `size` bytes and releases it afterwards.
+///
+/// `upload` gets the number of bytes it may write (`None` if unlimited).
+fn reserved_upload<F, R>(
+    session: u64,
+    size: Option<u64>,
+    upload: F,
+) -> impl Future<Item = R::Item, Error = ActixError>
+where
+    F: FnOnce(Option<u64>) -> R,
+    R: IntoFuture<Error = ActixError>,
+{
+    let manager = SessionsManager::from_registry();
+
+    manager
+        .send(manager::ReserveUpload { session, size })
+        .flatten_fut()
+        .from_err::<ActixError>()
+        .and_then(move |limit| {
+            upload(limit).into_future().then(move |result| {
+                if let Some(bytes) = limit {
+                    manager.do_send(manager::ReleaseUpload { session, bytes });
+                }
+                result
+            })
+        })
 }
 
 fn create_blob_scope<S: 'static>(r: HttpRequest<S>) -> impl Responder {
     let session = session_id(&r).map_err(|e| return e).unwrap();
+    let ttl = match blob_ttl(&r) {
+        Ok(ttl) => ttl,
+        Err(e) => return futures::future::err::<HttpResponse, ActixError>(e).responder(),
+    };
 
     let session_manager = SessionsManager::from_registry();
 
     if r.content_type() == "multipart/form-data" {
-        r.multipart()
-            .map_err(|e| ErrorInternalServerError(format!("err: {}", e)))
-            .fold(Vec::new(), move |mut blobs, part| {
-                session_manager
-                    .send(manager::CreateBlob { session })
-                    .flatten_fut()
-                    .map_err(|e| ErrorInternalServerError(format!("err: {}", e)))
-                    .and_then(|(blob_id, blob)| {
-                        use actix_web::multipart::MultipartItem;
-
-                        match part {
-                            MultipartItem::Field(payload) => futures::future::Either::B(
-                                blob.write(payload)
-                                    .map_err(|e| ErrorInternalServerError(format!("err: {}", e)))
-                                    .and_then(move |_| {
-                                        blobs.push(blob_id);
-                                        Ok(blobs)
-                                    }),
-                            ),
-                            _ => futures::future::Either::A(futures::future::ok(blobs)),
-                        }
-                    })
-            })
-            .map_err(|e| ErrorInternalServerError(format!("err: {}", e)))
-            .and_then(move |blobs| Ok(HttpResponse::Ok().json(blobs)))
-            .responder()
+        let multipart = r.multipart();
+
+        reserved_upload(session, content_length(&r), move |limit| {
+            multipart
+                .map_err(|e| ErrorInternalServerError(format!("err: {}", e)))
+                .fold((Vec::new(), limit), move |(mut blobs, left), part| {
+                    use actix_web::multipart::MultipartItem;
+
+                    let payload = match part {
+                        MultipartItem::Field(payload) => payload,
+                        _ => return future::Either::A(future::ok((blobs, left))),
+                    };
+                    let delete_manager = session_manager.clone();
+
+                    future::Either::B(
+                        session_manager
+                            .send(manager::CreateBlob { session, ttl })
+                            .flatten_fut()
+                            .and_then(move |(blob_id, blob)| {
+                                blob.clone().write(payload, left).then(move |result| {
+                                    match result {
+                                        Ok(_) => {
+                                            events::post(HubEvent::BlobUploaded {
+                                                session_id: session,
+                                                blob_id,
+                                            });
+                                            blobs.push(blob_id);
+                                            let size = blob.size();
+                                            Ok((blobs, left.map(|left| left.saturating_sub(size))))
+                                        }
+                                        Err(e) => {
+                                            // do not leave an empty blob behind
+                                            delete_manager
+                                                .do_send(manager::DeleteBlob { session, blob_id });
+                                            Err(e)
+                                        }
+                                    }
+                                })
+                            })
+                            .from_err::<ActixError>(),
+                    )
+                })
+        })
+        .and_then(move |(blobs, _left)| Ok(HttpResponse::Ok().json(blobs)))
+        .responder()
     } else {
         session_manager
-            .send(manager::CreateBlob { session })
+            .send(manager::CreateBlob { session, ttl })
             .flatten_fut()
-            .map_err(|e| ErrorInternalServerError(format!("err: {}", e)))
+            .from_err::<ActixError>()
             .and_then(|(blob_id, _blob)| Ok(HttpResponse::Created().json(blob_id)))
             .responder()
     }
@@ -300,15 +649,105 @@ fn list_peers(
 }
 
 fn add_peers(
-    (path, body): (Path<SessionPath>, Json<Vec<NodeId>>),
+    (path, body): (Path<SessionPath>, Json<AddPeers>),
+) -> impl Future<Item = HttpResponse, Error = actix_web::Error> {
+    let session_id = path.session_id;
+    let (node_ids, explicit) = match body.into_inner() {
+        AddPeers::Nodes(node_ids) => (future::Either::A(future::ok(node_ids)), true),
+        AddPeers::Selector { selector } => (
+            future::Either::B(PeerInventory::from_registry().send(SelectPeers(selector))),
+            false,
+        ),
+    };
+
+    node_ids
+        .and_then(|node_ids: Vec<NodeId>| {
+            PeerInventory::from_registry()
+                .send(Unschedulable(node_ids.clone()))
+                .map(|unschedulable| (node_ids, unschedulable))
+        })
+        .from_err()
+        .and_then(move |(node_ids, unschedulable)| {
+            // Cordoned peers are an error when asked for explicitly, otherwise they are skipped.
+            match unschedulable.first() {
+                Some(node_id) if explicit => {
+                    return future::Either::A(future::err(SessionErr::PeerUnschedulable(*node_id)))
+                }
+                _ => (),
+            }
+            let node_ids = node_ids
+                .into_iter()
+                .filter(|node_id| !unschedulable.contains(node_id))
+                .collect();
+
+            future::Either::B(
+                SessionsManager::from_registry()
+                    .send(manager::Update::new(session_id, |session| {
+                        Ok(session.add_peers(node_ids))
+                    }))
+                    .flatten_fut(),
+            )
+        })
+        .from_err()
+        .and_then(|all_peers| Ok(HttpResponse::Ok().json(all_peers)))
+}
+
+fn list_tasks(
+    path: Path<SessionPath>,
 ) -> impl Future<Item = HttpResponse, Error = actix_web::Error> {
     SessionsManager::from_registry()
         .send(manager::Update::new(path.session_id, |session| {
-            Ok(session.add_peers(body.into_inner()))
+            Ok(session.tasks().list())
         }))
         .flatten_fut()
         .from_err()
-        .and_then(|all_peers| Ok(HttpResponse::Ok().json(all_peers)))
+        .and_then(|list| Ok(HttpResponse::Ok().json(list)))
+}
+
+fn submit_tasks<S>(
+    (r, path, body): (HttpRequest<S>, Path<SessionPath>, Json<TaskSubmit>),
+) -> impl Future<Item = HttpResponse, Error = actix_web::Error> {
+    let submit = body.into_inner();
+    let hub_url = match submit.hub_url {
+        Some(ref url) => url.trim_end_matches('/').to_string(),
+        None => {
+            let info = r.connection_info();
+            format!("{}://{}", info.scheme(), info.host())
+        }
+    };
+    let blob_url = format!("{}/sessions/{}/blobs/", hub_url, path.session_id);
+
+    SessionsManager::from_registry()
+        .send(manager::SubmitTasks::new(path.session_id, submit, blob_url))
+        .flatten_fut()
+        .from_err()
+        .and_then(|task_ids| Ok(HttpResponse::Created().json(task_ids)))
+}
+
+fn create_deployments(
+    (path, body): (Path<SessionPath>, Json<SessionDeploymentSpec>),
+) -> impl Future<Item = HttpResponse, Error = actix_web::Error> {
+    SessionsManager::from_registry()
+        .send(manager::CreateDeployments::new(
+            path.session_id,
+            body.into_inner(),
+        ))
+        .flatten_fut()
+        .from_err()
+        .and_then(|results| Ok(HttpResponse::Ok().json(results)))
+}
+
+fn update_deployments(
+    (path, body): (Path<SessionPath>, Json<SessionCommandSpec>),
+) -> impl Future<Item = HttpResponse, Error = actix_web::Error> {
+    SessionsManager::from_registry()
+        .send(manager::UpdateDeployments::new(
+            path.session_id,
+            body.into_inner(),
+        ))
+        .flatten_fut()
+        .from_err()
+        .and_then(|results| Ok(HttpResponse::Ok().json(results)))
 }
 
 fn delete_deployment(
@@ -390,17 +829,25 @@ where
 fn upload_scope<S: 'static>(r: HttpRequest<S>) -> impl Responder {
     let session = session_id(&r).map_err(|e| return e).unwrap();
     let blob_id = blob_id(&r).map_err(|e| return e).unwrap();
-    let manager = SessionsManager::from_registry();
+    let size = content_length(&r);
 
-    let blob_fut = manager
+    let res_fut = SessionsManager::from_registry()
         .send(manager::GetBlob { session, blob_id })
-        .flatten_fut();
-    let res_fut = blob_fut
-        .and_then(move |res: SessionOk| match res {
-            SessionOk::Blob(blob) => blob.write(r.payload()),
+        .flatten_fut()
+        .from_err::<ActixError>()
+        .and_then(move |res| match res {
+            SessionOk::Blob(blob) => reserved_upload(session, size, move |limit| {
+                blob.write(r.payload(), limit).from_err::<ActixError>()
+            }),
             _ => unreachable!(),
         })
-        .and_then(|_| Ok(HttpResponse::build(StatusCode::NO_CONTENT).finish()));
+        .and_then(move |_| {
+            events::post(HubEvent::BlobUploaded {
+                session_id: session,
+                blob_id,
+            });
+            Ok(HttpResponse::build(StatusCode::NO_CONTENT).finish())
+        });
 
     session_future_responder(res_fut)
 }
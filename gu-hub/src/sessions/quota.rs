@@ -0,0 +1,385 @@
+//! Storage quotas for hub sessions.
+//!
+//! Limits are configured in the hub config (`server-cfg` section), both for all sessions
+//! together and for every single session.
+//!
+
+use std::{cmp, collections::HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use gu_model::session::StorageUsage;
+
+use super::responses::SessionErr;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageLimits {
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_total_bytes: Option<u64>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_blob_count: Option<u64>,
+}
+
+impl StorageLimits {
+    /// Checks if one more blob fits into the limit.
+    pub fn check_new_blob(&self, usage: &Usage) -> Result<(), SessionErr> {
+        match self.max_blob_count {
+            Some(max) if usage.blob_count >= max => Err(SessionErr::QuotaExceeded(format!(
+                "blob count limit of {} reached",
+                max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks if the usage fits into the limits, e.g. of an imported session.
+    pub fn check_usage(&self, usage: &Usage) -> Result<(), SessionErr> {
+        match (self.max_total_bytes, self.max_blob_count) {
+            (Some(max), _) if usage.total_bytes > max => Err(SessionErr::QuotaExceeded(format!(
+                "storage size limit of {} bytes exceeded",
+                max
+            ))),
+            (_, Some(max)) if usage.blob_count > max => Err(SessionErr::QuotaExceeded(format!(
+                "blob count limit of {} exceeded",
+                max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Number of bytes that can still be uploaded, `None` means unlimited.
+    pub fn bytes_left(&self, usage: &Usage) -> Option<u64> {
+        self.max_total_bytes
+            .map(|max| max.saturating_sub(usage.total_bytes))
+    }
+
+    pub fn describe(&self, usage: &Usage) -> StorageUsage {
+        StorageUsage {
+            total_bytes: usage.total_bytes,
+            blob_count: usage.blob_count,
+            max_total_bytes: self.max_total_bytes,
+            max_blob_count: self.max_blob_count,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageQuota {
+    /// Limits for all hub sessions together.
+    #[serde(default)]
+    pub global: StorageLimits,
+    /// Limits applied to each hub session separately.
+    #[serde(default)]
+    pub per_session: StorageLimits,
+}
+
+impl StorageQuota {
+    /// Upload allowance in bytes for a session, `None` means unlimited.
+    pub fn upload_allowance(
+        &self,
+        total: &Usage,
+        session: &Usage,
+    ) -> Result<Option<u64>, SessionErr> {
+        let allowance = match (
+            self.global.bytes_left(total),
+            self.per_session.bytes_left(session),
+        ) {
+            (Some(a), Some(b)) => Some(cmp::min(a, b)),
+            (a, b) => a.or(b),
+        };
+
+        match allowance {
+            Some(0) => Err(SessionErr::QuotaExceeded(
+                "storage size limit reached".to_string(),
+            )),
+            v => Ok(v),
+        }
+    }
+
+    /// Checks if a session with `added` usage can be added to the `total` one.
+    pub fn check_added_session(&self, total: &Usage, added: &Usage) -> Result<(), SessionErr> {
+        self.per_session.check_usage(added)?;
+        self.global.check_usage(&(*total + *added))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Usage {
+    pub total_bytes: u64,
+    pub blob_count: u64,
+}
+
+impl std::ops::Add for Usage {
+    type Output = Usage;
+
+    fn add(self, other: Usage) -> Usage {
+        Usage {
+            total_bytes: self.total_bytes + other.total_bytes,
+            blob_count: self.blob_count + other.blob_count,
+        }
+    }
+}
+
+/// Bytes promised to uploads that are still in progress.
+///
+/// Blob sizes only grow while data is written, so every upload reserves its
+/// allowance up front; concurrent uploads then cannot pass the quota together.
+#[derive(Debug, Default)]
+pub struct Reservations {
+    sessions: HashMap<u64, u64>,
+}
+
+impl Reservations {
+    fn usage(bytes: u64) -> Usage {
+        Usage {
+            total_bytes: bytes,
+            blob_count: 0,
+        }
+    }
+
+    /// Reserves space for an upload of `size` bytes (or of unknown size) to a session.
+    ///
+    /// Returns the upload limit, which is also the amount to release afterwards.
+    /// `None` means the upload is unlimited and nothing was reserved.
+    pub fn reserve(
+        &mut self,
+        quota: &StorageQuota,
+        session_id: u64,
+        total: &Usage,
+        session: &Usage,
+        size: Option<u64>,
+    ) -> Result<Option<u64>, SessionErr> {
+        let reserved_session = self.sessions.get(&session_id).cloned().unwrap_or_default();
+
+        let allowance = quota.upload_allowance(
+            &(*total + self.total()),
+            &(*session + Self::usage(reserved_session)),
+        )?;
+
+        let reserved = match (allowance, size) {
+            (None, _) => return Ok(None),
+            (Some(allowance), Some(size)) if size > allowance => {
+                return Err(SessionErr::QuotaExceeded(format!(
+                    "upload of {} bytes exceeds the {} bytes left",
+                    size, allowance
+                )));
+            }
+            (Some(_), Some(size)) => size,
+            (Some(allowance), None) => allowance,
+        };
+
+        *self.sessions.entry(session_id).or_insert(0) += reserved;
+        Ok(Some(reserved))
+    }
+
+    /// Bytes reserved by all uploads.
+    pub fn total(&self) -> Usage {
+        Self::usage(self.sessions.values().sum())
+    }
+
+    /// Gives back space reserved by `reserve`.
+    pub fn release(&mut self, session_id: u64, bytes: u64) {
+        let left = match self.sessions.get_mut(&session_id) {
+            Some(reserved) => {
+                *reserved = reserved.saturating_sub(bytes);
+                *reserved
+            }
+            None => return,
+        };
+        if left == 0 {
+            self.sessions.remove(&session_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn usage(total_bytes: u64, blob_count: u64) -> Usage {
+        Usage {
+            total_bytes,
+            blob_count,
+        }
+    }
+
+    fn limits(max_total_bytes: Option<u64>, max_blob_count: Option<u64>) -> StorageLimits {
+        StorageLimits {
+            max_total_bytes,
+            max_blob_count,
+        }
+    }
+
+    fn quota(global: Option<u64>, per_session: Option<u64>) -> StorageQuota {
+        StorageQuota {
+            global: limits(global, None),
+            per_session: limits(per_session, None),
+        }
+    }
+
+    #[test]
+    fn test_check_new_blob() {
+        assert!(limits(None, None).check_new_blob(&usage(0, 100)).is_ok());
+        assert!(limits(None, Some(2)).check_new_blob(&usage(0, 1)).is_ok());
+        assert!(limits(None, Some(2)).check_new_blob(&usage(0, 2)).is_err());
+    }
+
+    #[test]
+    fn test_check_added_session() {
+        let quota = StorageQuota {
+            global: limits(Some(100), Some(10)),
+            per_session: limits(Some(50), Some(3)),
+        };
+
+        assert!(quota
+            .check_added_session(&usage(50, 5), &usage(50, 3))
+            .is_ok());
+        assert!(quota
+            .check_added_session(&usage(0, 0), &usage(51, 1))
+            .is_err());
+        assert!(quota
+            .check_added_session(&usage(0, 0), &usage(10, 4))
+            .is_err());
+        assert!(quota
+            .check_added_session(&usage(60, 1), &usage(50, 1))
+            .is_err());
+        assert!(quota
+            .check_added_session(&usage(10, 8), &usage(10, 3))
+            .is_err());
+    }
+
+    #[test]
+    fn test_bytes_left() {
+        assert_eq!(limits(None, None).bytes_left(&usage(10, 0)), None);
+        assert_eq!(limits(Some(100), None).bytes_left(&usage(10, 0)), Some(90));
+        assert_eq!(limits(Some(100), None).bytes_left(&usage(150, 0)), Some(0));
+    }
+
+    #[test]
+    fn test_upload_allowance() {
+        assert_eq!(
+            quota(None, None)
+                .upload_allowance(&usage(10, 1), &usage(10, 1))
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            quota(Some(100), None)
+                .upload_allowance(&usage(30, 1), &usage(10, 1))
+                .unwrap(),
+            Some(70)
+        );
+        assert_eq!(
+            quota(None, Some(50))
+                .upload_allowance(&usage(30, 1), &usage(10, 1))
+                .unwrap(),
+            Some(40)
+        );
+        assert_eq!(
+            quota(Some(100), Some(50))
+                .upload_allowance(&usage(80, 1), &usage(10, 1))
+                .unwrap(),
+            Some(20)
+        );
+        assert!(quota(Some(100), Some(50))
+            .upload_allowance(&usage(80, 1), &usage(50, 1))
+            .is_err());
+    }
+
+    #[test]
+    fn test_reserve_unlimited() {
+        let mut reservations = Reservations::default();
+        let quota = quota(None, None);
+
+        assert_eq!(
+            reservations
+                .reserve(&quota, 1, &usage(0, 0), &usage(0, 0), Some(10))
+                .unwrap(),
+            None
+        );
+        assert!(reservations.sessions.is_empty());
+    }
+
+    #[test]
+    fn test_concurrent_reservations() {
+        let mut reservations = Reservations::default();
+        let quota = quota(Some(100), None);
+        let empty = usage(0, 0);
+
+        assert_eq!(
+            reservations
+                .reserve(&quota, 1, &empty, &empty, Some(60))
+                .unwrap(),
+            Some(60)
+        );
+        // the second upload only gets what the first one left
+        assert!(reservations
+            .reserve(&quota, 2, &empty, &empty, Some(60))
+            .is_err());
+        assert_eq!(
+            reservations
+                .reserve(&quota, 2, &empty, &empty, None)
+                .unwrap(),
+            Some(40)
+        );
+        assert!(reservations
+            .reserve(&quota, 3, &empty, &empty, None)
+            .is_err());
+
+        reservations.release(2, 40);
+        assert_eq!(
+            reservations
+                .reserve(&quota, 3, &empty, &empty, Some(40))
+                .unwrap(),
+            Some(40)
+        );
+    }
+
+    #[test]
+    fn test_reservations_per_session() {
+        let mut reservations = Reservations::default();
+        let quota = quota(None, Some(50));
+        let empty = usage(0, 0);
+
+        assert_eq!(
+            reservations
+                .reserve(&quota, 1, &empty, &empty, Some(30))
+                .unwrap(),
+            Some(30)
+        );
+        assert!(reservations
+            .reserve(&quota, 1, &empty, &empty, Some(30))
+            .is_err());
+        // other sessions have their own limit
+        assert_eq!(
+            reservations
+                .reserve(&quota, 2, &empty, &empty, Some(30))
+                .unwrap(),
+            Some(30)
+        );
+    }
+
+    #[test]
+    fn test_release() {
+        let mut reservations = Reservations::default();
+        let quota = quota(Some(100), None);
+        let empty = usage(0, 0);
+
+        reservations
+            .reserve(&quota, 1, &empty, &empty, Some(30))
+            .unwrap();
+        reservations
+            .reserve(&quota, 1, &empty, &empty, Some(30))
+            .unwrap();
+        reservations.release(1, 30);
+        assert_eq!(reservations.sessions.get(&1), Some(&30));
+        reservations.release(1, 100);
+        assert!(reservations.sessions.is_empty());
+        reservations.release(2, 10);
+        assert!(reservations.sessions.is_empty());
+    }
+}
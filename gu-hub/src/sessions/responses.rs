@@ -17,7 +17,7 @@ use super::{blob::Blob, manager::EnumeratedSessionInfo, session::SessionInfo};
 
 pub type SessionResult = Result<SessionOk, SessionErr>;
 
-fn include_version(mut build: HttpResponseBuilder, v: u64) -> HttpResponseBuilder {
+pub fn include_version(mut build: HttpResponseBuilder, v: u64) -> HttpResponseBuilder {
     let val = HeaderValue::from_str(&format!("{}", v)).expect("Invalid ETag");
     build.header(ETAG, val);
     build
@@ -70,6 +70,18 @@ pub enum SessionErr {
     CannotUpdatePeerDeployment,
     #[fail(display = "Blob is not uploaded yet")]
     BlobNotYetUploaded,
+    #[fail(display = "Storage quota exceeded: {}", _0)]
+    QuotaExceeded(String),
+    #[fail(display = "Version conflict, current version is {}", _0)]
+    VersionConflict(u64),
+    #[fail(display = "Task {} not found", _0)]
+    TaskNotFound(u64),
+    #[fail(display = "Invalid session archive: {}", _0)]
+    InvalidArchive(String),
+    #[fail(display = "{:?} peer is cordoned", _0)]
+    PeerUnschedulable(NodeId),
+    #[fail(display = "{} deployment is running task {}", _0, _1)]
+    DeploymentBusy(String, u64),
 }
 
 impl From<MailboxError> for SessionErr {
@@ -78,7 +90,11 @@ impl From<MailboxError> for SessionErr {
     }
 }
 
-impl actix_web::ResponseError for SessionErr {}
+impl actix_web::ResponseError for SessionErr {
+    fn error_response(&self) -> HttpResponse {
+        self.clone().into()
+    }
+}
 
 impl Into<HttpResponse> for SessionOk {
     fn into(self) -> HttpResponse {
@@ -107,7 +123,19 @@ impl Into<HttpResponse> for SessionErr {
             x @ SessionErr::SessionNotFoundError
             | x @ SessionErr::BlobNotFoundError
             | x @ SessionErr::NodeNotFound(_)
-            | x @ SessionErr::DeploymentNotFound(_) => HttpResponse::NotFound().body(x.to_string()),
+            | x @ SessionErr::DeploymentNotFound(_)
+            | x @ SessionErr::TaskNotFound(_) => HttpResponse::NotFound().body(x.to_string()),
+            SessionErr::VersionConflict(v) => {
+                include_version(HttpResponse::build(StatusCode::PRECONDITION_FAILED), v)
+                    .body(SessionErr::VersionConflict(v).to_string())
+            }
+            x @ SessionErr::QuotaExceeded(_) => {
+                HttpResponse::build(StatusCode::INSUFFICIENT_STORAGE).body(x.to_string())
+            }
+            x @ SessionErr::InvalidArchive(_) => HttpResponse::BadRequest().body(x.to_string()),
+            x @ SessionErr::PeerUnschedulable(_) | x @ SessionErr::DeploymentBusy(..) => {
+                HttpResponse::Conflict().body(x.to_string())
+            }
             x => HttpResponse::InternalServerError().body(x.to_string()),
         }
     }
@@ -1,36 +1,58 @@
 use std::{
     cmp,
-    collections::{HashMap, HashSet},
+    collections::{BTreeSet, HashMap, HashSet},
     fs, io,
     path::PathBuf,
 };
 
+use actix::SystemService;
 use bytes::Bytes;
 use chrono::DateTime;
+use chrono::Duration;
 use chrono::Utc;
-use futures::{future, prelude::*, stream};
+use futures::{future, prelude::*, stream, sync::oneshot};
 use log::error;
 use serde::{Deserialize, Serialize};
 use serde_json;
 
 use gu_base::files::{read_async, write_async};
 use gu_model::session::{BlobInfo, Metadata};
+use gu_model::task::TaskSubmit;
 use gu_net::rpc::peer::PeerSessionInfo;
 use gu_net::{rpc::peer, NodeId};
 
+use crate::{
+    image_peers,
+    inventory::{PeerInventory, Unschedulable},
+    repo,
+};
+
 use super::{
+    archive::PeerRecord,
     blob::Blob,
+    quota::Usage,
     responses::{SessionErr, SessionOk, SessionResult},
+    tasks::{Assignment, TaskQueue},
 };
 
+/// File with blob expiration times, stored next to the session `.info` file.
+const BLOB_EXPIRES_FILE: &str = ".blobs";
+/// File with hashes of images deployed in the session, kept in the hub repo until
+/// the session is deleted.
+const IMAGES_FILE: &str = ".images";
+
 pub struct Session {
     info: SessionInfo,
     state: Metadata,
     path: PathBuf,
     next_id: u64,
     storage: HashMap<u64, Blob>,
+    blob_expires: HashMap<u64, DateTime<Utc>>,
+    images: BTreeSet<String>,
     version: u64,
     peers: HashMap<NodeId, PeerState>,
+    watchers: Vec<oneshot::Sender<()>>,
+    tasks: TaskQueue,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -101,8 +123,12 @@ impl Session {
             path: path.clone(),
             next_id: 0,
             storage: HashMap::new(),
+            blob_expires: HashMap::new(),
+            images: BTreeSet::new(),
             version: 0,
             peers: HashMap::new(),
+            watchers: Vec::new(),
+            tasks: TaskQueue::default(),
         };
 
         let fut = fs::DirBuilder::new()
@@ -138,8 +164,12 @@ impl Session {
             path: path.clone(),
             next_id: 0,
             storage: HashMap::new(),
+            blob_expires: HashMap::new(),
+            images: BTreeSet::new(),
             version: 0,
             peers: HashMap::new(),
+            watchers: Vec::new(),
+            tasks: TaskQueue::default(),
         };
 
         entries_id_iter(&path).for_each(|id| {
@@ -158,6 +188,15 @@ impl Session {
             serde_json::from_slice::<Metadata>(a.as_ref()).map_err(|e| e.to_string())
         });
 
+        s.blob_expires = fs::read(path.join(BLOB_EXPIRES_FILE))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(bytes.as_ref()).ok())
+            .unwrap_or_default();
+        s.images = fs::read(path.join(IMAGES_FILE))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(bytes.as_ref()).ok())
+            .unwrap_or_default();
+
         info_fut.join(config_fut).and_then(|(info, state)| {
             s.info = info;
             s.state = state;
@@ -165,6 +204,10 @@ impl Session {
         })
     }
 
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
     pub fn info(&self) -> SessionInfo {
         self.info.clone()
     }
@@ -178,9 +221,12 @@ impl Session {
             self.state = val;
             self.state.version += 1;
         } else {
-            return futures::future::Either::B(Err(SessionErr::OverwriteError).into_future());
+            return futures::future::Either::B(
+                Err(SessionErr::VersionConflict(self.state.version)).into_future(),
+            );
         }
         self.version += 1;
+        self.notify_watchers();
 
         let new_state_version = self.state.version;
 
@@ -194,6 +240,26 @@ impl Session {
         )
     }
 
+    /// Version of the session, changed along with its config or blob set.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Returns a receiver that resolves on the next change of the session config or blob set.
+    pub fn watch(&mut self) -> oneshot::Receiver<()> {
+        // watches that timed out or whose clients are gone
+        self.watchers.retain(|watcher| !watcher.is_canceled());
+        let (tx, rx) = oneshot::channel();
+        self.watchers.push(tx);
+        rx
+    }
+
+    fn notify_watchers(&mut self) {
+        for watcher in self.watchers.drain(..) {
+            let _ = watcher.send(());
+        }
+    }
+
     fn new_blob_inner(&mut self, blob: Blob, id: Option<u64>) -> Result<(u64, Blob), SessionErr> {
         let id = match id {
             None => self.next_id,
@@ -201,6 +267,7 @@ impl Session {
         };
         self.next_id = cmp::max(id, self.next_id) + 1;
         self.version += 1;
+        self.notify_watchers();
 
         match self.storage.insert(id, blob.clone()) {
             Some(_) => Err(SessionErr::OverwriteError),
@@ -208,14 +275,74 @@ impl Session {
         }
     }
 
-    pub fn new_blob(&mut self) -> Result<(u64, Blob), SessionErr> {
+    pub fn new_blob(&mut self, ttl: Option<Duration>) -> Result<(u64, Blob), SessionErr> {
         let blob = Blob::new(self.path.join(format!("{}", self.next_id)))
             .map_err(|e| SessionErr::FileError(e.to_string()))?;
-        self.new_blob_inner(blob, None)
+        let (id, blob) = self.new_blob_inner(blob, None)?;
+
+        if let Some(ttl) = ttl {
+            self.blob_expires.insert(id, Utc::now() + ttl);
+            self.save_blob_expires()?;
+        }
+        Ok((id, blob))
+    }
+
+    fn save_blob_expires(&self) -> Result<(), SessionErr> {
+        let bytes = serde_json::to_vec(&self.blob_expires)
+            .map_err(|e| SessionErr::FileError(e.to_string()))?;
+        fs::write(self.path.join(BLOB_EXPIRES_FILE), bytes)
+            .map_err(|e| SessionErr::FileError(e.to_string()))
+    }
+
+    /// Deletes blobs with expired TTL; returns ids of removed blobs.
+    pub fn delete_expired_blobs(&mut self, now: DateTime<Utc>) -> Vec<u64> {
+        let expired: Vec<u64> = self
+            .blob_expires
+            .iter()
+            .filter(|(_, expires)| **expires <= now)
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in &expired {
+            if let Err(e) = self.delete_blob(*id) {
+                error!("Cannot delete expired blob {}: {}", id, e);
+            }
+        }
+        expired
+    }
+
+    /// Hashes of images deployed in this session.
+    pub fn images(&self) -> &BTreeSet<String> {
+        &self.images
+    }
+
+    /// Records the image of a deployment, so that the hub repo keeps it.
+    pub fn add_image(&mut self, image: &gu_model::envman::Image) {
+        let hash = match repo::normalize_hash(&image.hash) {
+            Some(hash) => hash,
+            None => return,
+        };
+        if self.images.insert(hash) {
+            let _ = serde_json::to_vec(&self.images)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| {
+                    fs::write(self.path.join(IMAGES_FILE), bytes).map_err(|e| e.to_string())
+                })
+                .map_err(|e| error!("Cannot save session images: {}", e));
+        }
+    }
+
+    /// Number of blobs and bytes stored in this session.
+    pub fn usage(&self) -> Usage {
+        Usage {
+            total_bytes: self.storage.values().map(Blob::size).sum(),
+            blob_count: self.storage.len() as u64,
+        }
     }
 
     pub fn set_blob(&mut self, id: u64, blob: Blob) -> SessionResult {
         self.version += 1;
+        self.notify_watchers();
         match self.storage.insert(id, blob) {
             Some(_) => Ok(SessionOk::Ok),
             None => Ok(SessionOk::Ok),
@@ -231,6 +358,10 @@ impl Session {
 
     pub fn delete_blob(&mut self, id: u64) -> SessionResult {
         self.version += 1;
+        self.notify_watchers();
+        if self.blob_expires.remove(&id).is_some() {
+            self.save_blob_expires()?;
+        }
         match self.storage.remove(&id).map(|b| b.clean_file()) {
             Some(Ok(())) => Ok(SessionOk::Ok),
             Some(Err(e)) => Err(SessionErr::FileError(e.to_string())),
@@ -247,8 +378,12 @@ impl Session {
 
     pub fn list_blobs(&self) -> Vec<BlobInfo> {
         self.storage
-            .keys()
-            .map(|e| BlobInfo { id: e.to_string() })
+            .iter()
+            .map(|(id, blob)| BlobInfo {
+                id: id.to_string(),
+                size: Some(blob.size()),
+                expires: self.blob_expires.get(id).cloned(),
+            })
             .collect()
     }
 
@@ -262,6 +397,38 @@ impl Session {
             .collect()
     }
 
+    pub fn peer_ids(&self) -> Vec<NodeId> {
+        self.peers.keys().cloned().collect()
+    }
+
+    pub fn peer_records(&self) -> Vec<PeerRecord> {
+        self.peers
+            .iter()
+            .map(|(node_id, peer)| PeerRecord {
+                node_id: *node_id,
+                deployments: peer.deployments.iter().cloned().collect(),
+            })
+            .collect()
+    }
+
+    /// Session deployments of given peers, optionally limited to given deployment ids.
+    pub fn peer_deployments(
+        &self,
+        node_ids: &[NodeId],
+        deployment_ids: &[String],
+    ) -> Vec<(NodeId, String)> {
+        node_ids
+            .iter()
+            .filter_map(|node_id| self.peers.get(node_id).map(|peer| (*node_id, peer)))
+            .flat_map(|(node_id, peer)| {
+                peer.deployments
+                    .iter()
+                    .filter(|id| deployment_ids.is_empty() || deployment_ids.contains(*id))
+                    .map(move |id| (node_id, id.clone()))
+            })
+            .collect()
+    }
+
     pub fn add_peers(&mut self, peers: Vec<NodeId>) -> Vec<NodeId> {
         let new_peers = peers
             .into_iter()
@@ -294,11 +461,22 @@ impl Session {
         if self.peers.get(&node_id).is_none() {
             return future::Either::A(future::err(SessionErr::NodeNotFound(node_id)));
         }
+        self.add_image(&body.image);
         future::Either::B(
-            peer(node_id)
-                .into_endpoint()
-                .send(body)
-                .map_err(|_| SessionErr::CannotCreatePeerDeployment)
+            PeerInventory::from_registry()
+                .send(Unschedulable(vec![node_id]))
+                .from_err()
+                .and_then(move |unschedulable| {
+                    if unschedulable.is_empty() {
+                        Ok(())
+                    } else {
+                        Err(SessionErr::PeerUnschedulable(node_id))
+                    }
+                })
+                .and_then(move |()| {
+                    image_peers::create_session(node_id, body)
+                        .map_err(|_| SessionErr::CannotCreatePeerDeployment)
+                })
                 .and_then(|v| {
                     future::result(v).map_err(|_| SessionErr::CannotCreatePeerDeployment)
                 }),
@@ -390,6 +568,50 @@ impl Session {
         )
     }
 
+    pub fn tasks(&self) -> &TaskQueue {
+        &self.tasks
+    }
+
+    pub fn tasks_mut(&mut self) -> &mut TaskQueue {
+        &mut self.tasks
+    }
+
+    /// Adds tasks to the session queue, checking that all their blobs exist.
+    pub fn submit_tasks(
+        &mut self,
+        submit: TaskSubmit,
+        blob_url: String,
+    ) -> Result<Vec<u64>, SessionErr> {
+        let missing_blob = submit
+            .tasks
+            .iter()
+            .flat_map(|task| task.inputs.iter().chain(task.outputs.iter()))
+            .any(|blob| !self.storage.contains_key(&blob.blob_id));
+        if missing_blob {
+            return Err(SessionErr::BlobNotFoundError);
+        }
+
+        Ok(self.tasks.submit(submit, blob_url))
+    }
+
+    /// Picks pending tasks to be run on session deployments.
+    pub fn next_task_assignments(&mut self) -> Vec<Assignment> {
+        if !self.tasks.has_pending() {
+            return Vec::new();
+        }
+        let deployments: Vec<(NodeId, String)> = self
+            .peers
+            .iter()
+            .flat_map(|(node_id, peer)| {
+                peer.deployments
+                    .iter()
+                    .map(move |deployment_id| (*node_id, deployment_id.clone()))
+            })
+            .collect();
+
+        self.tasks.assign(&deployments)
+    }
+
     pub fn clean_directory(&mut self) -> io::Result<()> {
         self.version += 1;
         match (&self.path).exists() {
@@ -410,3 +632,63 @@ fn drop_peer_deployment(
         .send(DestroySession { session_id })
         .then(|_| Ok(()))
 }
+
+#[cfg(test)]
+mod test {
+    use actix::System;
+
+    use super::*;
+
+    fn session(dir: &tempfile::TempDir) -> Session {
+        Session::new(SessionInfo::default(), dir.path().to_path_buf()).0
+    }
+
+    #[test]
+    fn test_watch_prunes_canceled() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut session = session(&dir);
+
+        for _ in 0..3 {
+            drop(session.watch());
+        }
+        let _watch = session.watch();
+        assert_eq!(session.watchers.len(), 1);
+    }
+
+    #[test]
+    fn test_config_change_notifies() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut session = session(&dir);
+        let version = session.version();
+
+        let watch = session.watch();
+        let _ = session.set_metadata(Metadata::default());
+        assert!(watch.wait().is_ok());
+        assert_eq!(session.metadata().version, 1);
+        assert_ne!(session.version(), version);
+    }
+
+    #[test]
+    fn test_blob_changes_notify() {
+        System::run(|| {
+            let dir = tempfile::tempdir().unwrap();
+            let mut session = session(&dir);
+            let version = session.version();
+
+            let watch = session.watch();
+            let (blob_id, _blob) = session.new_blob(None).unwrap();
+            assert!(watch.wait().is_ok());
+            assert_ne!(session.version(), version);
+            // blob changes do not touch the config
+            assert_eq!(session.metadata().version, 0);
+
+            let version = session.version();
+            let watch = session.watch();
+            session.delete_blob(blob_id).unwrap();
+            assert!(watch.wait().is_ok());
+            assert_ne!(session.version(), version);
+
+            System::current().stop();
+        });
+    }
+}
@@ -0,0 +1,476 @@
+//! Hub session task queue.
+//!
+//! Keeps task state and decides which task runs on which deployment.
+//! Tasks are not persisted, they are lost on hub restart.
+//!
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    time::Duration,
+};
+
+use gu_model::{
+    envman::Command,
+    task::{TaskSpec, TaskState, TaskStatus, TaskSubmit},
+};
+use gu_net::NodeId;
+
+use super::responses::SessionErr;
+
+const DEFAULT_MAX_PER_PEER: usize = 1;
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+
+struct TaskEntry {
+    spec: TaskSpec,
+    status: TaskStatus,
+    max_per_peer: usize,
+    max_attempts: u32,
+    timeout: Option<Duration>,
+    blob_url: String,
+    tried: HashSet<NodeId>,
+}
+
+impl TaskEntry {
+    fn commands(&self) -> Vec<Command> {
+        let downloads = self.spec.inputs.iter().map(|input| Command::DownloadFile {
+            uri: format!("{}{}", self.blob_url, input.blob_id),
+            file_path: input.file_path.clone(),
+            format: input.format,
+        });
+        let uploads = self.spec.outputs.iter().map(|output| Command::UploadFile {
+            uri: format!("{}{}", self.blob_url, output.blob_id),
+            file_path: output.file_path.clone(),
+            format: output.format,
+        });
+
+        downloads
+            .chain(self.spec.commands.iter().cloned())
+            .chain(uploads)
+            .collect()
+    }
+}
+
+/// Task attempt to be started on a deployment.
+pub struct Assignment {
+    pub task_id: u64,
+    pub attempt: u32,
+    pub node_id: NodeId,
+    pub deployment_id: String,
+    pub commands: Vec<Command>,
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Default)]
+pub struct TaskQueue {
+    next_id: u64,
+    tasks: BTreeMap<u64, TaskEntry>,
+    pending: VecDeque<u64>,
+    /// Deployments with a running attempt, the attempt may be already timed out.
+    busy: HashMap<(NodeId, String), (u64, u32)>,
+    /// Deployments running a fan-out command batch, no tasks are started there.
+    reserved: HashSet<(NodeId, String)>,
+}
+
+impl TaskQueue {
+    /// Adds tasks to the queue; `blob_url` is the session blobs URL prefix.
+    pub fn submit(&mut self, submit: TaskSubmit, blob_url: String) -> Vec<u64> {
+        let max_per_peer = submit.max_per_peer.unwrap_or(DEFAULT_MAX_PER_PEER).max(1);
+        let max_attempts = submit.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS).max(1);
+        let timeout = submit.timeout.map(Duration::from_secs);
+
+        submit
+            .tasks
+            .into_iter()
+            .map(|spec| {
+                let id = self.next_id;
+                self.next_id += 1;
+                self.tasks.insert(
+                    id,
+                    TaskEntry {
+                        spec,
+                        status: TaskStatus::new(id),
+                        max_per_peer,
+                        max_attempts,
+                        timeout,
+                        blob_url: blob_url.clone(),
+                        tried: HashSet::new(),
+                    },
+                );
+                self.pending.push_back(id);
+                id
+            })
+            .collect()
+    }
+
+    pub fn list(&self) -> Vec<TaskStatus> {
+        self.tasks.values().map(|t| t.status.clone()).collect()
+    }
+
+    pub fn status(&self, task_id: u64) -> Result<TaskStatus, SessionErr> {
+        self.tasks
+            .get(&task_id)
+            .map(|t| t.status.clone())
+            .ok_or(SessionErr::TaskNotFound(task_id))
+    }
+
+    /// Cancels a task; a running attempt is not interrupted, but its result is ignored.
+    pub fn cancel(&mut self, task_id: u64) -> Result<TaskStatus, SessionErr> {
+        let task = self
+            .tasks
+            .get_mut(&task_id)
+            .ok_or(SessionErr::TaskNotFound(task_id))?;
+
+        if !task.status.is_finished() {
+            task.status.state = TaskState::Cancelled;
+            self.pending.retain(|id| *id != task_id);
+        }
+        Ok(task.status.clone())
+    }
+
+    /// Assigns pending tasks to free deployments.
+    ///
+    /// A task is started on a peer running less tasks than its `max_per_peer`.
+    /// Peers on which a task has not been tried yet are preferred.
+    pub fn assign(&mut self, deployments: &[(NodeId, String)]) -> Vec<Assignment> {
+        let mut peer_load: HashMap<NodeId, usize> = HashMap::new();
+        for (node_id, _) in self.busy.keys() {
+            *peer_load.entry(*node_id).or_insert(0) += 1;
+        }
+        let mut free: Vec<(NodeId, String)> = deployments
+            .iter()
+            .filter(|d| !self.busy.contains_key(d) && !self.reserved.contains(d))
+            .cloned()
+            .collect();
+
+        let mut assignments = Vec::new();
+        let mut waiting = VecDeque::new();
+
+        while let Some(task_id) = self.pending.pop_front() {
+            let task = match self.tasks.get_mut(&task_id) {
+                Some(task) => task,
+                None => continue,
+            };
+            let max_per_peer = task.max_per_peer;
+            let available = |(node_id, _): &(NodeId, String)| {
+                peer_load.get(node_id).cloned().unwrap_or(0) < max_per_peer
+            };
+            let pos = free
+                .iter()
+                .position(|d| available(d) && !task.tried.contains(&d.0))
+                .or_else(|| free.iter().position(|d| available(d)));
+
+            let (node_id, deployment_id) = match pos {
+                Some(pos) => free.remove(pos),
+                None => {
+                    waiting.push_back(task_id);
+                    continue;
+                }
+            };
+
+            *peer_load.entry(node_id).or_insert(0) += 1;
+            task.tried.insert(node_id);
+            task.status.attempts += 1;
+            task.status.state = TaskState::Running;
+            task.status.node_id = Some(node_id);
+            task.status.deployment_id = Some(deployment_id.clone());
+            self.busy.insert(
+                (node_id, deployment_id.clone()),
+                (task_id, task.status.attempts),
+            );
+
+            assignments.push(Assignment {
+                task_id,
+                attempt: task.status.attempts,
+                node_id,
+                deployment_id,
+                commands: task.commands(),
+                timeout: task.timeout,
+            });
+        }
+        self.pending = waiting;
+
+        assignments
+    }
+
+    /// Records result of a task attempt and frees its deployment.
+    pub fn finish(
+        &mut self,
+        node_id: NodeId,
+        deployment_id: String,
+        task_id: u64,
+        attempt: u32,
+        result: Result<Vec<String>, String>,
+    ) {
+        self.busy.remove(&(node_id, deployment_id));
+        self.complete_attempt(task_id, attempt, result);
+    }
+
+    /// Fails a timed out attempt; its deployment stays busy until the attempt returns.
+    pub fn time_out(&mut self, task_id: u64, attempt: u32) -> bool {
+        self.complete_attempt(task_id, attempt, Err("task timed out".to_string()))
+    }
+
+    fn complete_attempt(
+        &mut self,
+        task_id: u64,
+        attempt: u32,
+        result: Result<Vec<String>, String>,
+    ) -> bool {
+        let task = match self.tasks.get_mut(&task_id) {
+            Some(task) => task,
+            None => return false,
+        };
+        if task.status.state != TaskState::Running || task.status.attempts != attempt {
+            return false;
+        }
+
+        match result {
+            Ok(output) => {
+                task.status.state = TaskState::Done;
+                task.status.output = output;
+                task.status.error = None;
+            }
+            Err(e) => {
+                task.status.error = Some(e);
+                if task.status.attempts < task.max_attempts {
+                    task.status.state = TaskState::Pending;
+                    self.pending.push_front(task_id);
+                } else {
+                    task.status.state = TaskState::Failed;
+                }
+            }
+        }
+        true
+    }
+
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Task with an attempt running on the deployment.
+    pub fn running_task(&self, node_id: NodeId, deployment_id: &str) -> Option<u64> {
+        self.busy
+            .get(&(node_id, deployment_id.to_string()))
+            .map(|(task_id, _)| *task_id)
+    }
+
+    /// Keeps tasks off the deployments until they are released.
+    pub fn reserve(&mut self, deployments: &[(NodeId, String)]) {
+        self.reserved.extend(deployments.iter().cloned());
+    }
+
+    pub fn release(&mut self, deployments: &[(NodeId, String)]) {
+        for deployment in deployments {
+            self.reserved.remove(deployment);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gu_model::task::TaskBlob;
+
+    fn node(n: u8) -> NodeId {
+        NodeId::from([n; 20])
+    }
+
+    fn deployments(nodes: &[(u8, &str)]) -> Vec<(NodeId, String)> {
+        nodes
+            .iter()
+            .map(|&(n, id)| (node(n), id.to_string()))
+            .collect()
+    }
+
+    fn submit(tasks: usize, max_per_peer: Option<usize>, max_attempts: Option<u32>) -> TaskSubmit {
+        TaskSubmit {
+            tasks: vec![TaskSpec::default(); tasks],
+            max_per_peer,
+            max_attempts,
+            ..TaskSubmit::default()
+        }
+    }
+
+    #[test]
+    fn test_assign_one_per_peer() {
+        let mut queue = TaskQueue::default();
+        assert_eq!(
+            queue.submit(submit(3, None, None), String::new()),
+            vec![0, 1, 2]
+        );
+
+        let assignments = queue.assign(&deployments(&[(1, "a"), (1, "b"), (2, "c")]));
+        assert_eq!(assignments.len(), 2);
+        assert_ne!(assignments[0].node_id, assignments[1].node_id);
+        assert!(queue.has_pending());
+
+        let first = &assignments[0];
+        queue.finish(
+            first.node_id,
+            first.deployment_id.clone(),
+            first.task_id,
+            first.attempt,
+            Ok(vec!["out".to_string()]),
+        );
+        let status = queue.status(first.task_id).unwrap();
+        assert_eq!(status.state, TaskState::Done);
+        assert_eq!(status.output, vec!["out".to_string()]);
+
+        assert_eq!(
+            queue
+                .assign(&deployments(&[(1, "a"), (1, "b"), (2, "c")]))
+                .len(),
+            1
+        );
+        assert!(!queue.has_pending());
+    }
+
+    #[test]
+    fn test_max_per_peer_per_batch() {
+        let mut queue = TaskQueue::default();
+        queue.submit(submit(2, Some(2), None), String::new());
+        queue.submit(submit(1, None, None), String::new());
+
+        // the peer runs the two tasks allowing two per peer, the third waits
+        let assignments = queue.assign(&deployments(&[(1, "a"), (1, "b"), (1, "c")]));
+        assert_eq!(
+            assignments.iter().map(|a| a.task_id).collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        assert_eq!(queue.status(2).unwrap().state, TaskState::Pending);
+    }
+
+    #[test]
+    fn test_zero_max_per_peer() {
+        let mut queue = TaskQueue::default();
+        queue.submit(submit(2, Some(0), None), String::new());
+
+        // a zero limit would keep the tasks pending forever
+        let assignments = queue.assign(&deployments(&[(1, "a"), (1, "b")]));
+        assert_eq!(
+            assignments.iter().map(|a| a.task_id).collect::<Vec<_>>(),
+            vec![0]
+        );
+    }
+
+    #[test]
+    fn test_retry_on_other_peer() {
+        let mut queue = TaskQueue::default();
+        queue.submit(submit(1, None, Some(2)), String::new());
+        let peers = deployments(&[(1, "a"), (2, "b")]);
+
+        let first = queue.assign(&peers).remove(0);
+        queue.finish(
+            first.node_id,
+            first.deployment_id,
+            first.task_id,
+            first.attempt,
+            Err("failed".to_string()),
+        );
+        assert_eq!(queue.status(0).unwrap().state, TaskState::Pending);
+
+        let second = queue.assign(&peers).remove(0);
+        assert_ne!(second.node_id, first.node_id);
+        assert_eq!(second.attempt, 2);
+        queue.finish(
+            second.node_id,
+            second.deployment_id,
+            second.task_id,
+            second.attempt,
+            Err("failed".to_string()),
+        );
+        let status = queue.status(0).unwrap();
+        assert_eq!(status.state, TaskState::Failed);
+        assert_eq!(status.error, Some("failed".to_string()));
+    }
+
+    #[test]
+    fn test_time_out_keeps_deployment_busy() {
+        let mut queue = TaskQueue::default();
+        queue.submit(submit(2, None, Some(2)), String::new());
+        let peers = deployments(&[(1, "a")]);
+
+        let first = queue.assign(&peers).remove(0);
+        assert!(queue.time_out(first.task_id, first.attempt));
+        assert!(!queue.time_out(first.task_id, first.attempt));
+        assert_eq!(queue.status(0).unwrap().state, TaskState::Pending);
+        assert!(queue.assign(&peers).is_empty());
+
+        // result of the timed out attempt is ignored
+        queue.finish(
+            first.node_id,
+            first.deployment_id,
+            first.task_id,
+            first.attempt,
+            Ok(Vec::new()),
+        );
+        assert_eq!(queue.status(0).unwrap().state, TaskState::Pending);
+        assert_eq!(queue.assign(&peers)[0].task_id, 0);
+    }
+
+    #[test]
+    fn test_reserve() {
+        let mut queue = TaskQueue::default();
+        queue.submit(submit(1, None, None), String::new());
+        let peers = deployments(&[(1, "a")]);
+
+        queue.reserve(&peers);
+        assert!(queue.assign(&peers).is_empty());
+        assert_eq!(queue.running_task(node(1), "a"), None);
+
+        queue.release(&peers);
+        assert_eq!(queue.assign(&peers).len(), 1);
+        assert_eq!(queue.running_task(node(1), "a"), Some(0));
+        assert_eq!(queue.running_task(node(1), "b"), None);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut queue = TaskQueue::default();
+        queue.submit(submit(1, None, None), String::new());
+
+        assert_eq!(queue.cancel(0).unwrap().state, TaskState::Cancelled);
+        assert!(!queue.has_pending());
+        assert!(queue.assign(&deployments(&[(1, "a")])).is_empty());
+        assert!(queue.cancel(1).is_err());
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut queue = TaskQueue::default();
+        let spec = TaskSpec {
+            commands: vec![Command::Wait],
+            inputs: vec![TaskBlob {
+                blob_id: 1,
+                file_path: "in".to_string(),
+                format: Default::default(),
+            }],
+            outputs: vec![TaskBlob {
+                blob_id: 2,
+                file_path: "out".to_string(),
+                format: Default::default(),
+            }],
+        };
+        queue.submit(
+            TaskSubmit {
+                tasks: vec![spec],
+                ..TaskSubmit::default()
+            },
+            "http://hub/blob/".to_string(),
+        );
+
+        let commands = queue.assign(&deployments(&[(1, "a")])).remove(0).commands;
+        assert_eq!(commands.len(), 3);
+        match &commands[0] {
+            Command::DownloadFile { uri, file_path, .. } => {
+                assert_eq!(uri, "http://hub/blob/1");
+                assert_eq!(file_path, "in");
+            }
+            _ => panic!("download expected"),
+        }
+        match &commands[2] {
+            Command::UploadFile { uri, .. } => assert_eq!(uri, "http://hub/blob/2"),
+            _ => panic!("upload expected"),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! TLS for the hub HTTP API and provider WebSocket connections.
+//!
+//! When no certificate is configured a self-signed one is generated in the hub
+//! working directory. Clients pin its fingerprint instead of checking a CA chain.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TlsConfig {
+    #[serde(default)]
+    pub(crate) enabled: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) cert_file: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) key_file: Option<PathBuf>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            enabled: false,
+            cert_file: None,
+            key_file: None,
+        }
+    }
+}
+
+impl TlsConfig {
+    #[cfg(feature = "ssl")]
+    fn paths(&self, work_dir: &Path) -> (PathBuf, PathBuf) {
+        (
+            self.cert_file
+                .clone()
+                .unwrap_or_else(|| work_dir.join("hub-cert.pem")),
+            self.key_file
+                .clone()
+                .unwrap_or_else(|| work_dir.join("hub-key.pem")),
+        )
+    }
+}
+
+#[cfg(feature = "ssl")]
+pub(crate) use self::ssl::acceptor;
+
+#[cfg(feature = "ssl")]
+mod ssl {
+    use std::{fs, path::Path};
+
+    use log::info;
+    use openssl::{
+        asn1::Asn1Time,
+        bn::{BigNum, MsbOption},
+        error::ErrorStack,
+        hash::MessageDigest,
+        pkey::{PKey, Private},
+        rsa::Rsa,
+        ssl::{SslAcceptor, SslAcceptorBuilder, SslMethod},
+        x509::{X509NameBuilder, X509},
+    };
+
+    use super::TlsConfig;
+
+    const VALID_DAYS: u32 = 3650;
+
+    /// Loads the configured certificate, generating a self-signed one on first run.
+    /// Returns acceptor builder and the certificate fingerprint.
+    pub(crate) fn acceptor(
+        config: &TlsConfig,
+        work_dir: &Path,
+    ) -> Result<(SslAcceptorBuilder, String), String> {
+        let (cert_path, key_path) = config.paths(work_dir);
+
+        let (cert, key) = if cert_path.exists() && key_path.exists() {
+            let cert = fs::read(&cert_path).map_err(|e| format!("{:?}: {}", cert_path, e))?;
+            let key = fs::read(&key_path).map_err(|e| format!("{:?}: {}", key_path, e))?;
+            (
+                X509::from_pem(&cert).map_err(|e| format!("{:?}: {}", cert_path, e))?,
+                PKey::private_key_from_pem(&key).map_err(|e| format!("{:?}: {}", key_path, e))?,
+            )
+        } else if config.cert_file.is_some() || config.key_file.is_some() {
+            return Err(format!(
+                "missing TLS certificate {:?} or key {:?}",
+                cert_path, key_path
+            ));
+        } else {
+            let (cert, key) = self_signed().map_err(|e| e.to_string())?;
+            fs::write(&cert_path, cert.to_pem().map_err(|e| e.to_string())?)
+                .map_err(|e| format!("{:?}: {}", cert_path, e))?;
+            fs::write(
+                &key_path,
+                key.private_key_to_pem_pkcs8().map_err(|e| e.to_string())?,
+            )
+            .map_err(|e| format!("{:?}: {}", key_path, e))?;
+            info!("Generated self-signed certificate {:?}", cert_path);
+            (cert, key)
+        };
+
+        let fingerprint = gu_net::tls::fingerprint(&cert).map_err(|e| e.to_string())?;
+        let mut builder =
+            SslAcceptor::mozilla_intermediate(SslMethod::tls()).map_err(|e| e.to_string())?;
+        builder.set_private_key(&key).map_err(|e| e.to_string())?;
+        builder.set_certificate(&cert).map_err(|e| e.to_string())?;
+        builder.check_private_key().map_err(|e| e.to_string())?;
+
+        Ok((builder, fingerprint))
+    }
+
+    fn self_signed() -> Result<(X509, PKey<Private>), ErrorStack> {
+        let key = PKey::from_rsa(Rsa::generate(2048)?)?;
+
+        let mut name = X509NameBuilder::new()?;
+        name.append_entry_by_text(
+            "CN",
+            &hostname::get_hostname().unwrap_or_else(|| "gu-hub".to_string()),
+        )?;
+        let name = name.build();
+
+        let mut serial = BigNum::new()?;
+        serial.rand(128, MsbOption::MAYBE_ZERO, false)?;
+
+        let mut builder = X509::builder()?;
+        builder.set_version(2)?;
+        builder.set_serial_number(&serial.to_asn1_integer()?)?;
+        builder.set_subject_name(&name)?;
+        builder.set_issuer_name(&name)?;
+        builder.set_pubkey(&key)?;
+        builder.set_not_before(&Asn1Time::days_from_now(0)?)?;
+        builder.set_not_after(&Asn1Time::days_from_now(VALID_DAYS)?)?;
+        builder.sign(&key, MessageDigest::sha256())?;
+
+        Ok((builder.build(), key))
+    }
+}
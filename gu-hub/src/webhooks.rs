@@ -0,0 +1,470 @@
+//! Outbound webhooks for hub events.
+//!
+//! Subscriptions are kept in the `webhooks` config section. Every matching hub event
+//! is POSTed as JSON with `X-Gu-Signature: sha256=<hex>`, an HMAC-SHA256 of the body
+//! keyed with the subscription secret. Failed deliveries are retried with exponential
+//! backoff, recent attempts are kept in memory and listed by
+//! `GET /webhooks/{webhookId}/deliveries`.
+//!
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+
+use actix::prelude::*;
+use actix_web::{
+    self, client, http::StatusCode, AsyncResponder, HttpResponse, Json, Path, Responder, Scope,
+};
+use bytes::Bytes;
+use chrono::Utc;
+use futures::prelude::*;
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use gu_actix::prelude::*;
+use gu_base::Module;
+use gu_event_bus::Event;
+use gu_model::events::{DeliveryRecord, EventMessage, HubEvent, NewWebhook, WebhookInfo};
+use gu_persist::config::{self, ConfigManager, GetConfig, SetConfig};
+
+const MAX_ATTEMPTS: u32 = 5;
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Number of delivery attempts kept per webhook.
+const DELIVERY_LOG_SIZE: usize = 100;
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct WebhookEntry {
+    #[serde(flatten)]
+    info: WebhookInfo,
+    secret: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct WebhooksConfig {
+    #[serde(default)]
+    webhooks: Vec<WebhookEntry>,
+    /// ids of removed webhooks are not given again
+    #[serde(default)]
+    last_webhook_id: u64,
+}
+
+impl config::HasSectionId for WebhooksConfig {
+    const SECTION_ID: &'static str = "webhooks";
+}
+
+/// Checks if the event path is the filter path or lies below it.
+fn path_matches(filter: &str, path: &str) -> bool {
+    let filter = filter.trim_end_matches('/');
+    filter.is_empty()
+        || path == filter
+        || (path.starts_with(filter) && path[filter.len()..].starts_with('/'))
+}
+
+fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.input(body);
+    let code = mac.result().code();
+    format!(
+        "sha256={}",
+        code.iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    )
+}
+
+/// Delay before the next attempt: 2, 4, 8, ... seconds.
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_secs(1 << attempt.min(10))
+}
+
+#[derive(Clone)]
+struct Delivery {
+    webhook_id: u64,
+    event_id: u64,
+    path: String,
+    body: Bytes,
+    attempt: u32,
+}
+
+#[derive(Default)]
+pub struct WebhookManager {
+    webhooks: BTreeMap<u64, WebhookEntry>,
+    deliveries: HashMap<u64, VecDeque<DeliveryRecord>>,
+    last_event_id: u64,
+    last_webhook_id: u64,
+}
+
+impl WebhookManager {
+    fn save(&self, ctx: &mut Context<Self>) {
+        let config = WebhooksConfig {
+            webhooks: self.webhooks.values().cloned().collect(),
+            last_webhook_id: self.last_webhook_id,
+        };
+        ConfigManager::from_registry()
+            .send(SetConfig::new(config))
+            .flatten_fut()
+            .map_err(|e| error!("Cannot save webhooks: {}", e))
+            .into_actor(self)
+            .spawn(ctx);
+    }
+
+    fn deliver(&mut self, delivery: Delivery, ctx: &mut Context<Self>) {
+        let webhook = match self.webhooks.get(&delivery.webhook_id) {
+            Some(webhook) => webhook,
+            // removed while retrying
+            None => return,
+        };
+
+        let request = client::ClientRequest::post(&webhook.info.url)
+            .header("X-Gu-Event", delivery.path.as_str())
+            .header("X-Gu-Delivery", delivery.event_id.to_string())
+            .header("X-Gu-Signature", signature(&webhook.secret, &delivery.body))
+            .content_type("application/json")
+            .body(delivery.body.clone());
+
+        let response = match request {
+            Ok(request) => futures::future::Either::A(
+                request
+                    .send()
+                    .timeout(DELIVERY_TIMEOUT)
+                    .map(|response| response.status())
+                    .map_err(|e| e.to_string()),
+            ),
+            Err(e) => futures::future::Either::B(futures::future::err(e.to_string())),
+        };
+
+        response
+            .into_actor(self)
+            .then(move |result, act: &mut Self, ctx| {
+                let (status, error) = match result {
+                    Ok(status) if status.is_success() => (Some(status), None),
+                    Ok(status) => (Some(status), Some(format!("endpoint returned {}", status))),
+                    Err(e) => (None, Some(e)),
+                };
+                let failed = error.is_some();
+                act.record(
+                    &delivery,
+                    DeliveryRecord {
+                        event_id: delivery.event_id,
+                        path: delivery.path.clone(),
+                        attempt: delivery.attempt,
+                        ts: Utc::now(),
+                        status: status.map(|status| status.as_u16()),
+                        error,
+                    },
+                );
+
+                if failed && delivery.attempt < MAX_ATTEMPTS {
+                    let delay = backoff(delivery.attempt);
+                    let retry = Delivery {
+                        attempt: delivery.attempt + 1,
+                        ..delivery
+                    };
+                    ctx.run_later(delay, move |act, ctx| act.deliver(retry, ctx));
+                } else if failed {
+                    warn!(
+                        "Webhook {} delivery of event {} failed after {} attempts",
+                        delivery.webhook_id, delivery.event_id, delivery.attempt
+                    );
+                }
+                fut::ok(())
+            })
+            .spawn(ctx);
+    }
+
+    fn record(&mut self, delivery: &Delivery, record: DeliveryRecord) {
+        let log = self
+            .deliveries
+            .entry(delivery.webhook_id)
+            .or_insert_with(VecDeque::new);
+        if log.len() >= DELIVERY_LOG_SIZE {
+            log.pop_front();
+        }
+        log.push_back(record);
+    }
+}
+
+impl Actor for WebhookManager {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.wait(
+            ConfigManager::from_registry()
+                .send(GetConfig::new())
+                .flatten_fut()
+                .map_err(|e| error!("Cannot load webhooks: {}", e))
+                .into_actor(self)
+                .and_then(|config: Arc<WebhooksConfig>, act, _ctx| {
+                    act.webhooks = config
+                        .webhooks
+                        .iter()
+                        .map(|entry| (entry.info.id, entry.clone()))
+                        .collect();
+                    // configs saved before the counter was kept have only the ids
+                    act.last_webhook_id = act
+                        .webhooks
+                        .keys()
+                        .next_back()
+                        .cloned()
+                        .unwrap_or(0)
+                        .max(config.last_webhook_id);
+                    fut::ok(())
+                }),
+        );
+        ctx.wait(
+            gu_event_bus::subscribe("".into(), ctx.address().recipient())
+                .map(|_| ())
+                .map_err(|_| error!("Cannot subscribe to hub events"))
+                .into_actor(self),
+        );
+    }
+}
+
+impl Supervised for WebhookManager {}
+
+impl SystemService for WebhookManager {}
+
+impl Handler<Event<HubEvent>> for WebhookManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: Event<HubEvent>, ctx: &mut Self::Context) {
+        let targets: Vec<u64> = self
+            .webhooks
+            .values()
+            .filter(|webhook| path_matches(&webhook.info.path, msg.path()))
+            .map(|webhook| webhook.info.id)
+            .collect();
+        if targets.is_empty() {
+            return;
+        }
+
+        self.last_event_id += 1;
+        let message = EventMessage {
+            id: self.last_event_id,
+            path: msg.path().to_string(),
+            event: msg.data().clone(),
+        };
+        let body = match serde_json::to_vec(&message) {
+            Ok(body) => Bytes::from(body),
+            Err(e) => {
+                error!("cannot serialize event: {}", e);
+                return;
+            }
+        };
+
+        for webhook_id in targets {
+            self.deliver(
+                Delivery {
+                    webhook_id,
+                    event_id: message.id,
+                    path: message.path.clone(),
+                    body: body.clone(),
+                    attempt: 1,
+                },
+                ctx,
+            );
+        }
+    }
+}
+
+pub struct ListWebhooks;
+
+impl Message for ListWebhooks {
+    type Result = Vec<WebhookInfo>;
+}
+
+impl Handler<ListWebhooks> for WebhookManager {
+    type Result = MessageResult<ListWebhooks>;
+
+    fn handle(&mut self, _msg: ListWebhooks, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(
+            self.webhooks
+                .values()
+                .map(|entry| entry.info.clone())
+                .collect(),
+        )
+    }
+}
+
+pub struct AddWebhook(pub NewWebhook);
+
+impl Message for AddWebhook {
+    type Result = WebhookInfo;
+}
+
+impl Handler<AddWebhook> for WebhookManager {
+    type Result = MessageResult<AddWebhook>;
+
+    fn handle(&mut self, msg: AddWebhook, ctx: &mut Self::Context) -> Self::Result {
+        self.last_webhook_id += 1;
+        let info = WebhookInfo {
+            id: self.last_webhook_id,
+            url: msg.0.url,
+            path: msg.0.path,
+        };
+        info!("Webhook {} for {:?} added", info.url, info.path);
+
+        self.webhooks.insert(
+            info.id,
+            WebhookEntry {
+                info: info.clone(),
+                secret: msg.0.secret,
+            },
+        );
+        self.save(ctx);
+        MessageResult(info)
+    }
+}
+
+pub struct RemoveWebhook(pub u64);
+
+impl Message for RemoveWebhook {
+    type Result = Option<WebhookInfo>;
+}
+
+impl Handler<RemoveWebhook> for WebhookManager {
+    type Result = Option<WebhookInfo>;
+
+    fn handle(&mut self, msg: RemoveWebhook, ctx: &mut Self::Context) -> Self::Result {
+        let removed = self.webhooks.remove(&msg.0).map(|entry| entry.info);
+        if let Some(ref info) = removed {
+            info!("Webhook {} removed", info.url);
+            self.deliveries.remove(&msg.0);
+            self.save(ctx);
+        }
+        removed
+    }
+}
+
+/// Returns recent delivery attempts, `None` if the webhook does not exist.
+pub struct ListDeliveries(pub u64);
+
+impl Message for ListDeliveries {
+    type Result = Option<Vec<DeliveryRecord>>;
+}
+
+impl Handler<ListDeliveries> for WebhookManager {
+    type Result = MessageResult<ListDeliveries>;
+
+    fn handle(&mut self, msg: ListDeliveries, _ctx: &mut Self::Context) -> Self::Result {
+        if !self.webhooks.contains_key(&msg.0) {
+            return MessageResult(None);
+        }
+        MessageResult(Some(
+            self.deliveries
+                .get(&msg.0)
+                .map(|log| log.iter().cloned().collect())
+                .unwrap_or_default(),
+        ))
+    }
+}
+
+struct WebhooksModule;
+
+pub fn module() -> impl Module {
+    WebhooksModule
+}
+
+impl Module for WebhooksModule {
+    fn decorate_webapp<S: 'static>(&self, app: actix_web::App<S>) -> actix_web::App<S> {
+        app.scope("/webhooks", scope)
+    }
+}
+
+fn scope<S: 'static>(scope: Scope<S>) -> Scope<S> {
+    scope
+        .resource("", |r| {
+            r.get().with(list_webhooks);
+            r.post().with(add_webhook)
+        })
+        .resource("/{webhookId}", |r| r.delete().with(remove_webhook))
+        .resource("/{webhookId}/deliveries", |r| r.get().with(list_deliveries))
+}
+
+fn list_webhooks(_: ()) -> impl Responder {
+    WebhookManager::from_registry()
+        .send(ListWebhooks)
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))
+        .and_then(|webhooks| Ok(HttpResponse::Ok().json(webhooks)))
+        .responder()
+}
+
+fn add_webhook(body: Json<NewWebhook>) -> impl Responder {
+    WebhookManager::from_registry()
+        .send(AddWebhook(body.into_inner()))
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))
+        .and_then(|info| Ok(HttpResponse::Created().json(info)))
+        .responder()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WebhookPath {
+    webhook_id: u64,
+}
+
+fn remove_webhook(path: Path<WebhookPath>) -> impl Responder {
+    WebhookManager::from_registry()
+        .send(RemoveWebhook(path.webhook_id))
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))
+        .and_then(|removed| match removed {
+            Some(info) => Ok(HttpResponse::Ok().json(info)),
+            None => Ok(HttpResponse::build(StatusCode::NOT_FOUND).body("Webhook not found")),
+        })
+        .responder()
+}
+
+fn list_deliveries(path: Path<WebhookPath>) -> impl Responder {
+    WebhookManager::from_registry()
+        .send(ListDeliveries(path.webhook_id))
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))
+        .and_then(|deliveries| match deliveries {
+            Some(deliveries) => Ok(HttpResponse::Ok().json(deliveries)),
+            None => Ok(HttpResponse::build(StatusCode::NOT_FOUND).body("Webhook not found")),
+        })
+        .responder()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_path_matches() {
+        assert!(path_matches("", "/sessions/1"));
+        assert!(path_matches("/", "/sessions/1"));
+        assert!(path_matches("/sessions", "/sessions"));
+        assert!(path_matches("/sessions/", "/sessions/1/blobs"));
+        assert!(!path_matches("/sessions", "/sessionsx"));
+        assert!(!path_matches("/sessions/1", "/sessions/10"));
+        assert!(!path_matches("/peers", "/sessions"));
+    }
+
+    #[test]
+    fn test_signature() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            signature("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_ne!(
+            signature("other", b"what do ya want for nothing?"),
+            signature("Jefe", b"what do ya want for nothing?")
+        );
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(1), Duration::from_secs(2));
+        assert_eq!(backoff(2), Duration::from_secs(4));
+        assert_eq!(backoff(4), Duration::from_secs(16));
+        assert_eq!(backoff(20), Duration::from_secs(1024));
+    }
+}
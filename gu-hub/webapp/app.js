@@ -1,6 +1,71 @@
+const TOKEN_KEY = 'guHubToken';
+
 var app = angular.module('gu', ['ui.bootstrap', 'angularjs-gauge'])
     .filter("prettyJSON", () => json => JSON.stringify(json, null, " "))
-    .controller('AppController', function ($scope, pluginManager) {
+    .config(function ($httpProvider) {
+        $httpProvider.interceptors.push('authInterceptor');
+    })
+    .factory('authInterceptor', function ($q, $injector) {
+        return {
+            request: config => {
+                const token = localStorage.getItem(TOKEN_KEY);
+                if (token && !config.headers.Authorization) {
+                    config.headers.Authorization = 'Bearer ' + token;
+                }
+                return config;
+            },
+            responseError: rejection => {
+                if (rejection.status === 401) {
+                    $injector.get('auth').login();
+                }
+                return $q.reject(rejection);
+            }
+        };
+    })
+    .service('auth', function ($http, $uibModal, $window) {
+        let loginModal = null;
+
+        function login() {
+            if (loginModal) {
+                return loginModal.result;
+            }
+            loginModal = $uibModal.open({
+                animate: true,
+                backdrop: 'static',
+                keyboard: false,
+                templateUrl: 'login.html',
+                controller: function ($scope, $uibModalInstance) {
+                    $scope.form = {token: ''};
+                    $scope.login = function () {
+                        const headers = {Authorization: 'Bearer ' + $scope.form.token};
+                        $http.get('/auth/whoami', {headers: headers}).then(r => {
+                            localStorage.setItem(TOKEN_KEY, $scope.form.token);
+                            $uibModalInstance.close(r.data);
+                            $window.location.reload();
+                        }, r => {
+                            $scope.error = r.status === 401 ? 'Invalid API token' : (r.data || r.statusText);
+                        });
+                    };
+                }
+            });
+            loginModal.closed.then(() => loginModal = null);
+            return loginModal.result;
+        }
+
+        function logout() {
+            localStorage.removeItem(TOKEN_KEY);
+            $window.location.reload();
+        }
+
+        function whoami() {
+            return $http.get('/auth/whoami').then(r => r.data);
+        }
+
+        return {login: login, logout: logout, whoami: whoami};
+    })
+    .controller('AppController', function ($scope, pluginManager, auth) {
+        auth.whoami().then(identity => $scope.identity = identity);
+        $scope.logout = auth.logout;
 
         function tabActivator(tab) {
             return {
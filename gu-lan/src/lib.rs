@@ -52,6 +52,9 @@ pub struct HubDesc {
     pub host_name: String,
     /// nodes public key hash
     pub node_id: NodeId,
+    /// SHA-256 fingerprint of the hub TLS certificate, hubs without TLS have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cert_fingerprint: Option<String>,
 }
 
 /// Lists HUBs visible in local network.
@@ -102,6 +105,10 @@ pub fn list_hubs() -> impl futures::Future<Item = Vec<HubDesc>, Error = ()> {
                         Some(Ok(node_id)) => node_id,
                         _ => return None,
                     };
+                    let cert_fingerprint = match service_instance.extract("cert") {
+                        Some(Ok(cert_fingerprint)) => Some(cert_fingerprint),
+                        _ => None,
+                    };
                     let host_name = service_instance.host;
                     match (
                         service_instance.addrs_v4.first(),
@@ -113,6 +120,7 @@ pub fn list_hubs() -> impl futures::Future<Item = Vec<HubDesc>, Error = ()> {
                                 address,
                                 host_name,
                                 node_id,
+                                cert_fingerprint,
                             })
                         }
                         (_, _) => {
@@ -194,6 +202,12 @@ impl MdnsPublisher {
         ))
     }
 
+    /// Adds `key=value` TXT record, must be called before `start`.
+    pub fn add_txt<K: AsRef<str>, V: AsRef<str>>(&mut self, key: K, value: V) {
+        self.txt
+            .push(format!("{}={}", key.as_ref(), value.as_ref()));
+    }
+
     pub fn init_publisher<S>(port: u16, node_id: S, is_hub: bool) -> Self
     where
         S: AsRef<str>,
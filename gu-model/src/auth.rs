@@ -0,0 +1,97 @@
+//! Hub REST API tokens and roles.
+//!
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use failure::Fail;
+use serde::{Deserialize, Serialize};
+
+/// Role granted by an API token, roles are ordered by privilege.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    /// Can only read hub state.
+    ReadOnly,
+    /// Can manage sessions and deployments.
+    User,
+    /// Can manage peers, plugins, provider config and API tokens.
+    Admin,
+}
+
+#[derive(Debug, Fail)]
+#[fail(display = "unknown role: {}, expected admin, user or read-only", _0)]
+pub struct InvalidRole(String);
+
+impl FromStr for Role {
+    type Err = InvalidRole;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(Role::Admin),
+            "user" => Ok(Role::User),
+            "read-only" => Ok(Role::ReadOnly),
+            _ => Err(InvalidRole(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Role::Admin => "admin",
+            Role::User => "user",
+            Role::ReadOnly => "read-only",
+        })
+    }
+}
+
+/// Caller of the hub API.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Identity {
+    pub name: String,
+    pub role: Role,
+}
+
+/// API token description; the token itself is only returned on creation.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenInfo {
+    pub id: u64,
+    pub name: String,
+    pub role: Role,
+    pub created: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NewToken {
+    pub name: String,
+    pub role: Role,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedToken {
+    pub token: String,
+    pub info: TokenInfo,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_role_order_and_names() {
+        assert!(Role::Admin > Role::User);
+        assert!(Role::User > Role::ReadOnly);
+        for role in &[Role::Admin, Role::User, Role::ReadOnly] {
+            assert_eq!(role.to_string().parse::<Role>().unwrap(), *role);
+            assert_eq!(
+                serde_json::to_string(role).unwrap(),
+                format!("\"{}\"", role)
+            );
+        }
+        assert!("root".parse::<Role>().is_err());
+    }
+}
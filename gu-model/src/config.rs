@@ -0,0 +1,63 @@
+//! Remote provider configuration.
+//!
+//! Hub with full access to the provider can read and replace whitelisted
+//! provider config sections.
+//!
+use failure::Fail;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "with-actix")]
+use actix::prelude::*;
+#[cfg(feature = "with-actix")]
+use gu_net::rpc::PublicMessage;
+
+/// Config sections that can be managed remotely.
+pub const REMOTE_SECTIONS: &[&str] = &["permissions", "provider-server-cfg"];
+
+#[derive(Serialize, Deserialize, Debug, Fail)]
+#[serde(rename_all = "camelCase")]
+pub enum ConfigError {
+    #[fail(display = "access denied")]
+    AccessDenied,
+    #[fail(display = "unknown config section: {}", _0)]
+    UnknownSection(String),
+    #[fail(display = "invalid config value: {}", _0)]
+    InvalidValue(String),
+    #[fail(display = "config error: {}", _0)]
+    Error(String),
+}
+
+/// Returns the config section as JSON.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GetConfig {
+    pub section: String,
+}
+
+#[cfg(feature = "with-actix")]
+impl PublicMessage for GetConfig {
+    const ID: u32 = 42;
+}
+
+#[cfg(feature = "with-actix")]
+impl Message for GetConfig {
+    type Result = Result<serde_json::Value, ConfigError>;
+}
+
+/// Replaces the config section, the value must deserialize into the section type.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SetConfig {
+    pub section: String,
+    pub value: serde_json::Value,
+}
+
+#[cfg(feature = "with-actix")]
+impl PublicMessage for SetConfig {
+    const ID: u32 = 43;
+}
+
+#[cfg(feature = "with-actix")]
+impl Message for SetConfig {
+    type Result = Result<(), ConfigError>;
+}
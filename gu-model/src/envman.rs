@@ -1,4 +1,4 @@
-use std::{fmt, io};
+use std::{collections::BTreeMap, fmt, io};
 
 #[cfg(feature = "with-actix")]
 use actix::prelude::*;
@@ -19,6 +19,7 @@ pub enum Error {
     NoSuchSession(String),
     NoSuchChild(String),
     UnknownEnv(String),
+    AccessDenied(String),
 }
 
 impl From<io::Error> for Error {
@@ -45,6 +46,7 @@ impl fmt::Display for Error {
             Error::NoSuchSession(msg) => write!(f, "session not found: {}", msg)?,
             Error::NoSuchChild(msg) => write!(f, "child not found: {}", msg)?,
             Error::UnknownEnv(env_id) => write!(f, "unknown exec environment: {}", env_id)?,
+            Error::AccessDenied(msg) => write!(f, "access denied: {}", msg)?,
         }
         Ok(())
     }
@@ -62,6 +64,10 @@ impl From<String> for Error {
 pub struct Image {
     pub url: String,
     pub hash: String,
+    /// urls of other providers sharing the image; set by the hub, chunks are
+    /// downloaded from them and the origin `url` in parallel
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub peers: Vec<String>,
 }
 
 /// Message for session creation: local provisioning: downloads and unpacks the binaries
@@ -115,6 +121,35 @@ impl Default for ResourceFormat {
     }
 }
 
+/// Working directory, environment and stdin of a process started by `Exec` or `Start`
+#[derive(Clone, Serialize, Deserialize, Hash, Eq, PartialEq, Debug, Default)]
+pub struct ProcessOptions {
+    /// relative to the session workspace
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<String>,
+    /// variables set for the process
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// do not inherit the provider environment
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub clear_env: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin: Option<Stdin>,
+}
+
+fn is_false(v: &bool) -> bool {
+    !*v
+}
+
+/// Input fed to the process
+#[derive(Clone, Serialize, Deserialize, Hash, Eq, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum Stdin {
+    Content(String),
+    /// path relative to the session workspace
+    File(String),
+}
+
 #[derive(Clone, Serialize, Deserialize, Hash, Eq, PartialEq, Debug)]
 #[serde(rename_all = "camelCase")]
 pub enum Command {
@@ -122,9 +157,8 @@ pub enum Command {
         // return cmd output
         executable: String,
         args: Vec<String>,
-        #[serde(default)]
-        #[serde(skip_serializing_if = "Option::is_none")]
-        working_dir: Option<String>,
+        #[serde(flatten)]
+        options: ProcessOptions,
     },
     Open,
     Close,
@@ -132,6 +166,8 @@ pub enum Command {
         // return child process id
         executable: String,
         args: Vec<String>,
+        #[serde(flatten)]
+        options: ProcessOptions,
     },
 
     #[serde(rename_all = "camelCase")]
@@ -196,12 +232,99 @@ impl Message for DestroySession {
     type Result = Result<String, Error>;
 }
 
+/// Tells the provider that the hub drains it, a draining provider rejects new sessions.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SetDraining {
+    pub draining: bool,
+    /// seconds after which the provider destroys sessions still running, also when
+    /// disconnected from the hub
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+}
+
+#[cfg(feature = "with-actix")]
+impl PublicMessage for SetDraining {
+    const ID: u32 = 41;
+}
+
+#[cfg(feature = "with-actix")]
+impl Message for SetDraining {
+    type Result = Result<(), ()>;
+}
+
+/// Asks the provider for images it shares with other providers
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct ListSharedImages {}
+
+#[cfg(feature = "with-actix")]
+impl PublicMessage for ListSharedImages {
+    const ID: u32 = 46;
+}
+
+#[cfg(feature = "with-actix")]
+impl Message for ListSharedImages {
+    type Result = Result<SharedImages, ()>;
+}
+
+/// Images in the provider cache served over http at
+/// `http://<provider address>:<port>/images/<token>/<ALGO>---<hex>`
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SharedImages {
+    /// port of the image server; `None` if the provider does not share images
+    pub port: Option<u16>,
+    /// token required in image urls
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// hashes of shared images, e.g. `SHA1:<hex>`
+    pub hashes: Vec<String>,
+}
+
+/// Asks the provider whether its machine is in use by the local user
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct GetActivity {}
+
+#[cfg(feature = "with-actix")]
+impl PublicMessage for GetActivity {
+    const ID: u32 = 47;
+}
+
+#[cfg(feature = "with-actix")]
+impl Message for GetActivity {
+    type Result = Result<Activity, ()>;
+}
+
+/// Local activity on the provider machine; workloads are paused while it is busy.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Activity {
+    /// running sessions are paused
+    pub paused: bool,
+    /// CPU load of processes other than workloads, as a fraction of all CPUs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_load: Option<f32>,
+    /// seconds since the last user input
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_idle: Option<u64>,
+}
+
 #[cfg(test)]
 mod test {
     use serde_json;
 
     use super::*;
 
+    #[test]
+    fn test_set_draining_deserialization() {
+        let d: SetDraining = serde_json::from_str(r#"{"draining":true}"#).unwrap();
+        assert!(d.draining);
+        assert_eq!(d.timeout, None);
+
+        let d: SetDraining = serde_json::from_str(r#"{"draining":true,"timeout":30}"#).unwrap();
+        assert_eq!(d.timeout, Some(30));
+    }
+
     #[test]
     fn test_create_session_deserialization() {
         // given
@@ -223,6 +346,7 @@ mod test {
         assert_eq!(c.env_type, "hd");
         assert_eq!(c.image.url, "http://some.url/file.tgz");
         assert_eq!(c.image.hash, "12345");
+        assert!(c.image.peers.is_empty());
         assert_eq!(c.tags.len(), 1);
         assert_eq!(c.tags[0], "lato");
     }
@@ -258,6 +382,43 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_exec_process_options_deserialization() {
+        // given
+        let json = r#"
+        {
+            "exec": {
+                "executable": "run.sh",
+                "args": [],
+                "working_dir": "data",
+                "env": {"MODE": "fast"},
+                "clear_env": true,
+                "stdin": {"file": "input.txt"}
+            }
+        }"#;
+
+        // when
+        let c: Command = serde_json::from_str(json).unwrap();
+
+        // then
+        if let Command::Exec { options, .. } = c {
+            assert_eq!(options.working_dir, Some("data".to_string()));
+            assert_eq!(options.env.get("MODE"), Some(&"fast".to_string()));
+            assert!(options.clear_env);
+            assert_eq!(options.stdin, Some(Stdin::File("input.txt".to_string())));
+        } else {
+            panic!("Exec command expected");
+        }
+
+        let start: Command =
+            serde_json::from_str(r#"{"start": {"executable": "srv", "args": []}}"#).unwrap();
+        if let Command::Start { options, .. } = start {
+            assert_eq!(options, ProcessOptions::default());
+        } else {
+            panic!("Start command expected");
+        }
+    }
+
     #[test]
     fn test_session_update_multi_comm_deserialization() {
         // given
@@ -0,0 +1,138 @@
+//! Hub events.
+//!
+//! Events are posted on the hub event bus under hierarchical paths
+//! (e.g. `/sessions/12/deployments`) and streamed to clients by `GET /events`.
+//!
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "with-actix")]
+use gu_net::NodeId;
+
+#[cfg(not(feature = "with-actix"))]
+type NodeId = String;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeploymentState {
+    Created,
+    Updated,
+    Deleted,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum HubEvent {
+    #[serde(rename_all = "camelCase")]
+    PeerConnected { node_id: NodeId },
+    #[serde(rename_all = "camelCase")]
+    PeerDisconnected { node_id: NodeId },
+    /// Provider paused or resumed its workloads because of local activity.
+    #[serde(rename_all = "camelCase")]
+    PeerActivityChanged { node_id: NodeId, paused: bool },
+    #[serde(rename_all = "camelCase")]
+    SessionCreated { session_id: u64 },
+    #[serde(rename_all = "camelCase")]
+    SessionDeleted { session_id: u64 },
+    #[serde(rename_all = "camelCase")]
+    DeploymentChanged {
+        session_id: u64,
+        node_id: NodeId,
+        deployment_id: String,
+        state: DeploymentState,
+    },
+    #[serde(rename_all = "camelCase")]
+    BlobUploaded { session_id: u64, blob_id: u64 },
+    #[serde(rename_all = "camelCase")]
+    PluginInstalled { name: String },
+    #[serde(rename_all = "camelCase")]
+    PluginRemoved { name: String },
+}
+
+impl HubEvent {
+    /// Event bus path the event is posted under.
+    pub fn path(&self) -> String {
+        match self {
+            HubEvent::PeerConnected { node_id }
+            | HubEvent::PeerDisconnected { node_id }
+            | HubEvent::PeerActivityChanged { node_id, .. } => {
+                format!("/peers/{}", node_id.to_string())
+            }
+            HubEvent::SessionCreated { session_id } | HubEvent::SessionDeleted { session_id } => {
+                format!("/sessions/{}", session_id)
+            }
+            HubEvent::DeploymentChanged { session_id, .. } => {
+                format!("/sessions/{}/deployments", session_id)
+            }
+            HubEvent::BlobUploaded { session_id, .. } => format!("/sessions/{}/blobs", session_id),
+            HubEvent::PluginInstalled { name } | HubEvent::PluginRemoved { name } => {
+                format!("/plugins/{}", name)
+            }
+        }
+    }
+}
+
+/// Single message of the `GET /events` stream.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EventMessage {
+    /// Sequence number, increasing within one stream and across webhook deliveries.
+    pub id: u64,
+    pub path: String,
+    pub event: HubEvent,
+}
+
+/// Webhook subscription; the shared secret is never returned by the hub.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookInfo {
+    pub id: u64,
+    pub url: String,
+    /// Event path filter, empty path selects all events.
+    #[serde(default)]
+    pub path: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NewWebhook {
+    pub url: String,
+    #[serde(default)]
+    pub path: String,
+    /// Key of the `X-Gu-Signature` HMAC-SHA256 of the request body.
+    pub secret: String,
+}
+
+/// Single webhook delivery attempt.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DeliveryRecord {
+    pub event_id: u64,
+    pub path: String,
+    pub attempt: u32,
+    pub ts: DateTime<Utc>,
+    /// HTTP status returned by the webhook endpoint.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_event_json() {
+        let event = HubEvent::BlobUploaded {
+            session_id: 3,
+            blob_id: 7,
+        };
+        assert_eq!(event.path(), "/sessions/3/blobs");
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"type":"blobUploaded","sessionId":3,"blobId":7}"#
+        );
+    }
+}
@@ -58,7 +58,7 @@ fn to_hex(ch: u8) -> Result<u8, Error> {
     match ch {
         b'0'..=b'9' => Ok(ch - b'0'),
         b'a'..=b'f' => Ok(ch - b'a' + 10),
-        b'A'..=b'F' => Ok(ch - b'a' + 10),
+        b'A'..=b'F' => Ok(ch - b'A' + 10),
         _ => Err(Error::BadChar(ch)),
     }
 }
@@ -70,14 +70,15 @@ impl<D: Digest + Clone> DigestContentChecker<D> {
             return Err(Error::BadSize(output_size * 2, hexstr.len()));
         }
 
+        // collected into a Vec first, GenericArray panics when an error cuts the iterator short
         let expected_hash = hexstr
             .chunks_exact(2)
             .map(|ch| Ok(to_hex(ch[0])? << 4 | to_hex(ch[1])?))
-            .collect::<Result<_, Error>>()?;
+            .collect::<Result<Vec<u8>, Error>>()?;
 
         Ok(DigestContentChecker {
             digest,
-            expected_hash,
+            expected_hash: GenericArray::clone_from_slice(&expected_hash),
         })
     }
 }
@@ -186,6 +187,45 @@ pub fn digest<R: AsRef<[u8]>>(hash_name: R, bits: usize) -> Result<Box<dyn DynDi
     })
 }
 
+/// Computes the hash string (e.g. `SHA3:<hex>`) of streamed content.
+pub struct ContentHasher {
+    hash_name: &'static str,
+    digest: Box<dyn DynDigest>,
+}
+
+impl ContentHasher {
+    /// Creates hasher for `SHA1`, `SHA3` (256 bits) or `SHA3-<bits>` algorithm.
+    pub fn from_algo(algo: &str) -> Result<Self, Error> {
+        let (hash_name, bits) = match algo.to_ascii_uppercase().as_str() {
+            "SHA1" => ("SHA1", 160),
+            "SHA3" | "SHA3-256" => ("SHA3", 256),
+            "SHA3-224" => ("SHA3", 224),
+            "SHA3-384" => ("SHA3", 384),
+            "SHA3-512" => ("SHA3", 512),
+            _ => return Err(Error::UnknownHashFunc(algo.to_string(), 0)),
+        };
+
+        Ok(ContentHasher {
+            hash_name,
+            digest: digest(hash_name, bits)?,
+        })
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.digest.input(chunk)
+    }
+
+    pub fn finish(self) -> String {
+        let value: String = self
+            .digest
+            .result()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        format!("{}:{}", self.hash_name, value)
+    }
+}
+
 pub fn checker<R: AsRef<[u8]>>(hash_str: R) -> Result<impl ContentChecker, Error> {
     ParsedHash::from_hash_bytes(hash_str.as_ref())?.checker()
 }
@@ -238,6 +278,41 @@ mod test {
         test_value(b"golem1", "SHA3:e43d55ac264ee607918a78561e1f45779b192c747f5844d08a63697314ccf2445edb823cd6bbe14782a40a932176bcda9f35c097cbf49872095205ad102a7960")
     }
 
+    #[test]
+    fn test_content_hasher() {
+        let mut hasher = ContentHasher::from_algo("SHA3-224").unwrap();
+        hasher.update(b"gol");
+        hasher.update(b"em1");
+        assert_eq!(
+            hasher.finish(),
+            "SHA3:dd1a350cfe1d851f36a40d2b0f9f705a0bc076ab31dd81a662ebdf40"
+        );
+
+        let mut hasher = ContentHasher::from_algo("sha1").unwrap();
+        hasher.update(b"alamakota\n");
+        assert_eq!(
+            hasher.finish(),
+            "SHA1:c04e69c52dc35d93389a23189c333d150cadd719"
+        );
+
+        assert!(ContentHasher::from_algo("MD5").is_err());
+    }
+
+    #[test]
+    fn test_invalid_checker() {
+        assert!(checker("SHA1:c04e69c52dc35d93389a23189c333d150cadd71x").is_err());
+        assert!(checker("SHA1:c04e69c52dc35d93389a23189c333d150cadd7").is_err());
+        assert!(checker("SHA1").is_err());
+    }
+
+    #[test]
+    fn test_uppercase_hash() {
+        test_value(
+            b"alamakota\n",
+            "SHA1:C04E69C52DC35D93389A23189C333D150CADD719",
+        );
+    }
+
     #[test]
     fn test_parser() {
         let s =
@@ -2,11 +2,15 @@ pub mod dockerman;
 pub mod envman;
 pub mod wasman;
 
+pub mod auth;
+pub mod config;
 pub mod deployment;
+pub mod events;
 mod hub;
 pub mod peers;
 pub mod plugin;
 pub mod session;
+pub mod task;
 
 #[cfg(feature = "hash")]
 pub mod hash;
@@ -1,4 +1,8 @@
-use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use failure::Fail;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
 #[cfg(feature = "with-actix")]
 use gu_net::NodeId;
@@ -7,6 +11,7 @@ use gu_net::NodeId;
 type NodeId = String;
 
 use super::deployment::DeploymentInfo;
+use super::envman::Activity;
 use super::Tags;
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
@@ -35,3 +40,261 @@ pub struct PeerDetails {
     #[serde(default)]
     pub sessions: Vec<DeploymentInfo>,
 }
+
+/// Hub inventory entry of a provider that has connected to the hub at least once.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerInventoryInfo {
+    pub node_id: NodeId,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_name: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer_addr: Option<String>,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub online: bool,
+    /// Last known hardware snapshot of the provider.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hardware: Option<serde_json::Value>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Labels::is_empty")]
+    pub labels: Labels,
+    #[serde(default)]
+    pub state: PeerState,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drain: Option<DrainProgress>,
+    /// Local activity reported by an online provider.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub activity: Option<Activity>,
+}
+
+/// Hub-side scheduling state of a peer.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum PeerState {
+    Schedulable,
+    /// No new session peers or deployments, running deployments are kept.
+    Cordoned,
+    /// Cordoned and waiting for running deployments to finish.
+    Draining,
+}
+
+impl Default for PeerState {
+    fn default() -> Self {
+        PeerState::Schedulable
+    }
+}
+
+impl PeerState {
+    pub fn is_schedulable(&self) -> bool {
+        *self == PeerState::Schedulable
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DrainProgress {
+    pub started: DateTime<Utc>,
+    /// Deployments still running after the deadline are destroyed.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<DateTime<Utc>>,
+    /// Deployments still running on the peer.
+    #[serde(default)]
+    pub remaining: Vec<String>,
+    #[serde(default)]
+    pub finished: bool,
+    /// Deployments were destroyed after the deadline.
+    #[serde(default)]
+    pub forced: bool,
+}
+
+/// Operator assigned peer labels.
+pub type Labels = BTreeMap<String, String>;
+
+/// Label changes; `None` value removes the label.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerPatch {
+    #[serde(default)]
+    pub labels: BTreeMap<String, Option<String>>,
+    /// Cordons or uncordons the peer; draining is started with a separate request.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<PeerState>,
+}
+
+/// Tags reported for labeled peers, in `key=value` form.
+pub fn label_tags(labels: &Labels) -> Vec<String> {
+    labels
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect()
+}
+
+#[derive(Debug, Fail)]
+pub enum SelectorError {
+    #[fail(display = "invalid label selector requirement: {:?}", _0)]
+    InvalidRequirement(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Requirement {
+    Eq(String, String),
+    NotEq(String, String),
+    Exists(String),
+    NotExists(String),
+}
+
+/// Label selector, comma separated list of requirements that all have to match.
+///
+/// Requirement is one of `key=value`, `key!=value`, `key` (label is set)
+/// or `!key` (label is not set). An empty selector matches everything.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LabelSelector(Vec<Requirement>);
+
+impl LabelSelector {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn matches(&self, labels: &Labels) -> bool {
+        self.0.iter().all(|requirement| match requirement {
+            Requirement::Eq(key, value) => labels.get(key) == Some(value),
+            Requirement::NotEq(key, value) => labels.get(key) != Some(value),
+            Requirement::Exists(key) => labels.contains_key(key),
+            Requirement::NotExists(key) => !labels.contains_key(key),
+        })
+    }
+}
+
+/// Label keys consist of alphanumeric characters, `-`, `_`, `.` and `/`.
+pub fn valid_label_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == '.' || c == '/')
+}
+
+/// Label values cannot contain `,` or `=`, which separate selector requirements, nor
+/// surrounding whitespace trimmed off selectors, so any value can be selected.
+pub fn valid_label_value(value: &str) -> bool {
+    !value.contains(|c| c == ',' || c == '=') && value.trim() == value
+}
+
+impl FromStr for LabelSelector {
+    type Err = SelectorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+            .map(|part| {
+                let requirement = if let Some(pos) = part.find("!=") {
+                    Requirement::NotEq(part[..pos].trim().into(), part[pos + 2..].trim().into())
+                } else if let Some(pos) = part.find('=') {
+                    Requirement::Eq(part[..pos].trim().into(), part[pos + 1..].trim().into())
+                } else if part.starts_with('!') {
+                    Requirement::NotExists(part[1..].trim().into())
+                } else {
+                    Requirement::Exists(part.into())
+                };
+                let key = match requirement {
+                    Requirement::Eq(ref key, _)
+                    | Requirement::NotEq(ref key, _)
+                    | Requirement::Exists(ref key)
+                    | Requirement::NotExists(ref key) => key,
+                };
+                if valid_label_key(key) {
+                    Ok(requirement)
+                } else {
+                    Err(SelectorError::InvalidRequirement(part.into()))
+                }
+            })
+            .collect::<Result<_, _>>()
+            .map(LabelSelector)
+    }
+}
+
+impl fmt::Display for LabelSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parts: Vec<String> = self
+            .0
+            .iter()
+            .map(|requirement| match requirement {
+                Requirement::Eq(key, value) => format!("{}={}", key, value),
+                Requirement::NotEq(key, value) => format!("{}!={}", key, value),
+                Requirement::Exists(key) => key.clone(),
+                Requirement::NotExists(key) => format!("!{}", key),
+            })
+            .collect();
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+impl Serialize for LabelSelector {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for LabelSelector {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> Labels {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_label_selector_matches() {
+        let selector: LabelSelector = "gpu=yes, room!=lab1,team,!cordoned".parse().unwrap();
+        assert_eq!(selector.to_string(), "gpu=yes,room!=lab1,team,!cordoned");
+
+        assert!(selector.matches(&labels(&[
+            ("gpu", "yes"),
+            ("room", "lab2"),
+            ("team", "vfx")
+        ])));
+        assert!(selector.matches(&labels(&[("gpu", "yes"), ("team", "vfx")])));
+        assert!(!selector.matches(&labels(&[("gpu", "no"), ("team", "vfx")])));
+        assert!(!selector.matches(&labels(&[
+            ("gpu", "yes"),
+            ("room", "lab1"),
+            ("team", "vfx")
+        ])));
+        assert!(!selector.matches(&labels(&[("gpu", "yes"), ("team", "a"), ("cordoned", "")])));
+        assert!(LabelSelector::default().matches(&Labels::new()));
+    }
+
+    #[test]
+    fn test_label_selector_invalid() {
+        assert!("=yes".parse::<LabelSelector>().is_err());
+        assert!("gpu yes".parse::<LabelSelector>().is_err());
+        assert!(serde_json::from_str::<LabelSelector>("\"room=lab2\"").is_ok());
+    }
+
+    #[test]
+    fn test_valid_label_value() {
+        assert!(valid_label_value("lab-2"));
+        assert!(valid_label_value(""));
+        assert!(!valid_label_value("a,b"));
+        assert!(!valid_label_value("a=b"));
+        assert!(!valid_label_value(" lab"));
+    }
+}
@@ -1,6 +1,12 @@
+use failure::Fail;
 use semver::Version;
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "with-actix")]
+use actix::prelude::*;
+#[cfg(feature = "with-actix")]
+use gu_net::rpc::PublicMessage;
+
 /** Spec for plugin manifests **/
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 #[serde(rename_all = "kebab-case")]
@@ -27,7 +33,36 @@ pub struct SimpleExecEnvSpec {
     pub exec: String,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+impl PluginManifest {
+    /// Checks if the provider part of the plugin can run on a provider of given version.
+    pub fn check_provider(&self, provider_version: &Version) -> Result<(), ProviderPluginError> {
+        match self.platform {
+            None | Some(Platform::Universal) => (),
+            Some(ref platform) if *platform == Platform::current() => (),
+            Some(ref platform) => {
+                return Err(ProviderPluginError::UnsupportedPlatform(format!(
+                    "{:?}",
+                    platform
+                )));
+            }
+        }
+
+        match self.provider {
+            Some(ProviderActivator {
+                min_version: Some(ref min_version),
+                ..
+            }) if min_version > provider_version => Err(ProviderPluginError::UnsupportedVersion(
+                min_version.to_string(),
+            )),
+            Some(_) => Ok(()),
+            None => Err(ProviderPluginError::InvalidPackage(
+                "no provider part in plugin manifest".into(),
+            )),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case")]
 pub enum Platform {
     Universal, // javascript
@@ -45,14 +80,14 @@ impl Platform {
 
     #[cfg(target_arch = "x86_64")]
     #[cfg(target_os = "macos")]
-    fn current() -> Platform {
-        Platform::X86_64LinuxGnu
+    pub fn current() -> Platform {
+        Platform::X86_64Darwin
     }
 
     #[cfg(target_arch = "x86_64")]
     #[cfg(target_os = "windows")]
-    fn current() -> Platform {
-        Platform::X86_64LinuxGnu
+    pub fn current() -> Platform {
+        Platform::X86_64Windows
     }
 }
 
@@ -62,12 +97,112 @@ pub enum ResolveResult {
     ResolvedPath(String),
 }
 
+#[derive(Serialize, Deserialize, Debug, Fail)]
+#[serde(rename_all = "camelCase")]
+pub enum ProviderPluginError {
+    #[fail(display = "access denied")]
+    AccessDenied,
+    #[fail(display = "invalid plugin package: {}", _0)]
+    InvalidPackage(String),
+    #[fail(display = "unsupported platform: {}", _0)]
+    UnsupportedPlatform(String),
+    #[fail(display = "provider version {} or newer required", _0)]
+    UnsupportedVersion(String),
+    #[fail(display = "plugin error: {}", _0)]
+    Error(String),
+}
+
+/// Installs the provider part of a hub plugin on the provider and starts its
+/// execution environments. The package is a tar archive with `gu-plugin.json`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct InstallProviderPlugin {
+    pub id: String,
+    pub version: Option<Version>,
+    /// package download url
+    pub url: String,
+    /// package hash, e.g. `SHA1:<hex>`
+    pub hash: String,
+}
+
+#[cfg(feature = "with-actix")]
+impl PublicMessage for InstallProviderPlugin {
+    const ID: u32 = 44;
+}
+
+#[cfg(feature = "with-actix")]
+impl Message for InstallProviderPlugin {
+    type Result = Result<(), ProviderPluginError>;
+}
+
+/// Stops execution environments of a plugin installed by the hub and removes it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RetireProviderPlugin {
+    pub id: String,
+}
+
+#[cfg(feature = "with-actix")]
+impl PublicMessage for RetireProviderPlugin {
+    const ID: u32 = 45;
+}
+
+#[cfg(feature = "with-actix")]
+impl Message for RetireProviderPlugin {
+    type Result = Result<(), ProviderPluginError>;
+}
+
 #[cfg(test)]
 mod test {
-    use crate::plugin::Platform;
+    use crate::plugin::{Platform, PluginManifest, ProviderActivator, ProviderPluginError};
+    use semver::Version;
 
     #[test]
     fn test_to_platform() {
         eprintln!("{}", serde_json::to_string(&Platform::current()).unwrap())
     }
+
+    fn manifest(platform: Option<Platform>, min_version: Option<&str>) -> PluginManifest {
+        PluginManifest {
+            id: "test".into(),
+            platform,
+            provider: Some(ProviderActivator {
+                min_version: min_version.map(|v| Version::parse(v).unwrap()),
+                simple_exec_env: Vec::new(),
+            }),
+            ..PluginManifest::default()
+        }
+    }
+
+    #[test]
+    fn test_check_provider() {
+        let version = Version::parse("0.2.3").unwrap();
+
+        assert!(manifest(None, None).check_provider(&version).is_ok());
+        assert!(manifest(Some(Platform::current()), Some("0.2.0"))
+            .check_provider(&version)
+            .is_ok());
+        assert!(manifest(Some(Platform::Universal), Some("0.2.3"))
+            .check_provider(&version)
+            .is_ok());
+        match manifest(None, Some("0.3.0")).check_provider(&version) {
+            Err(ProviderPluginError::UnsupportedVersion(v)) => assert_eq!(v, "0.3.0"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(PluginManifest::default().check_provider(&version).is_err());
+    }
+
+    #[test]
+    fn test_check_platform() {
+        let version = Version::parse("0.2.3").unwrap();
+        let other = match Platform::current() {
+            Platform::X86_64LinuxGnu => Platform::X86_64Windows,
+            _ => Platform::X86_64LinuxGnu,
+        };
+
+        match manifest(Some(other), None).check_provider(&version) {
+            Err(ProviderPluginError::UnsupportedPlatform(_)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
 }
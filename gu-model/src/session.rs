@@ -3,6 +3,14 @@ use chrono::DateTime;
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
 
+#[cfg(feature = "with-actix")]
+use gu_net::NodeId;
+
+#[cfg(not(feature = "with-actix"))]
+type NodeId = String;
+
+use super::envman::{Command as DeploymentCommand, GenericCreateSession};
+use super::peers::LabelSelector;
 use super::Map;
 use super::Tags;
 
@@ -91,12 +99,108 @@ pub struct SessionDetails {
     pub name: Option<String>,
     #[serde(default)]
     pub tags: Tags,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<StorageUsage>,
+}
+
+/// Storage used by a hub session together with the quota applied to it.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageUsage {
+    pub total_bytes: u64,
+    pub blob_count: u64,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_total_bytes: Option<u64>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_blob_count: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Default, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct BlobInfo {
     pub id: String,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires: Option<DateTime<Utc>>,
+}
+
+/// Selects hub session peers; an empty selector matches all session peers.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerSelector {
+    /// Peer has to have all of the tags.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Tags::is_empty")]
+    pub tags: Tags,
+    /// Peer labels have to match the selector.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selector: Option<LabelSelector>,
+}
+
+impl PeerSelector {
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.selector.as_ref().map_or(true, |s| s.is_empty())
+    }
+}
+
+/// Peers to add to a hub session, given explicitly or by a label selector.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum AddPeers {
+    Nodes(Vec<NodeId>),
+    Selector { selector: LabelSelector },
+}
+
+/// Creates the same deployment on all selected session peers.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionDeploymentSpec {
+    pub deployment: GenericCreateSession,
+    #[serde(default)]
+    pub peers: PeerSelector,
+    /// Maximal number of peers processed at once.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<usize>,
+}
+
+/// Runs a command batch on session deployments of all selected peers.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionCommandSpec {
+    pub commands: Vec<DeploymentCommand>,
+    #[serde(default)]
+    pub peers: PeerSelector,
+    /// Limits the batch to given deployments; all session deployments are used when empty.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub deployments: Vec<String>,
+    /// Maximal number of deployments processed at once.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<usize>,
+}
+
+/// Result of a fan-out operation on a single peer.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerDeploymentResult {
+    pub node_id: NodeId,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deployment_id: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub output: Vec<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
 }
 
 #[cfg(test)]
@@ -147,4 +251,26 @@ mod test {
         };
         eprintln!("{}", serde_json::to_string(&command).unwrap());
     }
+
+    #[test]
+    fn test_session_details_usage() {
+        let details = SessionDetails {
+            id: 3,
+            usage: Some(StorageUsage {
+                total_bytes: 1024,
+                blob_count: 2,
+                max_total_bytes: Some(4096),
+                max_blob_count: None,
+            }),
+            ..SessionDetails::default()
+        };
+
+        let json = serde_json::to_value(&details).unwrap();
+        assert_eq!(json["usage"]["totalBytes"], json!(1024));
+        assert_eq!(json["usage"]["maxTotalBytes"], json!(4096));
+        assert!(json["usage"].get("maxBlobCount").is_none());
+
+        let old: SessionDetails = serde_json::from_str(r#"{"id": 1}"#).unwrap();
+        assert!(old.usage.is_none());
+    }
 }
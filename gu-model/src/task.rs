@@ -0,0 +1,142 @@
+//! Hub task queue.
+//!
+//! Tasks are submitted to a hub session and scheduled by the hub onto the
+//! session deployments.
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "with-actix")]
+use gu_net::NodeId;
+
+#[cfg(not(feature = "with-actix"))]
+type NodeId = String;
+
+use super::envman::{Command, ResourceFormat};
+
+/// Session blob transferred to or from a deployment workspace.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskBlob {
+    pub blob_id: u64,
+    pub file_path: String,
+    #[serde(default)]
+    pub format: ResourceFormat,
+}
+
+/// Single unit of work.
+///
+/// Inputs are downloaded before `commands` are run, outputs are uploaded after
+/// all commands succeed.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskSpec {
+    pub commands: Vec<Command>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub inputs: Vec<TaskBlob>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<TaskBlob>,
+}
+
+/// Batch of tasks submitted to a hub session.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskSubmit {
+    pub tasks: Vec<TaskSpec>,
+    /// Maximal number of tasks running at once on a single peer when a task of this
+    /// batch is started there; tasks of other batches keep their own limit.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_per_peer: Option<usize>,
+    /// How many times a task is tried before it is marked as failed.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
+    /// Single attempt timeout in seconds.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    /// Hub address used by providers to reach session blobs,
+    /// defaults to the address used to submit the tasks.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hub_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum TaskState {
+    Pending,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskStatus {
+    pub id: u64,
+    pub state: TaskState,
+    pub attempts: u32,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_id: Option<NodeId>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deployment_id: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub output: Vec<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl TaskStatus {
+    pub fn new(id: u64) -> Self {
+        TaskStatus {
+            id,
+            state: TaskState::Pending,
+            attempts: 0,
+            node_id: None,
+            deployment_id: None,
+            output: Vec::new(),
+            error: None,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        match self.state {
+            TaskState::Done | TaskState::Failed | TaskState::Cancelled => true,
+            TaskState::Pending | TaskState::Running => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_task_submit_deserialization() {
+        let json = r#"
+        {
+            "tasks": [{
+                "commands": [{"exec": {"executable": "render", "args": ["-f", "1"]}}],
+                "inputs": [{"blobId": 0, "filePath": "scene.blend"}],
+                "outputs": [{"blobId": 1, "filePath": "out", "format": "tar"}]
+            }],
+            "maxPerPeer": 2
+        }"#;
+
+        let submit: TaskSubmit = serde_json::from_str(json).unwrap();
+
+        assert_eq!(submit.tasks.len(), 1);
+        assert_eq!(submit.max_per_peer, Some(2));
+        assert!(submit.max_attempts.is_none());
+        assert_eq!(submit.tasks[0].inputs[0].format, ResourceFormat::Raw);
+        assert_eq!(submit.tasks[0].outputs[0].format, ResourceFormat::Tar);
+    }
+}
@@ -20,6 +20,9 @@ extern crate lazy_static;
 
 extern crate byteorder;
 extern crate gu_actix;
+extern crate gu_event_bus;
+#[cfg(feature = "ssl")]
+extern crate openssl;
 extern crate rand;
 
 use futures::{future, stream};
@@ -33,4 +36,6 @@ type Key = [u8; 20];
 
 mod proto;
 pub mod rpc;
+#[cfg(feature = "ssl")]
+pub mod tls;
 pub mod types;
@@ -125,10 +125,35 @@ where
         T::Result: Serialize + Send,
         A::Context: ToEnvelope<A, T>,
     {
-        let type_id = any::TypeId::of::<T>();
+        self.bind_endpoint(destination_id, |_sender, body: T| body)
+    }
+
+    /// Binds public message whose handler needs to know the sending node.
+    pub fn bind_with_sender<T: any::Any + Send>(&mut self, destination_id: u32)
+    where
+        A: Handler<message::WithSender<T>>,
+        T: Message + DeserializeOwned + Send + 'static,
+        T::Result: Serialize + Send,
+        A::Context: ToEnvelope<A, message::WithSender<T>>,
+    {
+        self.bind_endpoint(destination_id, |sender, body: T| message::WithSender {
+            sender,
+            body,
+        })
+    }
+
+    fn bind_endpoint<T, M>(&mut self, destination_id: u32, wrap: fn(message::NodeId, T) -> M)
+    where
+        A: Handler<M>,
+        T: DeserializeOwned + Send + 'static,
+        M: Message + Send + 'static,
+        M::Result: Serialize + Send,
+        A::Context: ToEnvelope<A, M>,
+    {
         let addr = self.address();
         let endpoint = Box::new(AddrWrapper {
             addr,
+            wrap,
             message: PhantomData,
         });
         MessageRouter::from_registry().do_send(router::BindDestination {
@@ -149,36 +174,41 @@ where
         let addr = self.address();
         let endpoint = Box::new(AddrWrapper {
             addr,
+            wrap: |_sender, body: T| body,
             message: PhantomData,
         });
         future::ok(public_destination(1))
     }
 }
 
-struct AddrWrapper<A, T>
+struct AddrWrapper<A, T, M>
 where
-    A: Actor + Handler<T>,
-    T: Message + DeserializeOwned,
-    T::Result: Serialize,
+    A: Actor + Handler<M>,
+    T: DeserializeOwned,
+    M: Message,
+    M::Result: Serialize,
 {
     addr: Addr<A>,
+    wrap: fn(message::NodeId, T) -> M,
     message: PhantomData<T>,
 }
 
-unsafe impl<A, T> Send for AddrWrapper<A, T>
+unsafe impl<A, T, M> Send for AddrWrapper<A, T, M>
 where
-    A: Actor + Handler<T>,
-    T: Message + DeserializeOwned,
-    T::Result: Serialize,
+    A: Actor + Handler<M>,
+    T: DeserializeOwned,
+    M: Message,
+    M::Result: Serialize,
 {
 }
 
-impl<A, T> router::LocalEndpoint for AddrWrapper<A, T>
+impl<A, T, M> router::LocalEndpoint for AddrWrapper<A, T, M>
 where
-    A: Actor + Handler<T>,
-    T: Message + DeserializeOwned + Send + 'static,
-    T::Result: Serialize + Send,
-    A::Context: ToEnvelope<A, T>,
+    A: Actor + Handler<M>,
+    T: DeserializeOwned + Send + 'static,
+    M: Message + Send + 'static,
+    M::Result: Serialize + Send,
+    A::Context: ToEnvelope<A, M>,
 {
     fn handle(
         &mut self,
@@ -200,16 +230,18 @@ where
             Ok(message) => {
                 let m = message.unit();
                 debug!("message parsed!");
-                let f = actix::fut::wrap_future(self.addr.send(message.body))
-                    .then(move |r, act, ctx| match r {
-                        Ok(b) => fut::ok(serde_json::to_string(&b).unwrap()),
-                        Err(e) => fut::err(()),
-                    })
-                    .and_then(move |r, act, ctx: &mut <MessageRouter as Actor>::Context| {
-                        m.do_reply(r, |reply| ctx.notify(reply));
-                        fut::ok(())
-                    })
-                    .map_err(|e, act, ctx| println!("error: {:?}", e));
+                let f = actix::fut::wrap_future(
+                    self.addr.send((self.wrap)(message.sender, message.body)),
+                )
+                .then(move |r, act, ctx| match r {
+                    Ok(b) => fut::ok(serde_json::to_string(&b).unwrap()),
+                    Err(e) => fut::err(()),
+                })
+                .and_then(move |r, act, ctx: &mut <MessageRouter as Actor>::Context| {
+                    m.do_reply(r, |reply| ctx.notify(reply));
+                    fut::ok(())
+                })
+                .map_err(|e, act, ctx| println!("error: {:?}", e));
                 ctx.spawn(f);
                 //ctx.spawn(f.into_actor(self));
             }
@@ -145,6 +145,16 @@ impl<B> RouteMessage<B> {
     }
 }
 
+/// Public message body together with the node it came from.
+pub struct WithSender<T> {
+    pub sender: NodeId,
+    pub body: T,
+}
+
+impl<T: Message> Message for WithSender<T> {
+    type Result = T::Result;
+}
+
 impl RouteMessage<String> {
     pub fn from_json<B: DeserializeOwned>(self) -> serde_json::Result<RouteMessage<B>> {
         let body: B = serde_json::from_str(self.body.as_ref())?;
@@ -55,7 +55,8 @@ pub use self::{
     context::{start_actor, RemotingContext},
     error::Error as RpcError,
     message::{
-        gen_destination_id, public_destination, DestinationId, EmitMessage, MessageId, RouteMessage,
+        gen_destination_id, public_destination, DestinationId, EmitMessage, MessageId,
+        RouteMessage, WithSender,
     },
     registry::RemotingSystemService,
     remoting::{peer, PublicMessage},
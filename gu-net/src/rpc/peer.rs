@@ -1,5 +1,6 @@
 use super::super::NodeId;
 use actix::prelude::*;
+use gu_event_bus::post_event;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 
@@ -54,12 +55,25 @@ pub struct PeerSessionInfo {
 pub enum UpdatePeer {
     Update(PeerInfo),
     Delete(NodeId),
+    /// Replaces tags of a connected peer.
+    SetTags(NodeId, Vec<String>),
 }
 
 impl Message for UpdatePeer {
     type Result = ();
 }
 
+/// Posted on the event bus under `/peers/{node_id}`.
+#[derive(Clone, Debug)]
+pub enum PeerEvent {
+    Connected(PeerInfo),
+    Disconnected(NodeId),
+}
+
+fn event_path(node_id: &NodeId) -> String {
+    format!("/peers/{}", node_id.to_string())
+}
+
 pub struct PeerManager {
     peers: HashMap<NodeId, PeerInfo>,
 }
@@ -86,10 +100,21 @@ impl Handler<UpdatePeer> for PeerManager {
     fn handle(&mut self, msg: UpdatePeer, ctx: &mut Self::Context) {
         match msg {
             UpdatePeer::Update(info) => {
+                post_event(
+                    event_path(&info.node_id),
+                    PeerEvent::Connected(info.clone()),
+                );
                 let _ = self.peers.insert(info.node_id, info);
             }
             UpdatePeer::Delete(node_id) => {
-                let _ = self.peers.remove(&node_id);
+                if self.peers.remove(&node_id).is_some() {
+                    post_event(event_path(&node_id), PeerEvent::Disconnected(node_id));
+                }
+            }
+            UpdatePeer::SetTags(node_id, tags) => {
+                if let Some(info) = self.peers.get_mut(&node_id) {
+                    info.tags = tags;
+                }
             }
         }
     }
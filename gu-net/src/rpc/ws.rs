@@ -89,13 +89,13 @@ impl<S> Worker<S> {
         ctx.binary(v);
     }
 
-    fn add_endpoint(&mut self, ctx: &mut <Self as Actor>::Context) {
+    fn add_endpoint(&mut self, node_name: String, ctx: &mut <Self as Actor>::Context) {
         MessageRouter::from_registry().do_send(AddEndpoint {
             node_id: self.peer_node_id.unwrap(),
             recipient: ctx.address().recipient(),
         });
         PeerManager::from_registry().do_send(peer::UpdatePeer::Update(peer::PeerInfo {
-            node_name: String::new(),
+            node_name,
             peer_addr: self.peer_addr.map(|addr| format!("{}", addr)),
             node_id: self.peer_node_id.unwrap(),
             sessions: Vec::new(),
@@ -143,7 +143,9 @@ impl<S> StreamHandler<ws::Message, ws::ProtocolError> for Worker<S> {
                             let mut peer_node_id: NodeId = hello.node_id.into();
                             self.peer_node_id = Some(peer_node_id);
                             self.reply_init(ctx);
-                            self.add_endpoint(ctx);
+                            let node_name =
+                                hello.node_name.map(|n| n.into_owned()).unwrap_or_default();
+                            self.add_endpoint(node_name, ctx);
                         }
                         Err(e) => {
                             ctx.close(Some(ws::CloseReason {
@@ -241,8 +243,37 @@ impl Client {
     }
 
     fn connect(uri: &str, node_id: NodeId) -> impl Future<Item = Addr<Client>, Error = ()> {
+        Self::connect_with(ws::Client::new(uri), node_id)
+    }
+
+    #[cfg(feature = "ssl")]
+    fn connect_pinned(
+        uri: &str,
+        node_id: NodeId,
+        cert_fingerprint: &str,
+    ) -> impl Future<Item = Addr<Client>, Error = ()> {
+        use actix_web::client::ClientConnector;
+
+        let connector = match ::tls::pinned_connector(cert_fingerprint) {
+            Ok(connector) => connector,
+            Err(e) => {
+                error!("tls connector: {}", e);
+                return future::Either::A(future::err(()));
+            }
+        };
+        let connector = ClientConnector::with_connector(connector).start();
+        future::Either::B(Self::connect_with(
+            ws::Client::with_connector(uri, connector),
+            node_id,
+        ))
+    }
+
+    fn connect_with(
+        mut client: ws::Client,
+        node_id: NodeId,
+    ) -> impl Future<Item = Addr<Client>, Error = ()> {
         info!("start connect");
-        ws::Client::new(uri)
+        client
             .connect()
             .conn_timeout(time::Duration::from_secs(15))
             .map_err(|e| {
@@ -411,16 +442,21 @@ impl Handler<EmitMessage<String>> for Client {
 pub struct ConnectionSupervisor {
     node_id: NodeId,
     peer_address: net::SocketAddr,
+    cert_fingerprint: Option<String>,
     connection: Option<Addr<Client>>,
 }
 
+/// Starts connection to the hub; with a certificate fingerprint the connection
+/// uses TLS and accepts only the pinned hub certificate.
 pub fn start_connection(
     node_id: NodeId,
     peer_address: net::SocketAddr,
+    cert_fingerprint: Option<String>,
 ) -> Addr<ConnectionSupervisor> {
     ConnectionSupervisor {
         node_id,
         peer_address,
+        cert_fingerprint,
         connection: None,
     }
     .start()
@@ -444,8 +480,30 @@ impl ConnectionSupervisor {
             return;
         }
 
+        let connect: Box<dyn Future<Item = Addr<Client>, Error = ()>> = match self.cert_fingerprint
+        {
+            None => Box::new(Client::connect(
+                &format!("http://{}/ws/", &self.peer_address),
+                self.node_id,
+            )),
+            #[cfg(feature = "ssl")]
+            Some(ref cert_fingerprint) => Box::new(Client::connect_pinned(
+                &format!("https://{}/ws/", &self.peer_address),
+                self.node_id,
+                cert_fingerprint,
+            )),
+            #[cfg(not(feature = "ssl"))]
+            Some(_) => {
+                error!(
+                    "hub {} requires TLS, which is not compiled in",
+                    self.peer_address
+                );
+                return;
+            }
+        };
+
         ctx.spawn(
-            Client::connect(&format!("http://{}/ws/", &self.peer_address), self.node_id)
+            connect
                 .into_actor(self)
                 .map(|r, act: &mut ConnectionSupervisor, ctx| {
                     debug!("set connection!");
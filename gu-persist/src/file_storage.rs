@@ -68,7 +68,7 @@ impl Handler<storage::Put> for FileStorage {
             fs::remove_file(&path)?;
         }
 
-        fs::create_dir_all(&self.dir)?;
+        fs::create_dir_all(path.parent().unwrap_or(&self.dir))?;
 
         let mut in_cursor = io::Cursor::new(msg.1);
         let mut out_file = fs::File::create(path)?;
@@ -79,5 +79,55 @@ impl Handler<storage::Put> for FileStorage {
     }
 }
 
+impl Handler<storage::Delete> for FileStorage {
+    type Result = Result<()>;
+
+    fn handle(&mut self, msg: storage::Delete, _ctx: &mut Self::Context) -> Self::Result {
+        use std::fs;
+
+        let path = self.key_path(&msg.0);
+
+        if !path.exists() {
+            return Ok(());
+        }
+        fs::remove_file(&path)?;
+
+        // drop namespace dirs left empty
+        let mut dir = path.parent();
+        while let Some(parent) = dir {
+            if parent == self.dir || fs::remove_dir(parent).is_err() {
+                break;
+            }
+            dir = parent.parent();
+        }
+
+        Ok(())
+    }
+}
+
+impl Handler<storage::List> for FileStorage {
+    type Result = Result<Vec<String>>;
+
+    fn handle(&mut self, msg: storage::List, _ctx: &mut Self::Context) -> Self::Result {
+        use std::fs;
+
+        let dir = self.dir.join(msg.0.as_ref());
+
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut keys: Vec<String> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && path.extension().map(|e| e == "json") == Some(true))
+            .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+            .collect();
+        keys.sort();
+
+        Ok(keys)
+    }
+}
+
 #[cfg(test)]
 mod test {}
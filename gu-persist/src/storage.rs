@@ -13,3 +13,17 @@ pub struct Put(pub Cow<'static, str>, pub Vec<u8>);
 impl Message for Put {
     type Result = Result<()>;
 }
+
+/// Removes the key, missing keys are ignored.
+pub struct Delete(pub Cow<'static, str>);
+
+impl Message for Delete {
+    type Result = Result<()>;
+}
+
+/// Lists keys stored directly under the `prefix/` namespace.
+pub struct List(pub Cow<'static, str>);
+
+impl Message for List {
+    type Result = Result<Vec<String>>;
+}
@@ -0,0 +1,306 @@
+//! File access below a directory that never follows symlinks.
+//!
+//! Sandboxed processes can replace any path in their workspace with a symlink to a
+//! host file. Paths are therefore resolved one component at a time with `openat`
+//! and `O_NOFOLLOW`, so a symlink fails the lookup instead of leading out of the
+//! directory, and the resolved file can not be swapped between a check and its use.
+
+use std::{
+    ffi::CString,
+    fs::File,
+    io,
+    path::{Component, Path},
+};
+
+fn invalid_path(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("path outside of the directory: {}", path.display()),
+    )
+}
+
+/// Names of the path components; only plain names are allowed.
+fn names(path: &Path) -> io::Result<Vec<CString>> {
+    path.components()
+        .filter(|c| *c != Component::CurDir)
+        .map(|c| match c {
+            Component::Normal(name) => name_to_cstring(name).ok_or_else(|| invalid_path(path)),
+            _ => Err(invalid_path(path)),
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+fn name_to_cstring(name: &std::ffi::OsStr) -> Option<CString> {
+    use std::os::unix::ffi::OsStrExt;
+
+    CString::new(name.as_bytes()).ok()
+}
+
+#[cfg(not(unix))]
+fn name_to_cstring(name: &std::ffi::OsStr) -> Option<CString> {
+    name.to_str().and_then(|name| CString::new(name).ok())
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::{
+        ffi::CStr,
+        fs::File,
+        io,
+        os::unix::io::{AsRawFd, FromRawFd},
+    };
+
+    pub const READ: libc::c_int = libc::O_RDONLY;
+    pub const WRITE: libc::c_int = libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC;
+
+    pub fn openat(dir: &File, name: &CStr, flags: libc::c_int) -> io::Result<File> {
+        let fd = unsafe {
+            libc::openat(
+                dir.as_raw_fd(),
+                name.as_ptr(),
+                flags | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+                0o644 as libc::c_uint,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+
+    pub fn open_dir(dir: &File, name: &CStr) -> io::Result<File> {
+        openat(dir, name, libc::O_RDONLY | libc::O_DIRECTORY)
+    }
+
+    pub fn mkdirat(dir: &File, name: &CStr) -> io::Result<()> {
+        if unsafe { libc::mkdirat(dir.as_raw_fd(), name.as_ptr(), 0o755) } == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    /// Opens a regular file; fifos or devices placed in the workspace would block
+    /// or misbehave, so they are opened non blocking and rejected.
+    pub fn open_file(dir: &File, name: &CStr, flags: libc::c_int) -> io::Result<File> {
+        let file = openat(dir, name, flags | libc::O_NONBLOCK)?;
+        if !file.metadata()?.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a regular file", name.to_string_lossy()),
+            ));
+        }
+        let fd = file.as_raw_fd();
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(file)
+    }
+
+    pub fn readlinkat(dir: &File, name: &CStr) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0u8; libc::PATH_MAX as usize];
+        let len = unsafe {
+            libc::readlinkat(
+                dir.as_raw_fd(),
+                name.as_ptr(),
+                buf.as_mut_ptr() as *mut libc::c_char,
+                buf.len(),
+            )
+        };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        buf.truncate(len as usize);
+        Ok(buf)
+    }
+
+    pub fn dir_path(dir: &File) -> String {
+        format!("/proc/self/fd/{}", dir.as_raw_fd())
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use std::{ffi::CStr, fs::File, io};
+
+    pub const READ: i32 = 0;
+    pub const WRITE: i32 = 1;
+
+    fn unsupported() -> io::Error {
+        io::Error::new(io::ErrorKind::Other, "not supported on this platform")
+    }
+
+    pub fn open_dir(_dir: &File, _name: &CStr) -> io::Result<File> {
+        Err(unsupported())
+    }
+
+    pub fn mkdirat(_dir: &File, _name: &CStr) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn open_file(_dir: &File, _name: &CStr, _flags: i32) -> io::Result<File> {
+        Err(unsupported())
+    }
+}
+
+/// Opens the directory at `path` below `dir`, creating missing ones when `create`.
+pub fn open_dir(dir: &File, path: &Path, create: bool) -> io::Result<File> {
+    let mut current = dir.try_clone()?;
+    for name in names(path)? {
+        current = match sys::open_dir(&current, &name) {
+            Err(ref e) if create && e.kind() == io::ErrorKind::NotFound => {
+                match sys::mkdirat(&current, &name) {
+                    Err(ref e) if e.kind() != io::ErrorKind::AlreadyExists => {
+                        return Err(io::Error::new(e.kind(), e.to_string()))
+                    }
+                    _ => sys::open_dir(&current, &name)?,
+                }
+            }
+            result => result?,
+        };
+    }
+    Ok(current)
+}
+
+/// Opens the regular file at `path` below `dir`. With `write` the file is created
+/// or truncated, along with missing parent directories.
+pub fn open_file(dir: &File, path: &Path, write: bool) -> io::Result<File> {
+    let mut names = names(path)?;
+    let name = names.pop().ok_or_else(|| invalid_path(path))?;
+    let mut parent = dir.try_clone()?;
+    for dir_name in names {
+        parent = match sys::open_dir(&parent, &dir_name) {
+            Err(ref e) if write && e.kind() == io::ErrorKind::NotFound => {
+                let _ = sys::mkdirat(&parent, &dir_name);
+                sys::open_dir(&parent, &dir_name)?
+            }
+            result => result?,
+        };
+    }
+    sys::open_file(&parent, &name, if write { sys::WRITE } else { sys::READ })
+}
+
+/// Writes a tar archive of the directory. Symlinks are stored as links, other
+/// special files are skipped.
+#[cfg(unix)]
+pub fn append_dir<W: io::Write>(builder: &mut tar::Builder<W>, dir: &File) -> io::Result<()> {
+    append_dir_entries(builder, dir, Path::new("."))
+}
+
+#[cfg(unix)]
+fn append_dir_entries<W: io::Write>(
+    builder: &mut tar::Builder<W>,
+    dir: &File,
+    prefix: &Path,
+) -> io::Result<()> {
+    use std::{ffi::OsStr, fs, os::unix::ffi::OsStrExt};
+
+    // the listing reads the open directory, so it can not be swapped meanwhile
+    let mut entries: Vec<_> = fs::read_dir(sys::dir_path(dir))?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<io::Result<_>>()?;
+    entries.sort();
+
+    for file_name in entries {
+        let name = name_to_cstring(&file_name).ok_or_else(|| invalid_path(prefix))?;
+        let path = prefix.join(&file_name);
+        match sys::open_dir(dir, &name) {
+            Ok(subdir) => {
+                builder.append_dir(&path, sys::dir_path(&subdir))?;
+                append_dir_entries(builder, &subdir, &path)?;
+                continue;
+            }
+            Err(ref e) if e.raw_os_error() == Some(libc::ENOTDIR) => (),
+            Err(ref e) if e.raw_os_error() == Some(libc::ELOOP) => (),
+            Err(e) => return Err(e),
+        }
+        match sys::open_file(dir, &name, sys::READ) {
+            Ok(mut file) => {
+                let mut header = tar::Header::new_gnu();
+                header.set_metadata(&file.metadata()?);
+                builder.append_data(&mut header, &path, &mut file)?;
+            }
+            Err(ref e) if e.raw_os_error() == Some(libc::ELOOP) => {
+                let target = sys::readlinkat(dir, &name)?;
+                let mut header = tar::Header::new_gnu();
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_size(0);
+                header.set_mode(0o777);
+                header.set_link_name(OsStr::from_bytes(&target))?;
+                builder.append_data(&mut header, &path, io::empty())?;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidInput => {
+                log::debug!("skipping special file {}", path.display())
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn append_dir<W: io::Write>(_builder: &mut tar::Builder<W>, _dir: &File) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "not supported on this platform",
+    ))
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use std::{fs, io::prelude::*, os::unix::fs::symlink};
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("gu-beneath-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_rejects_outside_paths() {
+        let dir = temp_dir("paths");
+        let root = File::open(&dir).unwrap();
+        assert!(open_file(&root, Path::new("../x"), true).is_err());
+        assert!(open_file(&root, Path::new("/etc/passwd"), false).is_err());
+        assert!(open_file(&root, Path::new(""), true).is_err());
+
+        open_file(&root, Path::new("./a/b/c.txt"), true)
+            .unwrap()
+            .write_all(b"ok")
+            .unwrap();
+        assert_eq!(fs::read(dir.join("a/b/c.txt")).unwrap(), b"ok");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_does_not_follow_symlinks() {
+        let dir = temp_dir("links");
+        let outside = temp_dir("outside");
+        fs::write(outside.join("secret"), b"s3cr3t-content").unwrap();
+        symlink(&outside, dir.join("dir")).unwrap();
+        symlink(outside.join("secret"), dir.join("file")).unwrap();
+        let root = File::open(&dir).unwrap();
+
+        assert!(open_file(&root, Path::new("dir/secret"), false).is_err());
+        assert!(open_file(&root, Path::new("dir/new"), true).is_err());
+        assert!(open_file(&root, Path::new("file"), false).is_err());
+        assert!(open_file(&root, Path::new("file"), true).is_err());
+        assert!(open_dir(&root, Path::new("dir"), true).is_err());
+        assert!(!outside.join("new").exists());
+        assert_eq!(fs::read(outside.join("secret")).unwrap(), b"s3cr3t-content");
+
+        let mut builder = tar::Builder::new(Vec::new());
+        append_dir(&mut builder, &root).unwrap();
+        let archive = builder.into_inner().unwrap();
+        assert!(!archive.windows(7).any(|w| w == b"s3cr3t-" as &[u8]));
+
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&outside).unwrap();
+    }
+}
@@ -22,7 +22,7 @@ use gu_net::{
     NodeId,
 };
 use gu_persist::config::{ConfigManager, ConfigSection, GetConfig, SetConfig};
-use log::error;
+use log::{error, warn};
 use prettytable::{cell, row};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json;
@@ -496,17 +496,20 @@ fn edit_config_list(
 pub struct ConnectManager {
     node_id: NodeId,
     connections: HashMap<SocketAddr, Addr<ConnectionSupervisor>>,
+    /// Pinned hub certificate fingerprints; hubs with a pin are connected over wss.
+    pins: HashMap<SocketAddr, String>,
     subscription: Option<Subscription>,
 }
 
 impl ConnectManager {
-    pub fn init<I>(id: NodeId, hubs: I) -> Self
+    pub fn init<I>(id: NodeId, hubs: I, pins: HashMap<SocketAddr, String>) -> Self
     where
         I: IntoIterator<Item = SocketAddr>,
     {
         let mut manager = ConnectManager {
             node_id: id,
             connections: HashMap::new(),
+            pins,
             subscription: None,
         };
 
@@ -536,7 +539,14 @@ impl ConnectManager {
             return;
         }
 
-        let supervisor = rpc::ws::start_connection(self.node_id, addr);
+        let pin = self.pins.get(&addr).cloned();
+        #[cfg(feature = "ssl")]
+        {
+            if let Some(ref pin) = pin {
+                use_pin_for_transfers(addr, pin);
+            }
+        }
+        let supervisor = rpc::ws::start_connection(self.node_id, addr, pin);
         self.connections.insert(addr, supervisor);
     }
 
@@ -558,6 +568,20 @@ impl Actor for ConnectManager {
     type Context = Context<Self>;
 }
 
+/// Downloads from and uploads to the hub check its pinned certificate too.
+#[cfg(feature = "ssl")]
+fn use_pin_for_transfers(addr: SocketAddr, pin: &str) {
+    use actix_web::client::ClientConnector;
+
+    match gu_net::tls::pinned_connector(pin) {
+        Ok(connector) => gu_downloader::connectors::set_connector(
+            addr.to_string(),
+            ClientConnector::with_connector(connector).start(),
+        ),
+        Err(e) => error!("tls connector for {}: {}", addr, e),
+    }
+}
+
 impl Handler<NewInstance> for ConnectManager {
     type Result = ();
 
@@ -567,6 +591,16 @@ impl Handler<NewInstance> for ConnectManager {
 
             let ip = IpAddr::V4(*ip);
             let sock = SocketAddr::new(ip, *port);
+            // the advertised certificate is not trusted, the hub must be saved with its pin
+            if let Some(Ok(fingerprint)) = msg.data.extract::<_, String>("cert") {
+                if !self.pins.contains_key(&sock) {
+                    warn!(
+                        "Skipping hub {} with certificate {}: no pinned certificate",
+                        sock, fingerprint
+                    );
+                    return;
+                }
+            }
             self.connect_to(sock);
         } else {
             error!("Invalid mDNS instance")
@@ -599,8 +633,7 @@ impl Handler<Connect> for ConnectManager {
             return None;
         }
 
-        let supervisor = rpc::ws::start_connection(self.node_id, msg.0);
-        self.connections.insert(msg.0, supervisor);
+        self.connect_to(msg.0);
         Some(())
     }
 }
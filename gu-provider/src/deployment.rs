@@ -18,6 +18,12 @@ pub trait Destroy {
     fn destroy(&mut self) -> Box<dyn Future<Item = (), Error = Error>> {
         Box::new(future::ok(()))
     }
+
+    /// Destroys the deployment when its manager is dropped. Actors may be gone
+    /// by then, so this must not depend on them.
+    fn destroy_now(&mut self) {
+        let _ = self.destroy().wait();
+    }
 }
 
 pub trait GetStatus {
@@ -114,6 +120,6 @@ impl<T: IntoDeployInfo + Destroy + GetStatus> DeployManager<T> {
 
 impl<T: IntoDeployInfo + Destroy> Drop for DeployManager<T> {
     fn drop(&mut self) {
-        let _ = future::join_all(self.deploys.values_mut().map(Destroy::destroy)).wait();
+        self.deploys.values_mut().for_each(Destroy::destroy_now);
     }
 }
@@ -24,6 +24,7 @@ use gu_net::rpc::peer::PeerSessionInfo;
 use gu_net::rpc::peer::PeerSessionStatus;
 use gu_persist::config::ConfigModule;
 
+use crate::idle;
 use crate::provision;
 use crate::workspace::{Workspace, WorkspacesManager};
 
@@ -51,6 +52,8 @@ struct DockerSession {
     workspace: Workspace,
     container: async_docker::communicate::Container,
     status: PeerSessionStatus,
+    /// container is frozen while the machine is busy
+    paused: bool,
 }
 
 impl DockerSession {
@@ -89,28 +92,85 @@ impl DockerSession {
     fn do_exec(
         &mut self,
         executable: String,
-        mut args: Vec<String>,
-        working_dir: Option<String>,
+        args: Vec<String>,
+        options: ProcessOptions,
     ) -> impl Future<Item = String, Error = String> {
-        args.insert(0, executable);
-        let cfg = {
+        let ProcessOptions {
+            working_dir,
+            env,
+            clear_env,
+            stdin,
+        } = options;
+        let env: Vec<String> = env
+            .into_iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+
+        let mut cmd: Vec<String> = Vec::new();
+        // stdin is redirected by the shell, content is stored in a temporary file first
+        let stdin_content = match stdin {
+            Some(Stdin::Content(content)) => {
+                let path = format!("/tmp/gu-stdin-{}", uuid::Uuid::new_v4());
+                let script = format!(
+                    "{{ rm -f {0}; exec \"$0\" \"$@\"; }} < {0}",
+                    shell_quote(&path)
+                );
+                cmd.extend(vec!["/bin/sh".into(), "-c".into(), script]);
+                Some((content, path))
+            }
+            Some(Stdin::File(path)) => {
+                let script = format!("exec \"$0\" \"$@\" < {}", shell_quote(&path));
+                cmd.extend(vec!["/bin/sh".into(), "-c".into(), script]);
+                None
+            }
+            None => None,
+        };
+        cmd.push(executable);
+        cmd.extend(args);
+
+        // exec inherits the container environment, so it is cleared by unsetting its variables
+        let env = if clear_env {
+            future::Either::A(self.container.inspect().map_err(|e| format!("{}", e)).map(
+                move |details| {
+                    let container_env = details
+                        .config()
+                        .and_then(|config| config.env())
+                        .cloned()
+                        .unwrap_or_default();
+                    cleared_env(&container_env, env)
+                },
+            ))
+        } else {
+            future::Either::B(future::ok(env))
+        };
+        let cfg = env.map(move |env| {
             use async_docker::models::*;
 
             let mut config = ExecConfig::new()
                 .with_attach_stdout(true)
                 .with_attach_stderr(true)
-                .with_cmd(args);
+                .with_cmd(cmd);
             if let Some(working_dir) = working_dir {
                 config.set_working_dir(working_dir)
             }
+            if !env.is_empty() {
+                config.set_env(env)
+            }
             config
-        };
+        });
 
+        let upload = match stdin_content {
+            Some((content, path)) => {
+                future::Either::A(self.write_file(content.into(), path).map(|_| ()))
+            }
+            None => future::Either::B(future::ok(())),
+        };
+        let container = self.container.clone();
         let container_copy = self.container.clone();
 
-        self.container
-            .exec(&cfg)
-            .map_err(|e| format!("{}", e))
+        upload
+            .join(cfg)
+            .and_then(move |((), cfg)| container.exec(&cfg).map_err(|e| format!("{}", e)))
             .and_then(|(stream, id)| {
                 stream
                     .fold(String::new(), |mut s, (_t, it)| {
@@ -262,7 +322,8 @@ impl DockerSession {
         file_path: String,
         format: ResourceFormat,
     ) -> impl Future<Item = String, Error = String> {
-        use actix_web::client;
+        use actix_web::http::Method;
+        use gu_downloader::connectors;
 
         let data = self
             .container
@@ -276,7 +337,7 @@ impl DockerSession {
                     Box::new(
                         provision::untar_single_file_stream(data)
                             .and_then(move |(file_size, stream)| {
-                                client::put(&url)
+                                connectors::request(Method::PUT, &url)
                                     .content_length(file_size)
                                     .streaming(
                                         stream.map_err(|e| {
@@ -290,7 +351,7 @@ impl DockerSession {
                     )
                 }
                 ResourceFormat::Tar => Box::new(
-                    client::put(&url)
+                    connectors::request(Method::PUT, &url)
                         .streaming(data.map_err(|e| actix_web::error::ErrorInternalServerError(e)))
                         .into_future()
                         .map_err(|e| e.to_string())
@@ -415,7 +476,9 @@ impl Actor for DockerMan {
         match new_docker(None) {
             Ok(docker_api) => {
                 self.docker_api = Some(docker_api);
-                envman::register("docker", ctx.address())
+                envman::register("docker", ctx.address());
+                idle::IdleMonitor::from_registry()
+                    .do_send(idle::AddWorkload::new("docker", ctx.address().recipient()))
             }
             Err(e) => {
                 error!("docker start failed: {}", e);
@@ -476,6 +539,7 @@ impl Handler<CreateSession<CreateOptions>> for DockerMan {
                                 workspace,
                                 container: api.container(Cow::from(id.clone())),
                                 status: PeerSessionStatus::CREATED,
+                                paused: false,
                             };
                             let maybe_start = if msg.options.autostart {
                                 info!("Autostarting the container");
@@ -486,6 +550,7 @@ impl Handler<CreateSession<CreateOptions>> for DockerMan {
                                 fut::Either::B(fut::ok(()))
                             };
                             act.deploys.insert_deploy(id.clone(), deploy);
+                            act.report_containers();
                             fut::Either::A(maybe_start.and_then(|_, _, _| fut::ok(id)))
                         } else {
                             fut::Either::B(fut::err(Error::UnknownEnv(msg.env_type.clone())))
@@ -498,6 +563,25 @@ impl Handler<CreateSession<CreateOptions>> for DockerMan {
     }
 }
 
+/// Quotes the argument for `sh -c` scripts
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Exec environment without the variables of the container: a variable given without
+/// a value is unset by Docker.
+fn cleared_env(container_env: &[String], env: Vec<String>) -> Vec<String> {
+    let name = |var: &str| var.splitn(2, '=').next().unwrap_or_default().to_string();
+    let set: HashSet<String> = env.iter().map(|var| name(var)).collect();
+
+    container_env
+        .iter()
+        .map(|var| name(var))
+        .filter(|var| !set.contains(var))
+        .chain(env)
+        .collect()
+}
+
 impl DockerMan {
     fn run_for_deployment<F, R>(
         &mut self,
@@ -532,15 +616,12 @@ fn run_command(
         Command::Exec {
             executable,
             args,
-            working_dir,
+            options,
         } => docker_man.run_for_deployment(session_id, |deployment| {
-            deployment.do_exec(executable, args, working_dir)
+            deployment.do_exec(executable, args, options)
         }),
         // TODO: FIXME @destruktiv: same as Exec but async
-        Command::Start {
-            executable: _,
-            args: _,
-        } => docker_man.run_for_deployment(session_id, DockerSession::do_start),
+        Command::Start { .. } => docker_man.run_for_deployment(session_id, DockerSession::do_start),
         // TODO: FIXME @destruktiv: same as Exec but async
         Command::Stop { child_id: _ } => Box::new(fut::ok("Stop mock".to_string())),
         Command::Wait => docker_man.run_for_deployment(session_id, DockerSession::do_wait),
@@ -655,12 +736,58 @@ impl Handler<DestroySession> for DockerMan {
             _ => return ActorResponse::reply(Err(Error::UnknownEnv("docker".into()))),
         };
 
-        ActorResponse::r#async(
-            self.deploys
-                .destroy_deploy(&msg.session_id)
-                .and_then(|_| Ok("done".into()))
-                .into_actor(self),
-        )
+        let destroy = self.deploys.destroy_deploy(&msg.session_id);
+        self.report_containers();
+        ActorResponse::r#async(destroy.and_then(|_| Ok("done".into())).into_actor(self))
+    }
+}
+
+impl DockerMan {
+    /// Lets the idle monitor tell load of our containers from the owner's one.
+    fn report_containers(&mut self) {
+        let ids = self
+            .deploys
+            .values_mut()
+            .map(|session| session.container.id().to_owned())
+            .collect();
+        idle::IdleMonitor::from_registry().do_send(idle::SetContainers(ids));
+    }
+}
+
+impl Handler<idle::SetPaused> for DockerMan {
+    type Result = ();
+
+    fn handle(&mut self, msg: idle::SetPaused, ctx: &mut Self::Context) -> Self::Result {
+        // docker has no priorities, containers are frozen in both modes
+        let ids: Vec<String> = self
+            .deploys
+            .values_mut()
+            .filter(|session| session.paused != msg.paused)
+            .map(|session| session.container.id().to_owned())
+            .collect();
+
+        for id in ids {
+            let container = match self.deploys.deploy_mut(&id) {
+                Ok(session) => session.container.clone(),
+                Err(_) => continue,
+            };
+            let change = if msg.paused {
+                future::Either::A(container.pause())
+            } else {
+                future::Either::B(container.unpause())
+            };
+            ctx.spawn(
+                change
+                    .into_actor(self)
+                    .map(move |_, act, _ctx| {
+                        if let Ok(session) = act.deploys.deploy_mut(&id) {
+                            session.paused = msg.paused;
+                        }
+                    })
+                    // only running containers can be paused
+                    .map_err(|e, _act, _ctx| debug!("cannot pause/resume container: {}", e)),
+            );
+        }
     }
 }
 
@@ -721,3 +848,23 @@ impl gu_base::Module for Init {
 pub fn module() -> impl gu_base::Module {
     Init { should_run: false }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cleared_env() {
+        let container_env = vec!["PATH=/bin".to_string(), "HOME=/root".to_string()];
+        let env = vec!["HOME=/work".to_string(), "MODE=fast".to_string()];
+
+        assert_eq!(
+            cleared_env(&container_env, env),
+            vec!["PATH", "HOME=/work", "MODE=fast"]
+        );
+        assert_eq!(
+            cleared_env(&container_env, Vec::new()),
+            vec!["PATH", "HOME"]
+        );
+    }
+}
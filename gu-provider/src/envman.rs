@@ -6,11 +6,27 @@ use futures::{future, prelude::*};
 use gu_actix::prelude::*;
 use gu_model::envman::*;
 use gu_net::rpc::peer::PeerSessionInfo;
-use gu_net::rpc::{PublicMessage, RemotingContext, RemotingSystemService};
+use gu_net::rpc::{PublicMessage, RemotingContext, RemotingSystemService, WithSender};
+use gu_net::NodeId;
+use gu_persist::config::{ConfigManager, ConfigModule, GetConfig};
+use log::{error, info};
 use serde::{de::DeserializeOwned, Serialize};
 use serde_json::Value as JsonValue;
 use std::borrow::Cow;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::permission::{AccessLevel, PermissionConfig};
+use crate::status::StatusManager;
+
+/// Environments that hubs with sandbox access cannot use
+const FULL_ACCESS_ENVS: &[&str] = &["hd"];
+
+/// Session owners are kept across restarts, as are the sessions themselves.
+const OWNERS_FILE: &str = "session-owners.json";
 
 /// Actor
 #[derive(Default)]
@@ -19,16 +35,24 @@ struct EnvMan {
     session_update_map: BTreeMap<String, Recipient<SessionUpdate>>,
     get_sessions_map: BTreeMap<String, Recipient<GetSessions>>,
     destroy_session_map: BTreeMap<String, Recipient<DestroySession>>,
+    /// node that created each session, by prefixed session id
+    owners: HashMap<String, NodeId>,
+    draining: bool,
+    /// changed with every drain flag update, so that a stale deadline is ignored
+    drain_generation: u64,
 }
 
 impl Actor for EnvMan {
     type Context = RemotingContext<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        ctx.bind::<CreateSession<JsonValue>>(CreateSession::<JsonValue>::ID);
-        ctx.bind::<SessionUpdate>(SessionUpdate::ID);
-        ctx.bind::<GetSessions>(GetSessions::ID);
-        ctx.bind::<DestroySession>(DestroySession::ID);
+        self.load_owners();
+
+        ctx.bind_with_sender::<CreateSession<JsonValue>>(CreateSession::<JsonValue>::ID);
+        ctx.bind_with_sender::<SessionUpdate>(SessionUpdate::ID);
+        ctx.bind_with_sender::<GetSessions>(GetSessions::ID);
+        ctx.bind_with_sender::<DestroySession>(DestroySession::ID);
+        ctx.bind_with_sender::<SetDraining>(SetDraining::ID);
     }
 }
 
@@ -113,6 +137,25 @@ where
     }
 }
 
+struct Unregister {
+    env_type: String,
+}
+
+impl Message for Unregister {
+    type Result = ();
+}
+
+impl Handler<Unregister> for EnvMan {
+    type Result = ();
+
+    fn handle(&mut self, msg: Unregister, _ctx: &mut Self::Context) -> Self::Result {
+        self.create_map.remove(&msg.env_type);
+        self.session_update_map.remove(&msg.env_type);
+        self.get_sessions_map.remove(&msg.env_type);
+        self.destroy_session_map.remove(&msg.env_type);
+    }
+}
+
 fn extract_prefix(s: &str) -> Result<(&str, &str), Error> {
     if let Some(break_pos) = s.find("::") {
         return Ok((&s[..break_pos], &s[break_pos + 2..]));
@@ -120,53 +163,147 @@ fn extract_prefix(s: &str) -> Result<(&str, &str), Error> {
     return Err(Error::NoSuchSession(s.to_owned()));
 }
 
-impl Handler<CreateSession<JsonValue>> for EnvMan {
-    type Result = ActorResponse<EnvMan, String, Error>;
+fn access_level(node_id: NodeId) -> impl Future<Item = AccessLevel, Error = Error> {
+    ConfigManager::from_registry()
+        .send(GetConfig::new())
+        .flatten_fut()
+        .map_err(|e| Error::Error(e.to_string()))
+        .and_then(move |c: Arc<PermissionConfig>| Ok(c.access_level(&node_id)))
+}
 
-    fn handle(&mut self, msg: CreateSession<JsonValue>, _ctx: &mut Self::Context) -> Self::Result {
-        let env_type = msg.env_type.clone();
-        if let Some(address) = self.create_map.get(&env_type) {
-            return ActorResponse::r#async(
-                address
-                    .send(msg)
-                    .and_then(move |session_id| Ok(format!("{}::{}", env_type, session_id)))
-                    .into_actor(self),
-            );
-        }
-        ActorResponse::reply(Err(Error::UnknownEnv(env_type)))
+fn check_env_access(level: AccessLevel, env_type: &str) -> Result<(), Error> {
+    match level {
+        AccessLevel::Sandbox if FULL_ACCESS_ENVS.contains(&env_type) => Err(Error::AccessDenied(
+            format!("{} sessions require full access", env_type),
+        )),
+        _ => Ok(()),
     }
 }
 
-impl Handler<SessionUpdate> for EnvMan {
-    type Result = ActorResponse<EnvMan, Vec<String>, Vec<String>>;
+fn owners_path() -> PathBuf {
+    ConfigModule::new().work_dir().join(OWNERS_FILE)
+}
 
-    fn handle(&mut self, msg: SessionUpdate, _ctx: &mut Self::Context) -> Self::Result {
-        let (prefix, session_id) = match extract_prefix(&msg.session_id) {
-            Ok(v) => v,
-            Err(_e) => {
-                return ActorResponse::reply(Err(vec!["Invalid environment prefix".to_string()]));
+impl EnvMan {
+    fn load_owners(&mut self) {
+        let path = owners_path();
+        if let Ok(bytes) = fs::read(&path) {
+            match serde_json::from_slice(&bytes) {
+                Ok(owners) => self.owners = owners,
+                Err(e) => error!("Invalid session owners file {:?}: {}", path, e),
             }
-        };
+        }
+    }
 
-        match self.session_update_map.get(prefix) {
-            Some(r) => ActorResponse::r#async(
-                r.send(SessionUpdate {
-                    session_id: session_id.into(),
-                    commands: msg.commands,
-                })
-                .map_err(|_e| Vec::new())
-                .flatten_fut()
-                .into_actor(self),
+    fn save_owners(&self) {
+        let path = owners_path();
+        match serde_json::to_vec(&self.owners) {
+            Ok(bytes) => {
+                if let Err(e) = fs::write(&path, bytes) {
+                    error!("Cannot save session owners {:?}: {}", path, e)
+                }
+            }
+            Err(e) => error!("Cannot serialize session owners: {}", e),
+        }
+    }
+
+    /// Nodes with sandbox access can only use sessions they created.
+    fn check_session_access(
+        &self,
+        sender: &NodeId,
+        level: AccessLevel,
+        session_id: &str,
+    ) -> Result<(), Error> {
+        let (env_type, _) = extract_prefix(session_id)?;
+        check_env_access(level, env_type)?;
+        match level {
+            AccessLevel::Sandbox if self.owners.get(session_id) != Some(sender) => Err(
+                Error::AccessDenied(format!("session {} belongs to another node", session_id)),
             ),
-            None => ActorResponse::reply(Err(Vec::new())),
+            _ => Ok(()),
         }
     }
 }
 
-impl Handler<GetSessions> for EnvMan {
+impl Handler<WithSender<CreateSession<JsonValue>>> for EnvMan {
+    type Result = ActorResponse<EnvMan, String, Error>;
+
+    fn handle(
+        &mut self,
+        msg: WithSender<CreateSession<JsonValue>>,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        if self.draining {
+            return ActorResponse::reply(Err(Error::Error(
+                "provider is draining, no new sessions are accepted".into(),
+            )));
+        }
+        let sender = msg.sender;
+        let msg = msg.body;
+        let env_type = msg.env_type.clone();
+
+        ActorResponse::r#async(
+            access_level(sender)
+                .and_then({
+                    let env_type = env_type.clone();
+                    move |level| check_env_access(level, &env_type)
+                })
+                .into_actor(self)
+                .and_then(move |(), act, _ctx| match act.create_map.get(&env_type) {
+                    Some(address) => fut::Either::A(
+                        address
+                            .send(msg)
+                            .and_then(move |session_id| Ok(format!("{}::{}", env_type, session_id)))
+                            .into_actor(act)
+                            .map(move |session_id, act, _ctx| {
+                                act.owners.insert(session_id.clone(), sender);
+                                act.save_owners();
+                                session_id
+                            }),
+                    ),
+                    None => fut::Either::B(fut::err(Error::UnknownEnv(env_type))),
+                }),
+        )
+    }
+}
+
+impl Handler<WithSender<SessionUpdate>> for EnvMan {
+    type Result = ActorResponse<EnvMan, Vec<String>, Vec<String>>;
+
+    fn handle(&mut self, msg: WithSender<SessionUpdate>, _ctx: &mut Self::Context) -> Self::Result {
+        let sender = msg.sender;
+        let msg = msg.body;
+
+        ActorResponse::r#async(
+            access_level(sender)
+                .map_err(|e| vec![e.to_string()])
+                .into_actor(self)
+                .and_then(move |level, act, _ctx| {
+                    if let Err(e) = act.check_session_access(&sender, level, &msg.session_id) {
+                        return fut::Either::B(fut::err(vec![e.to_string()]));
+                    }
+                    let (prefix, session_id) = extract_prefix(&msg.session_id).unwrap();
+                    match act.session_update_map.get(prefix) {
+                        Some(r) => fut::Either::A(
+                            r.send(SessionUpdate {
+                                session_id: session_id.into(),
+                                commands: msg.commands,
+                            })
+                            .map_err(|_e| Vec::new())
+                            .flatten_fut()
+                            .into_actor(act),
+                        ),
+                        None => fut::Either::B(fut::err(Vec::new())),
+                    }
+                }),
+        )
+    }
+}
+
+impl Handler<WithSender<GetSessions>> for EnvMan {
     type Result = ActorResponse<EnvMan, Vec<PeerSessionInfo>, ()>;
 
-    fn handle(&mut self, _msg: GetSessions, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: WithSender<GetSessions>, _ctx: &mut Self::Context) -> Self::Result {
         fn add_sessions_prefix(
             prefix: String,
             sessions: Vec<PeerSessionInfo>,
@@ -180,6 +317,7 @@ impl Handler<GetSessions> for EnvMan {
                 .collect()
         }
 
+        let sender = msg.sender;
         let j = future::join_all(
             self.get_sessions_map
                 .iter()
@@ -195,37 +333,132 @@ impl Handler<GetSessions> for EnvMan {
         );
 
         ActorResponse::r#async(
-            j.and_then(|v: Vec<Vec<PeerSessionInfo>>| Ok(v.into_iter().flatten().collect()))
-                .into_actor(self),
+            access_level(sender)
+                .map_err(|_| ())
+                .join(j)
+                .into_actor(self)
+                .map(move |(level, v), act, _ctx| {
+                    v.into_iter()
+                        .flatten()
+                        .filter(|session| {
+                            act.check_session_access(&sender, level, &session.id)
+                                .is_ok()
+                        })
+                        .collect::<Vec<_>>()
+                }),
         )
     }
 }
 
-impl Handler<DestroySession> for EnvMan {
+impl Handler<WithSender<DestroySession>> for EnvMan {
     type Result = ActorResponse<EnvMan, String, Error>;
 
     fn handle(
         &mut self,
-        msg: DestroySession,
+        msg: WithSender<DestroySession>,
         _ctx: &mut Self::Context,
-    ) -> <Self as Handler<DestroySession>>::Result {
-        let (prefix, session_id) = match extract_prefix(&msg.session_id) {
-            Ok(v) => v,
-            Err(e) => return ActorResponse::reply(Err(e)),
-        };
-
-        match self.destroy_session_map.get(prefix) {
-            Some(address) => ActorResponse::r#async(
-                address
-                    .send(DestroySession {
-                        session_id: session_id.into(),
-                        ..msg
-                    })
-                    .flatten_fut()
-                    .into_actor(self),
-            ),
-            None => ActorResponse::reply(Err(Error::UnknownEnv(prefix.into()))),
+    ) -> Self::Result {
+        let sender = msg.sender;
+        let msg = msg.body;
+
+        ActorResponse::r#async(access_level(sender).into_actor(self).and_then(
+            move |level, act, _ctx| {
+                if let Err(e) = act.check_session_access(&sender, level, &msg.session_id) {
+                    return fut::Either::B(fut::err(e));
+                }
+                let full_id = msg.session_id.clone();
+                let (prefix, session_id) = extract_prefix(&full_id).unwrap();
+                match act.destroy_session_map.get(prefix) {
+                    Some(address) => fut::Either::A(
+                        address
+                            .send(DestroySession {
+                                session_id: session_id.into(),
+                                ..msg
+                            })
+                            .flatten_fut()
+                            .into_actor(act)
+                            .map(move |result, act, _ctx| {
+                                if act.owners.remove(&full_id).is_some() {
+                                    act.save_owners();
+                                }
+                                result
+                            }),
+                    ),
+                    None => fut::Either::B(fut::err(Error::UnknownEnv(prefix.into()))),
+                }
+            },
+        ))
+    }
+}
+
+impl EnvMan {
+    fn set_draining(&mut self, msg: SetDraining, ctx: &mut RemotingContext<Self>) {
+        self.draining = msg.draining;
+        self.drain_generation += 1;
+        if let (true, Some(timeout)) = (msg.draining, msg.timeout) {
+            let generation = self.drain_generation;
+            ctx.run_later(Duration::from_secs(timeout), move |act, ctx| {
+                if act.draining && act.drain_generation == generation {
+                    act.destroy_all_sessions(ctx);
+                }
+            });
         }
+        StatusManager::from_registry().do_send(msg);
+    }
+
+    /// Destroys sessions of all environments, when the drain deadline has passed.
+    fn destroy_all_sessions(&mut self, ctx: &mut RemotingContext<Self>) {
+        for (prefix, get_sessions) in &self.get_sessions_map {
+            let destroy = match self.destroy_session_map.get(prefix) {
+                Some(destroy) => destroy.clone(),
+                None => continue,
+            };
+            let prefix = prefix.clone();
+            let destroyed = get_sessions
+                .send(GetSessions::default())
+                .map_err(|_| ())
+                .flatten_fut()
+                .and_then(move |sessions| {
+                    future::join_all(sessions.into_iter().map(move |session| {
+                        let full_id = format!("{}::{}", prefix, session.id);
+                        info!("drain deadline passed, destroying session {}", full_id);
+                        destroy
+                            .send(DestroySession {
+                                session_id: session.id,
+                            })
+                            .then(move |_| Ok(full_id))
+                    }))
+                });
+            ctx.spawn(destroyed.into_actor(self).map(|destroyed, act, _ctx| {
+                for full_id in destroyed {
+                    act.owners.remove(&full_id);
+                }
+                act.save_owners();
+            }));
+        }
+    }
+}
+
+impl Handler<WithSender<SetDraining>> for EnvMan {
+    type Result = ActorResponse<EnvMan, (), ()>;
+
+    fn handle(&mut self, msg: WithSender<SetDraining>, _ctx: &mut Self::Context) -> Self::Result {
+        let sender = msg.sender;
+        let msg = msg.body;
+
+        ActorResponse::r#async(
+            access_level(sender)
+                .map_err(|_| ())
+                .into_actor(self)
+                .and_then(move |level, act, ctx| {
+                    // draining stops the work of all hubs
+                    if level != AccessLevel::FullAccess {
+                        return fut::err(());
+                    }
+                    act.set_draining(msg, ctx);
+                    fut::ok(())
+                }),
+        )
     }
 }
 
@@ -249,10 +482,54 @@ where
     })
 }
 
+/// Removes the execution environment, new sessions of this type are rejected.
+pub fn unregister(env_type: impl Into<String>) {
+    EnvMan::from_registry().do_send(Unregister {
+        env_type: env_type.into(),
+    })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn test_env_access() {
+        assert!(check_env_access(AccessLevel::NoAccess, "docker").is_ok());
+        assert!(check_env_access(AccessLevel::NoAccess, "hd").is_ok());
+        assert!(check_env_access(AccessLevel::Sandbox, "hd").is_err());
+        assert!(check_env_access(AccessLevel::Sandbox, "hd-sandbox").is_ok());
+        assert!(check_env_access(AccessLevel::FullAccess, "hd").is_ok());
+    }
+
+    #[test]
+    fn test_session_owner() {
+        let owner: NodeId = "0x0000000000000000000000000000000000000001"
+            .parse()
+            .unwrap();
+        let other: NodeId = "0x0000000000000000000000000000000000000002"
+            .parse()
+            .unwrap();
+        let mut envman = EnvMan::default();
+        envman.owners.insert("hd-sandbox::1".into(), owner);
+
+        assert!(envman
+            .check_session_access(&owner, AccessLevel::Sandbox, "hd-sandbox::1")
+            .is_ok());
+        assert!(envman
+            .check_session_access(&other, AccessLevel::Sandbox, "hd-sandbox::1")
+            .is_err());
+        assert!(envman
+            .check_session_access(&other, AccessLevel::FullAccess, "hd-sandbox::1")
+            .is_ok());
+        assert!(envman
+            .check_session_access(&other, AccessLevel::NoAccess, "hd-sandbox::1")
+            .is_ok());
+        assert!(envman
+            .check_session_access(&owner, AccessLevel::Sandbox, "hd::2")
+            .is_err());
+    }
+
     #[test]
     fn test_split() {
         let (p, s) = extract_prefix("hd::12345").unwrap();
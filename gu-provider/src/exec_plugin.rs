@@ -1,9 +1,11 @@
 use crate::deployment::{DeployManager, Destroy, IntoDeployInfo};
 use crate::workspace::{Workspace, WorkspacesManager};
-use crate::{envman, status};
+use crate::{envman, idle, status};
 use actix::prelude::*;
 use gu_hdman::process_pool::{self as pp, KillAll, ProcessPool};
-use gu_model::envman::{Command, CreateSession, DestroySession, GetSessions, SessionUpdate};
+use gu_model::envman::{
+    Command, CreateSession, DestroySession, GetSessions, ProcessOptions, SessionUpdate, Stdin,
+};
 use gu_model::plugin::{PluginManifest, ResolveResult, SimpleExecEnvSpec};
 use std::path::{Path, PathBuf};
 use std::process;
@@ -25,10 +27,15 @@ pub struct PluginMan {
     exec: PathBuf,
     deploys: DeployManager<PlugSession>,
     workspaces_man: WorkspacesManager,
+    paused: idle::PausedProcesses,
 }
 
 impl PluginMan {
-    fn from_spec(base_path: &Path, spec: SimpleExecEnvSpec, config: &ConfigModule) -> Self {
+    pub(crate) fn from_spec(
+        base_path: &Path,
+        spec: SimpleExecEnvSpec,
+        config: &ConfigModule,
+    ) -> Self {
         let code = spec.code;
         let exec = base_path.join(spec.exec);
         let deploys = Default::default();
@@ -38,6 +45,7 @@ impl PluginMan {
             exec,
             deploys,
             workspaces_man,
+            paused: Default::default(),
         }
     }
 }
@@ -52,6 +60,10 @@ impl Actor for PluginMan {
             self.code.clone(),
             ctx.address().recipient(),
         ));
+        idle::IdleMonitor::from_registry().do_send(idle::AddWorkload::new(
+            self.code.clone(),
+            ctx.address().recipient(),
+        ));
     }
 }
 
@@ -264,13 +276,20 @@ impl Handler<SessionUpdate> for PluginMan {
                                 "--spec".into(),
                                 spec_path.to_string_lossy().into(),
                             ],
+                            ..Default::default()
                         });
                         Box::new(futures::future::ok("Ok".into()))
                     }
                     Command::Exec {
                         executable,
                         mut args,
-                        /*TODO */ working_dir,
+                        options:
+                            ProcessOptions {
+                                working_dir,
+                                env,
+                                clear_env,
+                                stdin,
+                            },
                     } => {
                         let mut driver_args: Vec<String> = vec![
                             "exec".into(),
@@ -280,20 +299,51 @@ impl Handler<SessionUpdate> for PluginMan {
                             work_dir.to_string_lossy().into(),
                             "--spec".into(),
                             spec_path.to_string_lossy().into(),
-                            "--".into(),
-                            executable,
                         ];
+                        // the driver runs the process inside the image, so it resolves the dir
+                        if let Some(working_dir) = working_dir {
+                            driver_args.push("--cwd".into());
+                            driver_args.push(working_dir);
+                        }
+                        driver_args.push("--".into());
+                        driver_args.push(executable);
                         driver_args.append(&mut args);
 
-                        Box::new(
+                        let stdin = match stdin {
+                            None => futures::future::Either::A(Ok(None).into_future()),
+                            Some(Stdin::Content(content)) => futures::future::Either::A(
+                                Ok(Some(content.into_bytes())).into_future(),
+                            ),
+                            Some(Stdin::File(file_path)) => futures::future::Either::B(
+                                resolve_path(
+                                    &exec,
+                                    &image_path,
+                                    &work_dir,
+                                    &spec_path,
+                                    file_path.as_ref(),
+                                )
+                                .and_then(|resp| match resp {
+                                    ResolveResult::ResolvedPath(input_path) => {
+                                        fs::read(input_path).map(Some).map_err(|e| e.to_string())
+                                    }
+                                }),
+                            ),
+                        };
+                        let exec = exec.clone();
+                        let pool = pool.clone();
+
+                        Box::new(stdin.and_then(move |stdin| {
                             pool.send(pp::Exec {
-                                executable: exec.clone(),
+                                executable: exec,
                                 args: driver_args,
+                                env,
+                                clear_env,
+                                stdin,
                             })
                             .map_err(|_e| "process pool destroyed".into())
                             .and_then(|r| r)
-                            .and_then(|(stdout, _stderr)| Ok(stdout)),
-                        )
+                            .and_then(|(stdout, _stderr)| Ok(stdout))
+                        }))
                     }
                     Command::DownloadFile {
                         uri,
@@ -435,11 +485,71 @@ impl Handler<DestroySession> for PluginMan {
     }
 }
 
+/// Destroys all sessions of the environment, unregisters and stops it.
+pub struct Retire;
+
+impl Message for Retire {
+    type Result = ();
+}
+
+impl Handler<Retire> for PluginMan {
+    type Result = ActorResponse<Self, (), ()>;
+
+    fn handle(&mut self, _: Retire, _ctx: &mut Self::Context) -> Self::Result {
+        envman::unregister(self.code.clone());
+        status::StatusManager::from_registry()
+            .do_send(status::RemoveProvider::new(self.code.clone()));
+
+        let sessions: Vec<_> = self
+            .deploys
+            .deploys_info()
+            .into_iter()
+            .map(|info| {
+                self.deploys
+                    .destroy_deploy(&info.id)
+                    .then(|r| Ok(r.unwrap_or_else(|e| log::warn!("destroy session: {}", e))))
+            })
+            .collect();
+
+        ActorResponse::r#async(futures::future::join_all(sessions).into_actor(self).then(
+            |_: Result<Vec<()>, ()>, _act, ctx| {
+                ctx.stop();
+                fut::ok(())
+            },
+        ))
+    }
+}
+
+impl Handler<idle::SetPaused> for PluginMan {
+    type Result = ();
+
+    fn handle(&mut self, msg: idle::SetPaused, ctx: &mut Self::Context) -> Self::Result {
+        // reniced processes are resumed also when their sessions are gone
+        self.paused.set_paused(Vec::new(), msg);
+        let pools: Vec<Addr<ProcessPool>> = self
+            .deploys
+            .values_mut()
+            .map(|session| session.pool.clone())
+            .collect();
+
+        for pool in pools {
+            ctx.spawn(pool.send(pp::List).map_err(|_| ()).into_actor(self).map(
+                move |pids, act, _ctx| {
+                    act.paused.set_paused(
+                        pids.iter().filter_map(|pid| pid.to_string().parse().ok()),
+                        msg,
+                    )
+                },
+            ));
+        }
+    }
+}
+
 impl Handler<status::GetEnvStatus> for PluginMan {
     type Result = MessageResult<status::GetEnvStatus>;
 
     fn handle(&mut self, _: GetEnvStatus, _ctx: &mut Self::Context) -> Self::Result {
-        MessageResult(self.deploys.status())
+        MessageResult(self.paused.env_status(self.deploys.status()))
     }
 }
 
@@ -6,7 +6,9 @@ use std::{
     fs,
     fs::OpenOptions,
     path::{Path, PathBuf},
-    process, result, time,
+    process, result,
+    sync::Arc,
+    time,
 };
 
 use actix::{fut, prelude::*};
@@ -21,21 +23,27 @@ use gu_net::rpc::{
     peer::{PeerSessionInfo, PeerSessionStatus},
     *,
 };
-use gu_persist::config::ConfigModule;
+use gu_persist::config::{ConfigManager, ConfigModule, GetConfig};
 
 use crate::deployment::{DeployManager, Destroy, IntoDeployInfo};
 
+use super::beneath;
 /**
 
 Host direct manager.
 
 */
 use super::id::generate_new_id;
-use super::provision::{download_step, untgz, upload_step};
+use super::provision::{
+    download_step, download_step_beneath, untgz, upload_step, upload_step_beneath,
+};
 use super::workspace::{Workspace, WorkspacesManager};
 use super::{
-    envman, status,
-    sync_exec::{Exec, ExecResult, SyncExecManager},
+    envman, idle,
+    sandbox::{Sandbox, SandboxConfig},
+    server::ProviderConfig,
+    status,
+    sync_exec::{self, Exec, ExecResult, Input, ProcessEnv, RunningProcesses, SyncExecManager},
 };
 
 impl IntoDeployInfo for HdSessionInfo {
@@ -51,20 +59,45 @@ impl IntoDeployInfo for HdSessionInfo {
     }
 }
 
+fn log_terminated(ids: Vec<String>, statuses: Vec<std::io::Result<process::ExitStatus>>) {
+    for (id, status) in ids.into_iter().zip(statuses) {
+        match status {
+            Ok(status) => info!("process {} terminated: {}", id, status),
+            Err(e) => error!("cannot terminate process {}: {}", id, e),
+        }
+    }
+}
+
 impl Destroy for HdSessionInfo {
     fn destroy(&mut self) -> Box<dyn Future<Item = (), Error = Error>> {
-        debug!("killing all running child processes");
-        let _ = self
-            .processes
-            .values_mut()
-            .map(|child| child.kill())
-            .collect::<Vec<_>>();
-        let _ = self
-            .processes
-            .values_mut()
-            .map(|child| child.wait())
-            .collect::<Vec<_>>();
-        Box::new(self.workspace.clear_dir().map_err(From::from).into_future())
+        debug!("terminating all running child processes");
+        sync_exec::interrupt(&self.sync_processes);
+        let (ids, children): (Vec<String>, Vec<process::Child>) = self.processes.drain().unzip();
+        let workspace = self.workspace.clone();
+        let clear_dir = move |_| workspace.clear_dir().map_err(Error::from);
+        if children.is_empty() {
+            return Box::new(clear_dir(()).into_future());
+        }
+
+        // waiting for the grace period must not hold up the sync exec thread
+        let grace = self.kill_timeout;
+        Box::new(
+            gu_hdman::download::cpu_pool()
+                .spawn_fn(move || -> result::Result<(), Error> {
+                    log_terminated(ids, sync_exec::terminate(children, grace));
+                    Ok(())
+                })
+                .and_then(clear_dir),
+        )
+    }
+
+    fn destroy_now(&mut self) {
+        sync_exec::interrupt(&self.sync_processes);
+        let (ids, children) = self.processes.drain().unzip();
+        log_terminated(ids, sync_exec::terminate(children, self.kill_timeout));
+        if let Err(e) = self.workspace.clear_dir() {
+            error!("cannot clear session dir: {}", e);
+        }
     }
 }
 
@@ -75,6 +108,11 @@ pub struct HdMan {
     #[allow(unused)]
     cache_dir: PathBuf,
     workspaces_man: WorkspacesManager,
+    kill_timeout: time::Duration,
+    /// mount point of sandbox roots; set for the sandboxed manager
+    sandbox_root: Option<PathBuf>,
+    sandbox_config: SandboxConfig,
+    paused: idle::PausedProcesses,
 }
 
 impl envman::EnvManService for HdMan {
@@ -85,33 +123,68 @@ impl Actor for HdMan {
     type Context = RemotingContext<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        envman::register("hd", ctx.address());
+        let (env_type, status_name) = if self.sandbox_root.is_some() {
+            ("hd-sandbox", "hostDirectSandbox")
+        } else {
+            ("hd", "hostDirect")
+        };
+        envman::register(env_type, ctx.address());
 
         status::StatusManager::from_registry().do_send(status::AddProvider::new(
-            "hostDirect",
+            status_name,
             ctx.address().recipient(),
         ));
+        idle::IdleMonitor::from_registry()
+            .do_send(idle::AddWorkload::new(env_type, ctx.address().recipient()));
 
         ctx.run_interval(time::Duration::from_secs(10), |act, _| {
             act.scan_for_processes()
         });
+
+        ConfigManager::from_registry()
+            .send(GetConfig::new())
+            .flatten_fut()
+            .map_err(|e| error!("Cannot read provider config: {}", e))
+            .into_actor(self)
+            .and_then(|config: Arc<ProviderConfig>, act, _ctx| {
+                act.kill_timeout = config.kill_timeout();
+                act.sandbox_config = config.sandbox.clone();
+                fut::ok(())
+            })
+            .spawn(ctx);
     }
 }
 
 impl HdMan {
-    pub fn start(config: &ConfigModule) -> Addr<Self> {
+    /// Starts the manager of `hd` sessions, or of `hd-sandbox` ones when `sandboxed`.
+    pub fn start(config: &ConfigModule, sandboxed: bool) -> Addr<Self> {
         let cache_dir = config.cache_dir().to_path_buf().join("images");
         log::info!("creating cache dir: {}", cache_dir.display());
         fs::create_dir_all(&cache_dir)
             .map_err(|e| error!("Cannot create HdMan dir: {:?}", e))
             .unwrap();
 
-        let workspaces_man = WorkspacesManager::new(&config, "hd").unwrap();
+        let (workspaces_man, sandbox_root) = if sandboxed {
+            let sandbox_root = config.runtime_dir().join("sandbox-root");
+            fs::create_dir_all(&sandbox_root)
+                .map_err(|e| error!("Cannot create sandbox root dir: {:?}", e))
+                .unwrap();
+            (
+                WorkspacesManager::new(&config, "hd-sandbox").unwrap(),
+                Some(sandbox_root),
+            )
+        } else {
+            (WorkspacesManager::new(&config, "hd").unwrap(), None)
+        };
 
         start_actor(HdMan {
             deploys: Default::default(),
             cache_dir,
             workspaces_man,
+            kill_timeout: ProviderConfig::default().kill_timeout(),
+            sandbox_root,
+            sandbox_config: SandboxConfig::default(),
+            paused: Default::default(),
         })
     }
 
@@ -188,6 +261,11 @@ struct HdSessionInfo {
     note: Option<String>,
     config_files: HashSet<PathBuf>,
     processes: HashMap<String, process::Child>,
+    /// processes of `Command::Exec` run by the synchronous executor
+    sync_processes: RunningProcesses,
+    /// grace period for processes to exit after SIGTERM
+    kill_timeout: time::Duration,
+    sandbox: Option<Sandbox>,
 }
 
 impl HdSessionInfo {
@@ -199,6 +277,22 @@ impl HdSessionInfo {
         id
     }
 
+    /// Path of a file in the workspace.
+    fn get_file_path(&self, file_path: &str) -> PathBuf {
+        self.workspace.path().join(file_path)
+    }
+
+    /// Workspace directory of a sandboxed session. Paths are resolved below it
+    /// without following symlinks, as sandboxed processes can place them there.
+    fn sandbox_dir(&self) -> Result<Option<fs::File>, String> {
+        match self.sandbox {
+            Some(_) => fs::File::open(self.workspace.path())
+                .map(Some)
+                .map_err(|e| format!("{}: {}", self.workspace.path().display(), e)),
+            None => Ok(None),
+        }
+    }
+
     fn get_session_exec_path(&self, executable: &String) -> String {
         self.workspace
             .path()
@@ -243,6 +337,13 @@ impl Handler<CreateSession> for HdMan {
             Err(e) => return ActorResponse::reply(Err(e.into())),
         }
         let workspace_path = workspace.path().clone();
+        let sandbox = self.sandbox_root.as_ref().map(|root| {
+            Sandbox::new(
+                self.sandbox_config.clone(),
+                root.clone(),
+                workspace_path.clone(),
+            )
+        });
 
         let session = HdSessionInfo {
             workspace,
@@ -250,7 +351,10 @@ impl Handler<CreateSession> for HdMan {
             dirty: false,
             note: msg.note,
             processes: HashMap::new(),
+            sync_processes: RunningProcesses::default(),
             config_files: HashSet::new(),
+            kill_timeout: self.kill_timeout,
+            sandbox,
         };
 
         self.deploys.insert_deploy(session_id.clone(), session);
@@ -283,6 +387,42 @@ impl Handler<CreateSession> for HdMan {
     }
 }
 
+/// Working dir and environment of a process, with paths resolved in the workspace
+fn process_env(
+    session: &HdSessionInfo,
+    options: ProcessOptions,
+) -> Result<(PathBuf, ProcessEnv), String> {
+    let cwd = session
+        .workspace
+        .resolve(options.working_dir.unwrap_or_default())
+        .map_err(|e| e.to_string())?;
+    let stdin = match options.stdin {
+        Some(Stdin::Content(content)) => Some(Input::Bytes(content.into_bytes())),
+        Some(Stdin::File(path)) => Some(Input::File(
+            match session.sandbox_dir()? {
+                Some(dir) => beneath::open_file(&dir, Path::new(&path), false),
+                None => session
+                    .workspace
+                    .resolve(path)
+                    .and_then(|path| fs::File::open(path)),
+            }
+            .map_err(|e| e.to_string())?,
+        )),
+        None => None,
+    };
+
+    Ok((
+        cwd,
+        ProcessEnv {
+            env: options.env,
+            clear_env: options.clear_env,
+            stdin,
+            sandbox: session.sandbox.clone(),
+            running: Some(session.sync_processes.clone()),
+        },
+    ))
+}
+
 fn run_command(
     hd_man: &mut HdMan,
     session_id: String,
@@ -299,12 +439,14 @@ fn run_command(
         Command::Exec {
             executable,
             args,
-            working_dir,
+            options,
         } => {
             let executable = session.get_session_exec_path(&executable);
             let session_id = session_id.clone();
-            let session_dir = session.workspace.path().to_owned();
-            let cwd = session_dir.join(working_dir.unwrap_or_default());
+            let (cwd, env) = match process_env(session, options) {
+                Ok(v) => v,
+                Err(e) => return Box::new(fut::err(e)),
+            };
 
             info!("executing sync: {} {:?}", executable, args);
             Box::new(
@@ -314,6 +456,7 @@ fn run_command(
                             executable,
                             args,
                             cwd,
+                            env,
                         })
                         .flatten_fut()
                         .map_err(move |e| e.to_string()),
@@ -336,16 +479,25 @@ fn run_command(
                 }),
             )
         }
-        Command::Start { executable, args } => {
+        Command::Start {
+            executable,
+            args,
+            options,
+        } => {
             let executable = session.get_session_exec_path(&executable);
+            let (cwd, env) = match process_env(session, options) {
+                Ok(v) => v,
+                Err(e) => return Box::new(fut::err(e)),
+            };
 
             info!("executing async: {} {:?}", executable, args);
-            // TODO: critical section
-            // TODO: env::set_current_dir(&base_dir)?;
 
-            let child_res = process::Command::new(&executable)
-                .args(&args)
-                .spawn()
+            let child_res = env
+                .spawn(
+                    process::Command::new(&executable)
+                        .current_dir(&cwd)
+                        .args(&args),
+                )
                 .map_err(|e| Error::IoError(e.to_string()))
                 .map(|child| session.insert_process(child));
 
@@ -356,7 +508,8 @@ fn run_command(
         }
         Command::Stop { child_id } => {
             let session_id = session_id.clone();
-            info!("killing: {:?}", &child_id);
+            let grace = session.kill_timeout;
+            info!("stopping: {:?}", &child_id);
 
             let kill_res = session
                 .processes
@@ -365,15 +518,13 @@ fn run_command(
 
             Box::new(
                 fut::result(kill_res).and_then(move |child, hd_man: &mut HdMan, _ctx| {
-                    SyncExecManager::from_registry()
-                        .send(Exec::Kill(child))
-                        .map_err(|e| format!("{}", e))
-                        .and_then(|r| {
-                            if let Ok(ExecResult::Kill(output)) = r {
-                                Ok(output)
-                            } else {
-                                Err(format!("wrong result {:?}", r))
-                            }
+                    gu_hdman::download::cpu_pool()
+                        .spawn_fn(move || {
+                            sync_exec::terminate(vec![child], grace)
+                                .pop()
+                                .unwrap()
+                                .map(|status| status.to_string())
+                                .map_err(|e| e.to_string())
                         })
                         .into_actor(hd_man)
                         .and_then(move |output, hd_man, _ctx| {
@@ -396,26 +547,41 @@ fn run_command(
             file_path,
             format,
         } => {
-            let path = session.workspace.path().join(file_path);
-            Box::new(fut::wrap_future(handle_download_file(uri, path, format)))
+            let download: Box<dyn Future<Item = (), Error = String>> = match session.sandbox_dir() {
+                Ok(Some(dir)) => download_step_beneath(&uri, dir, file_path.into(), format),
+                Ok(None) => Box::new(download_step(
+                    &uri,
+                    session.get_file_path(&file_path),
+                    format,
+                )),
+                Err(e) => return Box::new(fut::err(e)),
+            };
+            Box::new(fut::wrap_future(
+                download.and_then(move |_| Ok(format!("{:?} file downloaded", uri))),
+            ))
         }
         Command::WriteFile { content, file_path } => {
-            let path = session.workspace.path().join(file_path);
+            let path = session.get_file_path(&file_path);
             let create_new = session.config_files.insert(path.clone());
+            let sandbox_dir = match session.sandbox_dir() {
+                Ok(dir) => dir,
+                Err(e) => return Box::new(fut::err(e)),
+            };
             let bytes = content.into_bytes();
             Box::new(fut::wrap_future(gu_hdman::download::cpu_pool().spawn_fn(
                 move || {
                     use std::io::prelude::*;
 
-                    if !create_new {
-                        let _ = fs::remove_file(&path);
+                    let mut f = match sandbox_dir {
+                        Some(dir) => beneath::open_file(&dir, Path::new(&file_path), true),
+                        None => {
+                            if !create_new {
+                                let _ = fs::remove_file(&path);
+                            }
+                            OpenOptions::new().create_new(true).write(true).open(path)
+                        }
                     }
-
-                    let mut f = OpenOptions::new()
-                        .create_new(true)
-                        .write(true)
-                        .open(path)
-                        .map_err(|e| format!("io: {}", e))?;
+                    .map_err(|e| format!("io: {}", e))?;
 
                     f.write_all(bytes.as_ref())
                         .map_err(|e| format!("io: {}", e))?;
@@ -428,10 +594,20 @@ fn run_command(
             uri,
             file_path,
             format,
-        } => {
-            let path = session.workspace.path().join(file_path);
-            Box::new(fut::wrap_future(handle_upload_file(uri, path, format)))
-        }
+        } => match session.sandbox_dir() {
+            Ok(Some(dir)) => Box::new(fut::wrap_future(upload_step_beneath(
+                &uri,
+                dir,
+                file_path.into(),
+                format,
+            ))),
+            Ok(None) => Box::new(fut::wrap_future(upload_step(
+                &uri,
+                session.get_file_path(&file_path),
+                format,
+            ))),
+            Err(e) => Box::new(fut::err(e)),
+        },
         Command::AddTags(tags) => Box::new({
             session.workspace.add_tags(tags);
             fut::ok(format!(
@@ -491,24 +667,6 @@ impl Handler<SessionUpdate> for HdMan {
     }
 }
 
-fn handle_download_file(
-    url: String,
-    file_path: PathBuf,
-    format: ResourceFormat,
-) -> impl Future<Item = String, Error = String> {
-    download_step(url.as_ref(), file_path, format)
-        .and_then(move |_| Ok(format!("{:?} file downloaded", url)))
-        .map_err(|e| e.to_string())
-}
-
-fn handle_upload_file(
-    url: String,
-    file_path: PathBuf,
-    format: ResourceFormat,
-) -> impl Future<Item = String, Error = String> {
-    upload_step(&url, file_path, format)
-}
-
 // TODO: implement child process polling and status reporting
 #[derive(Serialize, Deserialize, Debug)]
 struct SessionStatus {
@@ -531,10 +689,25 @@ impl Handler<DestroySession> for HdMan {
         msg: DestroySession,
         _ctx: &mut Self::Context,
     ) -> <Self as Handler<DestroySession>>::Result {
-        ActorResponse::r#async(match self.deploys.destroy_deploy(&msg.session_id).wait() {
-            Ok(_) => fut::ok("Session closed".into()),
-            Err(e) => fut::err(e),
-        })
+        ActorResponse::r#async(
+            self.deploys
+                .destroy_deploy(&msg.session_id)
+                .map(|_| "Session closed".to_string())
+                .into_actor(self),
+        )
+    }
+}
+
+impl Handler<idle::SetPaused> for HdMan {
+    type Result = ();
+
+    fn handle(&mut self, msg: idle::SetPaused, _ctx: &mut Self::Context) -> Self::Result {
+        let mut pids = Vec::new();
+        for session in self.deploys.values_mut() {
+            pids.extend(session.processes.values().map(process::Child::id));
+            pids.extend(session.sync_processes.lock().unwrap().iter().cloned());
+        }
+        self.paused.set_paused(pids, msg);
     }
 }
 
@@ -546,6 +719,6 @@ impl Handler<status::GetEnvStatus> for HdMan {
         _msg: status::GetEnvStatus,
         _ctx: &mut Self::Context,
     ) -> <Self as Handler<status::GetEnvStatus>>::Result {
-        MessageResult(self.deploys.status())
+        MessageResult(self.paused.env_status(self.deploys.status()))
     }
 }
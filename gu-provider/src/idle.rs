@@ -0,0 +1,630 @@
+//! Pausing of work while the machine is used by its owner.
+//!
+//! With `idle.enabled` the provider periodically samples CPU load of processes it
+//! did not start and, when `idle.inputIdleCommand` is set, the time since the last
+//! input of the desktop user. When either shows the machine is busy, running
+//! workloads are paused; they are resumed once the machine has been idle for
+//! `idle.resumeAfter` seconds. The current state is reported to the hub.
+
+use std::{
+    borrow::Cow,
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use actix::prelude::*;
+use futures::prelude::*;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use gu_actix::prelude::*;
+use gu_model::envman::{Activity, GetActivity};
+use gu_net::rpc::{PublicMessage, RemotingContext, RemotingSystemService, WithSender};
+use gu_persist::config::{ConfigManager, GetConfig};
+
+use crate::permission::{AccessLevel, PermissionConfig};
+use crate::server::ProviderConfig;
+use crate::status::{EnvStatus, StatusManager};
+
+/// How workload processes are paused.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum PauseMode {
+    /// SIGSTOP / SIGCONT
+    Stop,
+    /// lowest scheduling priority; restoring the original one may need privileges
+    Renice,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct IdleConfig {
+    #[serde(default)]
+    enabled: bool,
+    /// load of other processes, as a fraction of all CPUs, above which the machine is busy
+    #[serde(default = "IdleConfig::default_max_cpu_load")]
+    max_cpu_load: f32,
+    /// seconds without user input after which the machine may be idle
+    #[serde(default = "IdleConfig::default_min_input_idle")]
+    min_input_idle: u64,
+    /// shell command printing milliseconds since the last user input, e.g. `xprintidle`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    input_idle_command: Option<String>,
+    /// seconds between checks
+    #[serde(default = "IdleConfig::default_check_interval")]
+    check_interval: u64,
+    /// seconds the machine has to be idle before paused work is resumed
+    #[serde(default = "IdleConfig::default_resume_after")]
+    resume_after: u64,
+    #[serde(default = "IdleConfig::default_pause_mode")]
+    pause_mode: PauseMode,
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        IdleConfig {
+            enabled: false,
+            max_cpu_load: Self::default_max_cpu_load(),
+            min_input_idle: Self::default_min_input_idle(),
+            input_idle_command: None,
+            check_interval: Self::default_check_interval(),
+            resume_after: Self::default_resume_after(),
+            pause_mode: Self::default_pause_mode(),
+        }
+    }
+}
+
+impl IdleConfig {
+    fn default_max_cpu_load() -> f32 {
+        0.25
+    }
+
+    fn default_min_input_idle() -> u64 {
+        300
+    }
+
+    fn default_check_interval() -> u64 {
+        5
+    }
+
+    fn default_resume_after() -> u64 {
+        60
+    }
+
+    fn default_pause_mode() -> PauseMode {
+        PauseMode::Stop
+    }
+
+    fn is_busy(&self, cpu_load: Option<f32>, input_idle: Option<u64>) -> bool {
+        cpu_load.map_or(false, |load| load > self.max_cpu_load)
+            || input_idle.map_or(false, |idle| idle < self.min_input_idle)
+    }
+}
+
+/// Pauses or resumes a workload. While the machine stays busy it is sent again on
+/// every check, so work started in the meantime is paused as well.
+#[derive(Message, Clone, Copy)]
+pub(crate) struct SetPaused {
+    pub paused: bool,
+    pub mode: PauseMode,
+}
+
+#[derive(Message)]
+pub(crate) struct AddWorkload(Cow<'static, str>, Recipient<SetPaused>);
+
+impl AddWorkload {
+    #[inline]
+    pub fn new(name: impl Into<Cow<'static, str>>, handler: Recipient<SetPaused>) -> Self {
+        AddWorkload(name.into(), handler)
+    }
+}
+
+/// Ids of docker containers created by the provider; their load is not counted.
+#[derive(Message)]
+pub(crate) struct SetContainers(pub Vec<String>);
+
+#[derive(Default)]
+pub struct IdleMonitor {
+    config: IdleConfig,
+    workloads: BTreeMap<Cow<'static, str>, Recipient<SetPaused>>,
+    containers: HashSet<String>,
+    last_sample: Option<CpuTimes>,
+    activity: Activity,
+    /// since when the machine has been idle
+    idle_since: Option<Instant>,
+    checking: bool,
+}
+
+impl Actor for IdleMonitor {
+    type Context = RemotingContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.bind_with_sender::<GetActivity>(GetActivity::ID);
+
+        ConfigManager::from_registry()
+            .send(GetConfig::new())
+            .flatten_fut()
+            .map_err(|e| error!("Cannot read provider config: {}", e))
+            .into_actor(self)
+            .and_then(|config: Arc<ProviderConfig>, act, ctx| {
+                act.config = config.idle.clone();
+                if act.config.enabled {
+                    let interval = Duration::from_secs(act.config.check_interval.max(1));
+                    ctx.run_interval(interval, |act, ctx| act.check(ctx));
+                }
+                fut::ok(())
+            })
+            .spawn(ctx);
+    }
+}
+
+impl RemotingSystemService for IdleMonitor {}
+
+impl IdleMonitor {
+    fn check(&mut self, ctx: &mut RemotingContext<Self>) {
+        if self.checking {
+            return;
+        }
+        self.checking = true;
+
+        let containers = self.containers.clone();
+        let command = self.config.input_idle_command.clone();
+        gu_hdman::download::cpu_pool()
+            .spawn_fn(move || -> Result<_, ()> {
+                let times = read_cpu_times(&containers)
+                    .map_err(|e| debug!("Cannot read cpu times: {}", e))
+                    .ok();
+                Ok((times, command.and_then(|command| input_idle(&command))))
+            })
+            .into_actor(self)
+            .then(|result, act, _ctx| {
+                act.checking = false;
+                if let Ok((times, input_idle)) = result {
+                    let cpu_load = match (act.last_sample.take(), &times) {
+                        (Some(last), Some(times)) => times.load_since(&last),
+                        _ => None,
+                    };
+                    act.last_sample = times;
+                    act.update(cpu_load, input_idle);
+                }
+                fut::ok(())
+            })
+            .spawn(ctx);
+    }
+
+    fn update(&mut self, cpu_load: Option<f32>, input_idle: Option<u64>) {
+        self.activity.cpu_load = cpu_load;
+        self.activity.input_idle = input_idle;
+
+        if self.config.is_busy(cpu_load, input_idle) {
+            self.idle_since = None;
+            if !self.activity.paused {
+                info!(
+                    "machine busy (cpu load: {:?}, input idle: {:?}), pausing work",
+                    cpu_load, input_idle
+                );
+                self.activity.paused = true;
+            }
+            self.notify_all();
+        } else {
+            let idle_since = *self.idle_since.get_or_insert_with(Instant::now);
+            if self.activity.paused {
+                if idle_since.elapsed() >= Duration::from_secs(self.config.resume_after) {
+                    info!("machine idle, resuming work");
+                    self.activity.paused = false;
+                }
+                self.notify_all();
+            }
+        }
+    }
+
+    fn set_paused(&self) -> SetPaused {
+        SetPaused {
+            paused: self.activity.paused,
+            mode: self.config.pause_mode,
+        }
+    }
+
+    fn notify_all(&self) {
+        let msg = self.set_paused();
+        StatusManager::from_registry().do_send(msg);
+        for workload in self.workloads.values() {
+            let _ = workload.do_send(msg);
+        }
+    }
+}
+
+impl Handler<AddWorkload> for IdleMonitor {
+    type Result = ();
+
+    fn handle(&mut self, msg: AddWorkload, _ctx: &mut Self::Context) -> Self::Result {
+        if self.activity.paused {
+            let _ = msg.1.do_send(self.set_paused());
+        }
+        self.workloads.insert(msg.0, msg.1);
+    }
+}
+
+impl Handler<SetContainers> for IdleMonitor {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetContainers, _ctx: &mut Self::Context) -> Self::Result {
+        self.containers = msg.0.into_iter().collect();
+    }
+}
+
+/// Activity is reported only to hubs managing the provider.
+impl Handler<WithSender<GetActivity>> for IdleMonitor {
+    type Result = ActorResponse<IdleMonitor, Activity, ()>;
+
+    fn handle(&mut self, msg: WithSender<GetActivity>, _ctx: &mut Self::Context) -> Self::Result {
+        let sender = msg.sender;
+
+        ActorResponse::r#async(
+            ConfigManager::from_registry()
+                .send(GetConfig::new())
+                .flatten_fut()
+                .map_err(|e| error!("cannot read permissions: {}", e))
+                .into_actor(self)
+                .and_then(move |config: Arc<PermissionConfig>, act, _ctx| {
+                    if config.highest_permission(&sender) == AccessLevel::NoAccess {
+                        debug!("activity query from unmanaging node {:?} rejected", sender);
+                        return fut::err(());
+                    }
+                    fut::ok(act.activity.clone())
+                }),
+        )
+    }
+}
+
+/// Pauses and resumes processes of a workload, remembering the original priority
+/// of reniced ones.
+#[derive(Default)]
+pub(crate) struct PausedProcesses {
+    /// priority before renice, by pid of the process group leader or process
+    priorities: HashMap<u32, i32>,
+}
+
+impl PausedProcesses {
+    /// Pauses or resumes the process groups led by `pids`, or the processes alone
+    /// when they do not lead one. Reniced processes are resumed whether listed or not.
+    pub fn set_paused(&mut self, pids: impl IntoIterator<Item = u32>, msg: SetPaused) {
+        match (msg.mode, msg.paused) {
+            (PauseMode::Stop, paused) => {
+                for pid in pids {
+                    if let Err(e) = signal_process(pid, paused) {
+                        debug!("cannot pause/resume process {}: {}", pid, e);
+                    }
+                }
+            }
+            (PauseMode::Renice, true) => {
+                for pid in pids {
+                    if let Err(e) = self.renice(pid) {
+                        debug!("cannot renice process {}: {}", pid, e);
+                    }
+                }
+            }
+            (PauseMode::Renice, false) => {
+                self.priorities
+                    .retain(|&pid, &mut priority| match set_priority(pid, priority) {
+                        Ok(()) => false,
+                        Err(ref e) if e.raw_os_error() == Some(ESRCH) => false,
+                        Err(e) => {
+                            warn!(
+                                "cannot restore priority {} of process {}: {}",
+                                priority, pid, e
+                            );
+                            true
+                        }
+                    });
+            }
+        }
+    }
+
+    /// Working environments stay `Paused` while their priority restore fails.
+    pub fn env_status(&self, status: EnvStatus) -> EnvStatus {
+        match status {
+            EnvStatus::Working if !self.priorities.is_empty() => EnvStatus::Paused,
+            status => status,
+        }
+    }
+
+    fn renice(&mut self, pid: u32) -> io::Result<()> {
+        // processes are reniced again on every check, the first priority is the original
+        if let Entry::Vacant(entry) = self.priorities.entry(pid) {
+            entry.insert(get_priority(pid)?);
+        }
+        set_priority(pid, RENICE_PRIORITY)
+    }
+}
+
+const RENICE_PRIORITY: i32 = 19;
+
+#[cfg(unix)]
+const ESRCH: i32 = libc::ESRCH;
+#[cfg(not(unix))]
+const ESRCH: i32 = 3;
+
+#[cfg(unix)]
+fn signal_process(pid: u32, paused: bool) -> io::Result<()> {
+    let signal = if paused { libc::SIGSTOP } else { libc::SIGCONT };
+    let pid = pid as libc::pid_t;
+    match unsafe { libc::kill(-pid, signal) == 0 || libc::kill(pid, signal) == 0 } {
+        true => Ok(()),
+        false => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(unix)]
+fn get_priority(pid: u32) -> io::Result<i32> {
+    // -1 is a valid priority, only errno tells an error apart
+    let get = |group: bool| unsafe {
+        clear_errno();
+        let priority = if group {
+            libc::getpriority(libc::PRIO_PGRP, pid as libc::id_t)
+        } else {
+            libc::getpriority(libc::PRIO_PROCESS, pid as libc::id_t)
+        };
+        match (priority, io::Error::last_os_error()) {
+            (-1, ref e) if e.raw_os_error().unwrap_or(0) != 0 => None,
+            (priority, _) => Some(priority),
+        }
+    };
+    get(true)
+        .or_else(|| get(false))
+        .ok_or_else(io::Error::last_os_error)
+}
+
+#[cfg(unix)]
+fn set_priority(pid: u32, priority: i32) -> io::Result<()> {
+    let done = unsafe {
+        libc::setpriority(libc::PRIO_PGRP, pid as libc::id_t, priority) == 0
+            || libc::setpriority(libc::PRIO_PROCESS, pid as libc::id_t, priority) == 0
+    };
+    match done {
+        true => Ok(()),
+        false => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn clear_errno() {
+    *libc::__errno_location() = 0;
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn clear_errno() {
+    *libc::__error() = 0;
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "macos"))))]
+unsafe fn clear_errno() {}
+
+#[cfg(not(unix))]
+fn unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "pausing processes is not supported")
+}
+
+#[cfg(not(unix))]
+fn signal_process(_pid: u32, _paused: bool) -> io::Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(not(unix))]
+fn get_priority(_pid: u32) -> io::Result<i32> {
+    Err(unsupported())
+}
+
+#[cfg(not(unix))]
+fn set_priority(_pid: u32, _priority: i32) -> io::Result<()> {
+    Err(unsupported())
+}
+
+/// CPU time counters, in clock ticks.
+#[derive(Debug)]
+struct CpuTimes {
+    total: u64,
+    busy: u64,
+    /// per process time of processes started by the provider
+    own: HashMap<u32, u64>,
+}
+
+impl CpuTimes {
+    /// Load of other processes since `last`, as a fraction of all CPUs.
+    fn load_since(&self, last: &CpuTimes) -> Option<f32> {
+        let total = self.total.checked_sub(last.total).filter(|&t| t > 0)?;
+        let busy = self.busy.saturating_sub(last.busy);
+        // processes started in between spent all their time within the interval
+        let own: u64 = self
+            .own
+            .iter()
+            .map(|(pid, ticks)| ticks.saturating_sub(last.own.get(pid).cloned().unwrap_or(0)))
+            .sum();
+        Some(busy.saturating_sub(own) as f32 / total as f32)
+    }
+}
+
+/// Total and busy time from the aggregate line of `/proc/stat`.
+fn parse_stat(stat: &str) -> Option<(u64, u64)> {
+    let line = stat.lines().find(|line| line.starts_with("cpu "))?;
+    let values: Vec<u64> = line
+        .split_whitespace()
+        .skip(1)
+        .take(8)
+        .map(|v| v.parse().ok())
+        .collect::<Option<_>>()?;
+    if values.len() < 5 {
+        return None;
+    }
+    // guest time is already included in user time
+    let total: u64 = values.iter().sum();
+    let idle = values[3] + values[4];
+    Some((total, total - idle))
+}
+
+/// Parent pid and user + system time, including waited-for children, from `/proc/<pid>/stat`.
+fn parse_pid_stat(stat: &str) -> Option<(u32, u64)> {
+    // the command name may contain spaces and parentheses
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let ppid = fields.get(1)?.parse().ok()?;
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    // time of waited-for children, so that work done by short-lived processes counts
+    let cutime: u64 = fields.get(13)?.parse().ok()?;
+    let cstime: u64 = fields.get(14)?.parse().ok()?;
+    Some((ppid, utime + stime + cutime + cstime))
+}
+
+#[cfg(target_os = "linux")]
+fn read_cpu_times(containers: &HashSet<String>) -> io::Result<CpuTimes> {
+    use std::fs;
+
+    let (total, busy) = parse_stat(&fs::read_to_string("/proc/stat")?)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid /proc/stat"))?;
+
+    let mut processes = Vec::new();
+    let mut own = HashSet::new();
+    own.insert(std::process::id());
+    for entry in fs::read_dir("/proc")? {
+        let pid: u32 = match entry?.file_name().to_str().and_then(|n| n.parse().ok()) {
+            Some(pid) => pid,
+            None => continue,
+        };
+        // processes may exit while being read
+        let (ppid, ticks) = match fs::read_to_string(format!("/proc/{}/stat", pid))
+            .ok()
+            .and_then(|stat| parse_pid_stat(&stat))
+        {
+            Some(v) => v,
+            None => continue,
+        };
+        if !containers.is_empty() {
+            let cgroup = fs::read_to_string(format!("/proc/{}/cgroup", pid)).unwrap_or_default();
+            if containers.iter().any(|id| cgroup.contains(id.as_str())) {
+                own.insert(pid);
+            }
+        }
+        processes.push((pid, ppid, ticks));
+    }
+
+    // descendants of the provider and of container processes
+    loop {
+        let len = own.len();
+        for &(pid, ppid, _) in &processes {
+            if own.contains(&ppid) {
+                own.insert(pid);
+            }
+        }
+        if own.len() == len {
+            break;
+        }
+    }
+
+    Ok(CpuTimes {
+        total,
+        busy,
+        own: processes
+            .into_iter()
+            .filter(|(pid, _, _)| own.contains(pid))
+            .map(|(pid, _, ticks)| (pid, ticks))
+            .collect(),
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_cpu_times(_containers: &HashSet<String>) -> io::Result<CpuTimes> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "cpu load sampling is not supported",
+    ))
+}
+
+/// Seconds since the last user input, from a command printing milliseconds.
+fn input_idle(command: &str) -> Option<u64> {
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .output()
+        .map_err(|e| debug!("Cannot run {}: {}", command, e))
+        .ok()?;
+    if !output.status.success() {
+        debug!("{} failed: {}", command, output.status);
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse::<u64>()
+        .map(|ms| ms / 1000)
+        .map_err(|e| debug!("Invalid output of {}: {}", command, e))
+        .ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_proc_stat() {
+        let stat = "cpu  100 10 50 800 40 0 0 0 0 0\ncpu0 50 5 25 400 20 0 0 0 0 0\n";
+        assert_eq!(parse_stat(stat), Some((1000, 160)));
+        assert_eq!(parse_stat("intr 1 2 3\n"), None);
+    }
+
+    #[test]
+    fn parse_proc_pid_stat() {
+        let stat = "1234 (my (odd) cmd) S 1 1234 1234 0 -1 4194560 100 0 0 0 25 7 3 2 20 0 1";
+        assert_eq!(parse_pid_stat(stat), Some((1, 37)));
+        assert_eq!(
+            parse_pid_stat("1234 (cmd) S 1 1234 1234 0 -1 0 100 0 0 0 25 7"),
+            None
+        );
+    }
+
+    #[test]
+    fn load_excludes_own_processes() {
+        let last = CpuTimes {
+            total: 1000,
+            busy: 100,
+            own: vec![(10, 50)].into_iter().collect(),
+        };
+        let now = CpuTimes {
+            total: 2000,
+            busy: 800,
+            own: vec![(10, 350), (11, 100)].into_iter().collect(),
+        };
+        assert_eq!(now.load_since(&last), Some(0.3));
+        assert_eq!(last.load_since(&last), None);
+    }
+
+    #[test]
+    fn busy_policy() {
+        let config = IdleConfig {
+            input_idle_command: Some("xprintidle".into()),
+            ..IdleConfig::default()
+        };
+        assert!(!config.is_busy(None, None));
+        assert!(!config.is_busy(Some(0.1), Some(600)));
+        assert!(config.is_busy(Some(0.5), Some(600)));
+        assert!(config.is_busy(Some(0.1), Some(10)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_restore_priority_of_exited_process() {
+        let mut paused = PausedProcesses::default();
+        paused.priorities.insert(i32::MAX as u32, 0);
+        assert!(paused.env_status(EnvStatus::Working) == EnvStatus::Paused);
+        assert!(paused.env_status(EnvStatus::Ready) == EnvStatus::Ready);
+
+        paused.set_paused(
+            Vec::new(),
+            SetPaused {
+                paused: false,
+                mode: PauseMode::Renice,
+            },
+        );
+        assert!(paused.env_status(EnvStatus::Working) == EnvStatus::Working);
+    }
+}
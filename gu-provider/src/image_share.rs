@@ -0,0 +1,253 @@
+//! Sharing of cached images with other providers.
+//!
+//! With `shareImages` enabled, images from the provider cache are served over http on
+//! `imagePort`. The hub asks providers which images they share and passes those urls to
+//! other providers deploying the same image, so not every chunk comes from the origin.
+//!
+//! Urls carry a token generated at startup and handed out only to nodes allowed to
+//! access the provider, so the server does not serve the cache to anyone who finds it.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use actix::prelude::*;
+use actix_web::{fs::NamedFile, server, App, HttpRequest, HttpResponse};
+use futures::prelude::*;
+use log::{debug, error, info};
+
+use gu_actix::prelude::*;
+use gu_hdman::image_manager;
+use gu_model::envman::{ListSharedImages, SharedImages};
+use gu_model::hash::{ContentHasher, ParsedHash};
+use gu_net::rpc::{PublicMessage, RemotingContext, RemotingSystemService, WithSender};
+use gu_persist::config::{ConfigManager, GetConfig};
+
+use crate::permission::{AccessLevel, PermissionConfig};
+use crate::server::ProviderConfig;
+
+/// Bounds of the chunk size accepted by the chunk hashes endpoint
+const MIN_CHUNK_SIZE: u32 = 64 * 1024;
+const MAX_CHUNK_SIZE: u32 = 64 * 1024 * 1024;
+
+#[derive(Default)]
+pub struct ImageShare {
+    /// port of the running image server
+    port: Option<u16>,
+    /// token required in image urls
+    token: String,
+}
+
+impl Actor for ImageShare {
+    type Context = RemotingContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.bind_with_sender::<ListSharedImages>(ListSharedImages::ID);
+
+        ConfigManager::from_registry()
+            .send(GetConfig::new())
+            .flatten_fut()
+            .map_err(|e| error!("Cannot read provider config: {}", e))
+            .into_actor(self)
+            .and_then(|config: Arc<ProviderConfig>, act, _ctx| {
+                if config.share_images {
+                    act.token = uuid::Uuid::new_v4().to_simple().to_string();
+                    act.port = start_server(config.image_port, act.token.clone());
+                }
+                fut::ok(())
+            })
+            .spawn(ctx);
+    }
+}
+
+impl RemotingSystemService for ImageShare {}
+
+struct ServerState {
+    token: String,
+    /// chunk hashes by image hash and chunk size; cached images never change
+    chunk_hashes: Mutex<HashMap<(String, u32), Arc<Vec<String>>>>,
+}
+
+/// Path of the cached image requested with a valid token.
+fn requested_image(r: &HttpRequest<Arc<ServerState>>) -> actix_web::Result<(String, PathBuf)> {
+    if r.match_info().get("token") != Some(r.state().token.as_str()) {
+        return Err(actix_web::error::ErrorForbidden("invalid token"));
+    }
+
+    r.match_info()
+        .get("hash")
+        .and_then(|name| ParsedHash::from_file_name(name).ok())
+        .and_then(|h| h.to_hash_str().ok())
+        .and_then(|hash| image_manager::cached_image(&hash).map(|path| (hash, path)))
+        .ok_or_else(|| actix_web::error::ErrorNotFound("image not found"))
+}
+
+/// Serves `/images/<token>/<ALGO>---<hex>` with range requests support.
+fn serve_image(r: &HttpRequest<Arc<ServerState>>) -> actix_web::Result<NamedFile> {
+    let (_, path) = requested_image(r)?;
+    Ok(NamedFile::open(path)?)
+}
+
+/// Serves `/images/<token>/<ALGO>---<hex>/chunks/<chunk size>`: json list of hashes of
+/// the image chunks, computed with the algorithm of the image hash.
+fn serve_chunk_hashes(
+    r: &HttpRequest<Arc<ServerState>>,
+) -> Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>> {
+    let chunk_size = match r.match_info().query::<u32>("size") {
+        Ok(size) if size >= MIN_CHUNK_SIZE && size <= MAX_CHUNK_SIZE => size,
+        _ => {
+            return Box::new(futures::future::err(actix_web::error::ErrorBadRequest(
+                "invalid chunk size",
+            )))
+        }
+    };
+    let (hash, path) = match requested_image(r) {
+        Ok(image) => image,
+        Err(e) => return Box::new(futures::future::err(e)),
+    };
+
+    let state = r.state().clone();
+    let key = (hash, chunk_size);
+    if let Some(hashes) = state.chunk_hashes.lock().unwrap().get(&key) {
+        return Box::new(futures::future::ok(HttpResponse::Ok().json(&**hashes)));
+    }
+
+    Box::new(
+        gu_hdman::download::cpu_pool()
+            .spawn_fn(move || {
+                let algo = ParsedHash::from_hash_bytes(key.0.as_bytes())
+                    .and_then(|h| h.algo_name().map(ToString::to_string))
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+                let file = fs::File::open(path)?;
+                let hashes = Arc::new(chunk_hashes(file, &algo, chunk_size as usize)?);
+                state
+                    .chunk_hashes
+                    .lock()
+                    .unwrap()
+                    .insert(key, hashes.clone());
+                Ok(hashes)
+            })
+            .map(|hashes: Arc<Vec<String>>| HttpResponse::Ok().json(&*hashes))
+            .map_err(|e: io::Error| {
+                debug!("Cannot hash image chunks: {}", e);
+                actix_web::error::ErrorInternalServerError(e)
+            }),
+    )
+}
+
+/// Hashes (e.g. `SHA1:<hex>`) of consecutive `chunk_size` chunks of the input.
+fn chunk_hashes<R: Read>(mut input: R, algo: &str, chunk_size: usize) -> io::Result<Vec<String>> {
+    let mut buf = vec![0u8; chunk_size];
+    let mut hashes = Vec::new();
+
+    loop {
+        let mut len = 0;
+        while len < chunk_size {
+            match input.read(&mut buf[len..])? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        if len == 0 {
+            return Ok(hashes);
+        }
+
+        let mut hasher = ContentHasher::from_algo(algo)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        hasher.update(&buf[..len]);
+        hashes.push(hasher.finish());
+        if len < chunk_size {
+            return Ok(hashes);
+        }
+    }
+}
+
+fn start_server(port: u16, token: String) -> Option<u16> {
+    let state = Arc::new(ServerState {
+        token,
+        chunk_hashes: Mutex::new(HashMap::new()),
+    });
+    let server = server::new(move || {
+        App::with_state(state.clone())
+            .resource("/images/{token}/{hash}", |r| {
+                r.get().f(serve_image);
+                r.head().f(serve_image);
+            })
+            .resource("/images/{token}/{hash}/chunks/{size}", |r| {
+                r.get().f(serve_chunk_hashes);
+            })
+    });
+
+    match server.bind(("0.0.0.0", port)) {
+        Ok(server) => {
+            server.start();
+            info!("Sharing cached images on port {}", port);
+            Some(port)
+        }
+        Err(e) => {
+            error!("Cannot share images on port {}: {}", port, e);
+            None
+        }
+    }
+}
+
+/// Shared images are listed only to nodes allowed to access the provider.
+impl Handler<WithSender<ListSharedImages>> for ImageShare {
+    type Result = ActorResponse<ImageShare, SharedImages, ()>;
+
+    fn handle(
+        &mut self,
+        msg: WithSender<ListSharedImages>,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let sender = msg.sender;
+
+        ActorResponse::r#async(
+            ConfigManager::from_registry()
+                .send(GetConfig::new())
+                .flatten_fut()
+                .map_err(|e| error!("cannot read permissions: {}", e))
+                .into_actor(self)
+                .and_then(move |config: Arc<PermissionConfig>, act, _ctx| {
+                    if config.access_level(&sender) == AccessLevel::NoAccess {
+                        debug!("image list query from node {:?} rejected", sender);
+                        return fut::err(());
+                    }
+                    fut::ok(match act.port {
+                        Some(port) => SharedImages {
+                            port: Some(port),
+                            token: Some(act.token.clone()),
+                            hashes: image_manager::cached_images(),
+                        },
+                        None => SharedImages::default(),
+                    })
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_chunk_hashes() {
+        let data: Vec<u8> = (0..10u8).collect();
+
+        let hashes = chunk_hashes(&data[..], "SHA1", 4).unwrap();
+        assert_eq!(hashes.len(), 3);
+        for (hash, chunk) in hashes.iter().zip(data.chunks(4)) {
+            let mut hasher = ContentHasher::from_algo("SHA1").unwrap();
+            hasher.update(chunk);
+            assert_eq!(hash, &hasher.finish());
+        }
+
+        assert_eq!(chunk_hashes(&data[..], "SHA1", 5).unwrap().len(), 2);
+        assert!(chunk_hashes(&[][..], "SHA1", 4).unwrap().is_empty());
+        assert!(chunk_hashes(&data[..], "MD5", 4).is_err());
+    }
+}
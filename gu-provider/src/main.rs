@@ -5,6 +5,7 @@ extern crate futures;
 
 use gu_base::*;
 
+mod beneath;
 mod connect;
 mod deployment;
 pub mod envman;
@@ -12,8 +13,13 @@ mod fchain;
 #[cfg(feature = "env-hd")]
 mod hdman;
 mod id;
+mod idle;
+mod image_share;
 mod permission;
 mod provision;
+mod remote_config;
+mod remote_plugin;
+mod sandbox;
 mod server;
 mod status;
 mod sync_exec;
@@ -27,7 +27,7 @@ use futures::future;
 
 #[derive(Serialize_repr, Deserialize_repr, Clone, PartialEq, Eq, Hash, Copy)]
 #[repr(u8)]
-enum AccessLevel {
+pub(crate) enum AccessLevel {
     NoAccess = 0,
     Sandbox = 1,
     FullAccess = 2,
@@ -50,7 +50,7 @@ enum Permission {
 
 #[derive(Serialize, Deserialize, Default, Clone)]
 #[serde(rename_all = "camelCase")]
-struct PermissionConfig {
+pub(crate) struct PermissionConfig {
     allow_any: AccessLevel,
     permissions: HashSet<Permission>,
     #[serde(default)]
@@ -69,7 +69,19 @@ impl PermissionConfig {
         }
     }
 
-    fn highest_permission(&self, node_id: &NodeId) -> AccessLevel {
+    /// Certificate fingerprints pinned for saved hubs, by hub address.
+    pub(crate) fn hub_pins(&self) -> HashMap<SocketAddr, String> {
+        self.saved_hub_desc
+            .values()
+            .filter_map(|hub| {
+                hub.cert_fingerprint
+                    .as_ref()
+                    .map(|fingerprint| (hub.address, fingerprint.clone()))
+            })
+            .collect()
+    }
+
+    pub(crate) fn highest_permission(&self, node_id: &NodeId) -> AccessLevel {
         self.permissions
             .iter()
             .fold(AccessLevel::NoAccess, |h, x| match x {
@@ -84,6 +96,16 @@ impl PermissionConfig {
             })
     }
 
+    /// Access level of the node, at least the one allowed to any node.
+    pub(crate) fn access_level(&self, node_id: &NodeId) -> AccessLevel {
+        let level = self.highest_permission(node_id);
+        if self.allow_any as i32 > level as i32 {
+            self.allow_any
+        } else {
+            level
+        }
+    }
+
     fn is_managed_by(&self, node_id: &NodeId) -> bool {
         self.highest_permission(node_id) != AccessLevel::NoAccess
     }
@@ -175,6 +197,7 @@ fn set_node_status_future(
     node_or_auto: NodeOrAuto,
     ip: Option<SocketAddr>,
     host_name: Option<String>,
+    cert_fingerprint: Option<String>,
 ) -> impl Future<Item = (), Error = ()> {
     let node_or_auto_copy = node_or_auto.clone();
     let config_manager = ConfigManager::from_registry();
@@ -194,12 +217,19 @@ fn set_node_status_future(
                     });
                     if access_level != AccessLevel::NoAccess {
                         if host_name.is_some() && ip.is_some() {
+                            let cert_fingerprint = cert_fingerprint.or_else(|| {
+                                new_config
+                                    .saved_hub_desc
+                                    .get(&n)
+                                    .and_then(|hub| hub.cert_fingerprint.clone())
+                            });
                             new_config.saved_hub_desc.insert(
                                 n.clone(),
                                 HubDesc {
                                     address: ip.unwrap(),
                                     host_name: host_name.unwrap(),
                                     node_id: n.clone(),
+                                    cert_fingerprint,
                                 },
                             );
                         }
@@ -462,6 +492,7 @@ impl Module for PermissionModule {
                             node_or_auto_copy,
                             ip_copy,
                             host_name_copy,
+                            None,
                         )
                         .then(|_| Ok(System::current().stop())),
                     )
@@ -500,6 +531,8 @@ struct IpHostNameAccessLevel {
     pub address: Option<SocketAddr>,
     pub host_name: Option<String>,
     pub access_level: Option<AccessLevel>,
+    #[serde(default)]
+    pub cert_fingerprint: Option<String>,
 }
 
 fn config_methods<S: 'static>(scope: Scope<S>) -> Scope<S> {
@@ -547,6 +580,7 @@ fn config_methods<S: 'static>(scope: Scope<S>) -> Scope<S> {
                                 node_or_auto,
                                 hub.address,
                                 hub.host_name.clone(),
+                                hub.cert_fingerprint.clone(),
                             )
                             .map_err(|_| {
                                 actix_web::error::ErrorInternalServerError("cannot delete node")
@@ -5,25 +5,27 @@ use std::{
 };
 
 use actix_web::client::ClientResponse;
-use actix_web::http::header;
+use actix_web::http::{header, Method};
 use actix_web::HttpMessage;
 use futures::{future, prelude::*};
 use log::{debug, info};
 
 use gu_actix::{async_result, async_try};
 use gu_base::files::read_async;
-use gu_base::files::{untgz_async, write_async};
+use gu_base::files::{read_file_async, untgz_async, write_async, write_file_async};
+use gu_downloader::connectors;
 use gu_model::envman::ResourceFormat;
 
+use crate::beneath;
+
 pub fn download_step(
     url: &str,
     output_path: PathBuf,
     format: ResourceFormat,
 ) -> impl Future<Item = (), Error = String> {
-    use actix_web::client;
     use tar_async::decode::full;
 
-    let client_request = async_try!(client::ClientRequest::get(url)
+    let client_request = async_try!(connectors::request(Method::GET, url)
         .finish()
         .map_err(|e| format!("{}", e)));
 
@@ -80,13 +82,70 @@ pub fn download_step(
     )
 }
 
+/// Like `download_step`, but `path` is resolved below `dir` without following
+/// symlinks, and so are the paths of extracted tar entries.
+pub fn download_step_beneath(
+    url: &str,
+    dir: fs::File,
+    path: PathBuf,
+    format: ResourceFormat,
+) -> Box<dyn Future<Item = (), Error = String>> {
+    use tar_async::decode::full;
+
+    let response = match connectors::request(Method::GET, url).finish() {
+        Ok(request) => request
+            .send()
+            .map_err(|e| format!("send download request: {}", e)),
+        Err(e) => return Box::new(future::err(e.to_string())),
+    };
+    let io_err = |e: std::io::Error| format!("io: {}", e);
+
+    match format {
+        ResourceFormat::Raw => {
+            let file = match beneath::open_file(&dir, &path, true) {
+                Ok(file) => file,
+                Err(e) => return Box::new(future::err(io_err(e))),
+            };
+            Box::new(response.and_then(|resp| write_file_async(resp.payload(), file)))
+        }
+        ResourceFormat::Tar => {
+            let out_dir = match beneath::open_dir(&dir, &path, true) {
+                Ok(out_dir) => out_dir,
+                Err(e) => return Box::new(future::err(io_err(e))),
+            };
+            Box::new(response.and_then(move |resp| {
+                full::decode_tar(resp.payload())
+                    .map_err(|e| format!("tar: {}", e))
+                    .for_each(move |entry| {
+                        let entry_type = entry.header().entry_type().clone();
+                        let path: PathBuf = async_try!(entry
+                            .header()
+                            .path()
+                            .map_err(|e| format!("payload err: {}", e)))
+                        .to_owned();
+
+                        if entry_type.is_dir() {
+                            async_try!(beneath::open_dir(&out_dir, &path, true).map_err(io_err));
+                            future::Either::B(future::ok(()))
+                        } else if entry_type.is_file() {
+                            let file = async_try!(
+                                beneath::open_file(&out_dir, &path, true).map_err(io_err)
+                            );
+                            async_result!(write_file_async(entry, file))
+                        } else {
+                            future::Either::B(future::ok(()))
+                        }
+                    })
+            }))
+        }
+    }
+}
+
 pub fn upload_step(
     url: &str,
     input_path: PathBuf,
     format: ResourceFormat,
 ) -> impl Future<Item = String, Error = String> {
-    use actix_web::{client, error::ErrorInternalServerError};
-
     debug!(
         "streaming from {:?} to {} format: {:?}",
         &input_path, url, format
@@ -95,10 +154,42 @@ pub fn upload_step(
         ResourceFormat::Tar => Box::new(stream_tar(input_path)),
         ResourceFormat::Raw => Box::new(stream_raw(input_path)),
     };
+    upload_stream(url, source_stream)
+}
+
+/// Like `upload_step`, but `path` is resolved below `dir` without following symlinks;
+/// symlinks in uploaded directories are stored as links.
+pub fn upload_step_beneath(
+    url: &str,
+    dir: fs::File,
+    path: PathBuf,
+    format: ResourceFormat,
+) -> Box<dyn Future<Item = String, Error = String>> {
+    debug!("streaming from {:?} to {} format: {:?}", &path, url, format);
+    let source_stream: Box<dyn Stream<Item = bytes::Bytes, Error = String>> = match format {
+        ResourceFormat::Tar => match beneath::open_dir(&dir, &path, false) {
+            Ok(dir) => Box::new(stream_tar_beneath(dir)),
+            Err(e) => return Box::new(future::err(format!("io: {}", e))),
+        },
+        ResourceFormat::Raw => match beneath::open_file(&dir, &path, false) {
+            Ok(file) => Box::new(read_file_async(file)),
+            Err(e) => return Box::new(future::err(format!("io: {}", e))),
+        },
+    };
+    Box::new(upload_stream(url, source_stream))
+}
+
+fn upload_stream(
+    url: &str,
+    source_stream: Box<dyn Stream<Item = bytes::Bytes, Error = String>>,
+) -> impl Future<Item = String, Error = String> {
+    use actix_web::error::ErrorInternalServerError;
+
     let url_desc = url.to_owned();
 
     future::result(
-        client::put(url).streaming(source_stream.map_err(|x| ErrorInternalServerError(x))),
+        connectors::request(Method::PUT, url)
+            .streaming(source_stream.map_err(|x| ErrorInternalServerError(x))),
     )
     .map_err(|e| e.to_string())
     .and_then(|req| req.send().map_err(|e| e.to_string()))
@@ -134,6 +225,27 @@ pub fn stream_tar(input_path: PathBuf) -> impl Stream<Item = bytes::Bytes, Error
     })
 }
 
+fn stream_tar_beneath(dir: fs::File) -> impl Stream<Item = bytes::Bytes, Error = String> {
+    use gu_actix::pipe;
+    use std::thread;
+    use tar::Builder;
+
+    let (mut tx, rx) = pipe::sync_to_async(5);
+
+    thread::spawn(move || {
+        let result = {
+            let mut builder = Builder::new(&mut tx);
+            beneath::append_dir(&mut builder, &dir).and_then(|_| builder.finish())
+        };
+        // the error ends the stream, so the upload fails
+        if let Err(e) = result {
+            let _ = tx.send(Err(e));
+        }
+    });
+
+    rx.map_err(|e| e.to_string())
+}
+
 fn stream_raw(input_path: PathBuf) -> impl Stream<Item = bytes::Bytes, Error = String> {
     read_async(input_path)
 }
@@ -162,7 +274,6 @@ where
 }
 
 // TODO: support redirect
-// TODO: support https
 #[allow(unused)]
 pub fn download(
     url: &str,
@@ -170,14 +281,13 @@ pub fn download(
     use_cache: bool,
 ) -> impl Future<Item = (), Error = String> {
     info!("downloading from {} to {:?}", url, &output_path);
-    use actix_web::client;
 
     if use_cache && output_path.exists() {
         info!("using cached file {:?}", &output_path);
         return future::Either::A(future::ok(()));
     }
 
-    let client_request = client::ClientRequest::get(url).finish().unwrap();
+    let client_request = connectors::request(Method::GET, url).finish().unwrap();
 
     future::Either::B(
         client_request
@@ -247,9 +357,7 @@ where
     F: Fn(ClientResponse) -> S + 'static,
     S: Stream<Item = bytes::Bytes, Error = String> + 'static,
 {
-    use actix_web::client;
-
-    let client_request = client::ClientRequest::get(url).finish().unwrap();
+    let client_request = connectors::request(Method::GET, url).finish().unwrap();
 
     client_request
         .send()
@@ -0,0 +1,158 @@
+//! Remote configuration of the provider by hubs with full access.
+//!
+
+use std::sync::Arc;
+
+use actix::prelude::*;
+use futures::{future, prelude::*};
+use log::info;
+use serde_json::Value as JsonValue;
+
+use gu_actix::prelude::*;
+use gu_model::config::{ConfigError, GetConfig, SetConfig};
+use gu_net::rpc::{PublicMessage, RemotingContext, RemotingSystemService, WithSender};
+use gu_net::NodeId;
+use gu_persist::config::{self, ConfigManager, ConfigSection, HasSectionId};
+
+use crate::permission::{AccessLevel, PermissionConfig};
+use crate::server::ProviderConfig;
+
+#[derive(Default)]
+pub struct RemoteConfig;
+
+impl Actor for RemoteConfig {
+    type Context = RemotingContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.bind_with_sender::<GetConfig>(GetConfig::ID);
+        ctx.bind_with_sender::<SetConfig>(SetConfig::ID);
+    }
+}
+
+impl RemotingSystemService for RemoteConfig {}
+
+pub(crate) fn check_access(node_id: NodeId) -> impl Future<Item = (), Error = ConfigError> {
+    ConfigManager::from_registry()
+        .send(config::GetConfig::new())
+        .flatten_fut()
+        .map_err(|e| ConfigError::Error(e.to_string()))
+        .and_then(move |c: Arc<PermissionConfig>| {
+            if c.access_level(&node_id) == AccessLevel::FullAccess {
+                Ok(())
+            } else {
+                Err(ConfigError::AccessDenied)
+            }
+        })
+}
+
+type ConfigFuture<T> = Box<dyn Future<Item = T, Error = ConfigError>>;
+
+/// Config section managed remotely.
+struct RemoteSection {
+    id: &'static str,
+    get: fn() -> ConfigFuture<JsonValue>,
+    set: fn(JsonValue) -> ConfigFuture<()>,
+}
+
+/// Sections listed in `REMOTE_SECTIONS`.
+const SECTIONS: &[RemoteSection] = &[
+    RemoteSection {
+        id: PermissionConfig::SECTION_ID,
+        get: get_section::<PermissionConfig>,
+        set: set_section::<PermissionConfig>,
+    },
+    RemoteSection {
+        id: ProviderConfig::SECTION_ID,
+        get: get_section::<ProviderConfig>,
+        set: set_section::<ProviderConfig>,
+    },
+];
+
+fn remote_section(id: &str) -> Result<&'static RemoteSection, ConfigError> {
+    SECTIONS
+        .iter()
+        .find(|section| section.id == id)
+        .ok_or_else(|| ConfigError::UnknownSection(id.to_string()))
+}
+
+fn get_section<T>() -> ConfigFuture<JsonValue>
+where
+    T: ConfigSection + Send + Sync + 'static,
+{
+    Box::new(
+        ConfigManager::from_registry()
+            .send(config::GetConfig::<T>::new())
+            .flatten_fut()
+            .and_then(|c| c.to_json())
+            .map_err(|e| ConfigError::Error(e.to_string())),
+    )
+}
+
+fn set_section<T>(value: JsonValue) -> ConfigFuture<()>
+where
+    T: ConfigSection + Send + Sync + 'static,
+{
+    Box::new(
+        future::result(T::from_json(value))
+            .map_err(|e| ConfigError::InvalidValue(e.to_string()))
+            .and_then(|c| {
+                ConfigManager::from_registry()
+                    .send(config::SetConfig::new(c))
+                    .flatten_fut()
+                    .map_err(|e| ConfigError::Error(e.to_string()))
+            }),
+    )
+}
+
+impl Handler<WithSender<GetConfig>> for RemoteConfig {
+    type Result = ActorResponse<Self, JsonValue, ConfigError>;
+
+    fn handle(&mut self, msg: WithSender<GetConfig>, _ctx: &mut Self::Context) -> Self::Result {
+        let section = msg.body.section;
+
+        ActorResponse::r#async(
+            check_access(msg.sender)
+                .and_then(move |()| remote_section(&section))
+                .and_then(|section| (section.get)())
+                .into_actor(self),
+        )
+    }
+}
+
+impl Handler<WithSender<SetConfig>> for RemoteConfig {
+    type Result = ActorResponse<Self, (), ConfigError>;
+
+    fn handle(&mut self, msg: WithSender<SetConfig>, _ctx: &mut Self::Context) -> Self::Result {
+        let sender = msg.sender;
+        let SetConfig { section, value } = msg.body;
+
+        ActorResponse::r#async(
+            check_access(sender)
+                .and_then(move |()| remote_section(&section))
+                .and_then(move |section| {
+                    info!("config section {} changed by {:?}", section.id, sender);
+                    (section.set)(value)
+                })
+                .into_actor(self),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gu_model::config::REMOTE_SECTIONS;
+
+    #[test]
+    fn test_remote_sections() {
+        let ids: Vec<&str> = SECTIONS.iter().map(|section| section.id).collect();
+        assert_eq!(ids, REMOTE_SECTIONS);
+        for id in REMOTE_SECTIONS {
+            assert!(remote_section(id).is_ok());
+        }
+        match remote_section("unknown") {
+            Err(ConfigError::UnknownSection(id)) => assert_eq!(id, "unknown"),
+            _ => panic!("unknown section expected"),
+        }
+    }
+}
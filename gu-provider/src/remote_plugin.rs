@@ -0,0 +1,261 @@
+//! Provider parts of hub plugins.
+//!
+//! Hubs with full access push plugin packages to the provider. A package is
+//! downloaded from the hub repo, verified and unpacked into the work dir, and
+//! its execution environments are started without restarting the provider.
+//!
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use actix::prelude::*;
+use futures::prelude::*;
+use log::{info, warn};
+
+use gu_hdman::image_manager;
+use gu_model::{
+    config::ConfigError,
+    envman::Image,
+    hash::{self, ContentChecker},
+    plugin::{InstallProviderPlugin, PluginManifest, ProviderPluginError, RetireProviderPlugin},
+    Version,
+};
+use gu_net::rpc::{PublicMessage, RemotingContext, RemotingSystemService, WithSender};
+use gu_persist::config::ConfigModule;
+
+use crate::exec_plugin::{PluginMan, Retire};
+use crate::remote_config::check_access;
+
+/// Hub plugins are installed again on every hub connection, so the dir is
+/// cleared on startup.
+const PLUGINS_DIR: &str = "hub-plugins";
+
+struct InstalledPlugin {
+    hash: String,
+    envs: Vec<Addr<PluginMan>>,
+}
+
+#[derive(Default)]
+pub struct RemotePlugins {
+    plugins: HashMap<String, InstalledPlugin>,
+}
+
+impl Actor for RemotePlugins {
+    type Context = RemotingContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let _ = fs::remove_dir_all(ConfigModule::new().work_dir().join(PLUGINS_DIR));
+
+        ctx.bind_with_sender::<InstallProviderPlugin>(InstallProviderPlugin::ID);
+        ctx.bind_with_sender::<RetireProviderPlugin>(RetireProviderPlugin::ID);
+    }
+}
+
+impl RemotingSystemService for RemotePlugins {}
+
+impl RemotePlugins {
+    fn install(
+        &mut self,
+        id: String,
+        hash: String,
+        package: &Path,
+    ) -> Result<(), ProviderPluginError> {
+        let dir = plugin_dir(&id)?;
+        verify(package, &hash)?;
+        let (tmp_dir, manifest) = unpack(package, &dir)?;
+
+        if manifest.id != id {
+            let _ = fs::remove_dir_all(&tmp_dir);
+            return Err(ProviderPluginError::InvalidPackage(format!(
+                "package of plugin {}, expected {}",
+                manifest.id, id
+            )));
+        }
+        let provider_version =
+            Version::parse(env!("CARGO_PKG_VERSION")).expect("invalid crate version");
+        if let Err(e) = manifest.check_provider(&provider_version) {
+            let _ = fs::remove_dir_all(&tmp_dir);
+            return Err(e);
+        }
+
+        self.stop(&id);
+        let _ = fs::remove_dir_all(&dir);
+        fs::rename(&tmp_dir, &dir).map_err(io_error)?;
+
+        let config = ConfigModule::new();
+        let envs = manifest
+            .provider
+            .into_iter()
+            .flat_map(|provider| provider.simple_exec_env)
+            .map(|spec| {
+                info!("starting {} environment of plugin {}", spec.code, id);
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::PermissionsExt;
+
+                    let exec = dir.join(&spec.exec);
+                    if let Err(e) = fs::set_permissions(&exec, fs::Permissions::from_mode(0o755)) {
+                        warn!("cannot make {:?} executable: {}", exec, e);
+                    }
+                }
+                PluginMan::from_spec(&dir, spec, &config).start()
+            })
+            .collect();
+
+        self.plugins.insert(id, InstalledPlugin { hash, envs });
+        Ok(())
+    }
+
+    fn stop(&mut self, id: &str) {
+        if let Some(plugin) = self.plugins.remove(id) {
+            info!("stopping plugin {}", id);
+            for env in plugin.envs {
+                env.do_send(Retire);
+            }
+        }
+    }
+}
+
+fn plugin_dir(id: &str) -> Result<PathBuf, ProviderPluginError> {
+    if id.is_empty() || id.starts_with('.') || id.contains(|c| c == '/' || c == '\\') {
+        return Err(ProviderPluginError::InvalidPackage(format!(
+            "invalid plugin id: {}",
+            id
+        )));
+    }
+    Ok(ConfigModule::new()
+        .work_dir()
+        .join(PLUGINS_DIR)
+        .join(format!("{}.gu-plugin", id)))
+}
+
+fn io_error(e: io::Error) -> ProviderPluginError {
+    ProviderPluginError::Error(e.to_string())
+}
+
+fn access_error(e: ConfigError) -> ProviderPluginError {
+    match e {
+        ConfigError::AccessDenied => ProviderPluginError::AccessDenied,
+        e => ProviderPluginError::Error(e.to_string()),
+    }
+}
+
+/// Checks the downloaded package, a corrupted one is removed from the image cache.
+fn verify(package: &Path, hash: &str) -> Result<(), ProviderPluginError> {
+    let mut checker =
+        hash::checker(hash).map_err(|e| ProviderPluginError::InvalidPackage(e.to_string()))?;
+    let mut file = fs::File::open(package).map_err(io_error)?;
+    let mut buf = [0u8; 8192];
+    loop {
+        match file.read(&mut buf).map_err(io_error)? {
+            0 => break,
+            n => checker.update(&buf[..n]),
+        }
+    }
+
+    if checker.verify() {
+        Ok(())
+    } else {
+        let _ = fs::remove_file(package);
+        Err(ProviderPluginError::InvalidPackage("hash mismatch".into()))
+    }
+}
+
+/// Unpacks the package next to the plugin dir, returns the unpacked dir and the manifest.
+fn unpack(package: &Path, dir: &Path) -> Result<(PathBuf, PluginManifest), ProviderPluginError> {
+    let tmp_dir = dir.with_extension("tmp");
+    let _ = fs::remove_dir_all(&tmp_dir);
+    fs::create_dir_all(&tmp_dir).map_err(io_error)?;
+
+    let manifest = fs::File::open(package)
+        .and_then(|file| tar::Archive::new(file).unpack(&tmp_dir))
+        .map_err(|e| ProviderPluginError::InvalidPackage(e.to_string()))
+        .and_then(|()| {
+            let file = fs::File::open(tmp_dir.join("gu-plugin.json")).map_err(io_error)?;
+            serde_json::from_reader(file)
+                .map_err(|e| ProviderPluginError::InvalidPackage(e.to_string()))
+        });
+
+    match manifest {
+        Ok(manifest) => Ok((tmp_dir, manifest)),
+        Err(e) => {
+            let _ = fs::remove_dir_all(&tmp_dir);
+            Err(e)
+        }
+    }
+}
+
+impl Handler<WithSender<InstallProviderPlugin>> for RemotePlugins {
+    type Result = ActorResponse<Self, (), ProviderPluginError>;
+
+    fn handle(
+        &mut self,
+        msg: WithSender<InstallProviderPlugin>,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let sender = msg.sender;
+        let InstallProviderPlugin {
+            id,
+            version,
+            url,
+            hash,
+        } = msg.body;
+
+        ActorResponse::r#async(
+            check_access(sender)
+                .map_err(access_error)
+                .into_actor(self)
+                .and_then(move |(), act, _ctx| {
+                    if act.plugins.get(&id).map(|p| p.hash == hash) == Some(true) {
+                        return fut::Either::A(fut::ok(()));
+                    }
+                    info!("installing plugin {} {:?} from {:?}", id, version, sender);
+
+                    fut::Either::B(
+                        image_manager::image(Image {
+                            url,
+                            hash: hash.clone(),
+                            peers: Vec::new(),
+                        })
+                        .map_err(|e| ProviderPluginError::Error(e.to_string()))
+                        .into_actor(act)
+                        .and_then(move |package, act, _ctx| {
+                            let result = act.install(id.clone(), hash, &package);
+                            if let Err(ref e) = result {
+                                warn!("cannot install plugin {}: {}", id, e);
+                            }
+                            fut::result(result)
+                        }),
+                    )
+                }),
+        )
+    }
+}
+
+impl Handler<WithSender<RetireProviderPlugin>> for RemotePlugins {
+    type Result = ActorResponse<Self, (), ProviderPluginError>;
+
+    fn handle(
+        &mut self,
+        msg: WithSender<RetireProviderPlugin>,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let id = msg.body.id;
+
+        ActorResponse::r#async(
+            check_access(msg.sender)
+                .map_err(access_error)
+                .into_actor(self)
+                .and_then(move |(), act, _ctx| {
+                    act.stop(&id);
+                    fut::result(plugin_dir(&id).map(|dir| {
+                        let _ = fs::remove_dir_all(dir);
+                    }))
+                }),
+        )
+    }
+}
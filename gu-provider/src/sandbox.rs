@@ -0,0 +1,693 @@
+//! Sandbox for host-direct processes.
+//!
+//! Sandboxed processes run in new unprivileged user, mount and pid namespaces, and in
+//! a new network namespace unless networking is enabled. The root filesystem is an
+//! empty read-only tmpfs with configured host paths bound read-only, so only the
+//! session workspace is writable. Capabilities are dropped before exec and a seccomp
+//! filter denies syscalls that could be used to leave the sandbox.
+
+use std::{io, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SandboxConfig {
+    /// sandboxed processes share the host network
+    #[serde(default)]
+    pub network: bool,
+    /// host paths visible read-only in the sandbox
+    #[serde(default = "SandboxConfig::default_ro_paths")]
+    pub ro_paths: Vec<PathBuf>,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        SandboxConfig {
+            network: false,
+            ro_paths: Self::default_ro_paths(),
+        }
+    }
+}
+
+impl SandboxConfig {
+    fn default_ro_paths() -> Vec<PathBuf> {
+        ["/bin", "/sbin", "/lib", "/lib32", "/lib64", "/usr", "/etc"]
+            .iter()
+            .map(PathBuf::from)
+            .collect()
+    }
+}
+
+/// Sandbox of a single session.
+#[derive(Clone, Debug)]
+pub struct Sandbox {
+    config: SandboxConfig,
+    /// empty dir the sandbox root is mounted on
+    root: PathBuf,
+    workspace: PathBuf,
+}
+
+impl Sandbox {
+    pub(crate) fn new(config: SandboxConfig, root: PathBuf, workspace: PathBuf) -> Self {
+        Sandbox {
+            config,
+            root,
+            workspace,
+        }
+    }
+
+    /// Whether sandboxing is available on this platform.
+    pub fn is_supported() -> bool {
+        cfg!(all(
+            target_os = "linux",
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        ))
+    }
+
+    /// Sets up the command to run in the sandbox.
+    ///
+    /// The spawned process waits for the sandboxed one, which is pid 1 of its pid
+    /// namespace, and exits with its status. Pid 1 does not get signals it has no
+    /// handler for, so a sandboxed process without a SIGTERM handler is killed only
+    /// after the grace period.
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    pub fn apply(&self, command: &mut std::process::Command) -> io::Result<()> {
+        use std::os::unix::process::CommandExt;
+
+        let setup = linux::Setup::new(self)?;
+        unsafe {
+            command.pre_exec(move || setup.run());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    )))]
+    pub fn apply(&self, _command: &mut std::process::Command) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "sandbox is not supported on this platform",
+        ))
+    }
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod linux {
+    //! Everything run after fork is prepared in advance, so that the child does not
+    //! allocate.
+
+    use std::{
+        ffi::CString,
+        fs, io,
+        os::unix::ffi::OsStrExt,
+        path::{Path, PathBuf},
+        ptr,
+    };
+
+    use libc::c_int;
+
+    use super::Sandbox;
+
+    const NAMESPACES: c_int = libc::CLONE_NEWUSER | libc::CLONE_NEWNS | libc::CLONE_NEWPID;
+
+    /// Devices bound from the host /dev
+    const DEVICES: &[&str] = &["null", "zero", "full", "random", "urandom", "tty"];
+
+    enum Mount {
+        /// bind mount of a host path at the same path in the root
+        Bind {
+            path: CString,
+            is_dir: bool,
+            writable: bool,
+        },
+        /// symlink in the sandbox root, e.g. /lib -> usr/lib
+        Symlink {
+            path: CString,
+            target: CString,
+        },
+        Dir(CString),
+        Proc(CString),
+    }
+
+    pub(super) struct Setup {
+        namespaces: c_int,
+        uid_map: Vec<u8>,
+        gid_map: Vec<u8>,
+        root: CString,
+        mounts: Vec<Mount>,
+        filter: Vec<libc::sock_filter>,
+    }
+
+    fn cstr(path: &Path) -> io::Result<CString> {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    /// The path inside the sandbox root
+    fn in_root(root: &Path, path: &Path) -> io::Result<CString> {
+        cstr(&root.join(path.strip_prefix("/").unwrap_or(path)))
+    }
+
+    fn check(ret: c_int) -> io::Result<()> {
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    impl Setup {
+        pub(super) fn new(sandbox: &Sandbox) -> io::Result<Self> {
+            let root = &sandbox.root;
+            if !sandbox.workspace.is_absolute() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "workspace path is not absolute",
+                ));
+            }
+
+            let mut mounts = Vec::new();
+            // parents are created before their children
+            let add_dirs = |mounts: &mut Vec<Mount>, path: &Path| -> io::Result<()> {
+                let mut dirs: Vec<&Path> = path.ancestors().skip(1).collect();
+                dirs.reverse();
+                for dir in dirs.into_iter().skip(1) {
+                    mounts.push(Mount::Dir(in_root(root, dir)?));
+                }
+                Ok(())
+            };
+
+            for path in &sandbox.config.ro_paths {
+                let meta = match fs::symlink_metadata(path) {
+                    Ok(meta) => meta,
+                    Err(_) => continue,
+                };
+                add_dirs(&mut mounts, path)?;
+                if meta.file_type().is_symlink() {
+                    mounts.push(Mount::Symlink {
+                        path: in_root(root, path)?,
+                        target: cstr(&fs::read_link(path)?)?,
+                    });
+                } else {
+                    mounts.push(Mount::Bind {
+                        path: cstr(path)?,
+                        is_dir: meta.is_dir(),
+                        writable: false,
+                    });
+                }
+            }
+
+            mounts.push(Mount::Dir(in_root(root, Path::new("/dev"))?));
+            for device in DEVICES {
+                let path = PathBuf::from("/dev").join(device);
+                if path.exists() {
+                    mounts.push(Mount::Bind {
+                        path: cstr(&path)?,
+                        is_dir: false,
+                        writable: true,
+                    });
+                }
+            }
+            mounts.push(Mount::Proc(in_root(root, Path::new("/proc"))?));
+
+            add_dirs(&mut mounts, &sandbox.workspace)?;
+            mounts.push(Mount::Bind {
+                path: cstr(&sandbox.workspace)?,
+                is_dir: true,
+                writable: true,
+            });
+
+            let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+            Ok(Setup {
+                namespaces: if sandbox.config.network {
+                    NAMESPACES
+                } else {
+                    NAMESPACES | libc::CLONE_NEWNET
+                },
+                uid_map: format!("{} {} 1\n", uid, uid).into_bytes(),
+                gid_map: format!("{} {} 1\n", gid, gid).into_bytes(),
+                root: cstr(root)?,
+                mounts,
+                filter: seccomp::filter(),
+            })
+        }
+
+        /// Runs in the forked child, before exec.
+        pub(super) fn run(&self) -> io::Result<()> {
+            let mut cwd = [0 as libc::c_char; libc::PATH_MAX as usize];
+            unsafe {
+                if libc::getcwd(cwd.as_mut_ptr(), cwd.len()).is_null() {
+                    return Err(io::Error::last_os_error());
+                }
+                check(libc::unshare(self.namespaces))?;
+            }
+            self.map_ids()?;
+
+            // the pid namespace applies to children only
+            match unsafe { libc::fork() } {
+                -1 => return Err(io::Error::last_os_error()),
+                0 => (),
+                pid => wait_and_exit(pid),
+            }
+
+            unsafe {
+                check(libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL))?;
+            }
+            self.mount_root()?;
+            unsafe {
+                check(libc::chdir(cwd.as_ptr()))?;
+            }
+            drop_capabilities()?;
+            seccomp::install(&self.filter)
+        }
+
+        fn map_ids(&self) -> io::Result<()> {
+            // setgroups is missing on kernels older than 3.19
+            match write_file(b"/proc/self/setgroups\0", b"deny") {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+                r => r?,
+            }
+            write_file(b"/proc/self/uid_map\0", &self.uid_map)?;
+            write_file(b"/proc/self/gid_map\0", &self.gid_map)
+        }
+
+        fn mount_root(&self) -> io::Result<()> {
+            let root = self.root.as_ptr();
+            unsafe {
+                check(libc::mount(
+                    ptr::null(),
+                    b"/\0".as_ptr() as *const _,
+                    ptr::null(),
+                    libc::MS_REC | libc::MS_PRIVATE,
+                    ptr::null(),
+                ))?;
+                check(libc::mount(
+                    b"tmpfs\0".as_ptr() as *const _,
+                    root,
+                    b"tmpfs\0".as_ptr() as *const _,
+                    libc::MS_NOSUID | libc::MS_NODEV,
+                    b"mode=0755\0".as_ptr() as *const _,
+                ))?;
+                check(libc::chdir(root))?;
+            }
+
+            for mount in &self.mounts {
+                match mount {
+                    Mount::Dir(path) => mkdir(path)?,
+                    Mount::Symlink { path, target } => unsafe {
+                        check(libc::symlink(target.as_ptr(), path.as_ptr()))?
+                    },
+                    Mount::Proc(path) => {
+                        mkdir(path)?;
+                        // proc cannot be mounted when the host one is partially
+                        // masked, e.g. in a container; the sandbox runs without it
+                        unsafe {
+                            let _ = libc::mount(
+                                b"proc\0".as_ptr() as *const _,
+                                path.as_ptr(),
+                                b"proc\0".as_ptr() as *const _,
+                                libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                                ptr::null(),
+                            );
+                        }
+                    }
+                    Mount::Bind {
+                        path,
+                        is_dir,
+                        writable,
+                    } => self.bind(path, *is_dir, *writable)?,
+                }
+            }
+
+            unsafe {
+                // the old root is detached from under the new one
+                let dot = b".\0".as_ptr() as *const libc::c_char;
+                check(libc::syscall(libc::SYS_pivot_root, dot, dot) as c_int)?;
+                check(libc::umount2(dot, libc::MNT_DETACH))?;
+                check(libc::chdir(b"/\0".as_ptr() as *const _))?;
+                check(libc::mount(
+                    ptr::null(),
+                    b"/\0".as_ptr() as *const _,
+                    ptr::null(),
+                    libc::MS_REMOUNT | libc::MS_RDONLY | libc::MS_NOSUID | libc::MS_NODEV,
+                    ptr::null(),
+                ))
+            }
+        }
+
+        /// Binds the host path at the same path in the root; cwd is the root.
+        fn bind(&self, path: &CString, is_dir: bool, writable: bool) -> io::Result<()> {
+            // the target is relative to cwd
+            let target = unsafe { path.as_ptr().add(1) };
+            unsafe {
+                if is_dir {
+                    if libc::mkdir(target, 0o755) < 0
+                        && io::Error::last_os_error().raw_os_error() != Some(libc::EEXIST)
+                    {
+                        return Err(io::Error::last_os_error());
+                    }
+                } else {
+                    let fd = libc::open(target, libc::O_CREAT | libc::O_WRONLY, 0o644);
+                    check(fd)?;
+                    libc::close(fd);
+                }
+                check(libc::mount(
+                    path.as_ptr(),
+                    target,
+                    ptr::null(),
+                    libc::MS_BIND | libc::MS_REC,
+                    ptr::null(),
+                ))?;
+
+                if writable {
+                    return Ok(());
+                }
+                // flags locked by the host mount have to be kept
+                let mut stat: libc::statvfs = std::mem::zeroed();
+                check(libc::statvfs(target, &mut stat))?;
+                let mut flags = libc::MS_REMOUNT | libc::MS_BIND | libc::MS_RDONLY;
+                for &(st, ms) in &[
+                    (libc::ST_NOSUID, libc::MS_NOSUID),
+                    (libc::ST_NODEV, libc::MS_NODEV),
+                    (libc::ST_NOEXEC, libc::MS_NOEXEC),
+                    (libc::ST_NOATIME, libc::MS_NOATIME),
+                    (libc::ST_NODIRATIME, libc::MS_NODIRATIME),
+                    (libc::ST_RELATIME, libc::MS_RELATIME),
+                ] {
+                    if stat.f_flag & st != 0 {
+                        flags |= ms;
+                    }
+                }
+                check(libc::mount(
+                    ptr::null(),
+                    target,
+                    ptr::null(),
+                    flags,
+                    ptr::null(),
+                ))
+            }
+        }
+    }
+
+    /// Drops all capabilities, which the process has in its user namespace, so that
+    /// neither it nor anything it executes keeps them.
+    fn drop_capabilities() -> io::Result<()> {
+        #[repr(C)]
+        struct CapHeader {
+            version: u32,
+            pid: c_int,
+        }
+        #[repr(C)]
+        struct CapData {
+            effective: u32,
+            permitted: u32,
+            inheritable: u32,
+        }
+        const CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+        unsafe {
+            // the bounding set limits capabilities gained on exec, e.g. as root
+            let mut cap = 0;
+            while libc::prctl(libc::PR_CAPBSET_DROP, cap, 0, 0, 0) == 0 {
+                cap += 1;
+            }
+            if cap == 0 || io::Error::last_os_error().raw_os_error() != Some(libc::EINVAL) {
+                return Err(io::Error::last_os_error());
+            }
+            // ambient capabilities are missing on kernels older than 4.3
+            let _ = libc::prctl(
+                libc::PR_CAP_AMBIENT,
+                libc::PR_CAP_AMBIENT_CLEAR_ALL,
+                0,
+                0,
+                0,
+            );
+
+            let header = CapHeader {
+                version: CAPABILITY_VERSION_3,
+                pid: 0,
+            };
+            let data = [
+                CapData {
+                    effective: 0,
+                    permitted: 0,
+                    inheritable: 0,
+                },
+                CapData {
+                    effective: 0,
+                    permitted: 0,
+                    inheritable: 0,
+                },
+            ];
+            check(
+                libc::syscall(libc::SYS_capset, &header as *const CapHeader, data.as_ptr())
+                    as c_int,
+            )
+        }
+    }
+
+    fn mkdir(path: &CString) -> io::Result<()> {
+        unsafe {
+            if libc::mkdir(path.as_ptr(), 0o755) < 0 {
+                let e = io::Error::last_os_error();
+                if e.raw_os_error() != Some(libc::EEXIST) {
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_file(path: &[u8], content: &[u8]) -> io::Result<()> {
+        unsafe {
+            let fd = libc::open(path.as_ptr() as *const _, libc::O_WRONLY);
+            check(fd)?;
+            let written = libc::write(fd, content.as_ptr() as *const _, content.len());
+            libc::close(fd);
+            if written < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits for the sandboxed process and exits the same way.
+    fn wait_and_exit(pid: libc::pid_t) -> ! {
+        unsafe {
+            // termination signals reach the sandboxed processes through the group
+            libc::signal(libc::SIGTERM, libc::SIG_IGN);
+            libc::signal(libc::SIGINT, libc::SIG_IGN);
+            libc::signal(libc::SIGHUP, libc::SIG_IGN);
+            // pipes to the provider, including the one reporting exec errors,
+            // must be closed only by the sandboxed process
+            for fd in 0..libc::sysconf(libc::_SC_OPEN_MAX).min(65536) as c_int {
+                libc::close(fd);
+            }
+
+            let mut status = 0;
+            while libc::waitpid(pid, &mut status, 0) < 0 {
+                if io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+                    libc::_exit(127);
+                }
+            }
+            if libc::WIFSIGNALED(status) {
+                let signal = libc::WTERMSIG(status);
+                libc::signal(signal, libc::SIG_DFL);
+                libc::kill(libc::getpid(), signal);
+                libc::_exit(128 + signal);
+            }
+            libc::_exit(libc::WEXITSTATUS(status))
+        }
+    }
+
+    mod seccomp {
+        use std::io;
+
+        use libc::{c_long, sock_filter, sock_fprog};
+
+        #[cfg(target_arch = "x86_64")]
+        const AUDIT_ARCH: u32 = 0xc000_003e;
+        #[cfg(target_arch = "aarch64")]
+        const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+        const RET_KILL_PROCESS: u32 = 0x8000_0000;
+        const RET_ERRNO: u32 = 0x0005_0000;
+        const RET_ALLOW: u32 = 0x7fff_0000;
+
+        /// offsets in struct seccomp_data
+        const NR: u32 = 0;
+        const ARCH: u32 = 4;
+        const ARG0: u32 = 16;
+        /// low half of the second argument
+        const ARG1: u32 = 24;
+
+        const LD_W_ABS: u16 = 0x20;
+        const JEQ_K: u16 = 0x15;
+        const JGE_K: u16 = 0x35;
+        const JSET_K: u16 = 0x45;
+        const RET_K: u16 = 0x06;
+
+        /// namespace flags of clone
+        const CLONE_NAMESPACES: u32 = (libc::CLONE_NEWNS
+            | libc::CLONE_NEWUTS
+            | libc::CLONE_NEWIPC
+            | libc::CLONE_NEWUSER
+            | libc::CLONE_NEWPID
+            | libc::CLONE_NEWNET
+            | libc::CLONE_NEWCGROUP) as u32;
+
+        const DENIED: &[c_long] = &[
+            libc::SYS_mount,
+            libc::SYS_umount2,
+            libc::SYS_open_tree,
+            libc::SYS_move_mount,
+            libc::SYS_fsopen,
+            libc::SYS_fsconfig,
+            libc::SYS_fsmount,
+            libc::SYS_fspick,
+            libc::SYS_mount_setattr,
+            libc::SYS_pivot_root,
+            libc::SYS_chroot,
+            libc::SYS_unshare,
+            libc::SYS_setns,
+            libc::SYS_ptrace,
+            libc::SYS_process_vm_readv,
+            libc::SYS_process_vm_writev,
+            libc::SYS_kexec_load,
+            libc::SYS_init_module,
+            libc::SYS_finit_module,
+            libc::SYS_delete_module,
+            libc::SYS_reboot,
+            libc::SYS_swapon,
+            libc::SYS_swapoff,
+            libc::SYS_acct,
+            libc::SYS_bpf,
+            libc::SYS_perf_event_open,
+            libc::SYS_userfaultfd,
+            libc::SYS_keyctl,
+            libc::SYS_add_key,
+            libc::SYS_request_key,
+            libc::SYS_name_to_handle_at,
+            libc::SYS_open_by_handle_at,
+        ];
+
+        fn stmt(code: u16, k: u32) -> sock_filter {
+            sock_filter {
+                code,
+                jt: 0,
+                jf: 0,
+                k,
+            }
+        }
+
+        fn jump(code: u16, k: u32, jt: u8, jf: u8) -> sock_filter {
+            sock_filter { code, jt, jf, k }
+        }
+
+        pub(super) fn filter() -> Vec<sock_filter> {
+            let eperm = RET_ERRNO | libc::EPERM as u32;
+            let mut filter = vec![
+                stmt(LD_W_ABS, ARCH),
+                jump(JEQ_K, AUDIT_ARCH, 1, 0),
+                stmt(RET_K, RET_KILL_PROCESS),
+                stmt(LD_W_ABS, NR),
+            ];
+            // x32 syscalls
+            #[cfg(target_arch = "x86_64")]
+            filter.extend(vec![jump(JGE_K, 0x4000_0000, 0, 1), stmt(RET_K, eperm)]);
+            for &nr in DENIED {
+                filter.push(jump(JEQ_K, nr as u32, 0, 1));
+                filter.push(stmt(RET_K, eperm));
+            }
+            // clone3 flags cannot be inspected, libc falls back to clone
+            filter.push(jump(JEQ_K, libc::SYS_clone3 as u32, 0, 1));
+            filter.push(stmt(RET_K, RET_ERRNO | libc::ENOSYS as u32));
+            // TIOCSTI pushes input to a terminal shared with the provider
+            filter.extend(vec![
+                jump(JEQ_K, libc::SYS_ioctl as u32, 0, 4),
+                stmt(LD_W_ABS, ARG1),
+                jump(JEQ_K, libc::TIOCSTI as u32, 0, 1),
+                stmt(RET_K, eperm),
+                stmt(RET_K, RET_ALLOW),
+            ]);
+            filter.extend(vec![
+                jump(JEQ_K, libc::SYS_clone as u32, 0, 3),
+                stmt(LD_W_ABS, ARG0),
+                jump(JSET_K, CLONE_NAMESPACES, 0, 1),
+                stmt(RET_K, eperm),
+                stmt(RET_K, RET_ALLOW),
+            ]);
+            filter
+        }
+
+        pub(super) fn install(filter: &[sock_filter]) -> io::Result<()> {
+            let prog = sock_fprog {
+                len: filter.len() as u16,
+                filter: filter.as_ptr() as *mut _,
+            };
+            unsafe {
+                super::check(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+                super::check(libc::prctl(
+                    libc::PR_SET_SECCOMP,
+                    libc::SECCOMP_MODE_FILTER,
+                    &prog as *const sock_fprog,
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Sandbox, SandboxConfig};
+
+    #[test]
+    fn config_defaults() {
+        let config: SandboxConfig = serde_json::from_str("{}").unwrap();
+        assert!(!config.network);
+        assert!(config.ro_paths.iter().any(|path| path.ends_with("usr")));
+
+        let config: SandboxConfig =
+            serde_json::from_str(r#"{"network": true, "roPaths": ["/opt"]}"#).unwrap();
+        assert!(config.network);
+        assert_eq!(config.ro_paths, vec![std::path::PathBuf::from("/opt")]);
+    }
+
+    #[test]
+    #[ignore] // needs unprivileged user namespaces
+    fn writes_outside_workspace_fail() {
+        use std::{fs, process::Command};
+
+        let dir = std::env::temp_dir().join(format!("gu-sandbox-test-{}", std::process::id()));
+        let root = dir.join("root");
+        let workspace = dir.join("workspace");
+        fs::create_dir_all(&root).unwrap();
+        fs::create_dir_all(&workspace).unwrap();
+
+        let sandbox = Sandbox::new(SandboxConfig::default(), root, workspace.clone());
+        let run = |script: &str| {
+            let mut command = Command::new("/bin/sh");
+            command.current_dir(&workspace).args(&["-c", script]);
+            sandbox.apply(&mut command).unwrap();
+            command.status().unwrap().success()
+        };
+
+        assert!(run("echo ok > inside"));
+        assert!(workspace.join("inside").exists());
+        assert!(!run(&format!("echo x > {}", dir.join("outside").display())));
+        assert!(!dir.join("outside").exists());
+        assert!(!run("echo x > /etc/gu-sandbox-test"));
+        assert!(!run("mkdir /new-dir"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
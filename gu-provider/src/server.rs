@@ -2,7 +2,7 @@
 
 #[cfg(windows)]
 use std::net::ToSocketAddrs;
-use std::{collections::HashSet, net::SocketAddr, path::PathBuf, sync::Arc};
+use std::{collections::HashSet, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
 
 use ::actix::prelude::*;
 use actix_web::*;
@@ -19,7 +19,7 @@ use gu_base::daemon_lib::{DaemonCommand, DaemonHandler};
 use gu_base::SubCommand;
 use gu_base::{Decorator, Module};
 use gu_lan::MdnsPublisher;
-use gu_net::{rpc, NodeId};
+use gu_net::{rpc, rpc::RemotingSystemService, NodeId};
 use gu_persist::{
     config::{ConfigManager, ConfigModule, GetConfig, HasSectionId},
     http::{ServerClient, ServerConfig},
@@ -32,6 +32,14 @@ use crate::connect::{
 };
 #[cfg(feature = "env-hd")]
 use crate::hdman::HdMan;
+use crate::idle::{IdleConfig, IdleMonitor};
+use crate::image_share::ImageShare;
+use crate::permission::PermissionConfig;
+use crate::remote_config::RemoteConfig;
+use crate::remote_plugin::RemotePlugins;
+#[cfg(feature = "env-hd")]
+use crate::sandbox::Sandbox;
+use crate::sandbox::SandboxConfig;
 
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -46,6 +54,20 @@ pub(crate) struct ProviderConfig {
     publish_service: bool,
     #[serde(default = "ProviderConfig::default_connect_mode")]
     pub(crate) connect_mode: ConnectMode,
+    /// serve cached images to other providers
+    #[serde(default)]
+    pub(crate) share_images: bool,
+    #[serde(default = "ProviderConfig::default_image_port")]
+    pub(crate) image_port: u16,
+    /// seconds between SIGTERM and SIGKILL sent to stopped processes
+    #[serde(default = "ProviderConfig::default_kill_timeout")]
+    kill_timeout: u64,
+    /// sandbox of `hd-sandbox` sessions
+    #[serde(default)]
+    pub(crate) sandbox: SandboxConfig,
+    /// pausing of work while the machine is busy
+    #[serde(default)]
+    pub(crate) idle: IdleConfig,
 }
 
 impl Default for ProviderConfig {
@@ -56,6 +78,11 @@ impl Default for ProviderConfig {
             hub_addrs: HashSet::new(),
             publish_service: true,
             connect_mode: Self::default_connect_mode(),
+            share_images: false,
+            image_port: Self::default_image_port(),
+            kill_timeout: Self::default_kill_timeout(),
+            sandbox: SandboxConfig::default(),
+            idle: IdleConfig::default(),
         }
     }
 }
@@ -88,6 +115,18 @@ impl ProviderConfig {
     fn default_connect_mode() -> ConnectMode {
         ConnectMode::Manual
     }
+
+    fn default_image_port() -> u16 {
+        61624
+    }
+
+    fn default_kill_timeout() -> u64 {
+        10
+    }
+
+    pub(crate) fn kill_timeout(&self) -> Duration {
+        Duration::from_secs(self.kill_timeout)
+    }
 }
 
 impl HasSectionId for ProviderConfig {
@@ -187,7 +226,16 @@ impl Module for ServerModule {
             let config_module: &ConfigModule = dec.extract().unwrap();
 
             #[cfg(feature = "env-hd")]
-            let _ = HdMan::start(config_module);
+            {
+                let _ = HdMan::start(config_module, false);
+                if Sandbox::is_supported() {
+                    let _ = HdMan::start(config_module, true);
+                }
+            }
+            let _ = RemoteConfig::from_registry();
+            let _ = RemotePlugins::from_registry();
+            let _ = ImageShare::from_registry();
+            let _ = IdleMonitor::from_registry();
 
             ProviderServer::from_registry().do_send(InitServer {
                 decorator,
@@ -267,14 +315,21 @@ impl<D: Decorator + 'static> Handler<InitServer<D>> for ProviderServer {
                 .decorate_webapp(App::new().scope("/m", rpc::mock::scope))
         });
 
+        let config_manager = ConfigManager::from_registry();
+
         ActorResponse::r#async(
-            ConfigManager::from_registry()
+            config_manager
                 .send(GetConfig::new())
                 .flatten_fut()
-                .and_then(|config: Arc<ProviderConfig>| Ok(config.deref().clone()))
+                .join(config_manager.send(GetConfig::new()).flatten_fut())
+                .and_then(
+                    |(config, permissions): (Arc<ProviderConfig>, Arc<PermissionConfig>)| {
+                        Ok((config.deref().clone(), permissions.hub_pins()))
+                    },
+                )
                 .map_err(|e| error!("{}", e))
                 .into_actor(self)
-                .and_then(move |config: ProviderConfig, act: &mut Self, _ctx| {
+                .and_then(move |(config, hub_pins), act: &mut Self, _ctx| {
                     let keys = EthAccount::load_or_generate(keystore_path, "").unwrap();
 
                     #[cfg(unix)]
@@ -332,7 +387,8 @@ impl<D: Decorator + 'static> Handler<InitServer<D>> for ProviderServer {
                     act.publish_service(config.publish_service);
 
                     let connect =
-                        ConnectManager::init(act.node_id.unwrap(), config.hub_addrs).start();
+                        ConnectManager::init(act.node_id.unwrap(), config.hub_addrs, hub_pins)
+                            .start();
                     connect.do_send(AutoMdns(config.connect_mode == ConnectMode::Auto));
                     act.connections = Some(connect);
 
@@ -6,8 +6,11 @@ use futures::{future, prelude::*};
 use serde::{Deserialize, Serialize};
 
 use gu_base::Module;
+use gu_model::envman::SetDraining;
 use std::borrow::Cow;
 
+use crate::idle::SetPaused;
+
 pub fn module() -> impl Module {
     StatusModule
 }
@@ -86,9 +89,23 @@ impl AddProvider {
     }
 }
 
+#[derive(Message)]
+pub struct RemoveProvider(Cow<'static, str>);
+
+impl RemoveProvider {
+    #[inline]
+    pub fn new(name: impl Into<Cow<'static, str>>) -> RemoveProvider {
+        RemoveProvider(name.into())
+    }
+}
+
 #[derive(Default)]
 pub struct StatusManager {
     providers: BTreeMap<Cow<'static, str>, Recipient<GetEnvStatus>>,
+    /// Set by the hub; all environments are reported as disabled while draining.
+    draining: bool,
+    /// Set while the machine is busy; working environments are reported as paused.
+    paused: bool,
 }
 
 impl Actor for StatusManager {
@@ -107,15 +124,32 @@ impl Handler<AddProvider> for StatusManager {
     }
 }
 
+impl Handler<RemoveProvider> for StatusManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: RemoveProvider, _ctx: &mut Self::Context) -> Self::Result {
+        self.providers.remove(&msg.0);
+    }
+}
+
 impl Handler<ListEnvStatus> for StatusManager {
     type Result = ActorResponse<StatusManager, BTreeMap<String, EnvStatus>, String>;
 
     fn handle(&mut self, _msg: ListEnvStatus, _ctx: &mut Self::Context) -> Self::Result {
+        let draining = self.draining;
+        let paused = self.paused;
+
         ActorResponse::r#async(
             future::join_all(self.providers.clone().into_iter().map(
                 move |(env_name, env_addr)| {
                     let name = env_name.to_string();
-                    env_addr.send(GetEnvStatus).and_then(move |s| Ok((name, s)))
+                    env_addr
+                        .send(GetEnvStatus)
+                        .and_then(move |s| match (draining, paused, s) {
+                            (true, _, _) => Ok((name, EnvStatus::Disabled)),
+                            (false, true, EnvStatus::Working) => Ok((name, EnvStatus::Paused)),
+                            (false, _, s) => Ok((name, s)),
+                        })
                 },
             ))
             .and_then(
@@ -129,5 +163,22 @@ impl Handler<ListEnvStatus> for StatusManager {
     }
 }
 
+impl Handler<SetDraining> for StatusManager {
+    type Result = Result<(), ()>;
+
+    fn handle(&mut self, msg: SetDraining, _ctx: &mut Self::Context) -> Self::Result {
+        self.draining = msg.draining;
+        Ok(())
+    }
+}
+
+impl Handler<SetPaused> for StatusManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetPaused, _ctx: &mut Self::Context) -> Self::Result {
+        self.paused = msg.paused;
+    }
+}
+
 impl Supervised for StatusManager {}
 impl SystemService for StatusManager {}
@@ -1,4 +1,13 @@
-use std::{path::PathBuf, process};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+    process::{self, ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
 
 use actix::{fut, prelude::*};
 use log::debug;
@@ -6,6 +15,8 @@ use log::debug;
 use error::*;
 use gu_actix::*;
 
+use crate::sandbox::Sandbox;
+
 pub mod error {
     use std::{io, process};
 
@@ -58,6 +69,165 @@ impl SyncExecManager {
     }
 }
 
+/// Ids of processes run by the executor, shared with their owner, e.g. to pause them.
+pub type RunningProcesses = Arc<Mutex<HashSet<u32>>>;
+
+/// Asks processes run by the executor to exit, continuing them first if stopped.
+///
+/// The executor handles one message at a time, so a stopped process would hold
+/// back the `Terminate` sent for its session.
+pub fn interrupt(running: &RunningProcesses) {
+    #[cfg(unix)]
+    {
+        for &pid in running.lock().unwrap().iter() {
+            unsafe {
+                libc::kill(-(pid as libc::pid_t), libc::SIGTERM);
+                libc::kill(-(pid as libc::pid_t), libc::SIGCONT);
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = running;
+    }
+}
+
+/// Environment and stdin of a process
+#[derive(Debug, Default)]
+pub struct ProcessEnv {
+    /// variables set for the process
+    pub env: BTreeMap<String, String>,
+    /// do not inherit the provider environment
+    pub clear_env: bool,
+    pub stdin: Option<Input>,
+    pub sandbox: Option<Sandbox>,
+    /// set to register the process while `Exec::Run` waits for it
+    pub running: Option<RunningProcesses>,
+}
+
+#[derive(Debug)]
+pub enum Input {
+    Bytes(Vec<u8>),
+    File(fs::File),
+}
+
+impl ProcessEnv {
+    /// Spawns the command with the environment and stdin. Stdin bytes are written by
+    /// a separate thread, so that the process output can be read meanwhile.
+    ///
+    /// On unix the process leads a new process group, so that everything it spawns
+    /// can be terminated along with it.
+    pub fn spawn(self, command: &mut process::Command) -> io::Result<process::Child> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+
+            unsafe {
+                command.pre_exec(|| {
+                    if libc::setpgid(0, 0) == 0 {
+                        Ok(())
+                    } else {
+                        Err(io::Error::last_os_error())
+                    }
+                });
+            }
+        }
+        if let Some(sandbox) = &self.sandbox {
+            sandbox.apply(command)?;
+        }
+        if self.clear_env {
+            command.env_clear();
+        }
+        command.envs(self.env);
+
+        let bytes = match self.stdin {
+            Some(Input::File(file)) => {
+                command.stdin(file);
+                None
+            }
+            Some(Input::Bytes(bytes)) => {
+                command.stdin(Stdio::piped());
+                Some(bytes)
+            }
+            None => None,
+        };
+
+        let mut child = command.spawn()?;
+        if let (Some(bytes), Some(mut stdin)) = (bytes, child.stdin.take()) {
+            thread::spawn(move || {
+                if let Err(e) = stdin.write_all(&bytes) {
+                    debug!("stdin write failed: {}", e);
+                }
+            });
+        }
+        Ok(child)
+    }
+}
+
+#[cfg(unix)]
+fn signal_group(child: &process::Child, signal: libc::c_int) -> bool {
+    unsafe { libc::kill(-(child.id() as libc::pid_t), signal) == 0 }
+}
+
+/// Terminates processes along with their process groups. Groups get SIGTERM first;
+/// those still alive after the grace period are killed with SIGKILL.
+///
+/// Returns exit statuses of the processes in the given order.
+pub fn terminate(children: Vec<process::Child>, grace: Duration) -> Vec<io::Result<ExitStatus>> {
+    #[cfg(unix)]
+    {
+        for child in &children {
+            let _ = signal_group(child, libc::SIGTERM);
+            // groups stopped while the provider was busy handle SIGTERM only when continued
+            let _ = signal_group(child, libc::SIGCONT);
+        }
+
+        let deadline = Instant::now() + grace;
+        let mut children: Vec<(process::Child, Option<ExitStatus>)> =
+            children.into_iter().map(|child| (child, None)).collect();
+        loop {
+            let mut running = false;
+            for (child, status) in children.iter_mut() {
+                if status.is_none() {
+                    *status = child.try_wait().unwrap_or(None);
+                }
+                // the group outlives its leader while any process in it is running
+                running |= status.is_none() || signal_group(child, 0);
+            }
+            if !running || Instant::now() >= deadline {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        children
+            .into_iter()
+            .map(|(mut child, status)| {
+                // a reaped leader's group id may be reused once the group is empty
+                let group_alive = status.is_none() || signal_group(&child, 0);
+                if group_alive && signal_group(&child, libc::SIGKILL) {
+                    debug!("process group {} killed", child.id());
+                }
+                match status {
+                    Some(status) => Ok(status),
+                    None => child.wait(),
+                }
+            })
+            .collect()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = grace;
+        children
+            .into_iter()
+            .map(|mut child| {
+                let _ = child.kill();
+                child.wait()
+            })
+            .collect()
+    }
+}
+
 /// Message for executing commands
 #[derive(Debug)]
 pub enum Exec {
@@ -65,8 +235,13 @@ pub enum Exec {
         executable: String,
         args: Vec<String>,
         cwd: PathBuf,
+        env: ProcessEnv,
+    },
+    /// Terminates the process group, killing it after the grace period
+    Kill {
+        child: process::Child,
+        grace: Duration,
     },
-    Kill(process::Child),
 }
 
 #[derive(Debug)]
@@ -104,13 +279,29 @@ impl Handler<Exec> for SyncExec {
                 executable,
                 args,
                 cwd,
+                mut env,
             } => {
-                // TODO: critical section
-                // TODO: env::set_current_dir(&base_dir)?;
-                let output = process::Command::new(&executable)
-                    .current_dir(&cwd)
-                    .args(&args)
-                    .output();
+                let running = env.running.take();
+                let output = env
+                    .spawn(
+                        process::Command::new(&executable)
+                            .current_dir(&cwd)
+                            .args(&args)
+                            .stdin(Stdio::null())
+                            .stdout(Stdio::piped())
+                            .stderr(Stdio::piped()),
+                    )
+                    .and_then(|child| {
+                        let pid = child.id();
+                        if let Some(running) = &running {
+                            running.lock().unwrap().insert(pid);
+                        }
+                        let output = child.wait_with_output();
+                        if let Some(running) = &running {
+                            running.lock().unwrap().remove(&pid);
+                        }
+                        output
+                    });
                 match output {
                     Ok(output) => {
                         if output.status.success() {
@@ -127,10 +318,10 @@ impl Handler<Exec> for SyncExec {
                     Err(e) => Err(e.into()),
                 }
             }
-            Exec::Kill(mut child) => child
-                .kill()
-                .and_then(|_| child.wait().map_err(From::from))
-                .and_then(|_| Ok(ExecResult::Kill("Killed".into())))
+            Exec::Kill { child, grace } => terminate(vec![child], grace)
+                .pop()
+                .unwrap()
+                .map(|status| ExecResult::Kill(status.to_string()))
                 .map_err(From::from),
         }
     }
@@ -149,7 +340,7 @@ mod test {
 
     use gu_actix::flatten::FlattenFuture;
 
-    use super::{Exec, ExecResult, SyncExecManager};
+    use super::{Exec, ExecResult, Input, ProcessEnv, SyncExecManager};
 
     #[test]
     fn test_sync_exec_fail() {
@@ -159,7 +350,8 @@ mod test {
                     .send(Exec::Run {
                         executable: "/bin/ls".into(),
                         args: vec!["/1234567890asdfghjkl".into()],
-                        cwd: "/".into()
+                        cwd: "/".into(),
+                        env: Default::default(),
                     }).flatten_fut()
                     .and_then(|o: ExecResult| match o {
                         ExecResult::Run(o) => {
@@ -185,6 +377,7 @@ mod test {
                         executable: "/bin/echo".into(),
                         args: vec!["zima".into()],
                         cwd: "/".into(),
+                        env: Default::default(),
                     })
                     .flatten_fut()
                     .and_then(|o: ExecResult| match o {
@@ -203,6 +396,39 @@ mod test {
         });
     }
 
+    #[test]
+    fn test_sync_exec_env_stdin() {
+        System::run(|| {
+            Arbiter::spawn(
+                SyncExecManager::from_registry()
+                    .send(Exec::Run {
+                        executable: "/bin/sh".into(),
+                        args: vec!["-c".into(), "echo $ZIMA; cat".into()],
+                        cwd: "/".into(),
+                        env: ProcessEnv {
+                            env: vec![("ZIMA".to_string(), "lato".to_string())]
+                                .into_iter()
+                                .collect(),
+                            clear_env: true,
+                            stdin: Some(Input::Bytes(b"wiosna".to_vec())),
+                            ..Default::default()
+                        },
+                    })
+                    .flatten_fut()
+                    .and_then(|o: ExecResult| match o {
+                        ExecResult::Run(o) => {
+                            assert!(o.status.success());
+                            assert_eq!(String::from_utf8_lossy(&o.stdout), "lato\nwiosna");
+                            Ok(())
+                        }
+                        r => panic!("wrong result: {:?}", r),
+                    })
+                    .map_err(|e| panic!("error: {}", e))
+                    .then(|_| Ok(System::current().stop())),
+            )
+        });
+    }
+
     #[test]
     #[ignore]
     fn test_sync_exec_pwd() {
@@ -213,6 +439,7 @@ mod test {
                         executable: "/bin/pwd".into(),
                         args: vec![],
                         cwd: "/var/tmp".into(),
+                        env: Default::default(),
                     })
                     .flatten_fut()
                     .and_then(|o: ExecResult| match o {
@@ -231,4 +458,42 @@ mod test {
             )
         });
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_terminate_process_group() {
+        use std::io::Read;
+        use std::os::unix::process::ExitStatusExt;
+        use std::process::{Command, Stdio};
+        use std::time::{Duration, Instant};
+
+        use super::terminate;
+
+        // waits until the script sets up its traps and spawns the worker
+        let spawn = |script: &str| {
+            let mut child = ProcessEnv::default()
+                .spawn(
+                    Command::new("/bin/sh")
+                        .args(&["-c", &format!("{}; sleep 30 & echo; wait", script)])
+                        .stdout(Stdio::piped()),
+                )
+                .unwrap();
+            let _ = child.stdout.take().unwrap().read(&mut [0]).unwrap();
+            child
+        };
+
+        let started = Instant::now();
+        let status = terminate(vec![spawn(":")], Duration::from_secs(10))
+            .pop()
+            .unwrap()
+            .unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGTERM));
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        let status = terminate(vec![spawn("trap '' TERM")], Duration::from_millis(200))
+            .pop()
+            .unwrap()
+            .unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGKILL));
+    }
 }
@@ -8,7 +8,7 @@ use std::fs;
 use std::fs::DirBuilder;
 use std::io;
 use std::iter::FromIterator;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 use uuid::Uuid;
 
 pub struct WorkspacesManager {
@@ -135,6 +135,24 @@ impl Workspace {
     pub fn path(&self) -> &PathBuf {
         &self.path
     }
+
+    /// Path of a workspace entry, rejects paths leading outside of the workspace
+    pub fn resolve<P: AsRef<Path>>(&self, rel_path: P) -> io::Result<PathBuf> {
+        let rel_path = rel_path.as_ref();
+        let inside = rel_path.components().all(|c| match c {
+            Component::Normal(_) | Component::CurDir => true,
+            _ => false,
+        });
+
+        if inside {
+            Ok(self.path.join(rel_path))
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("path outside of the workspace: {}", rel_path.display()),
+            ))
+        }
+    }
 }
 
 #[cfg(test)]
@@ -176,4 +194,19 @@ mod tests {
         work.remove_tags(["tag1".to_string()].to_vec());
         assert_eq!(work.tags(), ["tag2".to_string()].to_vec());
     }
+
+    #[test]
+    fn resolve() {
+        let path = "/tmp/gu-unlimited/tests";
+        let work = Workspace::new("work".into(), path.into());
+
+        assert_eq!(
+            work.resolve("data/./in.txt").unwrap(),
+            PathBuf::from(path).join("data/./in.txt")
+        );
+        assert_eq!(work.resolve("").unwrap(), PathBuf::from(path));
+        assert!(work.resolve("../other").is_err());
+        assert!(work.resolve("data/../../other").is_err());
+        assert!(work.resolve("/etc/passwd").is_err());
+    }
 }